@@ -8,7 +8,7 @@ use std::io::{BufReader, BufWriter};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
-#[derive(Clone, Serialize, Deserialize, Inspect)]
+#[derive(Clone, Default, Serialize, Deserialize, Inspect)]
 pub struct Config {
     pub tree_col: Color,
     pub grass_col: Color,
@@ -21,6 +21,7 @@ pub struct Config {
     pub road_mid_col: Color,
     pub road_hig_col: Color,
     pub road_line_col: Color,
+    pub road_bike_col: Color,
     pub lot_residential_col: Color,
     pub lot_commercial_col: Color,
     pub special_building_col: Color,
@@ -28,10 +29,15 @@ pub struct Config {
 }
 
 fn load_config_start() -> Config {
-    let c = serde_json::from_reader(BufReader::new(
-        File::open("assets/config.json").expect("Could not open config file."),
-    ))
-    .unwrap();
+    // Tests run from the crate directory where there are no assets, they get the default colors
+    let f = match File::open("assets/config.json") {
+        Ok(f) => f,
+        Err(e) => {
+            log::warn!("could not open config file, using the default: {}", e);
+            return Config::default();
+        }
+    };
+    let c = serde_json::from_reader(BufReader::new(f)).unwrap();
     save_config(&c);
     c
 }
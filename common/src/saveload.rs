@@ -1,31 +1,43 @@
 use bincode::{DefaultOptions, Options};
 use serde::de::{DeserializeOwned, DeserializeSeed};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs::File;
-use std::io::{BufReader, BufWriter};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
 
-fn filename(name: &'static str) -> String {
-    format!("world/{}.bc", name)
+/// Path of a bincode file in the default `world/` directory
+pub fn world_path(name: &str) -> PathBuf {
+    PathBuf::from(format!("world/{}.bc", name))
 }
 
-fn create_file(path: &str) -> Option<File> {
-    File::create(path).map_err(|e| log::error!("{}", e)).ok()
+/// Path of a json file in the default `world/` directory
+pub fn world_path_json(name: &str) -> PathBuf {
+    PathBuf::from(format!("world/{}.json", name))
 }
 
-fn open_file(path: &str) -> Option<File> {
+fn create_file(path: &Path) -> Option<File> {
+    if let Some(parent) = path.parent() {
+        let _ = std::fs::create_dir_all(parent);
+    }
+    File::create(path)
+        .map_err(|e| log::error!("{}: {}", path.display(), e))
+        .ok()
+}
+
+fn open_file(path: &Path) -> Option<File> {
     File::open(path).ok()
 }
 
-pub fn save<T: Serialize>(x: &T, name: &'static str) -> Option<()> {
-    save_silent(x, name);
-    log::info!("successfully saved {}", name);
+pub fn save<T: Serialize>(x: &T, path: impl AsRef<Path>) -> Option<()> {
+    let path = path.as_ref();
+    save_silent(x, path)?;
+    log::info!("successfully saved {}", path.display());
     Some(())
 }
 
-pub fn save_silent<T: Serialize>(x: &T, name: &'static str) -> Option<()> {
-    let _ = std::fs::create_dir("world");
-
-    let file = create_file(&filename(name))?;
+pub fn save_silent<T: Serialize>(x: &T, path: impl AsRef<Path>) -> Option<()> {
+    let file = create_file(path.as_ref())?;
 
     let w = BufWriter::new(file);
 
@@ -35,74 +47,67 @@ pub fn save_silent<T: Serialize>(x: &T, name: &'static str) -> Option<()> {
     Some(())
 }
 
-pub fn load_or_default<T: DeserializeOwned + Default>(name: &'static str) -> T {
-    load(name).unwrap_or_default()
+pub fn load_or_default<T: DeserializeOwned + Default>(path: impl AsRef<Path>) -> T {
+    load(path).unwrap_or_default()
 }
 
-pub fn load<T: DeserializeOwned>(name: &'static str) -> Option<T> {
-    bincode::deserialize_from(load_reader(name)?)
-        .map_err(|err| log::error!("failed deserializing {}: {}", name, err))
+pub fn load<T: DeserializeOwned>(path: impl AsRef<Path>) -> Option<T> {
+    let path = path.as_ref();
+    bincode::deserialize_from(load_reader(path)?)
+        .map_err(|err| log::error!("failed deserializing {}: {}", path.display(), err))
         .map(|x| {
-            log::info!("successfully loaded {}", name);
+            log::info!("successfully loaded {}", path.display());
             x
         })
         .ok()
 }
 
-pub fn load_seed<S: DeserializeSeed<'static>>(name: &'static str, seed: S) -> Option<S::Value> {
+pub fn load_seed<S: DeserializeSeed<'static>>(path: impl AsRef<Path>, seed: S) -> Option<S::Value> {
+    let path = path.as_ref();
     seed.deserialize(&mut bincode::Deserializer::with_reader(
-        load_reader(name)?,
+        load_reader(path)?,
         DefaultOptions::new()
             .allow_trailing_bytes()
             .with_fixint_encoding(),
     ))
-    .map_err(|err| log::error!("failed deserializing {}: {}", name, err))
+    .map_err(|err| log::error!("failed deserializing {}: {}", path.display(), err))
     .map(|x| {
-        log::info!("successfully loaded {}", name);
+        log::info!("successfully loaded {}", path.display());
         x
     })
     .ok()
 }
 
-pub fn load_reader(name: &'static str) -> Option<BufReader<File>> {
-    let file = open_file(&filename(name))?;
-    Some(BufReader::new(file))
-}
-
-fn filename_json(name: &'static str) -> String {
-    format!("world/{}.json", name)
-}
-
-pub fn load_reader_json(name: &'static str) -> Option<BufReader<File>> {
-    let file = open_file(&filename_json(name))?;
+pub fn load_reader(path: impl AsRef<Path>) -> Option<BufReader<File>> {
+    let file = open_file(path.as_ref())?;
     Some(BufReader::new(file))
 }
 
 pub fn load_seed_json<S: DeserializeSeed<'static>>(
-    name: &'static str,
+    path: impl AsRef<Path>,
     seed: S,
 ) -> Option<S::Value> {
-    seed.deserialize(&mut serde_json::Deserializer::from_reader(
-        load_reader_json(name)?,
-    ))
-    .map_err(|err| log::error!("failed deserializing {}: {}", name, err))
+    let path = path.as_ref();
+    seed.deserialize(&mut serde_json::Deserializer::from_reader(load_reader(
+        path,
+    )?))
+    .map_err(|err| log::error!("failed deserializing {}: {}", path.display(), err))
     .map(|x| {
-        log::info!("successfully loaded {}", name);
+        log::info!("successfully loaded {}", path.display());
         x
     })
     .ok()
 }
 
-pub fn save_json<T: Serialize>(x: &T, name: &'static str) -> Option<()> {
-    save_silent_json(x, name);
-    log::info!("successfully saved {}", name);
+pub fn save_json<T: Serialize>(x: &T, path: impl AsRef<Path>) -> Option<()> {
+    let path = path.as_ref();
+    save_silent_json(x, path)?;
+    log::info!("successfully saved {}", path.display());
     Some(())
 }
 
-pub fn save_silent_json<T: Serialize>(x: &T, name: &'static str) -> Option<()> {
-    let _ = std::fs::create_dir("world");
-
-    let file = create_file(&filename_json(name))?;
+pub fn save_silent_json<T: Serialize>(x: &T, path: impl AsRef<Path>) -> Option<()> {
+    let file = create_file(path.as_ref())?;
 
     let w = BufWriter::new(file);
 
@@ -112,12 +117,320 @@ pub fn save_silent_json<T: Serialize>(x: &T, name: &'static str) -> Option<()> {
     Some(())
 }
 
-pub fn load_json<T: DeserializeOwned>(name: &'static str) -> Option<T> {
-    serde_json::from_reader(load_reader_json(name)?)
-        .map_err(|err| log::error!("failed deserializing {}: {}", name, err))
+pub fn load_json<T: DeserializeOwned>(path: impl AsRef<Path>) -> Option<T> {
+    let path = path.as_ref();
+    serde_json::from_reader(load_reader(path)?)
+        .map_err(|err| log::error!("failed deserializing {}: {}", path.display(), err))
         .map(|x| {
-            log::info!("successfully loaded {}", name);
+            log::info!("successfully loaded {}", path.display());
             x
         })
         .ok()
 }
+
+/// Magic bytes at the start of every versioned save file
+const SAVE_MAGIC: [u8; 4] = *b"EGSV";
+
+/// Versioned save files start with the magic bytes followed by the header and the payload.
+/// Files without the magic bytes are considered to be raw blobs with format version 0.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SaveHeader {
+    pub format_version: u32,
+    pub game_version: String,
+    /// Names of the registered components along with the id they were serialized with
+    pub registry: Vec<(String, u64)>,
+}
+
+impl SaveHeader {
+    pub fn component_id(&self, name: &str) -> Option<u64> {
+        self.registry
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, id)| *id)
+    }
+
+    /// Helper for migrations: components are matched by name on load
+    pub fn rename_component(&mut self, old: &str, new: &str) {
+        for (name, _) in &mut self.registry {
+            if name == old {
+                *name = new.to_string();
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    NotFound(String),
+    Io(std::io::Error),
+    Corrupted(String),
+    UnsupportedVersion { found: u32, supported: u32 },
+    MissingMigration { from: u32 },
+    Migration { from: u32, reason: String },
+    Incompatible { from: u32, reason: String },
+    Deserialize(String),
+}
+
+impl std::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadError::NotFound(path) => write!(f, "save file {} not found", path),
+            LoadError::Io(e) => write!(f, "io error: {}", e),
+            LoadError::Corrupted(e) => write!(f, "corrupted save header: {}", e),
+            LoadError::UnsupportedVersion { found, supported } => write!(
+                f,
+                "save format version {} is newer than supported version {}",
+                found, supported
+            ),
+            LoadError::MissingMigration { from } => {
+                write!(f, "no migration from save format version {}", from)
+            }
+            LoadError::Migration { from, reason } => write!(
+                f,
+                "migration from save format version {} failed: {}",
+                from, reason
+            ),
+            LoadError::Incompatible { from, reason } => write!(
+                f,
+                "saves older than format version {} cannot be loaded anymore: {}",
+                from + 1,
+                reason
+            ),
+            LoadError::Deserialize(e) => write!(f, "failed deserializing: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<std::io::Error> for LoadError {
+    fn from(e: std::io::Error) -> Self {
+        LoadError::Io(e)
+    }
+}
+
+pub struct SaveFile {
+    pub header: SaveHeader,
+    pub payload: Vec<u8>,
+}
+
+fn bincode_options() -> impl Options {
+    DefaultOptions::new()
+        .allow_trailing_bytes()
+        .with_fixint_encoding()
+}
+
+impl SaveFile {
+    pub fn new<T: Serialize>(header: SaveHeader, x: &T) -> Option<Self> {
+        let payload = bincode::serialize(x)
+            .map_err(|e| log::error!("failed serializing: {}", e))
+            .ok()?;
+        Some(Self { header, payload })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Option<()> {
+        let path = path.as_ref();
+        let file = create_file(path)?;
+        let mut w = BufWriter::new(file);
+
+        w.write_all(&SAVE_MAGIC)
+            .map_err(|e| log::error!("failed writing {}: {}", path.display(), e))
+            .ok()?;
+        bincode::serialize_into(&mut w, &self.header)
+            .map_err(|e| log::error!("failed serializing: {}", e))
+            .ok()?;
+        w.write_all(&self.payload)
+            .map_err(|e| log::error!("failed writing {}: {}", path.display(), e))
+            .ok()?;
+
+        log::info!("successfully saved {}", path.display());
+        Some(())
+    }
+
+    /// Reads the save file and upgrades it to the latest format version using the migrations
+    pub fn load(path: impl AsRef<Path>, migrations: &Migrations) -> Result<Self, LoadError> {
+        let path = path.as_ref();
+        let data = std::fs::read(path).map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => LoadError::NotFound(path.display().to_string()),
+            _ => LoadError::Io(e),
+        })?;
+
+        let mut file = if data.starts_with(&SAVE_MAGIC) {
+            let mut reader = &data[SAVE_MAGIC.len()..];
+            let header: SaveHeader = bincode_options()
+                .deserialize_from(&mut reader)
+                .map_err(|e| LoadError::Corrupted(e.to_string()))?;
+            Self {
+                header,
+                payload: reader.to_vec(),
+            }
+        } else {
+            Self {
+                header: SaveHeader {
+                    format_version: 0,
+                    game_version: "unknown".to_string(),
+                    registry: vec![],
+                },
+                payload: data,
+            }
+        };
+
+        migrations.apply(&mut file)?;
+        Ok(file)
+    }
+
+    pub fn deserialize<T: DeserializeOwned>(&self) -> Result<T, LoadError> {
+        bincode_options()
+            .deserialize(&self.payload)
+            .map_err(|e| LoadError::Deserialize(e.to_string()))
+    }
+
+    pub fn deserialize_seed<'de, S: DeserializeSeed<'de>>(
+        &'de self,
+        seed: S,
+    ) -> Result<S::Value, LoadError> {
+        seed.deserialize(&mut bincode::Deserializer::from_slice(
+            &self.payload,
+            bincode_options(),
+        ))
+        .map_err(|e| LoadError::Deserialize(e.to_string()))
+    }
+}
+
+/// A migration upgrades a save file from its format version to the next one.
+pub type Migration = fn(&mut SaveFile) -> Result<(), String>;
+
+enum Step {
+    Migrate(Migration),
+    /// The layout changed in a way the payload can't be converted, the reason is shown to the player
+    Incompatible(&'static str),
+}
+
+pub struct Migrations {
+    current_version: u32,
+    steps: BTreeMap<u32, Step>,
+}
+
+impl Migrations {
+    pub fn new(current_version: u32) -> Self {
+        Self {
+            current_version,
+            steps: BTreeMap::new(),
+        }
+    }
+
+    pub fn current_version(&self) -> u32 {
+        self.current_version
+    }
+
+    /// Registers the migration from `from_version` to `from_version + 1`
+    pub fn add(&mut self, from_version: u32, f: Migration) -> &mut Self {
+        self.steps.insert(from_version, Step::Migrate(f));
+        self
+    }
+
+    /// Registers a format change that cannot be migrated: bincode fields are positional,
+    /// so adding, removing or reordering fields breaks every save written before.
+    /// Loading such a save fails with `LoadError::Incompatible` instead of garbage.
+    pub fn incompatible(&mut self, from_version: u32, reason: &'static str) -> &mut Self {
+        self.steps.insert(from_version, Step::Incompatible(reason));
+        self
+    }
+
+    pub fn apply(&self, file: &mut SaveFile) -> Result<(), LoadError> {
+        let found = file.header.format_version;
+        if found > self.current_version {
+            return Err(LoadError::UnsupportedVersion {
+                found,
+                supported: self.current_version,
+            });
+        }
+
+        while file.header.format_version < self.current_version {
+            let from = file.header.format_version;
+            let step = self
+                .steps
+                .get(&from)
+                .ok_or(LoadError::MissingMigration { from })?;
+            match step {
+                Step::Migrate(f) => {
+                    f(file).map_err(|reason| LoadError::Migration { from, reason })?
+                }
+                Step::Incompatible(reason) => {
+                    return Err(LoadError::Incompatible {
+                        from,
+                        reason: reason.to_string(),
+                    })
+                }
+            }
+            file.header.format_version = from + 1;
+            log::info!("migrated save from format version {} to {}", from, from + 1);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LoadError, Migrations, SaveFile, SaveHeader};
+
+    fn header(format_version: u32) -> SaveHeader {
+        SaveHeader {
+            format_version,
+            game_version: "test".to_string(),
+            registry: vec![("Transform".to_string(), 1)],
+        }
+    }
+
+    #[test]
+    fn versioned_roundtrip_and_migrations() {
+        let dir = std::env::temp_dir().join(format!("egregoria_saveload_{}", std::process::id()));
+
+        let mut migrations = Migrations::new(2);
+        migrations.add(0, |_| Ok(()));
+        migrations.add(1, |file| {
+            file.header.rename_component("Transform", "Trans");
+            Ok(())
+        });
+
+        SaveFile::new(header(1), &vec![1u32, 2, 3])
+            .unwrap()
+            .save(dir.join("v1.bc"))
+            .unwrap();
+        let file = SaveFile::load(dir.join("v1.bc"), &migrations).unwrap();
+        assert_eq!(file.header.format_version, 2);
+        assert_eq!(file.header.component_id("Trans"), Some(1));
+        assert_eq!(file.deserialize::<Vec<u32>>().unwrap(), vec![1, 2, 3]);
+
+        // Files without header are considered to be at version 0
+        super::save(&vec![4u32, 5], dir.join("raw.bc")).unwrap();
+        let file = SaveFile::load(dir.join("raw.bc"), &migrations).unwrap();
+        assert_eq!(file.header.format_version, 2);
+        assert_eq!(file.deserialize::<Vec<u32>>().unwrap(), vec![4, 5]);
+
+        SaveFile::new(header(3), &0u32)
+            .unwrap()
+            .save(dir.join("future.bc"))
+            .unwrap();
+        assert!(matches!(
+            SaveFile::load(dir.join("future.bc"), &migrations),
+            Err(LoadError::UnsupportedVersion { found: 3, .. })
+        ));
+
+        let mut breaking = Migrations::new(3);
+        breaking.add(0, |_| Ok(()));
+        breaking.add(1, |_| Ok(()));
+        breaking.incompatible(2, "test layout change");
+        assert!(matches!(
+            SaveFile::load(dir.join("v1.bc"), &breaking),
+            Err(LoadError::Incompatible { from: 2, .. })
+        ));
+
+        assert!(matches!(
+            SaveFile::load(dir.join("missing.bc"), &migrations),
+            Err(LoadError::NotFound(_))
+        ));
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
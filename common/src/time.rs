@@ -53,6 +53,7 @@ impl TimeInterval {
     }
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct RecTimeInterval {
     pub start_hour: i32,
     pub start_second: i32,
@@ -158,3 +159,136 @@ impl GameTime {
         self.timestamp % Self::DAY as f64
     }
 }
+
+/// Game seconds simulated by one tick
+pub const TICK_DURATION: f64 = 1.0 / 30.0;
+
+/// Maximum number of ticks run by one update of the clock
+pub const MAX_CATCH_UP: u32 = 10;
+
+/// Drives the simulation at a fixed timestep, independently of the frame rate.
+/// Real time is scaled by the speed and accumulated, then consumed in ticks of `tick_duration`.
+#[derive(Clone, Debug)]
+pub struct SimulationClock {
+    tick_duration: f64,
+    /// Game seconds simulated per real second
+    speed: f64,
+    /// When more ticks are due, the backlog is dropped so that slow frames don't snowball
+    max_catch_up: u32,
+    paused: bool,
+    step_once: bool,
+    accumulator: f64,
+}
+
+impl Default for SimulationClock {
+    fn default() -> Self {
+        Self::new(TICK_DURATION)
+    }
+}
+
+impl SimulationClock {
+    pub fn new(tick_duration: f64) -> Self {
+        Self {
+            tick_duration,
+            speed: 1.0,
+            max_catch_up: MAX_CATCH_UP,
+            paused: false,
+            step_once: false,
+            accumulator: 0.0,
+        }
+    }
+
+    pub fn with_max_catch_up(mut self, max_catch_up: u32) -> Self {
+        self.max_catch_up = max_catch_up.max(1);
+        self
+    }
+
+    pub fn tick_duration(&self) -> f64 {
+        self.tick_duration
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    pub fn set_speed(&mut self, speed: f64) {
+        self.speed = speed.max(0.0);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    /// Runs exactly one tick on the next update, the clock must be paused
+    pub fn step_once(&mut self) {
+        self.step_once = true;
+    }
+
+    /// Number of ticks needed to simulate the given game seconds
+    pub fn ticks_in(&self, seconds: f64) -> u64 {
+        (seconds / self.tick_duration).ceil().max(0.0) as u64
+    }
+
+    /// Accumulates `real_delta` seconds of real time and returns how many ticks must be run
+    pub fn update(&mut self, real_delta: f64) -> u32 {
+        if self.paused {
+            self.accumulator = 0.0;
+            return std::mem::take(&mut self.step_once) as u32;
+        }
+        self.step_once = false;
+
+        self.accumulator += real_delta * self.speed;
+        let due = (self.accumulator / self.tick_duration) as u64;
+        let n = due.min(self.max_catch_up as u64) as u32;
+        self.accumulator -= n as f64 * self.tick_duration;
+        if due > n as u64 {
+            self.accumulator = self.accumulator.min(self.tick_duration);
+        }
+        n
+    }
+
+    /// Game time after one more tick
+    pub fn next_time(&self, time: &GameTime) -> GameTime {
+        GameTime::new(
+            self.tick_duration as f32,
+            time.timestamp + self.tick_duration,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SimulationClock;
+
+    #[test]
+    fn clock_ticks_at_fixed_rate() {
+        let mut clock = SimulationClock::new(0.1).with_max_catch_up(5);
+        assert_eq!(clock.update(0.05), 0);
+        assert_eq!(clock.update(0.05), 1);
+
+        clock.set_speed(3.0);
+        assert_eq!(clock.update(0.1), 3);
+
+        // a long frame only runs max_catch_up ticks and forgets the rest
+        assert_eq!(clock.update(10.0), 5);
+        assert!(clock.update(0.0) <= 1);
+
+        clock.pause();
+        assert_eq!(clock.update(1.0), 0);
+        clock.step_once();
+        assert_eq!(clock.update(1.0), 1);
+        assert_eq!(clock.update(1.0), 0);
+    }
+}
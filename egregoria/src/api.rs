@@ -1,15 +1,20 @@
-use crate::economy::{Market, Transaction};
-use crate::map_dynamic::{BuildingInfos, Itinerary, ParkingManagement};
-use crate::pedestrians::data::PedestrianID;
+use crate::economy::{Bid, Good, Market, Recipe};
+use crate::map_dynamic::{BikeParking, BuildingInfos, Itinerary, ParkingManagement};
+use crate::mode_choice::{
+    bike_cost, car_cost, rank_modes, transit_cost, walk_cost, Leg, ModeCost, ModeOption, TravelMode,
+};
+use crate::pedestrians::data::{Pedestrian, PedestrianID};
 use crate::pedestrians::put_pedestrian_in_coworld;
 use crate::physics::{Collider, Kinematics};
 use crate::rendering::meshrender_component::MeshRender;
+use crate::transit::{Bus, LineID, StopID, TransitNetwork, MAX_WAIT_FOR_BUS};
 use crate::vehicles::{put_vehicle_in_coworld, Vehicle, VehicleID, VehicleState};
 use crate::{Egregoria, ParCommandBuffer, SoulID};
+use common::GameTime;
 use geom::{Spline, Transform, Vec2};
 use imgui_inspect_derive::*;
-use legion::Entity;
-use map_model::{BuildingID, CarPath, Map, ParkingSpotID, PedestrianPath};
+use legion::{Entity, IntoQuery};
+use map_model::{BikePath, BuildingID, CarPath, Map, ParkingSpotID, PedestrianPath};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -29,7 +34,7 @@ pub enum Destination {
 
 debug_inspect_impl!(Destination);
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum RoutingStep {
     WalkTo(Vec2),
     DriveTo(VehicleID, Vec2),
@@ -39,6 +44,14 @@ pub enum RoutingStep {
     GetOutVehicle(VehicleID),
     GetInBuilding(BuildingID),
     GetOutBuilding(BuildingID),
+    /// Waits at the stop for a bus of the line, then boards it
+    WaitForBus(LineID, StopID),
+    /// Rides the bus the pedestrian is in until the stop, then alights
+    RideBus(StopID),
+    /// Rides the bike, preferring biking lanes
+    RideTo(VehicleID, Vec2),
+    Rack(VehicleID, BuildingID),
+    Unrack(VehicleID),
 }
 
 debug_inspect_impl!(RoutingStep);
@@ -56,14 +69,47 @@ impl RoutingStep {
             RoutingStep::GetInVehicle(vehicle) => goria.pos(vehicle.0).unwrap().is_close(pos, 3.0),
             RoutingStep::GetOutVehicle(vehicle) => matches!(
                 goria.comp::<Vehicle>(vehicle.0).unwrap().state,
-                VehicleState::Parked(_)
+                VehicleState::Parked(_) | VehicleState::Racked(_)
             ),
             &RoutingStep::GetInBuilding(build) => goria.read::<Map>().buildings()[build]
                 .door_pos
                 .is_close(pos, 3.0),
             RoutingStep::GetOutBuilding(_) => true,
+            &RoutingStep::WaitForBus(line, stop) => {
+                let network = goria.read::<TransitNetwork>();
+                let stop_pos = unwrap_or!(network.stop_pos(stop), return true);
+                stop_pos.is_close(pos, 10.0) && bus_at_stop(goria, &network, line, stop).is_some()
+            }
+            &RoutingStep::RideBus(stop) => {
+                let network = goria.read::<TransitNetwork>();
+                match goria.comp::<Location>(body.0) {
+                    Some(Location::Vehicle(v)) => match goria.comp::<Bus>(v.0) {
+                        Some(bus) => {
+                            bus.dwelling_at(&network) == Some(stop) || bus.at_terminus(&network)
+                        }
+                        None => true,
+                    },
+                    _ => true,
+                }
+            }
+            RoutingStep::RideTo(_, _) => true,
+            RoutingStep::Rack(bike, _) => goria.comp::<Itinerary>(bike.0).unwrap().has_ended(0.0),
+            RoutingStep::Unrack(_) => true,
+        }
+    }
+    /// Whether the step can still be done, the trip is planned again otherwise
+    pub fn is_possible(&self, goria: &Egregoria) -> bool {
+        match *self {
+            RoutingStep::WaitForBus(line, stop) => {
+                let daysec = goria.read::<GameTime>().daysec() as i32;
+                goria
+                    .read::<TransitNetwork>()
+                    .expects_bus(line, stop, daysec, MAX_WAIT_FOR_BUS)
+            }
+            _ => true,
         }
     }
+
     pub fn action(self, goria: &Egregoria, body: PedestrianID) -> Option<Action> {
         Some(match self {
             RoutingStep::WalkTo(obj) => {
@@ -99,25 +145,57 @@ impl RoutingStep {
             RoutingStep::GetOutVehicle(vehicle) => Action::GetOutVehicle(body, vehicle),
             RoutingStep::GetInBuilding(build) => Action::GetInBuilding(body, build),
             RoutingStep::GetOutBuilding(build) => Action::GetOutBuilding(body, build),
+            RoutingStep::WaitForBus(line, stop) => {
+                let network = goria.read::<TransitNetwork>();
+                Action::Board(body, bus_at_stop(goria, &network, line, stop)?)
+            }
+            RoutingStep::RideBus(_) => match goria.comp::<Location>(body.0)? {
+                Location::Vehicle(bus) => Action::Alight(body, *bus),
+                _ => return None,
+            },
+            RoutingStep::RideTo(bike, obj) => {
+                let pos = goria.pos(bike.0).unwrap();
+
+                let map = goria.read::<Map>();
+
+                if let Some(itin) = Itinerary::route(pos, obj, &*map, &BikePath) {
+                    Action::Navigate(bike.0, itin)
+                } else {
+                    Action::DoNothing
+                }
+            }
+            RoutingStep::Rack(bike, building) => {
+                if !goria.read::<Map>().buildings().contains_key(building) {
+                    return None;
+                }
+                Action::Rack(bike, building)
+            }
+            RoutingStep::Unrack(bike) => Action::Unrack(bike),
         })
     }
 }
 
-#[derive(Clone, Inspect)]
+#[derive(Clone, Inspect, Serialize, Deserialize)]
 pub struct Router {
     pub body: PedestrianID,
     car: Option<VehicleID>,
+    bike: Option<VehicleID>,
     steps: Vec<RoutingStep>,
     dest: Option<Destination>,
+    /// Modes considered for the current trip, cheapest (the one taken) first.
+    /// The ones that couldn't win weren't pathfinded and are costed with the straight line.
+    modes: Vec<ModeCost>,
 }
 
 impl Router {
-    pub fn new(body: PedestrianID, car: Option<VehicleID>) -> Self {
+    pub fn new(body: PedestrianID, car: Option<VehicleID>, bike: Option<VehicleID>) -> Self {
         Self {
             body,
             steps: vec![],
             car,
+            bike,
             dest: None,
+            modes: vec![],
         }
     }
 
@@ -129,18 +207,59 @@ impl Router {
         }
     }
 
+    /// Mode of the current trip, None if the router never went anywhere
+    pub fn mode(&self) -> Option<TravelMode> {
+        self.modes.first().map(|x| x.mode)
+    }
+
     pub fn body_pos(&self, goria: &Egregoria) -> Vec2 {
         goria.pos(self.body.0).unwrap()
     }
 
     fn clear_steps(&mut self, goria: &Egregoria) {
         for s in self.steps.drain(..) {
-            if let RoutingStep::Park(_, spot) = s {
-                goria.read::<ParkingManagement>().free(spot);
+            match s {
+                RoutingStep::Park(_, spot) => goria.read::<ParkingManagement>().free(spot),
+                RoutingStep::Rack(_, building) => goria.read::<BikeParking>().free(building),
+                _ => {}
             }
         }
     }
 
+    /// Forgets the current trip, the next `go_to` plans it again from where the body is
+    pub fn reset(&mut self, goria: &Egregoria) {
+        self.clear_steps(goria);
+        self.dest = None;
+    }
+
+    /// Whether the trip relies on something that isn't on the map anymore: a building, a parking
+    /// spot, or a lane or turn the body or its vehicles are routed through
+    pub fn is_stale(&self, goria: &Egregoria) -> bool {
+        let map = goria.read::<Map>();
+        let building_gone = |b: BuildingID| !map.buildings().contains_key(b);
+
+        if let Some(Destination::Building(b)) = self.dest {
+            if building_gone(b) {
+                return true;
+            }
+        }
+
+        let steps_stale = self.steps.iter().any(|step| match *step {
+            RoutingStep::Park(_, spot) => !map.parking.contains(spot),
+            RoutingStep::GetInBuilding(b)
+            | RoutingStep::GetOutBuilding(b)
+            | RoutingStep::Rack(_, b) => building_gone(b),
+            _ => false,
+        });
+
+        steps_stale
+            || std::iter::once(self.body.0)
+                .chain(self.car.map(|x| x.0))
+                .chain(self.bike.map(|x| x.0))
+                .filter_map(|e| goria.comp::<Itinerary>(e))
+                .any(|it| !it.is_valid(&map))
+    }
+
     pub fn go_to(&mut self, goria: &Egregoria, dest: Destination) -> Action {
         if self.dest.map(|x| x == dest).unwrap_or(false) {
             if let Some(action) = self.action(goria) {
@@ -172,46 +291,186 @@ impl Router {
         self.action(goria).unwrap_or(Action::DoNothing)
     }
 
-    fn steps_to(&self, goria: &Egregoria, obj: Vec2) -> Vec<RoutingStep> {
+    /// Steps to get to `obj`, choosing the mode with the lowest generalized cost
+    fn steps_to(&mut self, goria: &Egregoria, obj: Vec2) -> Vec<RoutingStep> {
         let mut steps = vec![];
+        let mut start = self.body_pos(goria);
         let loc = goria.comp::<Location>(self.body.0).unwrap();
-        if let Location::Building(cur_build) = loc {
-            steps.push(RoutingStep::GetOutBuilding(*cur_build));
+        match loc {
+            Location::Building(cur_build) => steps.push(RoutingStep::GetOutBuilding(*cur_build)),
+            Location::Vehicle(v) => {
+                // Riders get off at the next stop before going anywhere else
+                if let Some(bus) = goria.comp::<Bus>(v.0) {
+                    let network = goria.read::<TransitNetwork>();
+                    let stop = network
+                        .lines()
+                        .get(bus.line)
+                        .and_then(|l| l.stops.get(bus.stop))
+                        .copied()
+                        .unwrap_or_default();
+                    steps.push(RoutingStep::RideBus(stop));
+                    start = network.stop_pos(stop).unwrap_or(start);
+                }
+            }
+            Location::Outside => {}
         }
 
-        if let Some(car) = self.car {
-            let map = goria.read::<Map>();
+        let in_car = matches!(loc, Location::Vehicle(v) if Some(*v) == self.car);
+        let on_bike = matches!(loc, Location::Vehicle(v) if Some(*v) == self.bike);
+        let walking_speed = goria
+            .comp::<Pedestrian>(self.body.0)
+            .map_or(1.34, |p| p.walking_speed);
+        let map = goria.read::<Map>();
+
+        // Every mode that can be taken along with its steps, the cheapest one is chosen
+        let mut options: Vec<ModeOption<Vec<RoutingStep>>> = vec![];
+
+        let mut spot = None;
+        if let (Some(car), false) = (self.car, on_bike) {
             if let Some(spot_id) = goria.read::<ParkingManagement>().reserve_near(obj, &map) {
+                spot = Some(spot_id);
                 let lane = map.parking_to_drive(spot_id).unwrap();
-                let spot = *goria.read::<Map>().parking.get(spot_id).unwrap();
+                let parking = *map.parking.get(spot_id).unwrap();
 
                 let (pos, _, dir) = map.lanes()[lane]
                     .points
-                    .project_segment_dir(spot.trans.position());
+                    .project_segment_dir(parking.trans.position());
                 let parking_pos = pos - dir * 4.0;
+                let car_pos = goria.pos(car.0).unwrap();
 
-                if !matches!(loc, Location::Vehicle(_)) {
-                    steps.push(RoutingStep::WalkTo(goria.pos(car.0).unwrap()));
-                    steps.push(RoutingStep::GetInVehicle(car));
-                    steps.push(RoutingStep::Unpark(car));
+                let mut car_steps = vec![];
+                if !in_car {
+                    car_steps.push(RoutingStep::WalkTo(car_pos));
+                    car_steps.push(RoutingStep::GetInVehicle(car));
+                    car_steps.push(RoutingStep::Unpark(car));
                 }
+                car_steps.push(RoutingStep::DriveTo(car, parking_pos));
+                car_steps.push(RoutingStep::Park(car, spot_id));
+                car_steps.push(RoutingStep::GetOutVehicle(car));
+                options.push(ModeOption {
+                    leg: Some(Leg {
+                        mode: TravelMode::Car,
+                        from: car_pos,
+                        to: parking_pos,
+                    }),
+                    cost: Box::new(move |drive| {
+                        car_cost(
+                            start,
+                            car_pos,
+                            in_car,
+                            parking_pos,
+                            obj,
+                            drive,
+                            walking_speed,
+                        )
+                    }),
+                    data: car_steps,
+                });
+            }
+        }
 
-                steps.push(RoutingStep::DriveTo(car, parking_pos));
-                steps.push(RoutingStep::Park(car, spot_id));
-                steps.push(RoutingStep::GetOutVehicle(car));
+        let mut rack = None;
+        if let (Some(bike), false) = (self.bike, in_car) {
+            let building = goria.read::<BikeParking>().reserve_near(obj, &map);
+            if let Some(building) = building {
+                rack = Some(building);
+                let rack_pos = map.buildings()[building].door_pos;
+                let bike_pos = goria.pos(bike.0).unwrap();
+
+                let mut bike_steps = vec![];
+                if !on_bike {
+                    bike_steps.push(RoutingStep::WalkTo(bike_pos));
+                    bike_steps.push(RoutingStep::GetInVehicle(bike));
+                    bike_steps.push(RoutingStep::Unrack(bike));
+                }
+                bike_steps.push(RoutingStep::RideTo(bike, rack_pos));
+                bike_steps.push(RoutingStep::Rack(bike, building));
+                bike_steps.push(RoutingStep::GetOutVehicle(bike));
+                options.push(ModeOption {
+                    leg: Some(Leg {
+                        mode: TravelMode::Bike,
+                        from: bike_pos,
+                        to: rack_pos,
+                    }),
+                    cost: Box::new(move |ride| {
+                        bike_cost(start, bike_pos, on_bike, rack_pos, obj, ride, walking_speed)
+                    }),
+                    data: bike_steps,
+                });
             }
         }
 
+        // Once in the car or on the bike, the human keeps it
+        let mut ranked = vec![];
+        if in_car || on_bike {
+            ranked = rank_modes(&map, std::mem::take(&mut options));
+        }
+        if ranked.is_empty() {
+            options.push(ModeOption {
+                leg: Some(Leg {
+                    mode: TravelMode::Walk,
+                    from: start,
+                    to: obj,
+                }),
+                cost: Box::new(move |dist| walk_cost(dist, walking_speed)),
+                data: vec![],
+            });
+
+            let network = goria.read::<TransitNetwork>();
+            let daysec = goria.read::<GameTime>().daysec();
+            if let Some(trip) = network.find_trip(start, obj, daysec as i32) {
+                let cost = transit_cost(&network, &trip, start, obj, walking_speed, daysec);
+                if let (Some(cost), Some(board_pos)) = (cost, network.stop_pos(trip.board)) {
+                    options.push(ModeOption {
+                        leg: None,
+                        cost: Box::new(move |_| cost),
+                        data: vec![
+                            RoutingStep::WalkTo(board_pos),
+                            RoutingStep::WaitForBus(trip.line, trip.board),
+                            RoutingStep::RideBus(trip.alight),
+                        ],
+                    });
+                }
+            }
+            ranked = rank_modes(&map, options);
+        }
+
+        self.modes = ranked.iter().map(|(cost, _)| *cost).collect();
+
+        let chosen = ranked.into_iter().next();
+        let mode = chosen.as_ref().map(|(cost, _)| cost.mode);
+        if let (Some(spot), false) = (spot, mode == Some(TravelMode::Car)) {
+            goria.read::<ParkingManagement>().free(spot);
+        }
+        if let (Some(rack), false) = (rack, mode == Some(TravelMode::Bike)) {
+            goria.read::<BikeParking>().free(rack);
+        }
+        if let Some((_, mode_steps)) = chosen {
+            steps.extend(mode_steps);
+        }
+
         steps.push(RoutingStep::WalkTo(obj));
         steps
     }
 
+    /// The bus filled up before the body could get in, so it waits at the stop for the next one
+    pub fn missed_bus(&mut self, goria: &Egregoria, bus: VehicleID) {
+        let b = unwrap_or!(goria.comp::<Bus>(bus.0), return);
+        let network = goria.read::<TransitNetwork>();
+        if let Some(stop) = b.dwelling_at(&network) {
+            self.steps.push(RoutingStep::WaitForBus(b.line, stop));
+        }
+    }
+
     pub fn action(&mut self, goria: &Egregoria) -> Option<Action> {
         let step = unwrap_or!(self.steps.last(), return Some(Action::DoNothing));
         if step.ready(goria, self.body) {
             let step = self.steps.pop().unwrap();
             return step.action(goria, self.body);
         }
+        if !step.is_possible(goria) {
+            return None;
+        }
         Some(Action::DoNothing)
     }
 }
@@ -226,10 +485,30 @@ pub enum Action {
     Navigate(Entity, Itinerary),
     Park(VehicleID, ParkingSpotID),
     UnPark(VehicleID),
+    Board(PedestrianID, VehicleID),
+    Alight(PedestrianID, VehicleID),
+    Rack(VehicleID, BuildingID),
+    Unrack(VehicleID),
     Buy {
         buyer: SoulID,
         seller: SoulID,
-        trans: Transaction,
+        good: Good,
+        qty: i32,
+    },
+    Bid(Bid),
+    Hire {
+        worker: SoulID,
+        employer: SoulID,
+    },
+    ClockIn(SoulID),
+    Produce {
+        producer: SoulID,
+        recipe: Recipe,
+    },
+    Consume {
+        consumer: SoulID,
+        good: Good,
+        qty: i32,
     },
 }
 
@@ -320,19 +599,110 @@ impl Action {
 
                 goria.comp_mut::<Vehicle>(vehicle.0).unwrap().state = VehicleState::Driving;
             }
+            Action::Board(body, bus) => {
+                log::info!("{:?}", self);
+                let b = unwrap_or!(goria.comp_mut::<Bus>(bus.0), {
+                    log::warn!("Trying to board {:?} which isn't a bus", bus);
+                    return None;
+                });
+                if b.is_full() {
+                    return None;
+                }
+                b.passengers += 1;
+                *goria.comp_mut::<Location>(body.0).unwrap() = Location::Vehicle(bus);
+                walk_inside(goria, body);
+            }
+            Action::Alight(body, bus) => {
+                log::info!("{:?}", self);
+                if let Some(b) = goria.comp_mut::<Bus>(bus.0) {
+                    b.passengers = b.passengers.saturating_sub(1);
+                }
+                let trans = *goria.comp::<Transform>(bus.0)?;
+                walk_outside(
+                    goria,
+                    body,
+                    trans.position() + trans.direction().perpendicular() * 3.0,
+                );
+            }
+            Action::Rack(bike, building) => {
+                log::info!("{:?}", self);
+                let door_pos = goria.read::<Map>().buildings().get(building)?.door_pos;
+                goria
+                    .read::<ParCommandBuffer>()
+                    .remove_component::<Collider>(bike.0);
+                goria.comp_mut::<Kinematics>(bike.0)?.velocity = Vec2::ZERO;
+                goria.comp_mut::<Transform>(bike.0)?.set_position(door_pos);
+                goria.comp_mut::<Vehicle>(bike.0)?.state = VehicleState::Racked(building);
+            }
+            Action::Unrack(bike) => {
+                log::info!("{:?}", self);
+                let v = goria.comp::<Vehicle>(bike.0)?;
+                let w = v.kind.width();
+
+                if let VehicleState::Racked(building) = v.state {
+                    goria.read::<BikeParking>().free(building);
+                } else {
+                    log::warn!("Trying to unrack {:?} that wasn't racked", bike);
+                }
+
+                let coll =
+                    put_vehicle_in_coworld(goria, w, *goria.comp::<Transform>(bike.0).unwrap());
+                goria.read::<ParCommandBuffer>().add_component(bike.0, coll);
+
+                goria.comp_mut::<Vehicle>(bike.0).unwrap().state = VehicleState::Driving;
+            }
             Action::Buy {
                 buyer,
                 seller,
-                trans,
+                good,
+                qty,
             } => {
                 log::info!("{:?}", self);
-                goria.write::<Market>().apply(buyer, seller, trans);
+                goria.write::<Market>().buy(buyer, seller, good, qty);
+            }
+            Action::Bid(bid) => {
+                goria.write::<Market>().bid(bid);
+            }
+            Action::Hire { worker, employer } => {
+                log::info!("{:?}", self);
+                goria.write::<Market>().labor.hire(worker, employer);
+            }
+            Action::ClockIn(worker) => {
+                goria.write::<Market>().labor.clock_in(worker);
+            }
+            Action::Produce { producer, recipe } => {
+                goria.write::<Market>().produce(producer, &recipe);
+            }
+            Action::Consume {
+                consumer,
+                good,
+                qty,
+            } => {
+                goria.write::<Market>().consume(consumer, good, qty);
             }
         }
         Some(())
     }
 }
 
+/// Bus of the line dwelling at the stop that still has room
+fn bus_at_stop(
+    goria: &Egregoria,
+    network: &TransitNetwork,
+    line: LineID,
+    stop: StopID,
+) -> Option<VehicleID> {
+    <(Entity, &Bus)>::query()
+        .iter(&goria.world)
+        .find(|(_, bus)| {
+            bus.line == line
+                && !bus.is_full()
+                && !bus.at_terminus(network)
+                && bus.dwelling_at(network) == Some(stop)
+        })
+        .map(|(&e, _)| VehicleID(e))
+}
+
 fn walk_inside(goria: &mut Egregoria, body: PedestrianID) {
     let body = body.0;
     goria.comp_mut::<MeshRender>(body).unwrap().hide = true;
@@ -343,7 +713,7 @@ fn walk_inside(goria: &mut Egregoria, body: PedestrianID) {
     *goria.comp_mut::<Itinerary>(body).unwrap() = Itinerary::none();
 }
 
-fn walk_outside(goria: &mut Egregoria, body: PedestrianID, pos: Vec2) {
+pub(crate) fn walk_outside(goria: &mut Egregoria, body: PedestrianID, pos: Vec2) {
     let body = body.0;
     *goria.comp_mut::<Location>(body).unwrap() = Location::Outside;
     goria.comp_mut::<Transform>(body).unwrap().set_position(pos);
@@ -0,0 +1,124 @@
+use crate::economy::{Good, Market};
+use crate::map_dynamic::{Itinerary, ItineraryKind};
+use crate::vehicles::spawn_truck;
+use crate::{Egregoria, ParCommandBuffer, SoulID};
+use geom::{Transform, Vec2};
+use legion::{Entity, IntoQuery};
+use map_model::{CarPath, Map, RoadID, TraverseKind};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Trucks stop on the door of the buyer, a truck whose itinerary ended farther than this
+/// got lost, for example because a road of its route was removed
+const UNLOAD_DIST: f32 = 5.0;
+
+/// Goods sold through an order book that must be carried from the seller to the buyer
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct Shipment {
+    pub seller: SoulID,
+    pub buyer: SoulID,
+    pub good: Good,
+    pub qty: i32,
+    pub from: Vec2,
+    pub to: Vec2,
+}
+
+/// Component of the trucks carrying a shipment
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct Cargo(pub Shipment);
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct FreightStats {
+    pub delivered: u64,
+    /// Number of truck trips that went through every road
+    pub trips_per_road: BTreeMap<RoadID, u32>,
+}
+
+/// Sends a truck for every shipment waiting in the market.
+/// Shipments without any road between the two buildings are delivered right away.
+pub fn dispatch_trucks(goria: &mut Egregoria) {
+    let pending = std::mem::take(&mut goria.write::<Market>().pending);
+
+    for shipment in pending {
+        let it = {
+            let map = goria.read::<Map>();
+            let it = Itinerary::route(shipment.from, shipment.to, &map, &CarPath);
+            if let Some(ItineraryKind::Route(r)) = it.as_ref().map(Itinerary::kind) {
+                let roads: BTreeSet<RoadID> = r
+                    .reversed_route
+                    .iter()
+                    .chain(std::iter::once(&r.cur))
+                    .filter_map(|t| match t.kind {
+                        TraverseKind::Lane(l) => map.lanes().get(l).map(|l| l.parent),
+                        TraverseKind::Turn(_) => None,
+                    })
+                    .collect();
+
+                let mut stats = goria.write::<FreightStats>();
+                for road in roads {
+                    *stats.trips_per_road.entry(road).or_default() += 1;
+                }
+            }
+            it
+        };
+
+        match it {
+            Some(it) => {
+                let e = spawn_truck(goria, shipment.from, it);
+                goria
+                    .world
+                    .entry(e)
+                    .unwrap() // Unwrap ok: the truck was just spawned
+                    .add_component(Cargo(shipment));
+            }
+            None => {
+                log::warn!(
+                    "no road from {:?} to {:?}, delivering {:?} directly",
+                    shipment.seller,
+                    shipment.buyer,
+                    shipment.good
+                );
+                goria.write::<Market>().deliver(&shipment);
+                goria.write::<FreightStats>().delivered += 1;
+            }
+        }
+    }
+}
+
+/// Hands the cargo of the trucks that reached their destination to the buyers
+/// and removes the trucks. Trucks whose itinerary ended elsewhere are sent again to the buyer,
+/// or deliver directly if there is no road anymore.
+pub fn unload_trucks(goria: &mut Egregoria) {
+    let ended: Vec<(Entity, Shipment, Vec2)> = <(Entity, &Cargo, &Itinerary, &Transform)>::query()
+        .iter(&goria.world)
+        .filter(|(_, _, it, _)| it.has_ended(0.0))
+        .map(|(&e, &Cargo(shipment), _, trans)| (e, shipment, trans.position()))
+        .collect();
+
+    for (e, shipment, pos) in ended {
+        if !pos.is_close(shipment.to, UNLOAD_DIST) {
+            let it = Itinerary::route(pos, shipment.to, &goria.read::<Map>(), &CarPath);
+            if let Some(it) = it {
+                log::info!(
+                    "truck {:?} got lost, sending it again to {:?}",
+                    e,
+                    shipment.buyer
+                );
+                if let Some(x) = goria.comp_mut::<Itinerary>(e) {
+                    *x = it;
+                }
+                continue;
+            }
+            log::warn!(
+                "no road from the truck {:?} to {:?}, delivering {:?} directly",
+                e,
+                shipment.buyer,
+                shipment.good
+            );
+        }
+
+        goria.write::<Market>().deliver(&shipment);
+        goria.write::<FreightStats>().delivered += 1;
+        goria.read::<ParCommandBuffer>().kill(e);
+    }
+}
@@ -0,0 +1,124 @@
+use crate::economy::Money;
+use serde::{Deserialize, Serialize};
+use std::ops::{Add, AddAssign, Index, IndexMut, Mul, Sub, SubAssign};
+
+const N_GOODS: usize = 3;
+
+/// Catalogue of everything that can be produced, traded and consumed in the city
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Good {
+    /// Raw crops grown at farms
+    Crops,
+    /// Food processed from crops at workplaces
+    Food,
+    /// Groceries sold to the inhabitants by supermarkets
+    Groceries,
+}
+
+impl Good {
+    pub const ALL: [Good; N_GOODS] = [Good::Crops, Good::Food, Good::Groceries];
+
+    /// Price one unit is first sold for, before supply and demand move it
+    pub const fn price(self) -> Money {
+        match self {
+            Good::Crops => Money(100),
+            Good::Food => Money(300),
+            Good::Groceries => Money(500),
+        }
+    }
+}
+
+/// Quantity of every good of the catalogue
+#[derive(Copy, Clone, Default, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Goods([i32; N_GOODS]);
+
+impl Goods {
+    pub fn new(goods: &[(Good, i32)]) -> Self {
+        let mut v = Self::default();
+        for &(good, amount) in goods {
+            v[good] += amount;
+        }
+        v
+    }
+
+    /// Returns true if there is no more of any good than in `other`
+    pub fn is_smaller(&self, other: &Goods) -> bool {
+        self.0.iter().zip(other.0.iter()).all(|(a, b)| a <= b)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.iter().all(|&x| x == 0)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Good, i32)> + '_ {
+        Good::ALL.iter().map(move |&good| (good, self[good]))
+    }
+
+    /// Worth of the goods at their base price
+    pub fn value(&self) -> Money {
+        Money(
+            self.iter()
+                .map(|(good, amount)| good.price().0 * amount)
+                .sum(),
+        )
+    }
+}
+
+impl Index<Good> for Goods {
+    type Output = i32;
+
+    fn index(&self, good: Good) -> &i32 {
+        &self.0[good as usize]
+    }
+}
+
+impl IndexMut<Good> for Goods {
+    fn index_mut(&mut self, good: Good) -> &mut i32 {
+        &mut self.0[good as usize]
+    }
+}
+
+impl Add for Goods {
+    type Output = Goods;
+
+    fn add(mut self, rhs: Goods) -> Goods {
+        self += rhs;
+        self
+    }
+}
+
+impl AddAssign for Goods {
+    fn add_assign(&mut self, rhs: Goods) {
+        for (a, b) in self.0.iter_mut().zip(rhs.0.iter()) {
+            *a += b;
+        }
+    }
+}
+
+impl Sub for Goods {
+    type Output = Goods;
+
+    fn sub(mut self, rhs: Goods) -> Goods {
+        self -= rhs;
+        self
+    }
+}
+
+impl SubAssign for Goods {
+    fn sub_assign(&mut self, rhs: Goods) {
+        for (a, b) in self.0.iter_mut().zip(rhs.0.iter()) {
+            *a -= b;
+        }
+    }
+}
+
+impl Mul<i32> for Goods {
+    type Output = Goods;
+
+    fn mul(mut self, rhs: i32) -> Goods {
+        for a in self.0.iter_mut() {
+            *a *= rhs;
+        }
+        self
+    }
+}
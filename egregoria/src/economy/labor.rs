@@ -0,0 +1,268 @@
+use crate::economy::{Money, Recipe};
+use crate::SoulID;
+use common::SECONDS_PER_DAY;
+use geom::Vec2;
+use map_model::{BuildingID, BuildingKind};
+use ordered_float::OrderedFloat;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Share of the value added by the workers that is paid to them. The rest covers the days
+/// a producer sells less than it could make.
+pub const LABOR_SHARE: f32 = 0.5;
+
+/// Jobs offered by the buildings of the given kind
+pub fn jobs_for_building(kind: BuildingKind) -> u32 {
+    match kind {
+        BuildingKind::Workplace => 20,
+        BuildingKind::Supermarket => 8,
+        BuildingKind::Farm => 5,
+        BuildingKind::House => 0,
+    }
+}
+
+/// Paid for every shift a worker shows up to: its share of the value a fully staffed building
+/// adds in a day, at the base prices of the goods
+pub fn wage_for_building(kind: BuildingKind) -> Money {
+    let recipe = unwrap_or!(Recipe::for_building(kind), return Money(0));
+    let jobs = jobs_for_building(kind);
+    if jobs == 0 {
+        return Money(0);
+    }
+    let batches_per_day = SECONDS_PER_DAY as f32 / recipe.duration;
+    let value_added = (recipe.outputs.value().0 - recipe.inputs.value().0) as f32;
+    Money((value_added * batches_per_day * LABOR_SHARE / jobs as f32) as i32)
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Employer {
+    pub building: BuildingID,
+    pub pos: Vec2,
+    pub jobs: u32,
+    pub wage: Money,
+    pub employees: BTreeSet<SoulID>,
+}
+
+impl Employer {
+    pub fn has_opening(&self) -> bool {
+        (self.employees.len() as u32) < self.jobs
+    }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct LaborMarket {
+    pub employers: BTreeMap<SoulID, Employer>,
+    /// Employer of every employed worker
+    employment: BTreeMap<SoulID, SoulID>,
+    /// Workers who showed up at work since the last payday
+    attended: BTreeSet<SoulID>,
+}
+
+impl LaborMarket {
+    pub fn add_employer(
+        &mut self,
+        id: SoulID,
+        building: BuildingID,
+        pos: Vec2,
+        jobs: u32,
+        wage: Money,
+    ) {
+        self.employers.insert(
+            id,
+            Employer {
+                building,
+                pos,
+                jobs,
+                wage,
+                employees: BTreeSet::new(),
+            },
+        );
+    }
+
+    pub fn employer_of(&self, worker: SoulID) -> Option<(SoulID, &Employer)> {
+        let id = *self.employment.get(&worker)?;
+        Some((id, self.employers.get(&id)?))
+    }
+
+    pub fn n_employed(&self) -> usize {
+        self.employment.len()
+    }
+
+    pub fn n_jobs(&self) -> u32 {
+        self.employers.values().map(|x| x.jobs).sum()
+    }
+
+    /// Employer with an opening that is the least staffed, the closest to `pos` among equals:
+    /// a producer without workers holds up everyone down its supply chain
+    pub fn find_job(&self, pos: Vec2) -> Option<SoulID> {
+        self.employers
+            .iter()
+            .filter(|(_, e)| e.has_opening())
+            .min_by_key(|(&id, e)| {
+                (
+                    OrderedFloat(self.staffing(id)),
+                    OrderedFloat(e.pos.distance2(pos)),
+                )
+            })
+            .map(|(&id, _)| id)
+    }
+
+    /// Returns false if the worker already has a job or the employer is full
+    pub fn hire(&mut self, worker: SoulID, employer: SoulID) -> bool {
+        if self.employment.contains_key(&worker) {
+            return false;
+        }
+        let e = unwrap_or!(self.employers.get_mut(&employer), return false);
+        if !e.has_opening() {
+            return false;
+        }
+        e.employees.insert(worker);
+        self.employment.insert(worker, employer);
+        true
+    }
+
+    pub fn fire(&mut self, worker: SoulID) {
+        if let Some(employer) = self.employment.remove(&worker) {
+            if let Some(e) = self.employers.get_mut(&employer) {
+                e.employees.remove(&worker);
+            }
+        }
+        self.attended.remove(&worker);
+    }
+
+    pub fn clock_in(&mut self, worker: SoulID) {
+        if self.employment.contains_key(&worker) {
+            self.attended.insert(worker);
+        }
+    }
+
+    pub fn has_clocked_in(&self, worker: SoulID) -> bool {
+        self.attended.contains(&worker)
+    }
+
+    /// Fraction of the jobs of the employer that are filled, production goes at this pace
+    pub fn staffing(&self, employer: SoulID) -> f32 {
+        match self.employers.get(&employer) {
+            Some(e) if e.jobs > 0 => e.employees.len() as f32 / e.jobs as f32,
+            _ => 0.0,
+        }
+    }
+
+    /// Workers who showed up along with their employer and wage, and starts a new shift
+    pub(crate) fn end_shift(&mut self) -> Vec<(SoulID, SoulID, Money)> {
+        let attended = std::mem::take(&mut self.attended);
+        attended
+            .into_iter()
+            .filter_map(|worker| {
+                let (employer, e) = self.employer_of(worker)?;
+                Some((worker, employer, e.wage))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WAGE: Money = Money(1000);
+
+    fn labor(jobs: u32) -> LaborMarket {
+        let mut l = LaborMarket::default();
+        l.add_employer(SoulID(0), BuildingID::default(), Vec2::ZERO, jobs, WAGE);
+        l
+    }
+
+    #[test]
+    fn hire_up_to_the_openings() {
+        let mut l = labor(2);
+        assert!(l.hire(SoulID(1), SoulID(0)));
+        assert!(!l.hire(SoulID(1), SoulID(0)));
+        assert!(l.hire(SoulID(2), SoulID(0)));
+        assert!(!l.hire(SoulID(3), SoulID(0)));
+        assert!(!l.hire(SoulID(3), SoulID(42)));
+
+        assert_eq!(l.n_employed(), 2);
+        assert_eq!(l.find_job(Vec2::ZERO), None);
+        assert_eq!(l.employer_of(SoulID(2)).map(|(id, _)| id), Some(SoulID(0)));
+        assert!(l.employer_of(SoulID(3)).is_none());
+    }
+
+    #[test]
+    fn fire_frees_the_job() {
+        let mut l = labor(1);
+        assert!(l.hire(SoulID(1), SoulID(0)));
+        l.clock_in(SoulID(1));
+        l.fire(SoulID(1));
+
+        assert_eq!(l.n_employed(), 0);
+        assert!(!l.has_clocked_in(SoulID(1)));
+        assert_eq!(l.find_job(Vec2::ZERO), Some(SoulID(0)));
+        assert!(l.hire(SoulID(2), SoulID(0)));
+    }
+
+    #[test]
+    fn jobs_go_to_the_least_staffed_employer_first() {
+        let mut l = labor(2);
+        l.add_employer(
+            SoulID(1),
+            BuildingID::default(),
+            Vec2::new(100.0, 0.0),
+            4,
+            WAGE,
+        );
+
+        assert_eq!(l.find_job(Vec2::ZERO), Some(SoulID(0)));
+        assert!(l.hire(SoulID(10), SoulID(0)));
+        assert_eq!(l.find_job(Vec2::ZERO), Some(SoulID(1)));
+        assert!(l.hire(SoulID(11), SoulID(1)));
+        assert!(l.hire(SoulID(12), SoulID(1)));
+        assert_eq!(l.find_job(Vec2::ZERO), Some(SoulID(0)));
+    }
+
+    #[test]
+    fn wages_leave_producers_a_margin() {
+        for &kind in &[
+            BuildingKind::Farm,
+            BuildingKind::Workplace,
+            BuildingKind::Supermarket,
+        ] {
+            let recipe = Recipe::for_building(kind).unwrap();
+            let batches_per_day = SECONDS_PER_DAY as f32 / recipe.duration;
+            let value_added =
+                (recipe.outputs.value().0 - recipe.inputs.value().0) as f32 * batches_per_day;
+            let payroll = (wage_for_building(kind).0 * jobs_for_building(kind) as i32) as f32;
+            assert!(payroll > 0.0, "{:?} pays nothing", kind);
+            assert!(payroll < value_added, "{:?} pays more than it makes", kind);
+        }
+    }
+
+    #[test]
+    fn only_attending_workers_are_paid() {
+        let mut l = labor(3);
+        l.hire(SoulID(1), SoulID(0));
+        l.hire(SoulID(2), SoulID(0));
+
+        l.clock_in(SoulID(1));
+        l.clock_in(SoulID(1));
+        // Not employed, clocking in does nothing
+        l.clock_in(SoulID(3));
+
+        assert_eq!(l.end_shift(), vec![(SoulID(1), SoulID(0), WAGE)]);
+        // A new shift starts
+        assert!(!l.has_clocked_in(SoulID(1)));
+        assert!(l.end_shift().is_empty());
+    }
+
+    #[test]
+    fn staffing_is_the_filled_share() {
+        let mut l = labor(4);
+        assert_eq!(l.staffing(SoulID(0)), 0.0);
+        l.hire(SoulID(1), SoulID(0));
+        assert_eq!(l.staffing(SoulID(0)), 0.25);
+        assert_eq!(l.staffing(SoulID(42)), 0.0);
+
+        l.add_employer(SoulID(5), BuildingID::default(), Vec2::ZERO, 0, WAGE);
+        assert_eq!(l.staffing(SoulID(5)), 0.0);
+    }
+}
@@ -1,75 +1,538 @@
-use crate::economy::{EconomicAgent, Goods, Transaction};
+use crate::economy::{EconomicAgent, Good, Goods, LaborMarket, Money, Recipe, Shipment};
 use crate::SoulID;
-use std::collections::HashMap;
+use common::{GameTime, SECONDS_PER_DAY, SECONDS_PER_HOUR};
+use geom::Vec2;
+use legion::system;
+use map_model::BuildingID;
+use ordered_float::OrderedFloat;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
 
-#[derive(Default)]
+/// Cost, in cents per meter, buyers add to the price of a seller to account for the trip
+pub const DISTANCE_COST: f32 = 0.1;
+
+/// Relative change of the reference price per game second when all the demand or all the supply
+/// is left unmatched
+pub const PRICE_ADJUST_RATE: f32 = 0.002;
+
+/// Relative pull of the reference price toward the base price of the good per game second,
+/// per e-fold between the two: prices settle near the cost of making the goods unless supply or
+/// demand is left unmatched for good
+pub const PRICE_ANCHOR_RATE: f32 = 0.0018;
+
+/// Hourly samples of the price kept in the history, a week
+pub const PRICE_HISTORY_LEN: usize = 24 * 7;
+
+/// Standing offer to buy some units of a good at most at `max_price` each
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct Bid {
+    pub buyer: SoulID,
+    pub good: Good,
+    pub qty: i32,
+    pub max_price: Money,
+    pub pos: Vec2,
+}
+
+/// Seller of a good, it offers its whole stock at a price depending on how much it has left
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct Seller {
+    pub building: BuildingID,
+    pub pos: Vec2,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OrderBook {
+    /// Reference price in cents, sellers ask around it
+    pub price: f32,
+    pub history: VecDeque<Money>,
+    pub sellers: BTreeMap<SoulID, Seller>,
+    pub bids: BTreeMap<SoulID, Bid>,
+    /// Units bought directly from the sellers since the last price update.
+    /// Standing bids are counted once per update by their open quantity.
+    bought: i32,
+}
+
+impl OrderBook {
+    fn new(good: Good) -> Self {
+        Self {
+            price: good.price().0 as f32,
+            history: VecDeque::new(),
+            sellers: BTreeMap::new(),
+            bids: BTreeMap::new(),
+            bought: 0,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Market {
     pub agents: HashMap<SoulID, EconomicAgent>,
-    pub for_sale: HashMap<SoulID, Vec<Transaction>>,
+    pub books: BTreeMap<Good, OrderBook>,
+    pub labor: LaborMarket,
+    /// Goods sold through the order book waiting for a truck
+    pub pending: Vec<Shipment>,
+    /// Goods bought by every buyer that are still on their way
+    in_transit: BTreeMap<SoulID, Goods>,
+}
+
+impl Default for Market {
+    fn default() -> Self {
+        Self {
+            agents: HashMap::new(),
+            books: Good::ALL.iter().map(|&g| (g, OrderBook::new(g))).collect(),
+            labor: LaborMarket::default(),
+            pending: vec![],
+            in_transit: BTreeMap::new(),
+        }
+    }
+}
+
+/// Sellers running low ask for more, up to twice the reference price for their last unit
+fn ask_price(reference: f32, stock: i32) -> Money {
+    let price = reference * (1.0 + 1.0 / stock.max(1) as f32);
+    Money((price.round() as i32).max(1))
 }
 
 impl Market {
-    pub fn propose(&mut self, soul: SoulID, transactions: Vec<Transaction>) {
-        self.for_sale.insert(soul, transactions);
+    pub fn book(&self, good: Good) -> &OrderBook {
+        &self.books[&good]
     }
 
-    pub fn want(&self, seller: SoulID, goods: Goods) -> Option<Transaction> {
-        self.for_sale
-            .get(&seller)?
-            .iter()
-            .copied()
-            .filter(|trans| trans.delta.is_smaller(&goods))
-            .min_by_key(|trans| trans.cost)
+    fn book_mut(&mut self, good: Good) -> &mut OrderBook {
+        self.books
+            .get_mut(&good)
+            .expect("every good has an order book")
     }
 
-    pub fn apply(&mut self, buyer_id: SoulID, seller_id: SoulID, transaction: Transaction) -> bool {
-        if buyer_id == seller_id {
-            log::warn!(
-                "Trying to sell {:?} to itself ({:?})",
-                transaction,
-                buyer_id
-            );
-            return false;
+    /// Current reference price of the good
+    pub fn price(&self, good: Good) -> Money {
+        Money((self.book(good).price.round() as i32).max(1))
+    }
+
+    /// Registers `seller` as selling its stock of `good` from the given building
+    pub fn sell(&mut self, seller: SoulID, good: Good, building: BuildingID, pos: Vec2) {
+        self.book_mut(good)
+            .sellers
+            .insert(seller, Seller { building, pos });
+    }
+
+    /// Price `seller` asks for one unit of `good`, None if it has nothing to sell
+    pub fn ask(&self, seller: SoulID, good: Good) -> Option<Money> {
+        if !self.book(good).sellers.contains_key(&seller) {
+            return None;
+        }
+        let stock = self.agents.get(&seller)?.goods[good];
+        if stock <= 0 {
+            return None;
         }
+        Some(ask_price(self.book(good).price, stock))
+    }
+
+    /// Seller of `good` with the lowest price once the trip from `pos` is accounted for
+    pub fn best_seller(&self, buyer: SoulID, good: Good, pos: Vec2) -> Option<(SoulID, Seller)> {
+        best_seller(&self.agents, self.book(good), buyer, good, pos)
+            .map(|(id, seller, _)| (id, seller))
+    }
 
-        let (buyer, seller) = match common::get_mut_pair(&mut self.agents, &buyer_id, &seller_id) {
+    pub fn has_bid(&self, buyer: SoulID, good: Good) -> bool {
+        self.book(good).bids.contains_key(&buyer)
+    }
+
+    /// Places a bid, replacing the previous one of the buyer for this good
+    pub fn bid(&mut self, bid: Bid) {
+        self.book_mut(bid.good).bids.insert(bid.buyer, bid);
+    }
+
+    /// Buys `qty` units of `good` directly from `seller` at its asking price.
+    /// Returns false if the seller doesn't have them or the buyer can't afford them.
+    pub fn buy(&mut self, buyer: SoulID, seller: SoulID, good: Good, qty: i32) -> bool {
+        let price = match self.ask(seller, good) {
             Some(x) => x,
             None => {
-                log::warn!(
-                    "Trying to apply transaction to non existing agents: {:?} and/or {:?}",
-                    buyer_id,
-                    seller_id
-                );
+                log::warn!("{:?} has no {:?} to sell to {:?}", seller, good, buyer);
                 return false;
             }
         };
+        if !trade(&mut self.agents, buyer, seller, good, qty, price) {
+            return false;
+        }
+        if let Some(agent) = self.agents.get_mut(&buyer) {
+            agent.goods[good] += qty;
+        }
+        self.book_mut(good).bought += qty;
+        true
+    }
 
-        if buyer.money < transaction.cost {
-            log::warn!(
-                "Buyer {:?} doesnt have enough {:?} to fullfill {:?}",
-                buyer.id,
-                buyer.money,
-                transaction
-            );
+    pub fn in_transit(&self, buyer: SoulID) -> Goods {
+        self.in_transit.get(&buyer).copied().unwrap_or_default()
+    }
+
+    /// Hands over the goods of a shipment to its buyer
+    pub fn deliver(&mut self, shipment: &Shipment) {
+        let cargo = Goods::new(&[(shipment.good, shipment.qty)]);
+        if let Some(transit) = self.in_transit.get_mut(&shipment.buyer) {
+            *transit -= cargo;
+            if transit.is_empty() {
+                self.in_transit.remove(&shipment.buyer);
+            }
+        }
+        if let Some(agent) = self.agents.get_mut(&shipment.buyer) {
+            agent.goods += cargo;
+        }
+    }
+
+    /// Turns the inputs of the recipe into its outputs in the stock of the producer.
+    /// Returns false if the inputs are missing.
+    pub fn produce(&mut self, producer: SoulID, recipe: &Recipe) -> bool {
+        let agent = unwrap_or!(self.agents.get_mut(&producer), return false);
+        if !recipe.inputs.is_smaller(&agent.goods) {
+            return false;
+        }
+        agent.goods -= recipe.inputs;
+        agent.goods += recipe.outputs;
+        true
+    }
+
+    /// Uses up `qty` units of `good` from the stock of the agent.
+    /// Returns false if it doesn't have them.
+    pub fn consume(&mut self, agent: SoulID, good: Good, qty: i32) -> bool {
+        let agent = unwrap_or!(self.agents.get_mut(&agent), return false);
+        if agent.goods[good] < qty {
             return false;
         }
+        agent.goods[good] -= qty;
+        true
+    }
+
+    /// Matches the bids with the sellers, then moves the reference prices toward
+    /// the ones balancing supply and demand.
+    /// Matched goods leave the seller right away but only reach the buyer once a truck delivers them.
+    pub fn update(&mut self, delta: f32) {
+        let agents = &mut self.agents;
+        let pending = &mut self.pending;
+        let in_transit = &mut self.in_transit;
+        for (&good, book) in self.books.iter_mut() {
+            let mut order: Vec<Bid> = book.bids.values().copied().collect();
+            order.sort_by_key(|b| (std::cmp::Reverse(b.max_price), b.buyer));
+
+            let bought = std::mem::take(&mut book.bought);
+            for mut bid in order {
+                while bid.qty > 0 {
+                    let (seller, from, price) =
+                        unwrap_or!(best_seller(agents, book, bid.buyer, good, bid.pos), break);
+                    if price > bid.max_price {
+                        break;
+                    }
+                    let stock = agents[&seller].goods[good];
+                    let affordable = agents.get(&bid.buyer).map_or(0, |x| x.money.0 / price.0);
+                    let qty = bid.qty.min(stock).min(affordable);
+                    if qty <= 0 || !trade(agents, bid.buyer, seller, good, qty, price) {
+                        break;
+                    }
+                    bid.qty -= qty;
+
+                    *in_transit.entry(bid.buyer).or_default() += Goods::new(&[(good, qty)]);
+                    pending.push(Shipment {
+                        seller,
+                        buyer: bid.buyer,
+                        good,
+                        qty,
+                        from: from.pos,
+                        to: bid.pos,
+                    });
+                }
+
+                if bid.qty > 0 {
+                    book.bids.insert(bid.buyer, bid);
+                } else {
+                    book.bids.remove(&bid.buyer);
+                }
+            }
+
+            // Bids priced out of the market don't push the price further up
+            let open: i32 = book
+                .bids
+                .values()
+                .filter(|b| b.max_price.0 as f32 >= book.price)
+                .map(|b| b.qty)
+                .sum();
+            let demand = open + bought;
+            let supply: i32 = book
+                .sellers
+                .keys()
+                .filter_map(|id| agents.get(id))
+                .map(|x| x.goods[good].max(0))
+                .sum();
+            let excess = (demand - supply) as f32 / (demand + supply).max(1) as f32;
 
-        if !transaction.delta.is_smaller(&seller.goods) {
+            let base = good.price().0 as f32;
+            let anchor = (base / book.price).ln();
+            book.price = (book.price
+                * (1.0 + (PRICE_ADJUST_RATE * excess + PRICE_ANCHOR_RATE * anchor) * delta))
+                .max(base * 0.1)
+                .min(base * 10.0);
+        }
+    }
+
+    /// Pays the workers of the shift that just ended.
+    /// Employers that can't afford a worker fire them.
+    pub fn pay_wages(&mut self) {
+        for (worker, employer, wage) in self.labor.end_shift() {
+            if !pay(&mut self.agents, employer, worker, wage) {
+                log::info!("{:?} couldn't pay {:?} and fired them", employer, worker);
+                self.labor.fire(worker);
+            }
+        }
+    }
+
+    /// Samples the reference prices into their history
+    pub fn record_prices(&mut self) {
+        for book in self.books.values_mut() {
+            book.history
+                .push_back(Money((book.price.round() as i32).max(1)));
+            if book.history.len() > PRICE_HISTORY_LEN {
+                book.history.pop_front();
+            }
+        }
+    }
+}
+
+fn best_seller(
+    agents: &HashMap<SoulID, EconomicAgent>,
+    book: &OrderBook,
+    buyer: SoulID,
+    good: Good,
+    pos: Vec2,
+) -> Option<(SoulID, Seller, Money)> {
+    book.sellers
+        .iter()
+        .filter(|(&id, _)| id != buyer)
+        .filter_map(|(&id, &seller)| {
+            let stock = agents.get(&id)?.goods[good];
+            if stock <= 0 {
+                return None;
+            }
+            Some((id, seller, ask_price(book.price, stock)))
+        })
+        .min_by_key(|(_, seller, price)| {
+            OrderedFloat(price.0 as f32 + seller.pos.distance(pos) * DISTANCE_COST)
+        })
+}
+
+fn pay(
+    agents: &mut HashMap<SoulID, EconomicAgent>,
+    from: SoulID,
+    to: SoulID,
+    amount: Money,
+) -> bool {
+    let (from, to) = unwrap_or!(common::get_mut_pair(agents, &from, &to), return false);
+    if from.money < amount {
+        return false;
+    }
+    from.money -= amount;
+    to.money += amount;
+    true
+}
+
+fn trade(
+    agents: &mut HashMap<SoulID, EconomicAgent>,
+    buyer_id: SoulID,
+    seller_id: SoulID,
+    good: Good,
+    qty: i32,
+    price: Money,
+) -> bool {
+    if buyer_id == seller_id {
+        log::warn!("Trying to sell {:?} to itself ({:?})", good, buyer_id);
+        return false;
+    }
+
+    let (buyer, seller) = match common::get_mut_pair(agents, &buyer_id, &seller_id) {
+        Some(x) => x,
+        None => {
             log::warn!(
-                "Seller {:?} doesn't have enough {:?} to fullfill {:?}",
-                seller.id,
-                seller.goods,
-                transaction
+                "Trying to trade between non existing agents: {:?} and/or {:?}",
+                buyer_id,
+                seller_id
             );
             return false;
         }
+    };
 
-        seller.money += transaction.cost;
-        seller.goods -= transaction.delta;
+    let cost = Money(price.0 * qty);
+    if buyer.money < cost {
+        log::warn!(
+            "Buyer {:?} doesnt have enough {:?} to buy {} {:?}",
+            buyer.id,
+            buyer.money,
+            qty,
+            good
+        );
+        return false;
+    }
+
+    if seller.goods[good] < qty {
+        log::warn!(
+            "Seller {:?} doesn't have {} {:?} to sell",
+            seller.id,
+            qty,
+            good
+        );
+        return false;
+    }
 
-        buyer.money -= transaction.cost;
-        buyer.goods += transaction.delta;
+    seller.money += cost;
+    seller.goods[good] -= qty;
+    buyer.money -= cost;
 
-        true
+    true
+}
+
+#[system]
+pub fn market_update(#[resource] market: &mut Market, #[resource] time: &GameTime) {
+    market.update(time.delta);
+    if time.tick(SECONDS_PER_HOUR as u32) {
+        market.record_prices();
+    }
+    if time.tick(SECONDS_PER_DAY as u32) {
+        market.pay_wages();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn market(agents: &[(usize, i32, Goods)]) -> Market {
+        let mut m = Market::default();
+        for &(id, money, goods) in agents {
+            m.agents.insert(
+                SoulID(id),
+                EconomicAgent::new(SoulID(id), Money(money), goods),
+            );
+        }
+        m
+    }
+
+    fn bid(buyer: usize, qty: i32, max_price: i32) -> Bid {
+        Bid {
+            buyer: SoulID(buyer),
+            good: Good::Food,
+            qty,
+            max_price: Money(max_price),
+            pos: Vec2::ZERO,
+        }
+    }
+
+    #[test]
+    fn highest_bid_is_matched_first() {
+        let stock = Goods::new(&[(Good::Food, 2)]);
+        let mut m = market(&[
+            (0, 0, stock),
+            (1, 10_000, Goods::default()),
+            (2, 10_000, Goods::default()),
+        ]);
+        m.sell(SoulID(0), Good::Food, BuildingID::default(), Vec2::ZERO);
+        m.bid(bid(1, 2, 400));
+        m.bid(bid(2, 2, 1_000));
+
+        m.update(0.0);
+
+        // The richer bid takes the whole stock, the other one stays open
+        assert_eq!(m.in_transit(SoulID(2))[Good::Food], 2);
+        assert_eq!(m.in_transit(SoulID(1))[Good::Food], 0);
+        assert!(m.has_bid(SoulID(1), Good::Food));
+        assert!(!m.has_bid(SoulID(2), Good::Food));
+        assert_eq!(m.agents[&SoulID(0)].goods[Good::Food], 0);
+        assert_eq!(m.pending.len(), 1);
+
+        let paid = 10_000 - m.agents[&SoulID(2)].money.0;
+        assert!(paid > 0 && paid <= 2 * 1_000);
+        assert_eq!(m.agents[&SoulID(0)].money.0, paid);
+    }
+
+    #[test]
+    fn bid_below_ask_is_not_matched() {
+        let stock = Goods::new(&[(Good::Food, 5)]);
+        let mut m = market(&[(0, 0, stock), (1, 10_000, Goods::default())]);
+        m.sell(SoulID(0), Good::Food, BuildingID::default(), Vec2::ZERO);
+        m.bid(bid(1, 1, 1));
+
+        m.update(0.0);
+
+        assert!(m.has_bid(SoulID(1), Good::Food));
+        assert_eq!(m.agents[&SoulID(0)].goods[Good::Food], 5);
+        assert_eq!(m.agents[&SoulID(1)].money, Money(10_000));
+    }
+
+    #[test]
+    fn consuming_uses_up_the_stock() {
+        let mut m = market(&[(0, 0, Goods::new(&[(Good::Groceries, 2)]))]);
+
+        assert!(m.consume(SoulID(0), Good::Groceries, 1));
+        assert!(!m.consume(SoulID(0), Good::Groceries, 2));
+        assert_eq!(m.agents[&SoulID(0)].goods[Good::Groceries], 1);
+    }
+
+    #[test]
+    fn price_follows_supply_and_demand() {
+        // Unmatched demand raises the price
+        let mut m = market(&[(1, 10_000, Goods::default())]);
+        m.bid(bid(1, 3, 1_000));
+        let before = m.book(Good::Food).price;
+        m.update(1.0);
+        let after_one = m.book(Good::Food).price;
+        assert!(after_one > before);
+
+        // A standing bid counts the same every update instead of piling up
+        m.update(1.0);
+        let after_two = m.book(Good::Food).price;
+        let ratio = |a: f32, b: f32| b / a;
+        assert!((ratio(before, after_one) - ratio(after_one, after_two)).abs() < 1e-4);
+
+        // Unsold stock lowers it
+        let stock = Goods::new(&[(Good::Food, 10)]);
+        let mut m = market(&[(0, 0, stock)]);
+        m.sell(SoulID(0), Good::Food, BuildingID::default(), Vec2::ZERO);
+        let before = m.book(Good::Food).price;
+        m.update(1.0);
+        assert!(m.book(Good::Food).price < before);
+    }
+
+    #[test]
+    fn price_settles_back_near_the_base_price() {
+        let mut m = market(&[]);
+        let base = Good::Food.price().0 as f32;
+        m.books.get_mut(&Good::Food).unwrap().price = base * 5.0;
+        for _ in 0..SECONDS_PER_DAY {
+            m.update(1.0);
+        }
+        assert!((m.book(Good::Food).price - base).abs() < base * 0.1);
+    }
+
+    #[test]
+    fn priced_out_bids_are_not_demand() {
+        let mut m = market(&[(1, 10_000, Goods::default())]);
+        m.bid(bid(1, 3, 100));
+        let before = m.book(Good::Food).price;
+        m.update(1.0);
+        assert!(m.book(Good::Food).price <= before);
+        assert!(m.has_bid(SoulID(1), Good::Food));
+    }
+
+    #[test]
+    fn failed_buy_is_not_demand() {
+        let stock = Goods::new(&[(Good::Food, 1)]);
+        let mut m = market(&[(0, 0, stock), (1, 0, Goods::default())]);
+        m.sell(SoulID(0), Good::Food, BuildingID::default(), Vec2::ZERO);
+
+        // The buyer has no money
+        assert!(!m.buy(SoulID(1), SoulID(0), Good::Food, 1));
+        assert_eq!(m.book(Good::Food).bought, 0);
+
+        m.agents.get_mut(&SoulID(1)).unwrap().money = Money(10_000);
+        assert!(m.buy(SoulID(1), SoulID(0), Good::Food, 1));
+        assert_eq!(m.book(Good::Food).bought, 1);
+        assert_eq!(m.agents[&SoulID(1)].goods[Good::Food], 1);
     }
 }
@@ -1,30 +1,38 @@
 use crate::SoulID;
 use derive_more::{Add, AddAssign, Sub, SubAssign};
+use serde::{Deserialize, Serialize};
 
+mod freight;
+mod goods;
+mod labor;
 mod market;
+mod production;
 
+pub use freight::*;
+pub use goods::*;
+pub use labor::*;
 pub use market::*;
-
-#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Add, AddAssign, Sub, SubAssign)]
+pub use production::*;
+
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Add,
+    AddAssign,
+    Sub,
+    SubAssign,
+    Serialize,
+    Deserialize,
+)]
+/// Amount of money in cents
 pub struct Money(pub i32);
 
-#[derive(Copy, Clone, Default, Debug, Add, AddAssign, Sub, SubAssign)]
-pub struct Goods {
-    pub food: i32,
-}
-
-impl Goods {
-    pub fn is_smaller(&self, delta: &Goods) -> bool {
-        self.food <= delta.food
-    }
-}
-
-#[derive(Copy, Clone, Debug)]
-pub struct Transaction {
-    pub cost: Money,
-    pub delta: Goods,
-}
-
+#[derive(Clone, Serialize, Deserialize)]
 pub struct EconomicAgent {
     pub id: SoulID,
     pub money: Money,
@@ -0,0 +1,52 @@
+use crate::economy::{Good, Goods};
+use common::SECONDS_PER_HOUR;
+use map_model::BuildingKind;
+use serde::{Deserialize, Serialize};
+
+/// Producers stop working once they have this many batches of outputs in stock
+pub const MAX_STOCK_BATCHES: i32 = 10;
+
+/// Batches of outputs a producer starts with, so that the city doesn't starve
+/// while the supply chains fill up
+pub const STARTING_BATCHES: i32 = 2;
+
+/// Producers order more inputs once their stock and deliveries fall below this many batches
+pub const REORDER_BATCHES: i32 = 2;
+
+/// Batches of inputs a producer tops its stock up to when ordering, so that trucks carry
+/// several batches at once
+pub const ORDER_BATCHES: i32 = 4;
+
+/// Turns inputs into outputs at a producer building
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct Recipe {
+    pub inputs: Goods,
+    pub outputs: Goods,
+    /// Game seconds needed to complete one batch
+    pub duration: f32,
+}
+
+impl Recipe {
+    /// Recipe run by the buildings of the given kind, None if they don't produce anything
+    pub fn for_building(kind: BuildingKind) -> Option<Recipe> {
+        let hour = SECONDS_PER_HOUR as f32;
+        Some(match kind {
+            BuildingKind::Farm => Recipe {
+                inputs: Goods::default(),
+                outputs: Goods::new(&[(Good::Crops, 10)]),
+                duration: hour,
+            },
+            BuildingKind::Workplace => Recipe {
+                inputs: Goods::new(&[(Good::Crops, 4)]),
+                outputs: Goods::new(&[(Good::Food, 4)]),
+                duration: hour,
+            },
+            BuildingKind::Supermarket => Recipe {
+                inputs: Goods::new(&[(Good::Food, 2)]),
+                outputs: Goods::new(&[(Good::Groceries, 2)]),
+                duration: hour * 0.5,
+            },
+            BuildingKind::House => return None,
+        })
+    }
+}
@@ -1,6 +1,6 @@
 use geom::Vec2;
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 
 #[derive(Clone, Default, Debug, Serialize, Deserialize)]
 pub struct Movable;
@@ -24,14 +24,6 @@ impl Default for Selectable {
     }
 }
 
-pub struct TimeWarp(pub u32);
-
-impl Default for TimeWarp {
-    fn default() -> Self {
-        Self(1)
-    }
-}
-
 #[derive(Default)]
 pub struct History {
     pub values: [f32; 10],
@@ -46,6 +38,10 @@ impl History {
     pub fn avg(&self) -> f32 {
         (&self.values).iter().sum::<f32>() / (self.values.len() as f32)
     }
+
+    pub fn last(&self) -> f32 {
+        self.values[self.values.len() - 1]
+    }
 }
 
 #[derive(Default)]
@@ -55,6 +51,7 @@ pub struct RenderStats {
     pub render: History,
     pub souls_desires: History,
     pub souls_apply: History,
+    pub systems: BTreeMap<String, History>,
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, Hash)]
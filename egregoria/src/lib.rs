@@ -3,12 +3,13 @@
 #![allow(clippy::too_many_arguments)]
 
 use crate::api::Location;
-use crate::economy::Market;
-use crate::engine_interaction::{
-    KeyboardInfo, MouseInfo, Movable, RenderStats, Selectable, TimeWarp,
+use crate::economy::{
+    dispatch_trucks, market_update_system, unload_trucks, Cargo, FreightStats, Market,
 };
+use crate::engine_interaction::{KeyboardInfo, MouseInfo, Movable, RenderStats, Selectable};
 use crate::map_dynamic::{
-    add_trees_system, itinerary_update_system, BuildingInfos, Itinerary, ParkingManagement,
+    add_trees_system, itinerary_update_system, signal_control_system, BikeParking, BuildingInfos,
+    Itinerary, ParkingManagement,
 };
 use crate::pedestrians::{pedestrian_decision_system, Pedestrian};
 use crate::physics::systems::{
@@ -19,13 +20,20 @@ use crate::physics::{Collider, Kinematics};
 use crate::rendering::assets::AssetRender;
 use crate::rendering::immediate::{ImmediateDraw, ImmediateSound};
 use crate::rendering::meshrender_component::MeshRender;
+use crate::replay::ReplayRecorder;
 use crate::scenarios::scenario_runner::{run_scenario_system, RunningScenario};
+use crate::souls::Souls;
+use crate::transit::systems::{bus_update_system, dispatch_buses};
+use crate::transit::{Bus, TransitNetwork};
 use crate::vehicles::systems::{
     vehicle_cleanup_system, vehicle_decision_system, vehicle_state_update_system,
 };
-use crate::vehicles::Vehicle;
-use common::{GameTime, SECONDS_PER_DAY, SECONDS_PER_HOUR};
+use crate::vehicles::{turn_reservations_system, TurnReservations, Vehicle};
+use common::inspect::InspectedEntity;
+use common::saveload::{LoadError, SaveFile, SaveHeader};
+use common::{GameTime, SimulationClock, SECONDS_PER_DAY, SECONDS_PER_HOUR};
 use geom::{Transform, Vec2};
+use legion::serialize::{Canon, UnknownType};
 use legion::storage::Component;
 use legion::systems::Resource;
 use legion::{any, Entity, IntoQuery, Registry, Resources, World};
@@ -34,6 +42,7 @@ use serde::{Deserialize, Serialize};
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
 use std::ops::{Deref, DerefMut};
+use std::path::Path;
 use utils::frame_log::FrameLog;
 use utils::par_command_buffer::Deleted;
 pub use utils::par_command_buffer::ParCommandBuffer;
@@ -53,16 +62,28 @@ pub mod api;
 pub mod economy;
 pub mod engine_interaction;
 pub mod map_dynamic;
+pub mod migrations;
+pub mod mode_choice;
 pub mod pedestrians;
 pub mod physics;
 pub mod rendering;
+pub mod replay;
+pub mod saveslots;
 pub mod scenarios;
 pub mod souls;
+pub mod transit;
+pub mod undo;
 pub mod vehicles;
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Hash)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Hash)]
 pub struct SoulID(pub usize);
 
+/// Resource telling whether the simulation must be reproducible.
+/// When set, souls take their decisions sequentially in SoulID order instead of in parallel,
+/// as some decisions (like reserving a parking spot) mutate shared state.
+#[derive(Copy, Clone, Default)]
+pub struct Deterministic(pub bool);
+
 #[derive(Default)]
 pub struct Egregoria {
     pub world: World,
@@ -81,6 +102,7 @@ const RNG_SEED: u64 = 123;
 impl Egregoria {
     pub fn run(&mut self) {
         self.read::<FrameLog>().clear();
+        self.write::<ReplayRecorder>().advance();
         let t = std::time::Instant::now();
         self.schedule.execute(&mut self.world, &mut self.resources);
         ParCommandBuffer::apply(self);
@@ -89,22 +111,68 @@ impl Egregoria {
             .add_value(t.elapsed().as_secs_f32());
     }
 
+    /// Advances the game time by one tick of the `SimulationClock`, runs the simulation
+    /// then lets the souls take their decisions
+    pub fn tick(&mut self) {
+        if self.write::<ReplayRecorder>().take_restored() {
+            undo::reconcile_world(self);
+        }
+        {
+            let clock = self.read::<SimulationClock>();
+            let mut time = self.write::<GameTime>();
+            *time = clock.next_time(&time);
+        }
+        self.run();
+
+        unload_trucks(self);
+        dispatch_trucks(self);
+        dispatch_buses(self);
+
+        // Souls need the whole world to decide, so they are taken out while they do
+        let mut souls = std::mem::take(&mut *self.write::<Souls>());
+        souls.add_souls_to_empty_buildings(self);
+        souls.update(self);
+        *self.write::<Souls>() = souls;
+    }
+
+    /// Runs systems that live outside of the simulation, like the gui, once on the world
+    pub fn execute(&mut self, schedule: &mut SeqSchedule) {
+        self.read::<FrameLog>().clear();
+        schedule.execute(&mut self.world, &mut self.resources);
+        ParCommandBuffer::apply(self);
+    }
+
     pub fn init() -> Egregoria {
+        Self::init_with(RNG_SEED, false)
+    }
+
+    /// Two deterministic instances with the same seed fed with the same inputs
+    /// will have the same `state_hash` at every tick.
+    pub fn init_deterministic(seed: u64) -> Egregoria {
+        Self::init_with(seed, true)
+    }
+
+    fn init_with(seed: u64, deterministic: bool) -> Egregoria {
         let mut goria = Egregoria::default();
 
-        info!("Seed is {}", RNG_SEED);
+        info!("Seed is {}", seed);
 
         // Basic assets init
         goria.insert(GameTime::new(
             0.0,
             SECONDS_PER_DAY as f64 + 10.0 * SECONDS_PER_HOUR as f64,
         ));
+        goria.insert(Map::empty());
         goria.insert(CollisionWorld::new(100));
         goria.insert(KeyboardInfo::default());
         goria.insert(MouseInfo::default());
         goria.insert(RenderStats::default());
-        goria.insert(RandProvider::new(RNG_SEED));
+        goria.insert(RandProvider::new(seed));
+        goria.insert(Deterministic(deterministic));
+        goria.insert(ReplayRecorder::new(seed));
         goria.insert(ParkingManagement::default());
+        goria.insert(BikeParking::default());
+        goria.insert(TurnReservations::default());
         goria.insert(BuildingInfos::default());
         goria.insert(FrameLog::default());
         goria.insert(RunningScenario::default());
@@ -114,18 +182,26 @@ impl Egregoria {
         goria.insert(Deleted::<Collider>::default());
         goria.insert(Deleted::<Vehicle>::default());
         goria.insert(Market::default());
-        goria.insert(TimeWarp::default());
+        goria.insert(FreightStats::default());
+        goria.insert(TransitNetwork::default());
+        goria.insert(Souls::default());
+        goria.insert(InspectedEntity::default());
+        goria.insert(SimulationClock::default());
 
         // Dispatcher init
         goria
             .schedule
+            .add_system(signal_control_system())
             .add_system(vehicle_state_update_system())
+            .add_system(turn_reservations_system())
             .add_system(vehicle_decision_system())
             .add_system(itinerary_update_system())
+            .add_system(bus_update_system())
             .add_system(add_trees_system())
             .add_system(vehicle_cleanup_system())
             .add_system(pedestrian_decision_system())
             .add_system(run_scenario_system())
+            .add_system(market_update_system())
             .add_system(kinematics_apply_system())
             .add_system(coworld_synchronize_system())
             .add_system(coworld_maintain_system());
@@ -174,6 +250,108 @@ impl Egregoria {
     pub fn insert<T: Resource + Send + Sync>(&mut self, res: T) {
         self.resources.insert(res)
     }
+
+    /// Digest of the simulation state: components, simulated resources and the map.
+    /// Only stable within the same build, it is meant to compare runs, not to be stored.
+    pub fn state_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+
+        let entities: Vec<Entity> = <Entity>::query().iter(&self.world).copied().collect();
+
+        let mut registry = Registry::<u64>::new(entity_canon(&entities));
+        registry.on_unknown(UnknownType::Ignore);
+        registry.register::<Transform>(0);
+        registry.register::<Kinematics>(1);
+        registry.register::<Vehicle>(2);
+        registry.register::<Pedestrian>(3);
+        registry.register::<Itinerary>(4);
+        registry.register::<Bus>(5);
+        registry.register::<Location>(6);
+        hash_serialized(&mut hasher, &self.world.as_serializable(any(), &registry));
+
+        hash_serialized(&mut hasher, &*self.read::<GameTime>());
+        hash_with_entities(
+            &mut hasher,
+            &entities,
+            self.read::<CollisionWorld>().clone(),
+        );
+        hash_with_entities(&mut hasher, &entities, self.read::<BuildingInfos>().clone());
+        hash_with_entities(
+            &mut hasher,
+            &entities,
+            self.read::<TransitNetwork>().clone(),
+        );
+        hash_serialized(
+            &mut hasher,
+            &self.read::<ParkingManagement>().reserved_sorted(),
+        );
+        hash_serialized(&mut hasher, &self.read::<BikeParking>().used_sorted());
+        self.read::<RandProvider>().fingerprint().hash(&mut hasher);
+        self.read::<Souls>().hash_state(&mut hasher, &entities);
+
+        let market = self.read::<Market>();
+        let mut agents: Vec<_> = market.agents.values().collect();
+        agents.sort_by_key(|x| x.id);
+        for agent in agents {
+            (agent.id, agent.money.0, agent.goods).hash(&mut hasher);
+        }
+        for book in market.books.values() {
+            book.price.to_bits().hash(&mut hasher);
+        }
+        drop(market);
+
+        if let Some(map) = self.resources.get::<Map>() {
+            hash_serialized(&mut hasher, &SerializedMap::from(&*map));
+        }
+
+        hasher.finish()
+    }
+}
+
+struct HashWriter<'a>(&'a mut DefaultHasher);
+
+impl<'a> std::io::Write for HashWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.write(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn hash_serialized<T: Serialize>(hasher: &mut DefaultHasher, v: &T) {
+    if let Err(e) = bincode::serialize_into(HashWriter(hasher), v) {
+        log::error!("couldn't hash {}: {}", std::any::type_name::<T>(), e);
+    }
+}
+
+/// Entity ids depend on everything the process allocated before, so they are named after
+/// their position in `entities` instead when hashing.
+fn entity_canon(entities: &[Entity]) -> Canon {
+    let mut canon = Canon::default();
+    for (i, e) in entities.iter().enumerate() {
+        let _ = canon.canonize(*e, (i as u128).to_le_bytes());
+    }
+    canon
+}
+
+/// Hashes a value referring to entities of the world, they can only be serialized with a world.
+/// `entities` are the entities of the world in iteration order.
+fn hash_with_entities<T>(hasher: &mut DefaultHasher, entities: &[Entity], v: T)
+where
+    T: Component + Serialize + for<'de> Deserialize<'de>,
+{
+    let mut world = World::default();
+    let holder = world.push((v,));
+
+    let mut canon = entity_canon(entities);
+    let _ = canon.canonize(holder, u128::MAX.to_le_bytes());
+    let mut registry = Registry::<u64>::new(canon);
+    registry.register::<T>(0);
+
+    hash_serialized(hasher, &world.as_serializable(any(), &registry));
 }
 
 fn my_hash<T>(obj: T) -> u64
@@ -185,10 +363,31 @@ where
     hasher.finish()
 }
 
+/// Bump this when a change breaks the save format of a released version, including adding,
+/// removing or reordering a field of a saved type: bincode is positional and `#[serde(default)]`
+/// doesn't help it. Changes made between two releases share a single bump.
+/// Add the corresponding migration step, or an incompatible step if the payload can't be converted.
+pub const SAVE_FORMAT_VERSION: u32 = 1;
+
+fn register_comp<T>(
+    registry: &mut Registry<u64>,
+    entries: &mut Vec<(String, u64)>,
+    saved: Option<&SaveHeader>,
+    name: &'static str,
+) where
+    T: Component + Serialize + for<'de> Deserialize<'de>,
+{
+    let id = saved
+        .and_then(|h| h.component_id(name))
+        .unwrap_or_else(|| my_hash(name));
+    registry.register::<T>(id);
+    entries.push((name.to_string(), id));
+}
+
 macro_rules! register {
-    ($r: expr; $($t: ty),+,) => {
+    ($r: expr; $entries: expr; $saved: expr; $($t: ty),+,) => {
         $(
-            $r.register::<$t>(my_hash(stringify!($t)))
+            register_comp::<$t>(&mut $r, &mut $entries, $saved, stringify!($t))
         );+
     };
 }
@@ -197,9 +396,11 @@ pub struct NoSerialize;
 
 macro_rules! mk_save {
     ($($res: ty,)*) => {
-        fn registry() -> Registry<u64> {
+        /// Components are registered with the ids found in the header of the save if any
+        fn registry(saved: Option<&SaveHeader>) -> (Registry<u64>, Vec<(String, u64)>) {
             let mut registry = Registry::default();
-            register!(registry; Transform,
+            let mut entries = vec![];
+            register!(registry; entries; saved; Transform,
               AssetRender,
               Kinematics,
               Selectable,
@@ -210,32 +411,44 @@ macro_rules! mk_save {
               Collider,
               MeshRender,
               Location,
+              Cargo,
+              Bus,
               $($res,)*
             );
-            registry
+            (registry, entries)
         }
 
-        pub fn load_from_disk(goria: &mut Egregoria) {
-            let registry = registry();
+        /// Loads the world and the map saved in `dir`
+        pub fn load_from_disk(goria: &mut Egregoria, dir: &Path) -> Result<(), LoadError> {
+            let migrations = migrations::migrations();
+
+            let world_file = SaveFile::load(dir.join("world.bc"), &migrations)?;
+            let map_file = SaveFile::load(dir.join("map.bc"), &migrations)?;
 
-            let _ = common::saveload::load_seed("world", registry.as_deserialize()).map(|mut w: World| {
-                log::info!("successfully loaded world with {} entities", w.len());
-                goria.world.move_from(&mut w, &any());
-            });
+            let (registry, _) = registry(Some(&world_file.header));
+            let mut w: World = world_file.deserialize_seed(registry.as_deserialize())?;
+            let map: SerializedMap = map_file.deserialize()?;
+
+            log::info!("successfully loaded world with {} entities", w.len());
+            goria.world.move_from(&mut w, &any());
 
             $(
             extract_resource::<$res>(goria);
             )*
 
-            goria.insert::<Map>(
-                common::saveload::load::<map_model::SerializedMap>("map")
-                    .map(|x| x.into())
-                    .unwrap_or_default(),
-            );
+            goria.insert::<Map>(map.into());
+            Ok(())
         }
 
-        pub fn save_to_disk(goria: &mut Egregoria) {
-            let registry = registry();
+        /// Saves the world and the map to `dir`, creating it if needed
+        pub fn save_to_disk(goria: &mut Egregoria, dir: &Path) {
+            let (registry, entries) = registry(None);
+
+            let header = SaveHeader {
+                format_version: SAVE_FORMAT_VERSION,
+                game_version: env!("CARGO_PKG_VERSION").to_string(),
+                registry: entries,
+            };
 
             let to_remove = vec![
                 $(
@@ -247,8 +460,12 @@ macro_rules! mk_save {
                 .world
                 .as_serializable(!legion::query::component::<NoSerialize>(), &registry);
 
-            common::saveload::save(&s, "world");
-            common::saveload::save(&SerializedMap::from(&*goria.read::<Map>()), "map");
+            if let Some(file) = SaveFile::new(header.clone(), &s) {
+                file.save(dir.join("world.bc"));
+            }
+            if let Some(file) = SaveFile::new(header, &SerializedMap::from(&*goria.read::<Map>())) {
+                file.save(dir.join("map.bc"));
+            }
 
             for ent in to_remove {
                 goria.world.remove(ent);
@@ -257,7 +474,17 @@ macro_rules! mk_save {
     }
 }
 
-mk_save!(CollisionWorld, ParkingManagement, BuildingInfos,);
+mk_save!(
+    GameTime,
+    CollisionWorld,
+    ParkingManagement,
+    BikeParking,
+    BuildingInfos,
+    Market,
+    Souls,
+    FreightStats,
+    TransitNetwork,
+);
 
 fn extract_resource<T: Resource + Clone + Sync + Send>(goria: &mut Egregoria) {
     let (ent, res): (&Entity, &T) = match <(Entity, &T)>::query().iter(&goria.world).next() {
@@ -279,3 +506,124 @@ fn insert_resource<T: Resource + Clone + Sync + Send>(goria: &mut Egregoria) ->
     let res: T = goria.read::<T>().clone();
     goria.world.push((res,))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::economy::Money;
+    use crate::pedestrians::PedestrianID;
+    use map_model::BuildingKind;
+    use std::collections::BTreeMap;
+
+    /// A grid with houses and shops, the souls move in on the first tick
+    fn populated(seed: u64) -> Egregoria {
+        let mut goria = Egregoria::init_deterministic(seed);
+        map_model::procgen::add_grid(Vec2::ZERO, &mut *goria.write::<Map>(), 3);
+        goria.write::<Map>().build_buildings().for_each(drop);
+        goria
+    }
+
+    fn run_seeded(seed: u64, ticks: usize) -> u64 {
+        let mut goria = populated(seed);
+        for _ in 0..ticks {
+            goria.tick();
+        }
+        assert!(goria.read::<Souls>().routers().next().is_some());
+        goria.state_hash()
+    }
+
+    #[test]
+    fn same_seed_same_state() {
+        assert_eq!(run_seeded(7, 3000), run_seeded(7, 3000));
+    }
+
+    #[test]
+    fn save_load_resumes_souls() {
+        let mut goria = populated(3);
+        for _ in 0..3000 {
+            goria.tick();
+        }
+
+        let dir = std::env::temp_dir().join(format!("egregoria_resume_{}", std::process::id()));
+        save_to_disk(&mut goria, &dir);
+        let mut loaded = Egregoria::init_deterministic(3);
+        let loaded_ok = load_from_disk(&mut loaded, &dir);
+        let _ = std::fs::remove_dir_all(&dir);
+        loaded_ok.expect("couldn't load the saved world");
+
+        // The generator isn't saved, both worlds go on from the same seed
+        *goria.write::<RandProvider>() = RandProvider::new(5);
+        *loaded.write::<RandProvider>() = RandProvider::new(5);
+        assert_eq!(goria.state_hash(), loaded.state_hash());
+
+        let bodies: Vec<PedestrianID> = loaded.read::<Souls>().routers().map(|x| x.body).collect();
+        assert!(!bodies.is_empty());
+        for body in bodies {
+            assert!(loaded.comp::<Pedestrian>(body.0).is_some());
+        }
+
+        for _ in 0..500 {
+            goria.tick();
+            loaded.tick();
+        }
+        assert_eq!(goria.state_hash(), loaded.state_hash());
+    }
+
+    /// Employers pay their workers every day out of what they sell
+    #[test]
+    fn employers_stay_solvent() {
+        let mut goria = Egregoria::init_deterministic(11);
+        goria.insert(SimulationClock::new(0.25));
+        {
+            let mut map = goria.write::<Map>();
+            map_model::procgen::add_grid(Vec2::ZERO, &mut map, 3);
+            let road = map
+                .roads()
+                .iter()
+                .find(|(_, r)| r.src_point.y.abs() < 1.0 && r.dst_point.y.abs() < 1.0)
+                .map(|(id, _)| id)
+                .expect("the grid has a road along y = 0");
+            let obb = geom::OBB::new(Vec2::new(50.0, -60.0), Vec2::new(1.0, 0.0), 80.0, 80.0);
+            map.build_special_building(road, obb, BuildingKind::Farm);
+            map.build_buildings().for_each(drop);
+        }
+
+        let per_day = goria
+            .read::<SimulationClock>()
+            .ticks_in(SECONDS_PER_DAY as f64);
+        let days = |goria: &mut Egregoria, n: u64| {
+            for _ in 0..n * per_day {
+                goria.tick();
+            }
+            let market = goria.read::<Market>();
+            let money: BTreeMap<SoulID, Money> = market
+                .labor
+                .employers
+                .keys()
+                .map(|id| (*id, market.agents[id].money))
+                .collect();
+            (money, market.labor.clone())
+        };
+
+        // The first day everyone moves in and finds a job
+        let (before, labor) = days(&mut goria, 1);
+        assert!(before.len() >= 3);
+        assert!(labor.n_employed() > 0);
+
+        let (after, labor_after) = days(&mut goria, 3);
+        // Nobody was fired for want of a wage
+        assert_eq!(labor_after.n_employed(), labor.n_employed());
+
+        // Sales cover at least half of the wages, the starting capital isn't what keeps
+        // the employers afloat. A producer can still lose money on its own when its
+        // suppliers are short.
+        let wages: i32 = labor
+            .employers
+            .values()
+            .map(|e| e.wage.0 * e.employees.len() as i32 * 3)
+            .sum();
+        let lost: i32 = before.iter().map(|(id, m)| m.0 - after[id].0).sum();
+        assert!(lost <= wages / 2, "lost {} while paying {}", lost, wages);
+        assert!(after.values().all(|m| m.0 > 0));
+    }
+}
@@ -0,0 +1,79 @@
+use dashmap::DashMap;
+use geom::Vec2;
+use map_model::{BuildingID, Map, ProjectKind};
+use ordered_float::OrderedFloat;
+use serde::{Deserialize, Serialize};
+
+/// Bikes that fit in the rack in front of every building
+pub const BIKE_RACK_CAPACITY: u32 = 8;
+
+/// Furthest from the destination a cyclist is willing to lock their bike, in meters
+pub const MAX_RACK_DIST: f32 = 150.0;
+
+/// Bike racks at the door of the buildings, counting the bikes locked or about to be.
+/// Souls reserve racks while deciding in parallel, hence the DashMap
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct BikeParking {
+    used: DashMap<BuildingID, u32>,
+}
+
+impl BikeParking {
+    pub fn n_reserved(&self) -> u32 {
+        self.used.iter().map(|x| *x.value()).sum()
+    }
+
+    /// Used racks in a stable order, as the DashMap iteration order isn't
+    pub fn used_sorted(&self) -> Vec<(BuildingID, u32)> {
+        let mut v: Vec<(BuildingID, u32)> =
+            self.used.iter().map(|x| (*x.key(), *x.value())).collect();
+        v.sort_unstable();
+        v
+    }
+
+    /// Forgets the racks of the buildings that were removed
+    pub fn retain_existing(&mut self, map: &Map) {
+        let buildings = map.buildings();
+        self.used.retain(|&id, _| buildings.contains_key(id));
+    }
+
+    pub fn free(&self, building: BuildingID) {
+        let emptied = match self.used.get_mut(&building) {
+            Some(mut n) => {
+                *n = n.saturating_sub(1);
+                *n == 0
+            }
+            None => {
+                log::warn!("no bike was racked at {:?}", building);
+                false
+            }
+        };
+        if emptied {
+            self.used.remove_if(&building, |_, &n| n == 0);
+        }
+    }
+
+    /// Reserves a place in the rack of the building closest to `near` that still has room
+    pub fn reserve_near(&self, near: Vec2, map: &Map) -> Option<BuildingID> {
+        let buildings = map.buildings();
+        let mut candidates: Vec<BuildingID> = map
+            .spatial_map()
+            .query_around(near, MAX_RACK_DIST)
+            .filter_map(|k| match k {
+                ProjectKind::Building(id) => Some(id),
+                _ => None,
+            })
+            .filter(|&id| buildings.contains_key(id))
+            .collect();
+        candidates.sort_by_key(|&id| (OrderedFloat(buildings[id].door_pos.distance2(near)), id));
+
+        candidates.into_iter().find(|&id| {
+            let mut n = self.used.entry(id).or_insert(0);
+            if *n < BIKE_RACK_CAPACITY {
+                *n += 1;
+                true
+            } else {
+                false
+            }
+        })
+    }
+}
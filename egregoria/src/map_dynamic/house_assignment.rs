@@ -1,6 +1,6 @@
 use crate::pedestrians::data::PedestrianID;
 use crate::SoulID;
-use map_model::BuildingID;
+use map_model::{BuildingID, Map};
 use serde::{Deserialize, Serialize};
 use slotmap::SecondaryMap;
 use std::ops::{Index, IndexMut};
@@ -29,21 +29,35 @@ impl BuildingInfos {
         self.assignment.get_mut(building)
     }
 
-    pub fn set_owner(&mut self, building: BuildingID, soul: SoulID) {
-        if let Some(x) = self.get_mut(building) {
-            x.owner = Some(soul)
+    /// Infos of buildings are created on first use, so buildings added to the map
+    /// don't need to be registered.
+    fn get_or_insert(&mut self, building: BuildingID) -> &mut BuildingInfo {
+        if !self.assignment.contains_key(building) {
+            self.insert(building);
         }
+        &mut self.assignment[building]
+    }
+
+    /// Forgets the infos of the buildings that were removed
+    pub fn retain_existing(&mut self, map: &Map) {
+        let buildings = map.buildings();
+        self.assignment.retain(|id, _| buildings.contains_key(id));
+    }
+
+    pub fn set_owner(&mut self, building: BuildingID, soul: SoulID) {
+        self.get_or_insert(building).owner = Some(soul);
     }
 
     pub fn get_in(&mut self, building: BuildingID, e: PedestrianID) {
-        if cfg!(debug_assertions) && self[building].inside.contains(&e) {
+        let inside = &mut self.get_or_insert(building).inside;
+        if cfg!(debug_assertions) && inside.contains(&e) {
             log::warn!(
                 "called get_in({:?}, {:?}) but it was already inside",
                 building,
                 e
             );
         }
-        self[building].inside.push(e);
+        inside.push(e);
     }
 
     pub fn get_out(&mut self, building: BuildingID, e: PedestrianID) {
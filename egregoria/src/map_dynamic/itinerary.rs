@@ -1,11 +1,13 @@
 use common::GameTime;
 use geom::Transform;
-use geom::Vec2;
+use geom::{Spline, Vec2};
 use imgui::Ui;
 use imgui_inspect::{InspectArgsDefault, InspectRenderDefault};
 use imgui_inspect_derive::*;
 use legion::system;
-use map_model::{Map, Pathfinder, Traversable, TraverseDirection, TraverseKind};
+use map_model::{
+    Lane, LaneID, Map, Pathfinder, Traversable, TraverseDirection, TraverseKind, TurnID,
+};
 use serde::{Deserialize, Serialize};
 
 #[derive(Default, Debug, Serialize, Deserialize, Inspect)]
@@ -32,6 +34,12 @@ pub struct Route {
 
 pub const OBJECTIVE_OK_DIST: f32 = 4.5;
 
+/// Seconds spent moving sideways when changing lanes
+pub const LANE_CHANGE_TIME: f32 = 3.0;
+
+/// Shortest distance along the lane a lane change is done over, in meters
+pub const MIN_LANE_CHANGE_LENGTH: f32 = 8.0;
+
 impl Itinerary {
     pub fn none() -> Self {
         Self {
@@ -110,6 +118,15 @@ impl Itinerary {
 
         if self.local_path.is_empty() {
             if let ItineraryKind::Route(r) = &mut self.kind {
+                // The planned lane change couldn't be done along the lane: the vehicle waits at its
+                // end until the lane change decision finds a gap in the target lane
+                if let (TraverseKind::Lane(_), Some(TraverseKind::Lane(_)), Some(last)) =
+                    (r.cur.kind, r.reversed_route.last().map(|t| t.kind), v)
+                {
+                    self.local_path.push(last);
+                    return None;
+                }
+
                 r.cur = r.reversed_route.pop()?;
 
                 let points = r.cur.points(map)?;
@@ -143,7 +160,10 @@ impl Itinerary {
                     return;
                 });
 
-                if k.can_pass(time, map.lanes()) {
+                let turn_allowed = self
+                    .next_turn()
+                    .map_or(true, |turn| map.turn_allowed(turn, time));
+                if k.can_pass(time, map.lanes()) && turn_allowed {
                     self.advance(map);
                 }
             }
@@ -159,6 +179,81 @@ impl Itinerary {
         }
     }
 
+    /// Adjacent lane the route changes to before the end of the current lane, if any
+    pub fn lane_change_target(&self) -> Option<LaneID> {
+        match &self.kind {
+            ItineraryKind::Route(Route {
+                cur:
+                    Traversable {
+                        kind: TraverseKind::Lane(_),
+                        ..
+                    },
+                reversed_route,
+                ..
+            }) => match reversed_route.last()?.kind {
+                TraverseKind::Lane(to) => Some(to),
+                TraverseKind::Turn(_) => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Turn taken right after the current traversable, if any
+    pub fn next_turn(&self) -> Option<TurnID> {
+        match &self.kind {
+            ItineraryKind::Route(r) => match r.reversed_route.last()?.kind {
+                TraverseKind::Turn(turn) => Some(turn),
+                TraverseKind::Lane(_) => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Moves the vehicle at `pos` heading `dir` onto the adjacent lane `to` along a smooth lateral
+    /// trajectory. Either the route planned the change, or the turn at the end of the current
+    /// lane must also exist from `to`, the route then continues with that turn.
+    /// Returns whether the lane was changed.
+    pub fn change_lane(&mut self, map: &Map, to: LaneID, pos: Vec2, dir: Vec2, speed: f32) -> bool {
+        let r = match &mut self.kind {
+            ItineraryKind::Route(r) if r.cur.kind.is_lane() => r,
+            _ => return false,
+        };
+        let lane = unwrap_or!(map.lanes().get(to), return false);
+
+        match r.reversed_route.last_mut().map(|t| &mut t.kind) {
+            Some(TraverseKind::Lane(id)) => {
+                if *id != to {
+                    return false;
+                }
+                r.cur = r.reversed_route.pop().unwrap(); // Unwrap ok: just matched on last
+            }
+            Some(TraverseKind::Turn(turn)) => {
+                let new_turn = TurnID::new(turn.parent, to, turn.dst, turn.bidirectional);
+                let exists = map
+                    .intersections()
+                    .get(turn.parent)
+                    .and_then(|inter| inter.find_turn(new_turn))
+                    .is_some();
+                if !exists {
+                    return false;
+                }
+                *turn = new_turn;
+                r.cur = Traversable::new(TraverseKind::Lane(to), TraverseDirection::Forward);
+            }
+            None => {
+                r.cur = Traversable::new(TraverseKind::Lane(to), TraverseDirection::Forward);
+            }
+        }
+
+        let end = if r.reversed_route.is_empty() {
+            Some(r.end_pos)
+        } else {
+            None
+        };
+        self.local_path = lane_change_path(lane, pos, dir, speed, end);
+        true
+    }
+
     pub fn remaining_points(&self) -> usize {
         self.local_path.len()
     }
@@ -205,6 +300,81 @@ impl Itinerary {
         self.local_path.splice(0..0, points.into_iter());
     }
 
+    /// Remaining length in meters, from the current point to the end
+    pub fn length(&self, map: &Map) -> f32 {
+        let local: f32 = self
+            .local_path
+            .windows(2)
+            .map(|w| w[0].distance(w[1]))
+            .sum();
+        match &self.kind {
+            ItineraryKind::Route(r) => {
+                // The lane changed to is only driven along once with the lane it was changed from
+                let mut prev_lane = r.cur.kind.is_lane();
+                let mut total = local;
+                for t in r.reversed_route.iter().rev() {
+                    let is_lane = t.kind.is_lane();
+                    if !(is_lane && prev_lane) {
+                        total += t.points(map).map_or(0.0, |p| p.length());
+                    }
+                    prev_lane = is_lane;
+                }
+                total
+            }
+            _ => local,
+        }
+    }
+
+    /// Points a vehicle at `pos` goes through over the next `dist` meters of the route, starting
+    /// with `pos`. The bool tells whether the end of the itinerary is that close.
+    pub fn path_ahead(&self, map: &Map, pos: Vec2, dist: f32) -> (Vec<Vec2>, bool) {
+        let mut path = vec![pos];
+        let mut length = 0.0;
+        if extend_path(
+            &mut path,
+            &mut length,
+            self.local_path.iter().copied(),
+            dist,
+        ) {
+            return (path, false);
+        }
+
+        let r = match &self.kind {
+            ItineraryKind::Route(r) => r,
+            _ => return (path, true),
+        };
+
+        let mut prev_lane = r.cur.kind.is_lane();
+        for (i, t) in r.reversed_route.iter().enumerate().rev() {
+            let is_lane = t.kind.is_lane();
+            // The lane changed to isn't driven from its start
+            if is_lane && prev_lane {
+                return (path, false);
+            }
+            prev_lane = is_lane;
+
+            let points = unwrap_or!(t.points(map), return (path, false));
+            let far = if i == 0 {
+                let (proj, id) = points.project_segment(r.end_pos);
+                let to_end = points.as_slice()[..id].iter().copied();
+                extend_path(
+                    &mut path,
+                    &mut length,
+                    to_end
+                        .chain(std::iter::once(proj))
+                        .chain(std::iter::once(r.end_pos)),
+                    dist,
+                )
+            } else {
+                extend_path(&mut path, &mut length, points.iter().copied(), dist)
+            };
+            if far {
+                return (path, false);
+            }
+        }
+        (path, true)
+    }
+
     pub fn has_ended(&self, time: f64) -> bool {
         match self.kind {
             ItineraryKind::WaitUntil(x) => time > x,
@@ -215,6 +385,73 @@ impl Itinerary {
     pub fn is_none(&self) -> bool {
         matches!(self.kind, ItineraryKind::None)
     }
+
+    /// Whether the lanes and turns left on the route still exist
+    pub fn is_valid(&self, map: &Map) -> bool {
+        match &self.kind {
+            ItineraryKind::Route(r) => r
+                .reversed_route
+                .iter()
+                .chain(std::iter::once(&r.cur))
+                .all(|t| t.raw_points(map).is_some()),
+            _ => true,
+        }
+    }
+}
+
+/// Pushes the points to `path` until it is longer than `max`, returns whether it is
+fn extend_path(
+    path: &mut Vec<Vec2>,
+    length: &mut f32,
+    points: impl Iterator<Item = Vec2>,
+    max: f32,
+) -> bool {
+    for p in points {
+        let last = *path.last().unwrap(); // Unwrap ok: paths start with the position
+        let d = last.distance(p);
+        if d < 0.01 {
+            continue;
+        }
+        *length += d;
+        path.push(p);
+        if *length > max {
+            return true;
+        }
+    }
+    false
+}
+
+/// Points going from `pos` heading `dir` to the lane, `speed * LANE_CHANGE_TIME` meters
+/// further along it, then following the lane up to its end or to `end`
+fn lane_change_path(lane: &Lane, pos: Vec2, dir: Vec2, speed: f32, end: Option<Vec2>) -> Vec<Vec2> {
+    let points = &lane.points;
+    let (proj, _) = points.project_segment(pos);
+    let along = points.distance_along(proj);
+    let change_length = (speed * LANE_CHANGE_TIME).max(MIN_LANE_CHANGE_LENGTH);
+    let (target, target_dir) = points.point_dir_along((along + change_length).min(lane.length));
+
+    let strength = pos.distance(target) * 0.5;
+    let s = Spline {
+        from: pos,
+        to: target,
+        from_derivative: dir * strength,
+        to_derivative: target_dir * strength,
+    };
+
+    let mut path: Vec<Vec2> = s.points(5).skip(1).collect();
+    let (_, target_seg) = points.project_segment(target);
+    match end {
+        Some(end) => {
+            let (end_proj, end_seg) = points.project_segment(end);
+            if target_seg < end_seg {
+                path.extend_from_slice(&points.as_slice()[target_seg..end_seg]);
+            }
+            path.push(end_proj);
+            path.push(end);
+        }
+        None => path.extend_from_slice(&points.as_slice()[target_seg..]),
+    }
+    path
 }
 
 impl Default for ItineraryKind {
@@ -1,9 +1,13 @@
 mod add_trees;
+mod bike_parking;
 mod house_assignment;
 mod itinerary;
 mod parking;
+mod signals;
 
 pub use add_trees::*;
+pub use bike_parking::*;
 pub use house_assignment::*;
 pub use itinerary::*;
 pub use parking::*;
+pub use signals::*;
@@ -9,6 +9,17 @@ pub struct ParkingManagement {
 }
 
 impl ParkingManagement {
+    pub fn n_reserved(&self) -> usize {
+        self.reserved_spots.len()
+    }
+
+    /// Reserved spots in a stable order, as the DashMap iteration order isn't
+    pub fn reserved_sorted(&self) -> Vec<ParkingSpotID> {
+        let mut v: Vec<ParkingSpotID> = self.reserved_spots.iter().map(|x| *x.key()).collect();
+        v.sort_unstable();
+        v
+    }
+
     pub fn free(&self, spot: ParkingSpotID) {
         if self.reserved_spots.remove(&spot).is_none() {
             log::warn!("{:?} wasn't reserved", spot);
@@ -0,0 +1,43 @@
+use crate::physics::{CollisionWorld, PhysicsGroup};
+use common::GameTime;
+use legion::system;
+use map_model::{DetectorReading, Lane, Map};
+
+/// Length of the end of the incoming lanes covered by the detectors, in meters
+pub const DETECTOR_LENGTH: f32 = 40.0;
+
+/// Vehicles slower than this in the detection zone are queued, in m/s
+const QUEUE_SPEED: f32 = 2.0;
+
+/// Counts the vehicles queued or arriving at the end of the lane
+pub fn read_detector(lane: &Lane, cow: &CollisionWorld) -> DetectorReading {
+    let stop_line = lane.control_point();
+    let mut reading = DetectorReading::default();
+    for (h, pos) in cow.query_around(stop_line, DETECTOR_LENGTH) {
+        let obj = unwrap_or!(cow.get(h), continue).1;
+        if !matches!(obj.group, PhysicsGroup::Vehicles)
+            || lane.dist2_to(pos) > (lane.width * 0.5).powi(2)
+            // Already through the stop line
+            || (stop_line - pos).dot(obj.dir) < 0.0
+        {
+            continue;
+        }
+        if obj.speed < QUEUE_SPEED {
+            reading.queue += 1;
+        } else {
+            reading.arrivals += 1;
+        }
+    }
+    reading
+}
+
+#[system]
+pub fn signal_control(
+    #[resource] map: &mut Map,
+    #[resource] cow: &CollisionWorld,
+    #[resource] time: &GameTime,
+) {
+    map.update_signals(time.delta, time.seconds as f32, |lane| {
+        read_detector(lane, cow)
+    });
+}
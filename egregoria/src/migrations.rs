@@ -0,0 +1,24 @@
+use crate::SAVE_FORMAT_VERSION;
+use common::saveload::Migrations;
+
+/// Steps upgrading old saves to `SAVE_FORMAT_VERSION`.
+/// A step receives the file at version `from` and must leave it in the format of `from + 1`,
+/// for example renamed components can be fixed with `SaveHeader::rename_component`.
+pub fn migrations() -> Migrations {
+    let mut m = Migrations::new(SAVE_FORMAT_VERSION);
+    // Saves from before versioning are raw blobs without a header. Version 1 is the first
+    // versioned format and it changed most saved types at once:
+    // - the souls, the market and the game time are saved along the world
+    // - the map stores its geographic origin
+    // - goods, recipes and producer souls replaced the food-only economy, the market keeps
+    //   order books, tracks shipments and has a labor market
+    // - humans have a job and a household budget
+    // - routers can ride buses, bikes and remember the cost of the travel modes
+    // - vehicles can be trucks or bicycles, plan lane changes and carry car-following parameters
+    // - intersections have actuated signals, signal plans and turn policies
+    m.incompatible(
+        0,
+        "the economy, travel modes, driving and signals were reworked",
+    );
+    m
+}
@@ -0,0 +1,390 @@
+use crate::map_dynamic::Itinerary;
+use crate::transit::{Timetable, TransitNetwork, TransitTrip};
+use crate::vehicles::{VehicleKind, TIME_TO_PARK};
+use common::SECONDS_PER_DAY;
+use geom::Vec2;
+use imgui_inspect::InspectDragf;
+use imgui_inspect_derive::*;
+use map_model::{BikePath, CarPath, Map, Pathfinder, PedestrianPath};
+use ordered_float::OrderedFloat;
+use serde::{Deserialize, Serialize};
+
+/// Paths are this much longer than the straight line on average, used for the short legs
+/// that aren't pathfinded
+pub const DETOUR_FACTOR: f32 = 1.3;
+
+/// Waiting feels longer than travelling, it is weighted this much in the total cost
+pub const WAIT_WEIGHT: f32 = 2.0;
+
+/// Cents a game second of travel is worth to a human, turns money into time
+pub const VALUE_OF_TIME: f32 = 5.0;
+
+/// Fuel and wear of driving a meter, in cents
+pub const CAR_COST_PER_METER: f32 = 0.05;
+
+/// Game seconds spent looking for the spot and parking on top of the parking animation
+pub const PARKING_SEARCH_TIME: f32 = 10.0;
+
+/// Game seconds spent locking or unlocking a bike at a rack
+pub const BIKE_LOCK_TIME: f32 = 3.0;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum TravelMode {
+    Walk,
+    Car,
+    Transit,
+    Bike,
+}
+
+debug_inspect_impl!(TravelMode);
+
+/// Generalized cost of a trip with a mode, broken down in game seconds
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, Inspect)]
+pub struct ModeCost {
+    pub mode: TravelMode,
+    #[inspect(proxy_type = "InspectDragf")]
+    pub walk: f32,
+    #[inspect(proxy_type = "InspectDragf")]
+    pub wait: f32,
+    #[inspect(proxy_type = "InspectDragf")]
+    pub in_vehicle: f32,
+    #[inspect(proxy_type = "InspectDragf")]
+    pub parking: f32,
+    /// Money spent, as time through `VALUE_OF_TIME`
+    #[inspect(proxy_type = "InspectDragf")]
+    pub money: f32,
+}
+
+impl ModeCost {
+    fn new(mode: TravelMode) -> Self {
+        Self {
+            mode,
+            walk: 0.0,
+            wait: 0.0,
+            in_vehicle: 0.0,
+            parking: 0.0,
+            money: 0.0,
+        }
+    }
+
+    pub fn total(&self) -> f32 {
+        self.walk + self.wait * WAIT_WEIGHT + self.in_vehicle + self.parking + self.money
+    }
+}
+
+/// Length in meters of the path between the two points, None if there is none
+pub fn path_length(map: &Map, pather: &impl Pathfinder, from: Vec2, to: Vec2) -> Option<f32> {
+    let it = Itinerary::route(from, to, map, pather)?;
+    Some(it.get_point().map_or(0.0, |p| p.distance(from)) + it.length(map))
+}
+
+fn walk_time(from: Vec2, to: Vec2, walking_speed: f32) -> f32 {
+    from.distance(to) * DETOUR_FACTOR / walking_speed
+}
+
+/// Part of a trip that is pathfinded, walked, driven or ridden depending on the mode
+#[derive(Copy, Clone, Debug)]
+pub struct Leg {
+    pub mode: TravelMode,
+    pub from: Vec2,
+    pub to: Vec2,
+}
+
+impl Leg {
+    /// Length of the path, None if there is none. Walkers can always go straight.
+    pub fn length(&self, map: &Map) -> Option<f32> {
+        match self.mode {
+            TravelMode::Walk => Some(
+                path_length(map, &PedestrianPath, self.from, self.to)
+                    .unwrap_or_else(|| self.from.distance(self.to) * DETOUR_FACTOR),
+            ),
+            TravelMode::Car => path_length(map, &CarPath, self.from, self.to),
+            TravelMode::Bike => path_length(map, &BikePath, self.from, self.to),
+            TravelMode::Transit => Some(self.from.distance(self.to)),
+        }
+    }
+
+    /// No path is shorter than the straight line
+    pub fn lower_bound(&self) -> f32 {
+        self.from.distance(self.to)
+    }
+}
+
+/// A mode that can be taken, its cost depends on the length of its leg if it has one
+pub struct ModeOption<'a, T> {
+    pub leg: Option<Leg>,
+    pub cost: Box<dyn Fn(f32) -> ModeCost + 'a>,
+    pub data: T,
+}
+
+/// Costs the options and sorts them from the cheapest. Paths are only looked for while an option
+/// can still be the cheapest: one costing more than the best so far with the straight line for
+/// its leg is dominated and keeps that estimate. Options whose leg has no path are dropped.
+pub fn rank_modes<T>(map: &Map, options: Vec<ModeOption<T>>) -> Vec<(ModeCost, T)> {
+    let mut estimated: Vec<(ModeCost, ModeOption<T>)> = options
+        .into_iter()
+        .map(|o| ((o.cost)(o.leg.map_or(0.0, |l| l.lower_bound())), o))
+        .collect();
+    estimated.sort_by_key(|(cost, _)| OrderedFloat(cost.total()));
+
+    let mut best = f32::INFINITY;
+    let mut ranked = Vec::with_capacity(estimated.len());
+    for (estimate, option) in estimated {
+        let cost = match option.leg {
+            Some(leg) if estimate.total() < best => match leg.length(map) {
+                Some(length) => (option.cost)(length),
+                None => continue,
+            },
+            _ => estimate,
+        };
+        best = best.min(cost.total());
+        ranked.push((cost, option.data));
+    }
+    ranked.sort_by_key(|(cost, _)| OrderedFloat(cost.total()));
+    ranked
+}
+
+/// Cost of walking `dist` meters
+pub fn walk_cost(dist: f32, walking_speed: f32) -> ModeCost {
+    ModeCost {
+        walk: dist / walking_speed,
+        ..ModeCost::new(TravelMode::Walk)
+    }
+}
+
+/// Cost of walking to the car at `car_pos` (unless already in it), driving `drive` meters to
+/// `parking_pos` and walking from there
+pub fn car_cost(
+    from: Vec2,
+    car_pos: Vec2,
+    in_car: bool,
+    parking_pos: Vec2,
+    to: Vec2,
+    drive: f32,
+    walking_speed: f32,
+) -> ModeCost {
+    let access = if in_car {
+        0.0
+    } else {
+        walk_time(from, car_pos, walking_speed)
+    };
+    ModeCost {
+        walk: access + walk_time(parking_pos, to, walking_speed),
+        in_vehicle: drive / VehicleKind::Car.cruising_speed(),
+        parking: TIME_TO_PARK + PARKING_SEARCH_TIME,
+        money: drive * CAR_COST_PER_METER / VALUE_OF_TIME,
+        ..ModeCost::new(TravelMode::Car)
+    }
+}
+
+/// Cost of walking to the bike at `bike_pos` (unless already on it), riding `ride` meters to
+/// the rack at `rack_pos` and walking from there
+pub fn bike_cost(
+    from: Vec2,
+    bike_pos: Vec2,
+    on_bike: bool,
+    rack_pos: Vec2,
+    to: Vec2,
+    ride: f32,
+    walking_speed: f32,
+) -> ModeCost {
+    let (access, unlock) = if on_bike {
+        (0.0, 0.0)
+    } else {
+        (walk_time(from, bike_pos, walking_speed), BIKE_LOCK_TIME)
+    };
+    ModeCost {
+        walk: access + walk_time(rack_pos, to, walking_speed),
+        in_vehicle: ride / VehicleKind::Bike.cruising_speed(),
+        parking: unlock + BIKE_LOCK_TIME,
+        ..ModeCost::new(TravelMode::Bike)
+    }
+}
+
+/// Seconds spent waiting at a stop reached at `arrival` seconds of the day for the first bus,
+/// buses passing there `offset` seconds after leaving the first stop of the line
+pub fn transit_wait(timetable: &Timetable, offset: f64, arrival: f64) -> f64 {
+    let day = SECONDS_PER_DAY as f64;
+    let at_first = (arrival - offset).rem_euclid(day);
+    timetable.next_departure(at_first.ceil() as i32) as f64 - at_first
+}
+
+/// Cost of the bus trip, waiting for the first bus passing at the stop after walking to it.
+/// `daysec` is the current time of the day.
+pub fn transit_cost(
+    network: &TransitNetwork,
+    trip: &TransitTrip,
+    from: Vec2,
+    to: Vec2,
+    walking_speed: f32,
+    daysec: f64,
+) -> Option<ModeCost> {
+    let line = network.lines().get(trip.line)?;
+    let access = walk_time(from, network.stop_pos(trip.board)?, walking_speed);
+    let egress = walk_time(network.stop_pos(trip.alight)?, to, walking_speed);
+
+    let offset = network.travel_time(trip.line, 0, trip.board_idx)?;
+    let wait = transit_wait(&line.timetable, offset, daysec + access as f64);
+    let ride = network.travel_time(trip.line, trip.board_idx, trip.alight_idx)?;
+
+    Some(ModeCost {
+        walk: access + egress,
+        wait: wait as f32,
+        in_vehicle: ride as f32,
+        ..ModeCost::new(TravelMode::Transit)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::SECONDS_PER_HOUR;
+    use geom::vec2;
+
+    const SPEED: f32 = 1.34;
+    const H: i32 = SECONDS_PER_HOUR;
+
+    /// Generalized costs of walking, driving and cycling `dist` meters east, with the vehicles
+    /// waiting at the start and parking at the destination
+    fn costs(dist: f32) -> (f32, f32, f32) {
+        let (from, to) = (Vec2::ZERO, vec2(dist, 0.0));
+        let path = dist * DETOUR_FACTOR;
+        (
+            walk_cost(path, SPEED).total(),
+            car_cost(from, from, false, to, to, path, SPEED).total(),
+            bike_cost(from, from, false, to, to, path, SPEED).total(),
+        )
+    }
+
+    #[test]
+    fn walking_wins_short_trips() {
+        let (walk, car, bike) = costs(5.0);
+        assert!(walk < bike && bike < car);
+    }
+
+    #[test]
+    fn cycling_wins_medium_trips() {
+        let (walk, car, bike) = costs(30.0);
+        assert!(bike < car && car < walk);
+    }
+
+    #[test]
+    fn driving_wins_long_trips() {
+        let (walk, car, bike) = costs(1000.0);
+        assert!(car < bike && bike < walk);
+    }
+
+    #[test]
+    fn a_far_away_car_loses_to_walking() {
+        let to = vec2(100.0, 0.0);
+        let walk = walk_cost(100.0 * DETOUR_FACTOR, SPEED).total();
+        let far = car_cost(Vec2::ZERO, vec2(-300.0, 0.0), false, to, to, 520.0, SPEED);
+        assert!(walk < far.total());
+
+        // Unless the human is already in it
+        let near = car_cost(Vec2::ZERO, Vec2::ZERO, true, to, to, 130.0, SPEED);
+        assert!(near.total() < walk);
+    }
+
+    #[test]
+    fn transit_wait_is_until_the_next_bus() {
+        let tt = Timetable::new(6 * H, 12 * H, H / 2);
+        let offset = 30.0;
+        let at = |daysec: i32| daysec as f64 + offset;
+
+        assert_eq!(transit_wait(&tt, offset, at(6 * H)), 0.0);
+        assert_eq!(transit_wait(&tt, offset, at(6 * H + 1)), (H / 2 - 1) as f64);
+        // Before the service starts
+        assert_eq!(transit_wait(&tt, offset, at(H)), (5 * H) as f64);
+        // After the last bus, the first one of the next day
+        assert_eq!(
+            transit_wait(&tt, offset, at(12 * H + 1)),
+            (SECONDS_PER_DAY + 6 * H - (12 * H + 1)) as f64
+        );
+        // Reached past midnight, while the first bus of the day is still on its way
+        assert_eq!(
+            transit_wait(&tt, offset, 10.0),
+            6.0 * H as f64 + offset - 10.0
+        );
+        assert_eq!(
+            transit_wait(&tt, offset, SECONDS_PER_DAY as f64 + at(6 * H)),
+            0.0
+        );
+    }
+
+    #[test]
+    fn transit_wins_when_the_bus_is_coming() {
+        let mut map = Map::empty();
+        map_model::procgen::add_grid(Vec2::ZERO, &mut map, 3);
+
+        let mut network = TransitNetwork::default();
+        let a = network.add_stop(&map, vec2(40.0, 0.0)).unwrap();
+        let b = network.add_stop(&map, vec2(160.0, 0.0)).unwrap();
+        let (from, to) = (vec2(40.0, 0.0), vec2(160.0, 0.0));
+
+        let walk = walk_cost(from.distance(to) * DETOUR_FACTOR, SPEED).total();
+        let line = network.add_line(
+            "1".to_string(),
+            vec![a, b],
+            Timetable::new(0, 20 * H, H / 10),
+        );
+        let daysec = 6.0 * H as f64;
+        let trip = network.find_trip(from, to, daysec as i32).unwrap();
+        assert_eq!(trip.line, line);
+
+        let frequent = transit_cost(&network, &trip, from, to, SPEED, daysec).unwrap();
+        assert!(frequent.wait <= (H / 10) as f32);
+        assert!(frequent.total() < walk);
+
+        // With buses every 5 hours, the next one is long gone
+        network.set_timetable(line, Timetable::new(0, 20 * H, 5 * H));
+        let rare = transit_cost(&network, &trip, from, to, SPEED, daysec).unwrap();
+        assert!(rare.wait > H as f32);
+        assert!(rare.total() > walk);
+    }
+
+    #[test]
+    fn dominated_modes_are_not_pathfinded() {
+        // Nothing can be pathfinded on an empty map, the legs that are looked for are dropped
+        let map = Map::empty();
+        let leg = |mode| {
+            Some(Leg {
+                mode,
+                from: Vec2::ZERO,
+                to: vec2(1000.0, 0.0),
+            })
+        };
+        let options = vec![
+            ModeOption {
+                leg: None,
+                cost: Box::new(|_| ModeCost {
+                    wait: 100.0,
+                    ..ModeCost::new(TravelMode::Transit)
+                }),
+                data: "bus",
+            },
+            ModeOption {
+                leg: leg(TravelMode::Car),
+                cost: Box::new(|dist| ModeCost {
+                    in_vehicle: dist / 100.0,
+                    ..ModeCost::new(TravelMode::Car)
+                }),
+                data: "car",
+            },
+            ModeOption {
+                leg: leg(TravelMode::Bike),
+                cost: Box::new(|dist| ModeCost {
+                    in_vehicle: dist,
+                    ..ModeCost::new(TravelMode::Bike)
+                }),
+                data: "bike",
+            },
+        ];
+
+        // The car could have beaten the bus so it was looked for, the bike couldn't
+        let ranked = rank_modes(&map, options);
+        let names: Vec<_> = ranked.iter().map(|(_, x)| *x).collect();
+        assert_eq!(names, vec!["bus", "bike"]);
+        assert_eq!(ranked[1].0.in_vehicle, 1000.0);
+    }
+}
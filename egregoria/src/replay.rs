@@ -0,0 +1,216 @@
+use crate::saveslots;
+use crate::transit::{StopID, TransitCommand, TransitNetwork};
+use crate::undo::MapHistory;
+use crate::Egregoria;
+use map_model::{IntersectionID, Map, MapCommand};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+pub const REPLAYS_DIR: &str = "replays";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ReplayCommand {
+    Map(MapCommand),
+    Transit(TransitCommand),
+    Undo,
+    Redo,
+}
+
+/// Commands of a session along with the tick they were applied on.
+/// Played back on the same starting slot with the same seed in deterministic mode,
+/// it reproduces the session.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Replay {
+    /// Save slot the session started from, None if it started from an empty world
+    pub start: Option<String>,
+    pub seed: u64,
+    /// `Egregoria::state_hash` once the starting slot was loaded. The slot can be overwritten
+    /// after the recording, playback refuses to start from a different state.
+    #[serde(default)]
+    pub initial_hash: Option<u64>,
+    pub commands: Vec<(u64, ReplayCommand)>,
+}
+
+impl Replay {
+    pub fn load(path: impl AsRef<Path>) -> Option<Self> {
+        common::saveload::load_json(path)
+    }
+
+    /// Tick of the last command, 0 if there are none
+    pub fn last_tick(&self) -> u64 {
+        self.commands
+            .last()
+            .map(|(tick, _)| *tick)
+            .unwrap_or_default()
+    }
+}
+
+/// Resource through which every map and transit edit is made so that it ends up in the replay.
+/// Only map edits can be undone.
+pub struct ReplayRecorder {
+    tick: u64,
+    pub replay: Replay,
+    history: MapHistory,
+    /// The map was restored by undo or redo since the last tick
+    restored: bool,
+    /// The session can be played back, see `start`
+    recording: bool,
+}
+
+impl ReplayRecorder {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            tick: 0,
+            replay: Replay {
+                start: None,
+                seed,
+                initial_hash: None,
+                commands: vec![],
+            },
+            history: MapHistory::default(),
+            restored: false,
+            recording: false,
+        }
+    }
+
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    /// Records the state the session starts from, once the starting slot is loaded.
+    /// Sessions that aren't started are not recorded: they weren't deterministic, or
+    /// their starting world couldn't be loaded back.
+    pub fn start(&mut self, slot: Option<String>, initial_hash: u64) {
+        self.replay.start = slot;
+        self.replay.initial_hash = Some(initial_hash);
+        self.recording = true;
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording
+    }
+
+    pub(crate) fn advance(&mut self) {
+        self.tick += 1;
+    }
+
+    /// Whether the map was restored since the last call, the world then has to catch up with it
+    pub(crate) fn take_restored(&mut self) -> bool {
+        std::mem::take(&mut self.restored)
+    }
+
+    pub fn history(&self) -> &MapHistory {
+        &self.history
+    }
+
+    pub fn apply(&mut self, map: &mut Map, command: MapCommand) -> Option<IntersectionID> {
+        let continues_stroke = self.continues_stroke(&command);
+        self.history.push(map, &command, continues_stroke);
+        let v = command.apply(map);
+        self.replay
+            .commands
+            .push((self.tick, ReplayCommand::Map(command)));
+        v
+    }
+
+    /// Returns the new stop for `TransitCommand::AddStop`
+    pub fn apply_transit(
+        &mut self,
+        network: &mut TransitNetwork,
+        map: &Map,
+        command: TransitCommand,
+    ) -> Option<StopID> {
+        let v = command.apply(network, map);
+        self.replay
+            .commands
+            .push((self.tick, ReplayCommand::Transit(command)));
+        v
+    }
+
+    pub fn undo(&mut self, map: &mut Map) {
+        if self.history.undo(map) {
+            self.restored = true;
+            self.replay.commands.push((self.tick, ReplayCommand::Undo));
+        }
+    }
+
+    pub fn redo(&mut self, map: &mut Map) {
+        if self.history.redo(map) {
+            self.restored = true;
+            self.replay.commands.push((self.tick, ReplayCommand::Redo));
+        }
+    }
+
+    pub fn execute(&mut self, map: &mut Map, network: &mut TransitNetwork, command: ReplayCommand) {
+        match command {
+            ReplayCommand::Map(command) => {
+                self.apply(map, command);
+            }
+            ReplayCommand::Transit(command) => {
+                self.apply_transit(network, map, command);
+            }
+            ReplayCommand::Undo => self.undo(map),
+            ReplayCommand::Redo => self.redo(map),
+        }
+    }
+
+    /// The lot brush sets lot kinds every tick while the mouse is held,
+    /// the whole stroke is undone at once.
+    fn continues_stroke(&self, command: &MapCommand) -> bool {
+        if !matches!(command, MapCommand::SetLotKind(..)) {
+            return false;
+        }
+        match self.replay.commands.last() {
+            Some((tick, ReplayCommand::Map(MapCommand::SetLotKind(..)))) => tick + 1 >= self.tick,
+            _ => false,
+        }
+    }
+
+    /// Writes the replay to `replays/replay_<timestamp>.json` and returns the path,
+    /// None if the session isn't recorded
+    pub fn save(&self) -> Option<PathBuf> {
+        if !self.recording {
+            return None;
+        }
+        let path = Path::new(REPLAYS_DIR).join(format!("replay_{}.json", saveslots::now()));
+        common::saveload::save_json(&self.replay, &path)?;
+        Some(path)
+    }
+}
+
+/// Feeds the commands of a replay to an Egregoria instance as it ticks.
+pub struct ReplayPlayer {
+    replay: Replay,
+    next: usize,
+}
+
+impl ReplayPlayer {
+    pub fn new(replay: Replay) -> Self {
+        Self { replay, next: 0 }
+    }
+
+    pub fn replay(&self) -> &Replay {
+        &self.replay
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.next >= self.replay.commands.len()
+    }
+
+    /// Applies the commands recorded up to the current tick.
+    /// Must be called once the tick and the souls are done, as the gui issues commands between ticks.
+    pub fn apply(&mut self, goria: &mut Egregoria) {
+        let mut recorder = goria.write::<ReplayRecorder>();
+        let mut map = goria.write::<Map>();
+        let mut network = goria.write::<TransitNetwork>();
+        let tick = recorder.tick();
+
+        while let Some((cmd_tick, command)) = self.replay.commands.get(self.next) {
+            if *cmd_tick > tick {
+                break;
+            }
+            recorder.execute(&mut map, &mut network, command.clone());
+            self.next += 1;
+        }
+    }
+}
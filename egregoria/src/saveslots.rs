@@ -0,0 +1,142 @@
+use crate::pedestrians::Pedestrian;
+use crate::vehicles::Vehicle;
+use crate::{load_from_disk, save_to_disk, Egregoria};
+use common::saveload::LoadError;
+use common::GameTime;
+use legion::IntoQuery;
+use map_model::Map;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const SAVES_DIR: &str = "saves";
+pub const N_AUTOSAVES: usize = 3;
+
+const AUTOSAVE_PREFIX: &str = "autosave_";
+const META_FILE: &str = "meta.json";
+
+/// Stored next to the world in every slot so that saves can be listed without loading them
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SlotMeta {
+    pub name: String,
+    /// Seconds since the unix epoch
+    pub timestamp: u64,
+    pub game_day: i32,
+    pub game_hour: i32,
+    pub population: usize,
+    pub summary: String,
+}
+
+impl SlotMeta {
+    fn new(goria: &Egregoria, name: String) -> Self {
+        let time = goria.read::<GameTime>().daytime;
+        let map = goria.read::<Map>();
+
+        let population = <&Pedestrian>::query().iter(&goria.world).count();
+        let n_vehicles = <&Vehicle>::query().iter(&goria.world).count();
+
+        Self {
+            name,
+            timestamp: now(),
+            game_day: time.day,
+            game_hour: time.hour,
+            population,
+            summary: format!(
+                "{} buildings, {} roads, {} vehicles",
+                map.buildings().len(),
+                map.roads().len(),
+                n_vehicles
+            ),
+        }
+    }
+
+    pub fn is_autosave(&self) -> bool {
+        self.name.starts_with(AUTOSAVE_PREFIX)
+    }
+}
+
+/// Seconds since the unix epoch
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or_default()
+}
+
+/// Only keeps characters that are safe to use in a directory name
+pub fn sanitize_slot_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | ' '))
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+/// Directory of the slot, None if the name isn't a plain file stem that stays in the saves
+/// directory (no path separators or `..`)
+pub fn slot_dir(name: &str) -> Option<PathBuf> {
+    if name.is_empty() || sanitize_slot_name(name) != name {
+        return None;
+    }
+    Some(Path::new(SAVES_DIR).join(name))
+}
+
+pub fn save_slot(goria: &mut Egregoria, name: &str) {
+    let name = sanitize_slot_name(name);
+    let dir = unwrap_or!(slot_dir(&name), {
+        log::warn!("cannot save to a slot with an empty name");
+        return;
+    });
+    save_to_disk(goria, &dir);
+    common::saveload::save_json(&SlotMeta::new(goria, name), dir.join(META_FILE));
+}
+
+pub fn load_slot(goria: &mut Egregoria, name: &str) -> Result<(), LoadError> {
+    let dir = slot_dir(name).ok_or_else(|| {
+        LoadError::Io(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("invalid save slot name {:?}", name),
+        ))
+    })?;
+    load_from_disk(goria, &dir)
+}
+
+pub fn delete_slot(name: &str) {
+    let dir = unwrap_or!(slot_dir(name), {
+        log::error!("refusing to delete invalid save slot {:?}", name);
+        return;
+    });
+    if let Err(e) = std::fs::remove_dir_all(dir) {
+        log::error!("could not delete save slot {}: {}", name, e);
+    }
+}
+
+/// All the slots found in the saves directory, most recent first
+pub fn list_slots() -> Vec<SlotMeta> {
+    let mut slots: Vec<SlotMeta> = std::fs::read_dir(SAVES_DIR)
+        .into_iter()
+        .flatten()
+        .filter_map(|x| x.ok())
+        .filter_map(|entry| common::saveload::load_json(entry.path().join(META_FILE)))
+        .collect();
+    slots.sort_by_key(|x| std::cmp::Reverse(x.timestamp));
+    slots
+}
+
+/// Saves to the autosave slots in rotation, overwriting the oldest one
+pub fn autosave(goria: &mut Egregoria) {
+    let slots = list_slots();
+
+    let name = (0..N_AUTOSAVES)
+        .map(|i| format!("{}{}", AUTOSAVE_PREFIX, i))
+        .min_by_key(|name| {
+            slots
+                .iter()
+                .find(|x| &x.name == name)
+                .map(|x| x.timestamp)
+                .unwrap_or(0)
+        })
+        .unwrap(); // Unwrap ok: N_AUTOSAVES > 0
+
+    save_slot(goria, &name);
+}
@@ -1,5 +1,6 @@
 use crate::map_dynamic::Itinerary;
 use crate::rendering::immediate::ImmediateDraw;
+use crate::utils::rand_provider::RandProvider;
 use crate::vehicles::{make_vehicle_entity, Vehicle, VehicleKind, VehicleState};
 use crate::{Egregoria, ParCommandBuffer};
 use geom::Color;
@@ -27,7 +28,7 @@ impl UserData for LuaWorld {
                     Transform::new_cos_sin(pos.0, dir.0.try_normalize().unwrap_or(Vec2::UNIT_X)),
                     Vehicle {
                         ang_velocity: 0.0,
-                        wait_time: 0.0,
+                        lane_change_cooldown: 0.0,
                         state: VehicleState::Driving,
                         kind: VehicleKind::Car,
                     },
@@ -107,5 +108,6 @@ pub fn add_egregoria_lua_stdlib(lua: &Lua, w: &mut Egregoria) {
             },
         )
         .unwrap();
-    mods::add_fn(lua, "color", color)
+    mods::add_fn(lua, "color", color);
+    mods::seed_rand(lua, w.write::<RandProvider>().random());
 }
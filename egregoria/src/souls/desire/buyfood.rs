@@ -1,23 +1,23 @@
 use crate::api::{Action, Destination};
-use crate::economy::{Goods, Market};
-use crate::map_dynamic::BuildingInfos;
+use crate::economy::{Good, Market};
 use crate::souls::desire::Desire;
 use crate::souls::human::Human;
-use crate::Egregoria;
-use geom::Vec2;
-use map_model::{BuildingID, BuildingKind, Map};
-use ordered_float::OrderedFloat;
+use crate::{Egregoria, SoulID};
+use map_model::BuildingID;
+use serde::{Deserialize, Serialize};
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct BuyFood {
     min_level: i32,
-    supermarket: Option<BuildingID>,
+    /// Seller picked for the next purchase along with its building
+    seller: Option<(SoulID, BuildingID)>,
 }
 
 impl BuyFood {
     pub fn new(min_level: i32) -> Self {
         BuyFood {
             min_level,
-            supermarket: None,
+            seller: None,
         }
     }
 }
@@ -28,7 +28,17 @@ impl Desire<Human> for BuyFood {
     }
 
     fn score(&self, goria: &Egregoria, soul: &Human) -> f32 {
-        if goria.read::<Market>().agents[&soul.id].goods.food < self.min_level {
+        let market = goria.read::<Market>();
+        let agent = &market.agents[&soul.id];
+        // With the shelves empty, going shopping would only keep the human from work
+        let in_stock = self.seller.is_some()
+            || market
+                .best_seller(soul.id, Good::Groceries, soul.router.body_pos(goria))
+                .is_some();
+        if agent.goods[Good::Groceries] < self.min_level
+            && agent.money >= market.price(Good::Groceries)
+            && in_stock
+        {
             0.8
         } else {
             -100.0
@@ -36,39 +46,25 @@ impl Desire<Human> for BuyFood {
     }
 
     fn apply(&mut self, goria: &Egregoria, soul: &mut Human) -> Action {
-        if self.supermarket.is_none() {
-            self.supermarket = find_supermarket(soul.router.body_pos(goria), &goria.read::<Map>());
+        if self.seller.is_none() {
+            let pos = soul.router.body_pos(goria);
+            self.seller = goria
+                .read::<Market>()
+                .best_seller(soul.id, Good::Groceries, pos)
+                .map(|(id, seller)| (id, seller.building));
         }
 
-        if let Some(id) = self.supermarket {
-            if soul.router.arrived(Destination::Building(id)) {
-                if let Some(owner) = goria.read::<BuildingInfos>()[id].owner {
-                    let trans = goria.read::<Market>().want(owner, Goods { food: 1 });
-                    if trans.is_none() {
-                        log::warn!("No food in this supermarket :( {:?}", id);
-                        return Action::DoNothing;
-                    }
-
-                    let trans = trans.unwrap();
-                    return Action::Buy {
-                        buyer: soul.id,
-                        seller: owner,
-                        trans,
-                    };
-                }
-            }
-
-            soul.router.go_to(goria, Destination::Building(id))
-        } else {
-            Action::DoNothing
+        let (seller, building) = unwrap_or!(self.seller, return Action::DoNothing);
+        if soul.router.arrived(Destination::Building(building)) {
+            self.seller = None;
+            return Action::Buy {
+                buyer: soul.id,
+                seller,
+                good: Good::Groceries,
+                qty: 1,
+            };
         }
-    }
-}
 
-fn find_supermarket(pos: Vec2, map: &Map) -> Option<BuildingID> {
-    map.buildings()
-        .values()
-        .filter(|x| matches!(x.kind, BuildingKind::Supermarket))
-        .min_by_key(|b| OrderedFloat(b.door_pos.distance2(pos)))
-        .map(|x| x.id)
+        soul.router.go_to(goria, Destination::Building(building))
+    }
 }
@@ -1,13 +1,21 @@
 use crate::api::{Action, Destination};
+use crate::economy::{Good, Market};
 use crate::souls::desire::Desire;
 use crate::souls::human::Human;
 use crate::Egregoria;
 use common::{GameTime, RecTimeInterval, SECONDS_PER_HOUR};
 use map_model::BuildingID;
+use serde::{Deserialize, Serialize};
 
+/// Groceries eaten by a household every day
+pub const GROCERIES_PER_DAY: i32 = 1;
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Home {
     house: BuildingID,
     home_inter: RecTimeInterval,
+    /// Day of the last meal, households eat once a day at home
+    last_meal: Option<i32>,
 }
 
 impl Home {
@@ -18,6 +26,7 @@ impl Home {
                 (19, (offset * SECONDS_PER_HOUR as f32) as i32),
                 (6, (offset * SECONDS_PER_HOUR as f32) as i32),
             ),
+            last_meal: None,
         }
     }
 }
@@ -33,6 +42,18 @@ impl Desire<Human> for Home {
     }
 
     fn apply(&mut self, goria: &Egregoria, soul: &mut Human) -> Action {
+        let day = goria.read::<GameTime>().daytime.day;
+        if self.last_meal != Some(day)
+            && soul.router.arrived(Destination::Building(self.house))
+            && goria.read::<Market>().agents[&soul.id].goods[Good::Groceries] >= GROCERIES_PER_DAY
+        {
+            self.last_meal = Some(day);
+            return Action::Consume {
+                consumer: soul.id,
+                good: Good::Groceries,
+                qty: GROCERIES_PER_DAY,
+            };
+        }
         soul.router.go_to(goria, Destination::Building(self.house))
     }
 }
@@ -4,10 +4,12 @@ use ordered_float::OrderedFloat;
 
 mod buyfood;
 mod home;
+mod produce;
 mod work;
 
 pub use buyfood::*;
 pub use home::*;
+pub use produce::*;
 pub use work::*;
 
 pub trait Desire<T>: Send + Sync {
@@ -0,0 +1,79 @@
+use crate::api::Action;
+use crate::economy::{Bid, Market, Money, MAX_STOCK_BATCHES, ORDER_BATCHES, REORDER_BATCHES};
+use crate::souls::desire::Desire;
+use crate::souls::producer::Producer;
+use crate::Egregoria;
+use common::GameTime;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Produce;
+
+impl Desire<Producer> for Produce {
+    fn name(&self) -> &'static str {
+        "Produce"
+    }
+
+    fn score(&self, _goria: &Egregoria, _soul: &Producer) -> f32 {
+        1.0
+    }
+
+    fn apply(&mut self, goria: &Egregoria, soul: &mut Producer) -> Action {
+        let market = goria.read::<Market>();
+        let agent = unwrap_or!(market.agents.get(&soul.id), return Action::DoNothing);
+        let recipe = soul.recipe;
+
+        if (recipe.outputs * MAX_STOCK_BATCHES).is_smaller(&agent.goods) {
+            return Action::DoNothing;
+        }
+
+        // Inputs running low are ordered ahead, counting the ones already on their way,
+        // willing to pay a bit more than the market price but never more than the outputs
+        // sell for. Standing bids follow the price as it moves.
+        let in_transit = market.in_transit(soul.id);
+        let outputs_worth = recipe
+            .outputs
+            .iter()
+            .map(|(good, amount)| market.price(good).0 * amount)
+            .sum::<i32>();
+        for (good, amount) in recipe.inputs.iter() {
+            let have = agent.goods[good] + in_transit[good];
+            if have >= amount * REORDER_BATCHES && !market.has_bid(soul.id, good) {
+                continue;
+            }
+            let qty = amount * ORDER_BATCHES - have;
+            let max_price = Money(
+                (market.price(good).0 * 3 / 2)
+                    .min(outputs_worth * good.price().0 / recipe.inputs.value().0),
+            );
+            let standing = market.book(good).bids.get(&soul.id);
+            if standing.map_or(false, |b| b.qty == qty && b.max_price == max_price) || qty <= 0 {
+                continue;
+            }
+            return Action::Bid(Bid {
+                buyer: soul.id,
+                good,
+                qty,
+                max_price,
+                pos: soul.pos,
+            });
+        }
+
+        // Wait for the trucks
+        if !recipe.inputs.is_smaller(&agent.goods) {
+            return Action::DoNothing;
+        }
+
+        // Understaffed producers work slower
+        soul.progress += goria.read::<GameTime>().delta * market.labor.staffing(soul.id);
+        if soul.progress < recipe.duration {
+            return Action::DoNothing;
+        }
+        soul.progress = 0.0;
+
+        Action::Produce {
+            producer: soul.id,
+            recipe,
+        }
+    }
+}
@@ -1,25 +1,32 @@
 use crate::api::{Action, Destination};
+use crate::economy::Market;
 use crate::souls::desire::Desire;
 use crate::souls::human::Human;
 use crate::Egregoria;
 use common::{GameTime, RecTimeInterval, SECONDS_PER_HOUR};
-use map_model::BuildingID;
+use map_model::Map;
+use serde::{Deserialize, Serialize};
+
+/// Game seconds an unemployed human waits before looking for a job again
+const JOB_SEARCH_INTERVAL: u32 = SECONDS_PER_HOUR as u32;
 
 impl Work {
-    pub fn new(workplace: BuildingID, offset: f32) -> Self {
+    pub fn new(offset: f32) -> Self {
         Work {
-            workplace,
             work_inter: RecTimeInterval::new(
                 (8, (offset * SECONDS_PER_HOUR as f32) as i32),
                 (18, (offset * SECONDS_PER_HOUR as f32) as i32),
             ),
+            last_search: None,
         }
     }
 }
 
+/// The job itself is held by the labor market, so that employers can fire
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Work {
-    workplace: BuildingID,
     work_inter: RecTimeInterval,
+    last_search: Option<u32>,
 }
 
 impl Desire<Human> for Work {
@@ -33,7 +40,43 @@ impl Desire<Human> for Work {
     }
 
     fn apply(&mut self, goria: &Egregoria, soul: &mut Human) -> Action {
-        soul.router
-            .go_to(goria, Destination::Building(self.workplace))
+        let market = goria.read::<Market>();
+        let workplace = match market.labor.employer_of(soul.id) {
+            Some((_, employer)) => employer.building,
+            None => {
+                // Unemployed, look for a job near home from time to time
+                let now = goria.read::<GameTime>().seconds;
+                if self
+                    .last_search
+                    .map_or(false, |t| now < t + JOB_SEARCH_INTERVAL)
+                {
+                    return Action::DoNothing;
+                }
+                self.last_search = Some(now);
+
+                let home = unwrap_or!(
+                    goria.read::<Map>().buildings().get(soul.house),
+                    return Action::DoNothing
+                )
+                .door_pos;
+                return match market.labor.find_job(home) {
+                    Some(employer) => Action::Hire {
+                        worker: soul.id,
+                        employer,
+                    },
+                    None => Action::DoNothing,
+                };
+            }
+        };
+
+        if soul.router.arrived(Destination::Building(workplace)) {
+            if !market.labor.has_clocked_in(soul.id) {
+                return Action::ClockIn(soul.id);
+            }
+            return Action::DoNothing;
+        }
+        drop(market);
+
+        soul.router.go_to(goria, Destination::Building(workplace))
     }
 }
@@ -1,51 +1,61 @@
 use crate::api::Router;
-use crate::economy::{EconomicAgent, Goods, Market, Money};
+use crate::economy::{EconomicAgent, Good, Goods, Market, Money};
 use crate::map_dynamic::BuildingInfos;
 use crate::pedestrians::spawn_pedestrian;
-use crate::souls::desire::{BuyFood, Home, Work};
+use crate::souls::desire::{BuyFood, Home, Work, GROCERIES_PER_DAY};
 use crate::souls::Soul;
 use crate::utils::rand_provider::RandProvider;
-use crate::vehicles::spawn_parked_vehicle;
+use crate::vehicles::{spawn_parked_vehicle, spawn_racked_bike};
 use crate::{Egregoria, SoulID};
-use map_model::{BuildingID, BuildingKind, Map};
+use map_model::{BuildingID, Map};
+use serde::{Deserialize, Serialize};
 
 pub type HumanSoul = Soul<Human, (Work, Home, BuyFood)>;
 
+/// Share of the humans owning a bike
+const BIKE_OWNERSHIP: f32 = 0.4;
+
+/// Savings of a new household, a month of groceries:
+/// the unemployed can get by for a while but not forever.
+pub const STARTING_MONEY: Money =
+    Money(Good::Groceries.price().0 * GROCERIES_PER_DAY * SAVINGS_DAYS);
+const SAVINGS_DAYS: i32 = 30;
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Human {
     pub id: SoulID,
+    pub house: BuildingID,
     pub router: Router,
 }
 
 impl Human {
-    pub fn soul(goria: &mut Egregoria, id: SoulID, house: BuildingID) -> Option<HumanSoul> {
-        let map = goria.read::<Map>();
-        let work = map
-            .random_building(BuildingKind::Workplace, &mut *goria.write::<RandProvider>())?
-            .id;
-        let housepos = map.buildings()[house].door_pos;
-        drop(map);
+    pub fn soul(goria: &mut Egregoria, id: SoulID, house: BuildingID) -> HumanSoul {
+        let housepos = goria.read::<Map>().buildings()[house].door_pos;
 
         goria.write::<BuildingInfos>().set_owner(house, id);
 
         let body = spawn_pedestrian(goria, house);
         let car = spawn_parked_vehicle(goria, housepos);
 
+        let has_bike = goria.write::<RandProvider>().random::<f32>() < BIKE_OWNERSHIP;
+        let bike = if has_bike {
+            spawn_racked_bike(goria, housepos)
+        } else {
+            None
+        };
+
         let offset = goria.write::<RandProvider>().random::<f32>() * 0.5;
 
-        let router = Router::new(body, car);
+        let router = Router::new(body, car, bike);
 
         goria
             .write::<Market>()
             .agents
-            .insert(id, EconomicAgent::new(id, Money(10000), Goods { food: 0 }));
-
-        Some(Soul {
-            desires: (
-                Work::new(work, offset),
-                Home::new(house, offset),
-                BuyFood::new(7),
-            ),
-            extra: Human { id, router },
-        })
+            .insert(id, EconomicAgent::new(id, STARTING_MONEY, Goods::default()));
+
+        Soul {
+            desires: (Work::new(offset), Home::new(house, offset), BuyFood::new(7)),
+            extra: Human { id, house, router },
+        }
     }
 }
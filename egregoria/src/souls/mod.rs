@@ -4,15 +4,18 @@ use crate::map_dynamic::BuildingInfos;
 use crate::pedestrians::{Pedestrian, PedestrianID};
 use crate::souls::desire::Desires;
 use crate::souls::human::{Human, HumanSoul};
-use crate::souls::supermarket::Supermarket;
-use crate::souls::supermarket::SupermarketSoul;
-use crate::{Egregoria, SoulID};
+use crate::souls::producer::{Producer, ProducerSoul};
+use crate::{Deterministic, Egregoria, SoulID};
 use common::inspect::InspectedEntity;
 use common::GameTime;
+use legion::Entity;
 use map_model::{BuildingKind, Map};
 use rayon::iter::ParallelIterator;
 use rayon::iter::{IntoParallelRefMutIterator, ParallelExtend};
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
 use std::time::Instant;
 
 #[derive(Default)]
@@ -24,18 +27,20 @@ pub struct DebugSoul {
 
 pub mod desire;
 pub mod human;
-pub mod supermarket;
+pub mod producer;
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Soul<T, D: Desires<T>> {
     desires: D,
     extra: T,
 }
 
-#[derive(Default)]
+/// Resource holding the minds of the pedestrians and buildings, saved along the world
+#[derive(Default, Clone, Serialize, Deserialize)]
 pub struct Souls {
     pub growing: usize,
-    human_souls: HashMap<SoulID, HumanSoul>,
-    supermarket_souls: HashMap<SoulID, SupermarketSoul>,
+    human_souls: BTreeMap<SoulID, HumanSoul>,
+    producer_souls: BTreeMap<SoulID, ProducerSoul>,
     body_map: HashMap<PedestrianID, SoulID>,
 }
 
@@ -46,18 +51,27 @@ impl Souls {
         id
     }
 
+    /// Hashes the souls in id order for `Egregoria::state_hash`, the body map follows from them
+    pub(crate) fn hash_state(&self, hasher: &mut DefaultHasher, entities: &[Entity]) {
+        self.growing.hash(hasher);
+        crate::hash_with_entities(hasher, entities, self.human_souls.clone());
+        crate::hash_with_entities(hasher, entities, self.producer_souls.clone());
+    }
+
+    pub fn routers(&self) -> impl Iterator<Item = &Router> {
+        self.human_souls.values().map(|x| &x.extra.router)
+    }
+
+    pub fn routers_mut(&mut self) -> impl Iterator<Item = &mut Router> {
+        self.human_souls.values_mut().map(|x| &mut x.extra.router)
+    }
+
     pub fn add_souls_to_empty_buildings(&mut self, goria: &mut Egregoria) {
         let map = goria.read::<Map>();
         let infos = goria.read::<BuildingInfos>();
         let mut empty_buildings = vec![];
         for (id, building) in map.buildings() {
-            if !matches!(
-                building.kind,
-                BuildingKind::House | BuildingKind::Supermarket
-            ) {
-                continue;
-            }
-            if infos[id].owner.is_none() {
+            if infos.get(id).map_or(true, |x| x.owner.is_none()) {
                 empty_buildings.push((id, building.kind));
             }
         }
@@ -71,19 +85,18 @@ impl Souls {
 
             match kind {
                 BuildingKind::House => {
-                    if let Some(soul) = Human::soul(goria, id, build_id) {
-                        self.body_map.insert(soul.extra.router.body, id);
-                        self.human_souls.insert(id, soul);
+                    let soul = Human::soul(goria, id, build_id);
+                    self.body_map.insert(soul.extra.router.body, id);
+                    self.human_souls.insert(id, soul);
 
+                    n_souls_added += 1;
+                }
+                _ => {
+                    if let Some(soul) = Producer::soul(goria, id, build_id, kind) {
+                        self.producer_souls.insert(id, soul);
                         n_souls_added += 1;
                     }
                 }
-                BuildingKind::Supermarket => {
-                    let soul = Supermarket::soul(goria, id, build_id);
-                    self.supermarket_souls.insert(id, soul);
-                    n_souls_added += 1;
-                }
-                _ => unreachable!(),
             }
 
             if n_souls_added > 100 {
@@ -102,16 +115,29 @@ impl Souls {
         let t = Instant::now();
         let mut actions: Vec<Action> = vec![];
 
-        actions.par_extend(
-            self.human_souls
-                .par_iter_mut()
-                .map(move |(_, x): (_, &mut HumanSoul)| x.desires.decision(&mut x.extra, refgoria)),
-        );
+        // Souls are stored in BTreeMaps and par_extend keeps the iteration order,
+        // so actions are always applied in SoulID order
+        if refgoria.read::<Deterministic>().0 {
+            actions.extend(
+                self.human_souls
+                    .iter_mut()
+                    .map(|(_, x)| x.desires.decision(&mut x.extra, refgoria)),
+            );
+
+            actions.extend(
+                self.producer_souls
+                    .iter_mut()
+                    .map(|(_, x)| x.desires.decision(&mut x.extra, refgoria)),
+            );
+        } else {
+            actions.par_extend(self.human_souls.par_iter_mut().map(
+                move |(_, x): (_, &mut HumanSoul)| x.desires.decision(&mut x.extra, refgoria),
+            ));
 
-        #[allow(clippy::unit_arg)] // Fixme: remove this when supermarket's soul gets a desire
-        actions.par_extend(self.supermarket_souls.par_iter_mut().map(
-            move |(_, x): (_, &mut SupermarketSoul)| x.desires.decision(&mut x.extra, refgoria),
-        ));
+            actions.par_extend(self.producer_souls.par_iter_mut().map(
+                move |(_, x): (_, &mut ProducerSoul)| x.desires.decision(&mut x.extra, refgoria),
+            ));
+        }
 
         goria.set_read_only(false);
 
@@ -122,7 +148,23 @@ impl Souls {
 
         let t = Instant::now();
         for action in actions {
-            let _ = action.apply(goria);
+            let boarding = match action {
+                Action::Board(body, bus) => Some((body, bus)),
+                _ => None,
+            };
+            if action.apply(goria).is_some() {
+                continue;
+            }
+            if let Some((body, bus)) = boarding {
+                let human_souls = &mut self.human_souls;
+                let soul = self
+                    .body_map
+                    .get(&body)
+                    .and_then(|x| human_souls.get_mut(x));
+                if let Some(soul) = soul {
+                    soul.extra.router.missed_bus(goria, bus);
+                }
+            }
         }
         goria
             .write::<RenderStats>()
@@ -0,0 +1,67 @@
+use crate::economy::{
+    jobs_for_building, wage_for_building, EconomicAgent, Market, Money, Recipe, STARTING_BATCHES,
+};
+use crate::map_dynamic::BuildingInfos;
+use crate::souls::desire::Produce;
+use crate::souls::Soul;
+use crate::{Egregoria, SoulID};
+use geom::Vec2;
+use map_model::{BuildingID, BuildingKind, Map};
+use serde::{Deserialize, Serialize};
+
+pub type ProducerSoul = Soul<Producer, (Produce,)>;
+
+/// Days of full payroll a new producer can pay before selling anything
+const STARTING_PAYROLL_DAYS: i32 = 30;
+
+/// Farms, workplaces and supermarkets: they buy the inputs of their recipe, sell its outputs
+/// and employ the inhabitants to run it
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Producer {
+    pub id: SoulID,
+    pub building: BuildingID,
+    pub recipe: Recipe,
+    /// Where goods are picked up and delivered
+    pub pos: Vec2,
+    /// Game seconds spent on the current batch
+    pub progress: f32,
+}
+
+impl Producer {
+    pub fn soul(
+        goria: &mut Egregoria,
+        id: SoulID,
+        build: BuildingID,
+        kind: BuildingKind,
+    ) -> Option<ProducerSoul> {
+        let recipe = Recipe::for_building(kind)?;
+        let pos = goria.read::<Map>().buildings()[build].door_pos;
+
+        let wage = wage_for_building(kind);
+        let jobs = jobs_for_building(kind);
+        let capital = Money(wage.0 * jobs as i32 * STARTING_PAYROLL_DAYS);
+        let agent = EconomicAgent::new(id, capital, recipe.outputs * STARTING_BATCHES);
+
+        let market: &mut Market = &mut *goria.write::<Market>();
+        market.agents.insert(id, agent);
+        for (good, amount) in recipe.outputs.iter() {
+            if amount > 0 {
+                market.sell(id, good, build, pos);
+            }
+        }
+        market.labor.add_employer(id, build, pos, jobs, wage);
+
+        goria.write::<BuildingInfos>().set_owner(build, id);
+
+        Some(Soul {
+            desires: (Produce,),
+            extra: Producer {
+                id,
+                building: build,
+                recipe,
+                pos,
+                progress: 0.0,
+            },
+        })
+    }
+}
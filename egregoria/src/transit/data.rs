@@ -0,0 +1,403 @@
+use crate::vehicles::VehicleKind;
+use common::{GameTime, SECONDS_PER_DAY, SECONDS_PER_HOUR};
+use geom::Vec2;
+use map_model::{LaneKind, Map};
+use ordered_float::OrderedFloat;
+use serde::{Deserialize, Serialize};
+use slotmap::{new_key_type, DenseSlotMap};
+
+new_key_type! {
+    pub struct StopID;
+    pub struct LineID;
+}
+
+/// Game seconds a bus waits at every stop for passengers to get in and out
+pub const DWELL_TIME: f64 = 10.0;
+
+pub const BUS_CAPACITY: u32 = 60;
+
+/// Furthest a pedestrian is willing to walk to or from a stop, in meters
+pub const MAX_WALK_TO_STOP: f32 = 400.0;
+
+/// Furthest from a driving lane a stop can be placed, in meters
+pub const MAX_STOP_SNAP_DIST: f32 = 20.0;
+
+/// Longest a pedestrian waits at a stop for the next bus before looking for another way, in seconds
+pub const MAX_WAIT_FOR_BUS: i32 = SECONDS_PER_HOUR;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Stop {
+    /// On the nearest driving lane, where buses stop and pedestrians wait
+    pub pos: Vec2,
+}
+
+/// Departures of the buses of a line from its first stop, in seconds of the day
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Timetable {
+    pub first: i32,
+    pub last: i32,
+    /// Seconds between two departures
+    pub headway: i32,
+}
+
+impl Default for Timetable {
+    fn default() -> Self {
+        Self {
+            first: 6 * SECONDS_PER_HOUR,
+            last: 22 * SECONDS_PER_HOUR,
+            headway: SECONDS_PER_HOUR / 2,
+        }
+    }
+}
+
+impl Timetable {
+    pub fn new(first: i32, last: i32, headway: i32) -> Self {
+        Self {
+            first: first.max(0).min(SECONDS_PER_DAY - 1),
+            last: last.max(0).min(SECONDS_PER_DAY - 1),
+            headway: headway.max(1),
+        }
+    }
+
+    /// Whether a bus leaves the first stop during the last tick
+    pub fn departs(&self, time: &GameTime) -> bool {
+        let now = time.daysec();
+        let prev = now - time.delta as f64;
+        let (first, last) = (self.first as f64, self.last as f64);
+        if now < first || now > last {
+            return false;
+        }
+        let n_departed = |t: f64| ((t - first) / self.headway as f64).floor();
+        prev < first || n_departed(now) > n_departed(prev)
+    }
+
+    /// Seconds of the day of the first departure at or after `daysec`, wrapping to the next day
+    pub fn next_departure(&self, daysec: i32) -> i32 {
+        if daysec <= self.first {
+            return self.first;
+        }
+        let n = (daysec - self.first + self.headway - 1) / self.headway;
+        let next = self.first + n * self.headway;
+        if next > self.last {
+            self.first + SECONDS_PER_DAY
+        } else {
+            next
+        }
+    }
+
+    pub fn departures(&self) -> impl Iterator<Item = i32> + '_ {
+        (self.first..=self.last).step_by(self.headway as usize)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Line {
+    pub name: String,
+    /// Served in order, buses stop at the last one and leave the map
+    pub stops: Vec<StopID>,
+    pub timetable: Timetable,
+}
+
+/// Trip on a single line a pedestrian can take instead of walking
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct TransitTrip {
+    pub line: LineID,
+    pub board: StopID,
+    pub alight: StopID,
+    /// Indices of the stops in the line
+    pub board_idx: usize,
+    pub alight_idx: usize,
+    /// Meters walked to the first stop and from the last one
+    pub walk: f32,
+    /// Meters ridden as the crow flies
+    pub ride: f32,
+}
+
+/// Stops and lines drawn by the player, edited through `TransitCommand`s
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct TransitNetwork {
+    stops: DenseSlotMap<StopID, Stop>,
+    lines: DenseSlotMap<LineID, Line>,
+}
+
+impl TransitNetwork {
+    pub fn stops(&self) -> &DenseSlotMap<StopID, Stop> {
+        &self.stops
+    }
+
+    pub fn lines(&self) -> &DenseSlotMap<LineID, Line> {
+        &self.lines
+    }
+
+    pub fn stop_pos(&self, id: StopID) -> Option<Vec2> {
+        self.stops.get(id).map(|x| x.pos)
+    }
+
+    /// Adds a stop on the driving lane nearest to `pos`, None if there is none close enough
+    pub fn add_stop(&mut self, map: &Map, pos: Vec2) -> Option<StopID> {
+        let lane = &map.lanes()[map.nearest_lane(pos, LaneKind::Driving)?];
+        let (proj, _) = lane.points.project_segment(pos);
+        if !proj.is_close(pos, MAX_STOP_SNAP_DIST) {
+            return None;
+        }
+        Some(self.stops.insert(Stop { pos: proj }))
+    }
+
+    /// Removes the stop and takes it out of the lines serving it
+    pub fn remove_stop(&mut self, id: StopID) {
+        self.stops.remove(id);
+        for line in self.lines.values_mut() {
+            line.stops.retain(|&x| x != id);
+        }
+    }
+
+    pub fn nearest_stop(&self, pos: Vec2, max_dist: f32) -> Option<StopID> {
+        self.stops
+            .iter()
+            .filter(|(_, s)| s.pos.is_close(pos, max_dist))
+            .min_by_key(|(_, s)| OrderedFloat(s.pos.distance2(pos)))
+            .map(|(id, _)| id)
+    }
+
+    pub fn add_line(&mut self, name: String, stops: Vec<StopID>, timetable: Timetable) -> LineID {
+        self.lines.insert(Line {
+            name,
+            stops,
+            timetable,
+        })
+    }
+
+    pub fn remove_line(&mut self, id: LineID) {
+        self.lines.remove(id);
+    }
+
+    pub fn set_timetable(&mut self, id: LineID, timetable: Timetable) {
+        if let Some(line) = self.lines.get_mut(id) {
+            line.timetable = timetable;
+        }
+    }
+
+    /// Game seconds a bus of the line needs to go from the stop at index `from` to the one at `to`,
+    /// estimated from the distance between the stops
+    pub fn travel_time(&self, line: LineID, from: usize, to: usize) -> Option<f64> {
+        let stops = &self.lines.get(line)?.stops;
+        let mut t = 0.0;
+        for w in stops.get(from..=to)?.windows(2) {
+            let dist = self.stop_pos(w[0])?.distance(self.stop_pos(w[1])?);
+            t += (dist * 1.3 / VehicleKind::Bus.cruising_speed()) as f64 + DWELL_TIME;
+        }
+        Some(t)
+    }
+
+    /// Whether a bus of the line calls at the stop within `max_wait` seconds after `daysec`,
+    /// counting the buses already on their way. False if the line or the stop are gone.
+    pub fn expects_bus(&self, line: LineID, stop: StopID, daysec: i32, max_wait: i32) -> bool {
+        let l = unwrap_or!(self.lines.get(line), return false);
+        let idx = unwrap_or!(l.stops.iter().position(|&s| s == stop), return false);
+        let travel = unwrap_or!(self.travel_time(line, 0, idx), return false) as i32;
+
+        // The buses calling at the stop in the window left the first stop `travel` seconds earlier
+        let left_after = daysec - travel;
+        l.timetable.next_departure(left_after) <= left_after + max_wait
+    }
+
+    /// Best trip in service at `daysec` going from `from` to `to`, minimizing the distance walked
+    pub fn find_trip(&self, from: Vec2, to: Vec2, daysec: i32) -> Option<TransitTrip> {
+        let mut best: Option<TransitTrip> = None;
+        for (id, line) in &self.lines {
+            if daysec < line.timetable.first || daysec > line.timetable.last {
+                continue;
+            }
+            let positions: Vec<(StopID, Vec2)> = line
+                .stops
+                .iter()
+                .filter_map(|&s| Some((s, self.stop_pos(s)?)))
+                .collect();
+
+            for (i, &(board, board_pos)) in positions.iter().enumerate() {
+                let walk_to = board_pos.distance(from);
+                if walk_to > MAX_WALK_TO_STOP
+                    || !self.expects_bus(id, board, daysec, MAX_WAIT_FOR_BUS)
+                {
+                    continue;
+                }
+                for (j, &(alight, alight_pos)) in positions.iter().enumerate().skip(i + 1) {
+                    let walk = walk_to + alight_pos.distance(to);
+                    if walk > 2.0 * MAX_WALK_TO_STOP || best.map_or(false, |b| b.walk <= walk) {
+                        continue;
+                    }
+                    best = Some(TransitTrip {
+                        line: id,
+                        board,
+                        alight,
+                        board_idx: i,
+                        alight_idx: j,
+                        walk,
+                        ride: board_pos.distance(alight_pos),
+                    });
+                }
+            }
+        }
+        best
+    }
+}
+
+/// Component of the vehicles running a line
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Bus {
+    pub line: LineID,
+    /// Index in the stops of the line of the stop the bus is heading to or dwelling at
+    pub stop: usize,
+    /// Set while the bus dwells at its stop, time at which it leaves
+    pub leaves_at: Option<f64>,
+    pub passengers: u32,
+}
+
+impl Bus {
+    /// Stop the bus is dwelling at, None while it is driving
+    pub fn dwelling_at(&self, network: &TransitNetwork) -> Option<StopID> {
+        self.leaves_at?;
+        network.lines.get(self.line)?.stops.get(self.stop).copied()
+    }
+
+    /// Whether the bus reached the end of its line, everyone must get out
+    pub fn at_terminus(&self, network: &TransitNetwork) -> bool {
+        self.leaves_at.is_some()
+            && network
+                .lines
+                .get(self.line)
+                .map_or(true, |l| self.stop + 1 >= l.stops.len())
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.passengers >= BUS_CAPACITY
+    }
+}
+
+/// Edits of the transit network, recorded in replays like map commands
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum TransitCommand {
+    AddStop(Vec2),
+    RemoveStop(StopID),
+    AddLine {
+        name: String,
+        stops: Vec<StopID>,
+        timetable: Timetable,
+    },
+    RemoveLine(LineID),
+    SetTimetable(LineID, Timetable),
+}
+
+impl TransitCommand {
+    /// Applies the command to the network.
+    /// Returns the new stop for `AddStop`, None otherwise.
+    pub fn apply(&self, network: &mut TransitNetwork, map: &Map) -> Option<StopID> {
+        match *self {
+            TransitCommand::AddStop(pos) => return network.add_stop(map, pos),
+            TransitCommand::RemoveStop(id) => network.remove_stop(id),
+            TransitCommand::AddLine {
+                ref name,
+                ref stops,
+                timetable,
+            } => {
+                network.add_line(name.clone(), stops.clone(), timetable);
+            }
+            TransitCommand::RemoveLine(id) => network.remove_line(id),
+            TransitCommand::SetTimetable(id, timetable) => network.set_timetable(id, timetable),
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geom::vec2;
+
+    const H: i32 = SECONDS_PER_HOUR;
+    const TIMETABLE: Timetable = Timetable {
+        first: 6 * H,
+        last: 12 * H,
+        headway: H / 2,
+    };
+
+    #[test]
+    fn departs_every_headway_in_service() {
+        let departs = |timestamp: f64| TIMETABLE.departs(&GameTime::new(1.0, timestamp));
+        let (first, last, headway) = (6.0 * H as f64, 12.0 * H as f64, H as f64 / 2.0);
+        assert!(!departs(first - 1.0));
+        assert!(departs(first + 0.5));
+        assert!(!departs(first + 10.0));
+        assert!(departs(first + headway + 0.5));
+        assert!(departs(last));
+        assert!(!departs(last + headway + 0.5));
+        // Every day
+        assert!(departs(SECONDS_PER_DAY as f64 + first + 0.5));
+    }
+
+    #[test]
+    fn next_departure_wraps_to_the_next_day() {
+        assert_eq!(TIMETABLE.next_departure(0), 6 * H);
+        assert_eq!(TIMETABLE.next_departure(6 * H), 6 * H);
+        assert_eq!(TIMETABLE.next_departure(6 * H + 1), 6 * H + H / 2);
+        assert_eq!(TIMETABLE.next_departure(12 * H), 12 * H);
+        assert_eq!(
+            TIMETABLE.next_departure(12 * H + 1),
+            6 * H + SECONDS_PER_DAY
+        );
+    }
+
+    fn network() -> (TransitNetwork, LineID, [StopID; 3]) {
+        let mut net = TransitNetwork::default();
+        let stops = [
+            net.stops.insert(Stop {
+                pos: vec2(0.0, 0.0),
+            }),
+            net.stops.insert(Stop {
+                pos: vec2(1000.0, 0.0),
+            }),
+            net.stops.insert(Stop {
+                pos: vec2(2000.0, 0.0),
+            }),
+        ];
+        let line = net.add_line("1".to_string(), stops.to_vec(), TIMETABLE);
+        (net, line, stops)
+    }
+
+    #[test]
+    fn find_trip_follows_the_line() {
+        let (mut net, line, stops) = network();
+        let from = vec2(10.0, 0.0);
+        let to = vec2(1990.0, 0.0);
+
+        let trip = net.find_trip(from, to, 8 * H).unwrap();
+        assert_eq!(trip.line, line);
+        assert_eq!(trip.board, stops[0]);
+        assert_eq!(trip.alight, stops[2]);
+        assert_eq!((trip.board_idx, trip.alight_idx), (0, 2));
+
+        // Buses only go one way, and not at night
+        assert!(net.find_trip(to, from, 8 * H).is_none());
+        assert!(net.find_trip(from, to, H).is_none());
+
+        net.remove_line(line);
+        assert!(net.find_trip(from, to, 8 * H).is_none());
+    }
+
+    #[test]
+    fn expects_bus_counts_buses_on_their_way() {
+        let (mut net, line, stops) = network();
+
+        let wait = H / 2;
+        assert!(net.expects_bus(line, stops[0], 8 * H, wait));
+        // The last bus left the first stop at noon and needs a while to reach the last one
+        assert!(!net.expects_bus(line, stops[0], 12 * H + 10, wait));
+        assert!(net.expects_bus(line, stops[2], 12 * H + 10, wait));
+        assert!(!net.expects_bus(line, stops[2], 16 * H, wait));
+
+        net.remove_stop(stops[2]);
+        assert!(!net.expects_bus(line, stops[2], 8 * H, wait));
+        net.remove_line(line);
+        assert!(!net.expects_bus(line, stops[0], 8 * H, wait));
+    }
+}
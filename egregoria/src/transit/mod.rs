@@ -0,0 +1,4 @@
+mod data;
+pub mod systems;
+
+pub use data::*;
@@ -0,0 +1,101 @@
+use crate::map_dynamic::Itinerary;
+use crate::transit::{Bus, TransitNetwork, DWELL_TIME};
+use crate::vehicles::{make_vehicle_entity, Vehicle, VehicleKind, VehicleState};
+use crate::{Egregoria, ParCommandBuffer};
+use common::GameTime;
+use geom::{Transform, Vec2};
+use legion::{system, Entity};
+use map_model::{CarPath, Map};
+
+/// Spawns a bus at the first stop of every line with a departure during the last tick
+pub fn dispatch_buses(goria: &mut Egregoria) {
+    let time = *goria.read::<GameTime>();
+    let network = goria.read::<TransitNetwork>();
+    let departures: Vec<_> = network
+        .lines()
+        .iter()
+        .filter(|(_, line)| line.timetable.departs(&time))
+        .filter_map(|(id, line)| {
+            let first = network.stop_pos(*line.stops.first()?)?;
+            let second = network.stop_pos(*line.stops.get(1)?)?;
+            Some((id, first, second))
+        })
+        .collect();
+    drop(network);
+
+    for (line, first, second) in departures {
+        let dir = (second - first).try_normalize().unwrap_or(Vec2::UNIT_X);
+        let e = make_vehicle_entity(
+            goria,
+            Transform::new_cos_sin(first, dir),
+            Vehicle {
+                ang_velocity: 0.0,
+                lane_change_cooldown: 0.0,
+                state: VehicleState::Driving,
+                kind: VehicleKind::Bus,
+            },
+            Itinerary::none(),
+            true,
+        );
+        goria
+            .world
+            .entry(e)
+            .unwrap() // Unwrap ok: the bus was just spawned
+            .add_component(Bus {
+                line,
+                stop: 0,
+                leaves_at: Some(time.timestamp + DWELL_TIME),
+                passengers: 0,
+            });
+    }
+}
+
+/// Makes the buses dwell at their stops then drive to the next one.
+/// Buses leave the map once their last passenger got out at the end of the line.
+#[system(for_each)]
+pub fn bus_update(
+    #[resource] network: &TransitNetwork,
+    #[resource] map: &Map,
+    #[resource] time: &GameTime,
+    #[resource] buf: &ParCommandBuffer,
+    bus: &mut Bus,
+    it: &mut Itinerary,
+    trans: &Transform,
+    ent: &Entity,
+) {
+    let n_stops = network.lines().get(bus.line).map_or(0, |l| l.stops.len());
+
+    let leaves_at = match bus.leaves_at {
+        Some(x) => x,
+        None => {
+            if it.has_ended(time.timestamp) {
+                bus.leaves_at = Some(time.timestamp + DWELL_TIME);
+            }
+            return;
+        }
+    };
+
+    if time.timestamp < leaves_at {
+        return;
+    }
+
+    if bus.stop + 1 >= n_stops {
+        if bus.passengers == 0 {
+            buf.kill(*ent);
+        }
+        return;
+    }
+
+    // Stops that can't be reached (the roads changed) are skipped
+    while bus.stop + 1 < n_stops {
+        bus.stop += 1;
+        let stop = network.lines()[bus.line].stops[bus.stop];
+        let pos = unwrap_or!(network.stop_pos(stop), continue);
+        if let Some(route) = Itinerary::route(trans.position(), pos, map, &CarPath) {
+            *it = route;
+            bus.leaves_at = None;
+            return;
+        }
+        log::warn!("bus of {:?} couldn't reach {:?}", bus.line, stop);
+    }
+}
@@ -0,0 +1,406 @@
+use crate::api::{walk_outside, Location};
+use crate::map_dynamic::{BikeParking, BuildingInfos, Itinerary, ParkingManagement};
+use crate::pedestrians::PedestrianID;
+use crate::souls::Souls;
+use crate::vehicles::{Vehicle, VehicleState};
+use crate::Egregoria;
+use geom::Transform;
+use legion::{Entity, IntoQuery};
+use map_model::{Map, MapCommand, SerializedMap};
+use std::collections::VecDeque;
+
+/// Memory the history may use before the oldest edits are forgotten
+pub const HISTORY_BUDGET: usize = 64 * 1024 * 1024;
+
+/// Edits between two snapshots of the map, undoing replays at most that many
+pub const CHECKPOINT_INTERVAL: usize = 16;
+
+/// An undoable edit, several commands when they make a single stroke
+struct Step {
+    /// Serialized map right before the step, on every `CHECKPOINT_INTERVAL` steps
+    checkpoint: Option<Vec<u8>>,
+    commands: Vec<MapCommand>,
+}
+
+impl Step {
+    fn size(&self) -> usize {
+        self.checkpoint.as_ref().map_or(0, Vec::len)
+    }
+
+    /// Loading a whole map is too slow to be replayed, the next step snapshots the result
+    fn loads_map(&self) -> bool {
+        self.commands.iter().any(|c| {
+            matches!(
+                c,
+                MapCommand::LoadParis
+                    | MapCommand::LoadTestField
+                    | MapCommand::ImportOsm(..)
+                    | MapCommand::LoadFile(_)
+            )
+        })
+    }
+}
+
+/// Undo/redo history of the map.
+/// Stores the commands of every edit along with a serialized snapshot of the map taken every few
+/// edits. Undoing restores the last snapshot before the edit and replays the commands in between,
+/// so everything an edit destroyed (lanes, lots, buildings...) comes back with the same ids.
+/// Redoing applies the commands again.
+pub struct MapHistory {
+    steps: VecDeque<Step>,
+    /// Steps applied to the map, the following ones can be redone
+    done: usize,
+    size: usize,
+    budget: usize,
+}
+
+impl Default for MapHistory {
+    fn default() -> Self {
+        Self::with_budget(HISTORY_BUDGET)
+    }
+}
+
+impl MapHistory {
+    pub fn with_budget(budget: usize) -> Self {
+        Self {
+            steps: VecDeque::new(),
+            done: 0,
+            size: 0,
+            budget,
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.done > 0
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.done < self.steps.len()
+    }
+
+    /// Memory used by the snapshots in bytes
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Must be called right before `command` is applied to the map.
+    /// If `continues_stroke`, it is undone along with the previous command.
+    pub fn push(&mut self, map: &mut Map, command: &MapCommand, continues_stroke: bool) {
+        if continues_stroke && self.can_undo() && !self.can_redo() {
+            self.steps[self.done - 1].commands.push(command.clone());
+            return;
+        }
+
+        while self.can_redo() {
+            self.drop_back();
+        }
+
+        let since_checkpoint = self
+            .steps
+            .iter()
+            .rev()
+            .position(|s| s.checkpoint.is_some())
+            .map_or(self.steps.len(), |i| i + 1);
+
+        let mut checkpoint = None;
+        if self.steps.is_empty()
+            || since_checkpoint >= CHECKPOINT_INTERVAL
+            || self.steps.back().map_or(false, Step::loads_map)
+        {
+            // The map is replaced by its snapshot for the commands to be replayed on the same map
+            // when undoing, down to the order of the free slots
+            checkpoint = snapshot(map).filter(|x| restore(map, x));
+        }
+        if checkpoint.is_none() && self.steps.is_empty() {
+            return;
+        }
+
+        let step = Step {
+            checkpoint,
+            commands: vec![command.clone()],
+        };
+        self.size += step.size();
+        self.steps.push_back(step);
+        self.done += 1;
+        self.enforce_budget();
+    }
+
+    /// Returns false if there was nothing to undo
+    pub fn undo(&mut self, map: &mut Map) -> bool {
+        if !self.can_undo() {
+            return false;
+        }
+        let target = self.done - 1;
+        // The oldest step always has a snapshot
+        let (from, snapshot) = unwrap_or!(
+            (0..=target)
+                .rev()
+                .find_map(|i| Some((i, self.steps[i].checkpoint.as_ref()?))),
+            return false
+        );
+        if !restore(map, snapshot) {
+            return false;
+        }
+        for step in self.steps.range(from..target) {
+            for command in &step.commands {
+                command.apply(map);
+            }
+        }
+        self.done = target;
+        true
+    }
+
+    /// Returns false if there was nothing to redo
+    pub fn redo(&mut self, map: &mut Map) -> bool {
+        let step = unwrap_or!(self.steps.get(self.done), return false);
+        for command in &step.commands {
+            command.apply(map);
+        }
+        self.done += 1;
+        true
+    }
+
+    fn drop_back(&mut self) {
+        if let Some(step) = self.steps.pop_back() {
+            self.size -= step.size();
+        }
+    }
+
+    /// Forgets the oldest undos first, up to the next snapshot, then the furthest redos
+    fn enforce_budget(&mut self) {
+        while self.size > self.budget && !self.steps.is_empty() {
+            let oldest = self
+                .steps
+                .iter()
+                .skip(1)
+                .position(|s| s.checkpoint.is_some())
+                .map_or(self.steps.len(), |i| i + 1);
+            if oldest > self.done {
+                self.drop_back();
+                continue;
+            }
+            for step in self.steps.drain(..oldest) {
+                self.size -= step.size();
+            }
+            self.done -= oldest;
+        }
+    }
+}
+
+fn snapshot(map: &Map) -> Option<Vec<u8>> {
+    bincode::serialize(&SerializedMap::from(map))
+        .map_err(|e| log::error!("could not snapshot the map: {}", e))
+        .ok()
+}
+
+/// Returns false if the snapshot couldn't be read, the map is then left as it was
+fn restore(map: &mut Map, snapshot: &[u8]) -> bool {
+    match bincode::deserialize::<SerializedMap>(snapshot) {
+        Ok(x) => {
+            *map = x.into();
+            true
+        }
+        Err(e) => {
+            log::error!("could not restore the map snapshot: {}", e);
+            false
+        }
+    }
+}
+
+/// Brings the world in line with a map restored by undo or redo, as the history knows nothing
+/// of what happened in the world since the edits.
+/// What points at lanes, parking spots or buildings that don't exist anymore is dropped, then
+/// the souls whose trip relied on them plan it again from where they are.
+pub(crate) fn reconcile_world(goria: &mut Egregoria) {
+    // This also frees the spots and racks the stale trips hold
+    let mut souls = std::mem::take(&mut *goria.write::<Souls>());
+    for router in souls.routers_mut() {
+        if router.is_stale(goria) {
+            router.reset(goria);
+        }
+    }
+    *goria.write::<Souls>() = souls;
+
+    let map = goria.read::<Map>();
+
+    // Vehicles parked or parking on a removed spot take the closest free one
+    let mut moved = vec![];
+    {
+        let pm = goria.read::<ParkingManagement>();
+        for (e, vehicle, trans) in <(Entity, &Vehicle, &Transform)>::query().iter(&goria.world) {
+            let spot = match vehicle.state {
+                VehicleState::Parked(spot) | VehicleState::RoadToPark(_, _, spot) => spot,
+                _ => continue,
+            };
+            if map.parking.contains(spot) {
+                continue;
+            }
+            pm.free(spot);
+            if let Some(new_spot) = pm.reserve_near(trans.position(), &map) {
+                moved.push((*e, new_spot));
+            }
+        }
+        for spot in pm.reserved_sorted() {
+            if !map.parking.contains(spot) {
+                pm.free(spot);
+            }
+        }
+    }
+
+    goria.write::<BikeParking>().retain_existing(&map);
+    goria.write::<BuildingInfos>().retain_existing(&map);
+
+    let stale: Vec<Entity> = <(Entity, &Itinerary)>::query()
+        .iter(&goria.world)
+        .filter(|(_, it)| !it.is_valid(&map))
+        .map(|(e, _)| *e)
+        .collect();
+
+    let evicted: Vec<(PedestrianID, Transform)> = <(Entity, &Location, &Transform)>::query()
+        .iter(&goria.world)
+        .filter(
+            |(_, loc, _)| matches!(loc, Location::Building(b) if !map.buildings().contains_key(*b)),
+        )
+        .map(|(e, _, trans)| (PedestrianID(*e), *trans))
+        .collect();
+    drop(map);
+
+    for (e, new_spot) in moved {
+        if let Some(vehicle) = goria.comp_mut::<Vehicle>(e) {
+            match &mut vehicle.state {
+                VehicleState::Parked(spot) | VehicleState::RoadToPark(_, _, spot) => {
+                    *spot = new_spot
+                }
+                _ => {}
+            }
+        }
+    }
+
+    for e in stale {
+        if let Some(it) = goria.comp_mut::<Itinerary>(e) {
+            *it = Itinerary::none();
+        }
+    }
+
+    for (body, trans) in evicted {
+        walk_outside(goria, body, trans.position());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MapHistory, CHECKPOINT_INTERVAL};
+    use geom::vec2;
+    use map_model::{
+        IntersectionID, LanePatternBuilder, LotKind, Map, MapCommand, MapProject, ProjectKind,
+        SerializedMap,
+    };
+
+    /// Road going north from the ground at `x`
+    fn connection(x: f32) -> MapCommand {
+        MapCommand::MakeConnection {
+            from: MapProject {
+                pos: vec2(x, 0.0),
+                kind: ProjectKind::Ground,
+            },
+            to: MapProject {
+                pos: vec2(x, 100.0),
+                kind: ProjectKind::Ground,
+            },
+            interpoint: None,
+            pattern: LanePatternBuilder::new().build(),
+        }
+    }
+
+    fn edit(history: &mut MapHistory, map: &mut Map, command: MapCommand) {
+        history.push(map, &command, false);
+        command.apply(map);
+    }
+
+    /// Restoring a snapshot keeps the ids but not the order of iteration
+    fn inters(map: &Map) -> Vec<IntersectionID> {
+        let mut ids: Vec<IntersectionID> = map.intersections().keys().collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn undo_redo() {
+        let mut map = Map::empty();
+        let mut history = MapHistory::default();
+        assert!(!history.undo(&mut map));
+
+        edit(&mut history, &mut map, connection(0.0));
+        let ids = inters(&map);
+
+        assert!(history.undo(&mut map));
+        assert!(map.intersections().is_empty());
+        assert!(!history.can_undo());
+        assert!(history.can_redo());
+
+        assert!(history.redo(&mut map));
+        assert_eq!(inters(&map), ids);
+        assert!(history.can_undo());
+        assert!(!history.can_redo());
+
+        // A new edit forgets what could be redone
+        assert!(history.undo(&mut map));
+        edit(&mut history, &mut map, MapCommand::BuildHouses);
+        assert!(!history.can_redo());
+    }
+
+    #[test]
+    fn undo_replays_from_the_last_snapshot() {
+        let mut map = Map::empty();
+        let mut history = MapHistory::default();
+        let mut states = vec![inters(&map)];
+        let n = CHECKPOINT_INTERVAL * 2 + 3;
+        for i in 0..n {
+            edit(&mut history, &mut map, connection(i as f32 * 50.0));
+            states.push(inters(&map));
+            // Removals free slots that later edits reuse
+            if i % 3 == 2 {
+                let id = map.intersections().keys().next().unwrap();
+                edit(&mut history, &mut map, MapCommand::RemoveIntersection(id));
+                states.push(inters(&map));
+            }
+        }
+
+        // Far fewer snapshots than edits
+        let one = bincode::serialize(&SerializedMap::from(&map))
+            .unwrap()
+            .len();
+        assert!(history.size() < one * 4);
+
+        for state in states.iter().rev().skip(1) {
+            assert!(history.undo(&mut map));
+            assert_eq!(&inters(&map), state);
+        }
+        assert!(!history.can_undo());
+
+        while history.redo(&mut map) {}
+        assert_eq!(&inters(&map), states.last().unwrap());
+    }
+
+    #[test]
+    fn strokes_are_undone_at_once() {
+        let mut map = Map::empty();
+        let mut history = MapHistory::default();
+        edit(&mut history, &mut map, connection(0.0));
+        for _ in 0..3 {
+            let command = MapCommand::SetLotKind(vec![], LotKind::Residential);
+            history.push(&mut map, &command, true);
+        }
+        assert!(history.undo(&mut map));
+        assert!(!history.can_undo());
+    }
+
+    #[test]
+    fn budget_forgets_oldest() {
+        let mut map = Map::empty();
+        let mut history = MapHistory::with_budget(0);
+        edit(&mut history, &mut map, connection(0.0));
+        assert!(!history.can_undo());
+        assert_eq!(history.size(), 0);
+    }
+}
@@ -30,6 +30,11 @@ impl RandProvider {
         }
     }
 
+    /// Identifies the internal state of the generator without advancing it
+    pub fn fingerprint(&self) -> u64 {
+        self.rng.clone().next_u64()
+    }
+
     pub fn random<T>(&mut self) -> T
     where
         Standard: Distribution<T>,
@@ -1,3 +1,4 @@
+use crate::engine_interaction::RenderStats;
 use crate::utils::frame_log::FrameLog;
 use legion::systems::ParallelRunnable;
 use legion::{Resources, World};
@@ -25,13 +26,22 @@ impl SeqSchedule {
             }
 
             let elapsed = start.elapsed();
+            let name = sys.name().unwrap().to_string();
 
             let s = format!(
                 "system {} took {:.2}ms",
-                sys.name().unwrap(),
+                name,
                 elapsed.as_secs_f32() * 1000.0
             );
             res.get::<FrameLog>().unwrap().log_frame(s);
+
+            if let Some(mut stats) = res.get_mut::<RenderStats>() {
+                stats
+                    .systems
+                    .entry(name)
+                    .or_default()
+                    .add_value(elapsed.as_secs_f32());
+            }
         }
     }
 }
@@ -1,16 +1,18 @@
 use crate::engine_interaction::Selectable;
-use crate::map_dynamic::{Itinerary, ParkingManagement};
+use crate::map_dynamic::{BikeParking, Itinerary, ParkingManagement};
 use crate::physics::{Collider, CollisionWorld, Kinematics, PhysicsGroup, PhysicsObject};
 use crate::rendering::assets::{AssetID, AssetRender};
+use crate::rendering::meshrender_component::{CircleRender, MeshRender, RectRender};
+use crate::utils::rand_provider::RandProvider;
 use crate::utils::rand_world;
 use crate::Egregoria;
 use common::GameTime;
 use geom::Color;
-use geom::{Spline, Transform, Vec2};
+use geom::{vec2, Spline, Transform, Vec2};
 use imgui_inspect::InspectDragf;
 use imgui_inspect_derive::*;
 use legion::Entity;
-use map_model::{Map, ParkingSpotID};
+use map_model::{BuildingID, Map, ParkingSpotID};
 use serde::{Deserialize, Serialize};
 
 /// The duration for the parking animation.
@@ -26,6 +28,8 @@ pub enum VehicleState {
     Parked(ParkingSpotID),
     Driving,
     RoadToPark(Spline, f32, ParkingSpotID),
+    /// Bike locked at the rack in front of the building
+    Racked(BuildingID),
 }
 
 debug_inspect_impl!(VehicleState);
@@ -34,14 +38,17 @@ debug_inspect_impl!(VehicleState);
 pub enum VehicleKind {
     Car,
     Bus,
+    Truck,
+    Bike,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, Inspect)]
 pub struct Vehicle {
     #[inspect(proxy_type = "InspectDragf")]
     pub ang_velocity: f32,
+    /// Seconds before the vehicle considers changing lanes again
     #[inspect(proxy_type = "InspectDragf")]
-    pub wait_time: f32,
+    pub lane_change_cooldown: f32,
 
     pub state: VehicleState,
     pub kind: VehicleKind,
@@ -65,6 +72,8 @@ impl VehicleKind {
         match self {
             VehicleKind::Car => 4.5,
             VehicleKind::Bus => 9.0,
+            VehicleKind::Truck => 7.0,
+            VehicleKind::Bike => 1.8,
         }
     }
 
@@ -72,6 +81,8 @@ impl VehicleKind {
         match self {
             VehicleKind::Car => 3.0,
             VehicleKind::Bus => 2.0,
+            VehicleKind::Truck => 2.0,
+            VehicleKind::Bike => 1.0,
         }
     }
 
@@ -79,6 +90,38 @@ impl VehicleKind {
         match self {
             VehicleKind::Car => 9.0,
             VehicleKind::Bus => 9.0,
+            VehicleKind::Truck => 8.0,
+            VehicleKind::Bike => 4.0,
+        }
+    }
+
+    /// Braking the driver is comfortable with, `deceleration` is kept for emergencies
+    pub fn comfortable_deceleration(self) -> f32 {
+        match self {
+            VehicleKind::Car => 3.0,
+            VehicleKind::Bus => 2.0,
+            VehicleKind::Truck => 2.0,
+            VehicleKind::Bike => 2.0,
+        }
+    }
+
+    /// Seconds kept between the vehicle and its leader when following it
+    pub fn time_headway(self) -> f32 {
+        match self {
+            VehicleKind::Car => 1.2,
+            VehicleKind::Bus => 1.5,
+            VehicleKind::Truck => 1.5,
+            VehicleKind::Bike => 1.0,
+        }
+    }
+
+    /// Bumper to bumper distance kept with the leader when stopped, in meters
+    pub fn min_gap(self) -> f32 {
+        match self {
+            VehicleKind::Car => 2.0,
+            VehicleKind::Bus => 2.5,
+            VehicleKind::Truck => 2.5,
+            VehicleKind::Bike => 1.0,
         }
     }
 
@@ -86,6 +129,8 @@ impl VehicleKind {
         match self {
             VehicleKind::Car => 3.0,
             VehicleKind::Bus => 5.0,
+            VehicleKind::Truck => 4.5,
+            VehicleKind::Bike => 1.5,
         }
     }
 
@@ -93,6 +138,8 @@ impl VehicleKind {
         match self {
             VehicleKind::Car => 12.0,
             VehicleKind::Bus => 10.0,
+            VehicleKind::Truck => 11.0,
+            VehicleKind::Bike => 5.0,
         }
     }
 
@@ -100,6 +147,8 @@ impl VehicleKind {
         match self {
             VehicleKind::Car => 1.0,
             VehicleKind::Bus => 0.8,
+            VehicleKind::Truck => 0.9,
+            VehicleKind::Bike => 2.0,
         }
     }
 }
@@ -130,6 +179,73 @@ pub fn spawn_parked_vehicle(goria: &mut Egregoria, near: Vec2) -> Option<Vehicle
     )))
 }
 
+/// Spawns a bike locked at the rack of the building nearest to `near` with room left
+pub fn spawn_racked_bike(goria: &mut Egregoria, near: Vec2) -> Option<VehicleID> {
+    let map = goria.read::<Map>();
+    let building = goria.read::<BikeParking>().reserve_near(near, &map)?;
+    let pos = map.buildings()[building].door_pos;
+    drop(map);
+
+    let color = get_random_car_color(&mut goria.write::<RandProvider>());
+    let w = VehicleKind::Bike.width();
+
+    Some(VehicleID(
+        goria.world.push((
+            MeshRender::empty(0.7)
+                .add(RectRender {
+                    // Frame
+                    width: w,
+                    height: 0.15,
+                    color,
+                    ..Default::default()
+                })
+                .add(CircleRender {
+                    // Wheels
+                    offset: vec2(w * 0.35, 0.0),
+                    radius: 0.3,
+                    color: Color::BLACK,
+                })
+                .add(CircleRender {
+                    offset: vec2(-w * 0.35, 0.0),
+                    radius: 0.3,
+                    color: Color::BLACK,
+                })
+                .build(),
+            Transform::new(pos),
+            Kinematics::from_mass(15.0),
+            Selectable::default(),
+            Vehicle {
+                ang_velocity: 0.0,
+                lane_change_cooldown: 0.0,
+                state: VehicleState::Racked(building),
+                kind: VehicleKind::Bike,
+            },
+            Itinerary::none(),
+        )),
+    ))
+}
+
+/// Spawns a driving truck at `pos` following the itinerary
+pub fn spawn_truck(goria: &mut Egregoria, pos: Vec2, it: Itinerary) -> Entity {
+    let dir = it
+        .get_point()
+        .and_then(|p| (p - pos).try_normalize())
+        .unwrap_or(Vec2::UNIT_X);
+
+    make_vehicle_entity(
+        goria,
+        Transform::new_cos_sin(pos, dir),
+        Vehicle {
+            ang_velocity: 0.0,
+            lane_change_cooldown: 0.0,
+            state: VehicleState::Driving,
+            kind: VehicleKind::Truck,
+        },
+        it,
+        true,
+    )
+}
+
 pub fn make_vehicle_entity(
     goria: &mut Egregoria,
     trans: Transform,
@@ -138,12 +254,13 @@ pub fn make_vehicle_entity(
     mk_collider: bool,
 ) -> Entity {
     let w = vehicle.kind.width();
+    let tint = get_random_car_color(&mut goria.write::<RandProvider>());
     let e = goria.world.push((
         AssetRender {
             id: AssetID::CAR,
             hide: false,
             scale: w,
-            tint: get_random_car_color(),
+            tint,
             z: 0.7,
         },
         trans,
@@ -161,7 +278,7 @@ pub fn make_vehicle_entity(
     e
 }
 
-pub fn get_random_car_color() -> Color {
+pub fn get_random_car_color(rng: &mut RandProvider) -> Color {
     let car_colors: [(Color, f32); 9] = [
         (Color::from_hex(0x22_22_22), 0.22),  // Black
         (Color::from_hex(0xff_ff_ff), 0.19),  // White
@@ -176,7 +293,7 @@ pub fn get_random_car_color() -> Color {
 
     let total: f32 = car_colors.iter().map(|x| x.1).sum();
 
-    let r = rand::random::<f32>() * total;
+    let r = rng.random::<f32>() * total;
     let mut partial = 0.0;
     for (col, freq) in &car_colors {
         partial += freq;
@@ -191,7 +308,7 @@ impl Vehicle {
     pub fn new(kind: VehicleKind, spot: ParkingSpotID) -> Vehicle {
         Self {
             ang_velocity: 0.0,
-            wait_time: 0.0,
+            lane_change_cooldown: 0.0,
             state: VehicleState::Parked(spot),
             kind,
         }
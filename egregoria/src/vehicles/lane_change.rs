@@ -0,0 +1,277 @@
+use crate::map_dynamic::Itinerary;
+use crate::physics::{PhysicsGroup, PhysicsObject};
+use crate::vehicles::{Vehicle, VehicleKind};
+use geom::{Transform, Vec2};
+use map_model::{Lane, LaneID, Map, TraverseKind};
+use ordered_float::OrderedFloat;
+
+/// Seconds between two lane changes of the same vehicle, lets the previous one finish
+pub const LANE_CHANGE_COOLDOWN: f32 = 5.0;
+
+/// Bumper to bumper distance always required in the target lane, in meters
+const MIN_GAP: f32 = 2.0;
+
+/// Seconds of closing speed required as extra gap ahead and behind
+const HEADWAY_AHEAD: f32 = 1.0;
+const HEADWAY_BEHIND: f32 = 1.5;
+
+/// Leaders this far ahead don't slow the vehicle down, in meters
+const LOOKAHEAD: f32 = 30.0;
+
+/// How much faster the vehicle wants to go than its leader to overtake it, in m/s
+const SPEED_GAIN: f32 = 2.0;
+
+/// Overtaking isn't worth it this close to the end of the lane, in meters
+const MIN_REMAINING_TO_OVERTAKE: f32 = 40.0;
+
+/// Vehicles that still need to change lanes slow down to this speed near the end of the lane
+pub const LANE_CHANGE_CRAWL_SPEED: f32 = 3.0;
+
+/// Distance from the end of the lane at which vehicles that still need to change lanes crawl
+const CRAWL_DIST: f32 = 25.0;
+
+/// Distance from the end of the lane at which vehicles that still need to change lanes stop
+/// and wait for a gap
+const WAIT_DIST: f32 = 1.0;
+
+type Neighbors<'a> = [(Vec2, &'a PhysicsObject)];
+
+fn on_lane<'a, 'b>(
+    lane: &'b Lane,
+    neighs: &'b Neighbors<'a>,
+) -> impl Iterator<Item = (Vec2, &'a PhysicsObject)> + 'b {
+    neighs
+        .iter()
+        .copied()
+        .filter(move |(his_pos, _)| lane.dist2_to(*his_pos) < (lane.width * 0.5).powi(2))
+}
+
+/// Vehicle ahead on a lane, as (bumper to bumper gap, speed)
+fn leader(
+    lane: &Lane,
+    pos: Vec2,
+    dir: Vec2,
+    radius: f32,
+    neighs: &Neighbors,
+) -> Option<(f32, f32)> {
+    on_lane(lane, neighs)
+        .filter_map(|(his_pos, obj)| {
+            let along = (his_pos - pos).dot(dir);
+            if along <= 0.0 {
+                return None;
+            }
+            Some((along - radius - obj.radius, obj.speed))
+        })
+        .min_by_key(|&(gap, _)| OrderedFloat(gap))
+}
+
+/// Whether there is room on `lane` to move in next to `pos` without cutting anyone off.
+/// `urgency` between 0 and 1 shrinks the required gaps as the end of the lane gets closer.
+fn gap_accepted(
+    lane: &Lane,
+    pos: Vec2,
+    dir: Vec2,
+    me: &PhysicsObject,
+    urgency: f32,
+    neighs: &Neighbors,
+) -> bool {
+    let relax = 1.0 - urgency;
+    on_lane(lane, neighs).all(|(his_pos, obj)| {
+        let along = (his_pos - pos).dot(dir);
+        let gap = along.abs() - me.radius - obj.radius;
+        let required = if along >= 0.0 {
+            MIN_GAP + relax * HEADWAY_AHEAD * (me.speed - obj.speed).max(0.0)
+        } else {
+            MIN_GAP + relax * HEADWAY_BEHIND * (obj.speed - me.speed).max(0.0)
+        };
+        gap >= required
+    })
+}
+
+/// Length left on the current lane, which is what remains of the local path
+pub(super) fn remaining_on_lane(it: &Itinerary, pos: Vec2) -> f32 {
+    let path = it.local_path();
+    let first = path.first().map_or(0.0, |p| p.distance(pos));
+    first + path.windows(2).map(|w| w[0].distance(w[1])).sum::<f32>()
+}
+
+/// Changes lanes when the route needs it (to reach the lane of the next turn) or when an
+/// adjacent lane lets the vehicle overtake a slower leader.
+/// Returns the speed the vehicle shouldn't exceed while it waits for a gap.
+pub fn lane_change_decision(
+    vehicle: &mut Vehicle,
+    map: &Map,
+    it: &mut Itinerary,
+    trans: &Transform,
+    me: &PhysicsObject,
+    neighs: &Neighbors,
+) -> Option<f32> {
+    if matches!(vehicle.kind, VehicleKind::Bike) || vehicle.lane_change_cooldown > 0.0 {
+        return None;
+    }
+
+    let cur = match it.get_travers()?.kind {
+        TraverseKind::Lane(id) => id,
+        TraverseKind::Turn(_) => return None,
+    };
+
+    let pos = trans.position();
+    let dir = trans.direction();
+    let vehicles: Vec<(Vec2, &PhysicsObject)> = neighs
+        .iter()
+        .copied()
+        .filter(|(_, obj)| !std::ptr::eq(*obj, me) && matches!(obj.group, PhysicsGroup::Vehicles))
+        .collect();
+    let remaining = remaining_on_lane(it, pos);
+
+    if let Some(to) = it.lane_change_target() {
+        let lane = map.lanes().get(to)?;
+        let urgency = (1.0 - remaining / (CRAWL_DIST * 2.0)).max(0.0).min(1.0);
+        if gap_accepted(lane, pos, dir, me, urgency, &vehicles)
+            && it.change_lane(map, to, pos, dir, me.speed)
+        {
+            vehicle.lane_change_cooldown = LANE_CHANGE_COOLDOWN;
+            return None;
+        }
+        if remaining < CRAWL_DIST {
+            return Some(LANE_CHANGE_CRAWL_SPEED.min(remaining - WAIT_DIST).max(0.0));
+        }
+        return None;
+    }
+
+    if remaining < MIN_REMAINING_TO_OVERTAKE {
+        return None;
+    }
+
+    let lanes = map.lanes();
+    let cur_lane = lanes.get(cur)?;
+    let (gap, leader_speed) = leader(cur_lane, pos, dir, me.radius, &vehicles)?;
+    if gap > LOOKAHEAD || leader_speed + SPEED_GAIN > vehicle.kind.cruising_speed() {
+        return None;
+    }
+
+    let candidates: Vec<LaneID> = map
+        .roads()
+        .get(cur_lane.parent)?
+        .adjacent_driving_lanes(cur)
+        .collect();
+    for to in candidates {
+        let lane = unwrap_or!(lanes.get(to), continue);
+        let better = match leader(lane, pos, dir, me.radius, &vehicles) {
+            Some((other_gap, other_speed)) => {
+                other_gap > LOOKAHEAD || (other_gap > gap + 10.0 && other_speed > leader_speed)
+            }
+            None => true,
+        };
+        if better
+            && gap_accepted(lane, pos, dir, me, 0.0, &vehicles)
+            && it.change_lane(map, to, pos, dir, me.speed)
+        {
+            vehicle.lane_change_cooldown = LANE_CHANGE_COOLDOWN;
+            return None;
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::gap_accepted;
+    use crate::physics::{PhysicsGroup, PhysicsObject};
+    use geom::{vec2, Vec2};
+    use map_model::{Lane, LaneKind, LanePatternBuilder, Map, RoadSegmentKind};
+
+    const RADIUS: f32 = 2.25;
+
+    fn car(speed: f32) -> PhysicsObject {
+        PhysicsObject {
+            speed,
+            radius: RADIUS,
+            group: PhysicsGroup::Vehicles,
+            ..Default::default()
+        }
+    }
+
+    /// A straight road going east with a driving lane each way
+    fn road() -> Map {
+        let mut map = Map::empty();
+        let src = map.add_intersection(vec2(0.0, 0.0));
+        let dst = map.add_intersection(vec2(300.0, 0.0));
+        let pattern = LanePatternBuilder::new().build();
+        map.connect(src, dst, &pattern, RoadSegmentKind::Straight);
+        map
+    }
+
+    fn eastbound(map: &Map) -> &Lane {
+        map.lanes()
+            .values()
+            .find(|l| matches!(l.kind, LaneKind::Driving) && l.points.first().x < 150.0)
+            .expect("no eastbound lane")
+    }
+
+    /// Position on `lane` at `x` meters east, along with the direction of travel
+    fn at(lane: &Lane, x: f32) -> (Vec2, Vec2) {
+        let dir = vec2(1.0, 0.0);
+        (lane.points.first() + dir * x, dir)
+    }
+
+    /// Whether a vehicle going at `speed` next to the middle of the lane can move in, with another
+    /// vehicle `gap` meters ahead (behind if negative) going at `other_speed`
+    fn accepted(speed: f32, gap: f32, other_speed: f32, urgency: f32) -> bool {
+        let map = road();
+        let lane = eastbound(&map);
+        let (pos, dir) = at(lane, 150.0);
+        let along = gap + gap.signum() * 2.0 * RADIUS;
+        let other = car(other_speed);
+        let neighs = [(pos + dir * along, &other)];
+        gap_accepted(lane, pos, dir, &car(speed), urgency, &neighs)
+    }
+
+    #[test]
+    fn empty_lane_is_accepted() {
+        let map = road();
+        let lane = eastbound(&map);
+        let (pos, dir) = at(lane, 150.0);
+        assert!(gap_accepted(lane, pos, dir, &car(10.0), 0.0, &[]));
+
+        // Vehicles on the other lane don't count
+        let other = car(10.0);
+        let neighs = [(pos + vec2(0.0, 20.0), &other)];
+        assert!(gap_accepted(lane, pos, dir, &car(10.0), 0.0, &neighs));
+    }
+
+    #[test]
+    fn side_by_side_is_never_accepted() {
+        assert!(!accepted(10.0, 0.0, 10.0, 0.0));
+        assert!(!accepted(10.0, 0.0, 10.0, 1.0));
+        assert!(!accepted(10.0, 1.0, 10.0, 1.0));
+    }
+
+    #[test]
+    fn slower_leader_needs_headway() {
+        // 2 meters plus a second of closing speed
+        assert!(!accepted(10.0, 6.0, 0.0, 0.0));
+        assert!(accepted(10.0, 13.0, 0.0, 0.0));
+        // A leader going as fast only needs the minimum gap
+        assert!(accepted(10.0, 3.0, 10.0, 0.0));
+    }
+
+    #[test]
+    fn faster_follower_needs_headway() {
+        // 2 meters plus a second and a half of closing speed
+        assert!(!accepted(5.0, -8.0, 15.0, 0.0));
+        assert!(accepted(5.0, -18.0, 15.0, 0.0));
+        // A slower follower only needs the minimum gap
+        assert!(accepted(15.0, -3.0, 5.0, 0.0));
+    }
+
+    #[test]
+    fn urgency_shrinks_gaps() {
+        assert!(!accepted(10.0, 6.0, 0.0, 0.5));
+        assert!(accepted(10.0, 6.0, 0.0, 1.0));
+        assert!(!accepted(5.0, -8.0, 15.0, 0.5));
+        assert!(accepted(5.0, -8.0, 15.0, 1.0));
+        // But never below the minimum gap
+        assert!(!accepted(10.0, 1.0, 0.0, 1.0));
+    }
+}
@@ -1,4 +1,7 @@
 mod data;
+mod lane_change;
+mod reservations;
 pub mod systems;
 
 pub use data::*;
+pub use reservations::*;
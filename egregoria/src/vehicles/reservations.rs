@@ -0,0 +1,119 @@
+use crate::map_dynamic::Itinerary;
+use crate::physics::Kinematics;
+use crate::vehicles::lane_change::remaining_on_lane;
+use crate::vehicles::{Vehicle, VehicleState};
+use common::GameTime;
+use geom::Transform;
+use legion::world::SubWorld;
+use legion::{system, IntoQuery};
+use map_model::{LaneID, Map, TraverseKind, TurnID};
+use std::collections::{HashMap, HashSet};
+
+/// Vehicles closer than this to the end of their lane announce the turn they are about to take,
+/// in meters
+pub const APPROACH_DIST: f32 = 50.0;
+
+/// Vehicles slower than this aren't arriving anywhere soon, in m/s
+pub const STOPPED_SPEED: f32 = 0.5;
+
+/// Length at the start of a lane that must be free to enter it without blocking the
+/// intersection, in meters
+const ENTRY_ROOM: f32 = 8.0;
+
+/// Vehicle taking a turn or about to
+#[derive(Copy, Clone, Debug)]
+pub struct TurnUser {
+    /// Already in the turn
+    pub inside: bool,
+    /// Distance travelled along the turn, negative before entering it
+    pub along: f32,
+    /// Seconds before it reaches the turn
+    pub eta: f32,
+    pub speed: f32,
+    /// Neither its light nor the signal plan hold it
+    pub may_enter: bool,
+}
+
+/// Who is using or about to use every turn, rebuilt every tick.
+/// Vehicles check the users of the turns conflicting with theirs before entering it.
+#[derive(Default)]
+pub struct TurnReservations {
+    users: HashMap<TurnID, Vec<TurnUser>>,
+    /// Lanes whose start is taken by stopped vehicles
+    blocked_entries: HashSet<LaneID>,
+}
+
+impl TurnReservations {
+    pub fn users(&self, turn: TurnID) -> &[TurnUser] {
+        self.users.get(&turn).map_or(&[], |v| v.as_slice())
+    }
+
+    /// Entering the lane now would leave the vehicle stuck in the intersection
+    pub fn entry_blocked(&self, lane: LaneID) -> bool {
+        self.blocked_entries.contains(&lane)
+    }
+}
+
+#[system]
+#[read_component(Vehicle)]
+#[read_component(Itinerary)]
+#[read_component(Transform)]
+#[read_component(Kinematics)]
+pub fn turn_reservations(
+    #[resource] map: &Map,
+    #[resource] time: &GameTime,
+    #[resource] res: &mut TurnReservations,
+    sw: &SubWorld,
+) {
+    res.users.clear();
+    res.blocked_entries.clear();
+
+    for (vehicle, it, trans, kin) in
+        <(&Vehicle, &Itinerary, &Transform, &Kinematics)>::query().iter(sw)
+    {
+        if !matches!(vehicle.state, VehicleState::Driving) {
+            continue;
+        }
+        let pos = trans.position();
+        let speed = kin.velocity.magnitude();
+
+        match it.get_travers().map(|t| t.kind) {
+            Some(TraverseKind::Turn(id)) => {
+                let turn = unwrap_or!(
+                    map.intersections()
+                        .get(id.parent)
+                        .and_then(|inter| inter.find_turn(id)),
+                    continue
+                );
+                res.users.entry(id).or_default().push(TurnUser {
+                    inside: true,
+                    along: turn.points.distance_along(turn.points.project(pos)),
+                    eta: 0.0,
+                    speed,
+                    may_enter: true,
+                });
+            }
+            Some(TraverseKind::Lane(id)) => {
+                let lane = unwrap_or!(map.lanes().get(id), continue);
+                let remaining = remaining_on_lane(it, pos);
+                if speed < STOPPED_SPEED && lane.length - remaining < ENTRY_ROOM {
+                    res.blocked_entries.insert(id);
+                }
+
+                let turn = unwrap_or!(it.next_turn(), continue);
+                if remaining > APPROACH_DIST {
+                    continue;
+                }
+                res.users.entry(turn).or_default().push(TurnUser {
+                    inside: false,
+                    along: -remaining,
+                    eta: remaining / speed.max(1.0),
+                    speed,
+                    may_enter: !lane.control.get_behavior(time.seconds).is_red()
+                        && map.turn_allowed(turn, time.seconds),
+                });
+            }
+            None => {}
+        }
+    }
+}
@@ -1,8 +1,14 @@
-use crate::map_dynamic::{Itinerary, ParkingManagement, OBJECTIVE_OK_DIST};
+use crate::map_dynamic::{
+    BikeParking, Itinerary, ItineraryKind, ParkingManagement, OBJECTIVE_OK_DIST,
+};
 use crate::physics::Kinematics;
 use crate::physics::{Collider, CollisionWorld, PhysicsGroup, PhysicsObject};
 use crate::utils::Restrict;
-use crate::vehicles::{Vehicle, VehicleState, TIME_TO_PARK};
+use crate::vehicles::lane_change::{lane_change_decision, remaining_on_lane};
+use crate::vehicles::{
+    TurnReservations, Vehicle, VehicleKind, VehicleState, APPROACH_DIST, STOPPED_SPEED,
+    TIME_TO_PARK,
+};
 use crate::{Deleted, ParCommandBuffer};
 use common::GameTime;
 use geom::Transform;
@@ -10,16 +16,19 @@ use geom::{angle_lerp, Vec2};
 use geom::{both_dist_to_inter, Ray};
 use legion::system;
 use legion::Entity;
-use map_model::{Map, TrafficBehavior, Traversable, TraverseKind};
+use map_model::{ConflictKind, Map, TrafficBehavior, TraverseKind};
 
 #[system]
 pub fn vehicle_cleanup(
     #[resource] evts: &mut Deleted<Vehicle>,
     #[resource] pm: &mut ParkingManagement,
+    #[resource] bikes: &BikeParking,
 ) {
     for comp in evts.drain() {
-        if let VehicleState::Parked(id) | VehicleState::RoadToPark(_, _, id) = comp.state {
-            pm.free(id)
+        match comp.state {
+            VehicleState::Parked(id) | VehicleState::RoadToPark(_, _, id) => pm.free(id),
+            VehicleState::Racked(building) => bikes.free(building),
+            VehicleState::Driving => {}
         }
     }
 }
@@ -29,6 +38,7 @@ pub fn vehicle_decision(
     #[resource] map: &Map,
     #[resource] time: &GameTime,
     #[resource] cow: &CollisionWorld,
+    #[resource] reservations: &TurnReservations,
     it: &mut Itinerary,
     trans: &mut Transform,
     kin: &mut Kinematics,
@@ -36,13 +46,26 @@ pub fn vehicle_decision(
     collider: &Collider,
 ) {
     let (_, self_obj) = cow.get(collider.0).expect("Handle not in collision world");
-    let danger_length = (self_obj.speed.powi(2) / (2.0 * vehicle.kind.deceleration())).min(40.0);
-    let neighbors = cow.query_around(trans.position(), 12.0 + danger_length);
-    let objs =
-        neighbors.map(|(id, pos)| (pos, cow.get(id).expect("Handle not in collision world").1));
+    let neighbors = cow.query_around(trans.position(), lookahead(vehicle.kind, self_obj.speed));
+    let objs: Vec<(Vec2, &PhysicsObject)> = neighbors
+        .map(|(id, pos)| (pos, cow.get(id).expect("Handle not in collision world").1))
+        .collect();
 
-    let (desired_speed, desired_dir) =
-        calc_decision(vehicle, &map, &time, trans, self_obj, it, objs);
+    vehicle.lane_change_cooldown = (vehicle.lane_change_cooldown - time.delta).max(0.0);
+    let speed_cap =
+        lane_change_decision(vehicle, &map, it, trans, self_obj, &objs).unwrap_or(f32::INFINITY);
+
+    let (desired_speed, desired_dir) = calc_decision(
+        vehicle,
+        &map,
+        &time,
+        reservations,
+        trans,
+        self_obj,
+        it,
+        &objs,
+        speed_cap,
+    );
 
     physics(
         trans,
@@ -99,6 +122,7 @@ fn physics(
             trans.set_direction(spline.derivative(t).normalize());
             return;
         }
+        VehicleState::Racked(_) => return,
         VehicleState::Driving => {}
     }
 
@@ -131,172 +155,389 @@ fn physics(
     kin.velocity = trans.direction() * speed;
 }
 
+/// Meters ahead along its route a vehicle looks for leaders, enough to brake comfortably
+pub fn lookahead(kind: VehicleKind, speed: f32) -> f32 {
+    let braking = speed * speed / (2.0 * kind.comfortable_deceleration());
+    (kind.min_gap() + speed * kind.time_headway() + braking) * 1.5 + 20.0
+}
+
+/// Acceleration given by the Intelligent Driver Model to a vehicle going at `speed` wishing to go
+/// at `desired_speed`, following a leader as (bumper to bumper gap, speed) if any.
+pub fn idm_acceleration(
+    kind: VehicleKind,
+    speed: f32,
+    desired_speed: f32,
+    leader: Option<(f32, f32)>,
+) -> f32 {
+    let a = kind.acceleration();
+    let b = kind.comfortable_deceleration();
+
+    let free_road = 1.0 - (speed / desired_speed.max(0.1)).powi(4);
+    let interaction = leader.map_or(0.0, |(gap, leader_speed)| {
+        let closing = speed * (speed - leader_speed) / (2.0 * (a * b).sqrt());
+        let desired_gap = kind.min_gap() + (speed * kind.time_headway() + closing).max(0.0);
+        (desired_gap / gap.max(0.1)).powi(2)
+    });
+
+    a * (free_road - interaction)
+}
+
+/// Half the width of the path ahead of a vehicle, anything closer to it is in the way
+const PATH_HALF_WIDTH: f32 = 3.0;
+
+/// Extra room left to pedestrians, in meters
+const PEDESTRIAN_MARGIN: f32 = 1.0;
+
+/// Seconds a vehicle giving way needs between the arrival of the vehicle with priority and its own
+const CRITICAL_GAP: f32 = 3.0;
+
+/// Distance past the conflict point a vehicle inside the intersection must have driven before
+/// the conflicting turns are free, in meters
+const CONFLICT_CLEARANCE: f32 = 3.0;
+
+/// Average speed of vehicles joining the ring of a roundabout from its entry, in m/s
+const RING_ENTRY_SPEED: f32 = 5.0;
+
+/// Projection of `p` on the path, as (distance along the path, distance to the path, direction of
+/// the path there). None if `p` is behind the start of the path.
+fn project_on_path(path: &[Vec2], p: Vec2) -> Option<(f32, f32, Vec2)> {
+    let mut along = 0.0;
+    let mut best: Option<(f32, f32, Vec2)> = None;
+    for (i, w) in path.windows(2).enumerate() {
+        let (dir, length) = unwrap_or!((w[1] - w[0]).dir_dist(), continue);
+        let t = (p - w[0]).dot(dir);
+        if i == 0 && t < 0.0 {
+            return None;
+        }
+        let t = t.restrict(0.0, length);
+        let lateral = (w[0] + dir * t).distance(p);
+        if best.map_or(true, |(_, l, _)| lateral < l) {
+            best = Some((along + t, lateral, dir));
+        }
+        along += length;
+    }
+    best
+}
+
+/// Obstacle the vehicle must stop in front of at the end of its lane because of its traffic
+/// control, as a stopped leader
+fn traffic_control_obstacle(
+    kind: VehicleKind,
+    map: &Map,
+    time: &GameTime,
+    it: &Itinerary,
+    position: Vec2,
+    speed: f32,
+) -> Option<(f32, f32)> {
+    let r = match it.kind() {
+        ItineraryKind::Route(r) if !r.reversed_route.is_empty() => r,
+        _ => return None,
+    };
+    let lane = match r.cur.kind {
+        TraverseKind::Lane(id) => map.lanes().get(id)?,
+        TraverseKind::Turn(_) => return None,
+    };
+
+    let to_light = remaining_on_lane(it, position);
+    let gap = to_light - stop_line(kind);
+
+    let turn_blocked = it
+        .next_turn()
+        .map_or(false, |turn| !map.turn_allowed(turn, time.seconds));
+
+    match lane.control.get_behavior(time.seconds) {
+        TrafficBehavior::RED => Some((gap, 0.0)),
+        // The signal plan holds the turn even though the lane has its light
+        _ if turn_blocked => Some((gap, 0.0)),
+        // Go through if it is too late to stop comfortably
+        TrafficBehavior::ORANGE
+            if gap > speed * speed / (2.0 * kind.comfortable_deceleration()) =>
+        {
+            Some((gap, 0.0))
+        }
+        // Stop close enough to the intersection for the itinerary to move on to the turn
+        TrafficBehavior::STOP => Some((to_light - OBJECTIVE_OK_DIST * 0.9 + kind.min_gap(), 0.0)),
+        _ => None,
+    }
+}
+
+/// Distance from the end of the lane vehicles stop at, far enough not to enter the turn
+fn stop_line(kind: VehicleKind) -> f32 {
+    OBJECTIVE_OK_DIST * 1.05 + (kind.width() * 0.5 - OBJECTIVE_OK_DIST).max(0.0)
+}
+
+/// Obstacle at the stop line when the vehicle must wait before entering its next turn: a
+/// vehicle is still in a conflicting turn, one with priority is about to take one, or the
+/// destination lane has no room left.
+fn yield_obstacle(
+    kind: VehicleKind,
+    map: &Map,
+    reservations: &TurnReservations,
+    it: &Itinerary,
+    position: Vec2,
+    speed: f32,
+) -> Option<(f32, f32)> {
+    if !it.get_travers()?.kind.is_lane() {
+        return None;
+    }
+    let turn_id = it.next_turn()?;
+    let to_light = remaining_on_lane(it, position);
+    if to_light > APPROACH_DIST {
+        return None;
+    }
+    let turn = map
+        .intersections()
+        .get(turn_id.parent)?
+        .find_turn(turn_id)?;
+
+    let gap = to_light - stop_line(kind);
+    let can_stop = gap > speed * speed / (2.0 * kind.comfortable_deceleration());
+    let my_eta = to_light / speed.max(1.0);
+
+    // Don't block the intersection
+    if can_stop && reservations.entry_blocked(turn_id.dst) {
+        return Some((gap, 0.0));
+    }
+
+    for conflict in &turn.conflicts {
+        for user in reservations.users(conflict.other) {
+            let wait = if conflict.kind == ConflictKind::Entering {
+                // Only the vehicles already on the ring matter, the others yield too
+                let my_time = (to_light + conflict.dist) / speed.max(RING_ENTRY_SPEED);
+                conflict.yields
+                    && user.inside
+                    && user.along < conflict.other_dist + CONFLICT_CLEARANCE
+                    && (conflict.other_dist - user.along) / user.speed.max(1.0)
+                        < my_time + CRITICAL_GAP
+            } else if user.inside {
+                user.along < conflict.other_dist + CONFLICT_CLEARANCE
+            } else {
+                // Vehicles with priority that are stopped wait for someone else, going
+                // is what gets the intersection moving again
+                can_stop
+                    && conflict.yields
+                    && user.may_enter
+                    && user.speed > STOPPED_SPEED
+                    && user.eta < my_eta + CRITICAL_GAP
+            };
+            if wait {
+                return Some((gap, 0.0));
+            }
+        }
+    }
+    None
+}
+
 /// Decide the appropriate velocity and direction to aim for.
-pub fn calc_decision<'a>(
-    vehicle: &mut Vehicle,
+/// The speed follows the Intelligent Driver Model, the leaders being the vehicles and pedestrians
+/// on the path ahead, red lights, conflicting turns at the next intersection and the end of the
+/// itinerary. Elsewhere crossing vehicles reaching the conflict point first are leaders too.
+pub fn calc_decision(
+    vehicle: &Vehicle,
     map: &Map,
     time: &GameTime,
+    reservations: &TurnReservations,
     trans: &Transform,
     self_obj: &PhysicsObject,
     it: &Itinerary,
-    neighs: impl Iterator<Item = (Vec2, &'a PhysicsObject)>,
+    neighs: &[(Vec2, &PhysicsObject)],
+    speed_cap: f32,
 ) -> (f32, Vec2) {
     let default_return = (0.0, self_obj.dir);
-    if vehicle.wait_time > 0.0 {
-        vehicle.wait_time -= time.delta;
-        return default_return;
-    }
     let objective: Vec2 = unwrap_or!(it.get_point(), return default_return);
 
-    let terminal_pos = it.get_terminal();
-
-    let front_dist = calc_front_dist(vehicle, trans, self_obj, it, neighs);
-
     let position = trans.position();
+    let direction = trans.direction();
     let speed = self_obj.speed;
-    if speed.abs() < 0.2 && front_dist < 1.5 {
-        vehicle.wait_time = (position.x * 1000.0).fract().abs() * 0.5;
-        return default_return;
-    }
+    let kind = vehicle.kind;
 
     let dir_to_pos = unwrap_or!(
         (objective - position).try_normalize(),
         return default_return
     );
 
-    let time_to_stop = speed / vehicle.kind.deceleration();
-    let stop_dist = time_to_stop * speed * 0.5;
-
-    if let Some(pos) = terminal_pos {
-        if pos.is_close(trans.position(), 1.0 + stop_dist) {
-            return (0.0, dir_to_pos);
-        }
-    }
+    let lane_limit = match it.get_travers().map(|t| t.kind) {
+        Some(TraverseKind::Lane(id)) => map.lanes().get(id).map(|l| l.speed_limit),
+        Some(TraverseKind::Turn(id)) => map.lanes().get(id.dst).map(|l| l.speed_limit),
+        None => None,
+    };
+    let mut desired_speed = kind
+        .cruising_speed()
+        .min(lane_limit.unwrap_or(f32::INFINITY))
+        .min(speed_cap);
 
-    if let Some(Traversable {
-        kind: TraverseKind::Lane(l_id),
-        ..
-    }) = it.get_travers()
-    {
-        if let Some(l) = map.lanes().get(*l_id) {
-            let light = l.control_point();
-            match l.control.get_behavior(time.seconds) {
-                TrafficBehavior::RED | TrafficBehavior::ORANGE => {
-                    if light.is_close(
-                        position,
-                        OBJECTIVE_OK_DIST * 1.05
-                            + 2.0
-                            + stop_dist
-                            + (vehicle.kind.width() * 0.5 - OBJECTIVE_OK_DIST).max(0.0),
-                    ) {
-                        return (0.0, dir_to_pos);
-                    }
-                }
-                TrafficBehavior::STOP => {
-                    if light.is_close(position, OBJECTIVE_OK_DIST * 0.95 + stop_dist) {
-                        return (0.0, dir_to_pos);
-                    }
-                }
-                _ => {}
-            }
-        }
+    // Not facing the objective
+    if dir_to_pos.dot(direction) < 0.8 {
+        desired_speed = desired_speed.min(6.0);
     }
 
-    // Stop at 80 cm of object in front
-    if front_dist < 0.8 + stop_dist {
-        return (0.0, dir_to_pos);
-    }
+    let (path, reaches_end) = it.path_ahead(map, position, lookahead(kind, speed));
+    let mut leaders: Vec<(f32, f32)> = vec![];
 
-    // Not facing the objective
-    if dir_to_pos.dot(trans.direction()) < 0.8 {
-        return (6.0, dir_to_pos);
+    if reaches_end {
+        let length: f32 = path.windows(2).map(|w| w[0].distance(w[1])).sum();
+        // Stop a meter before the end, close enough for the itinerary to end
+        leaders.push((length - 1.0 + kind.min_gap(), 0.0));
     }
 
-    (vehicle.kind.cruising_speed(), dir_to_pos)
-}
-
-/// Calculates the distance to the closest problematic object in front of the car.
-/// It can be another car or a pedestrian, or it can be a potential collision point from a
-/// car coming perpendicularly.
-fn calc_front_dist<'a>(
-    vehicle: &mut Vehicle,
-    trans: &Transform,
-    self_obj: &PhysicsObject,
-    it: &Itinerary,
-    neighs: impl Iterator<Item = (Vec2, &'a PhysicsObject)>,
-) -> f32 {
-    let position = trans.position();
-    let direction = trans.direction();
+    leaders.extend(traffic_control_obstacle(
+        kind, map, time, it, position, speed,
+    ));
+    leaders.extend(yield_obstacle(kind, map, reservations, it, position, speed));
 
-    let mut min_front_dist: f32 = 50.0;
+    // The conflict table settles who goes first at the next intersection
+    let approaching_turn = it.get_travers().map_or(false, |t| t.kind.is_lane())
+        && it.next_turn().is_some()
+        && remaining_on_lane(it, position) < APPROACH_DIST;
 
+    let my_radius = self_obj.radius;
     let my_ray = Ray {
-        from: position - direction * vehicle.kind.width() * 0.5,
+        from: position,
         dir: direction,
     };
 
-    let my_radius = self_obj.radius;
-    let speed = self_obj.speed;
-
-    let on_lane = it.get_travers().map_or(false, |t| t.kind.is_lane());
-
-    // Collision avoidance
-    for (his_pos, nei_physics_obj) in neighs {
+    for &(his_pos, obj) in neighs {
         // Ignore myself
-        if std::ptr::eq(nei_physics_obj, self_obj) {
+        if std::ptr::eq(obj, self_obj) {
             continue;
         }
 
-        let towards_vec: Vec2 = his_pos - position;
-        let (towards_dir, dist) = unwrap_or!(towards_vec.dir_dist(), continue);
-
-        // cos of angle from self to obj
-        let cos_angle = towards_dir.dot(direction);
+        let is_vehicle = matches!(obj.group, PhysicsGroup::Vehicles);
+
+        if let Some((along, lateral, path_dir)) = project_on_path(&path, his_pos) {
+            let cos_direction = obj.dir.dot(path_dir);
+            // Oncoming vehicles are on another lane
+            if lateral < PATH_HALF_WIDTH && (!is_vehicle || cos_direction > 0.0) {
+                let mut gap = along - my_radius - obj.radius;
+                let mut his_speed = 0.0;
+                if is_vehicle {
+                    his_speed = obj.speed * cos_direction;
+                } else {
+                    gap -= PEDESTRIAN_MARGIN;
+                }
+                leaders.push((gap, his_speed));
+                continue;
+            }
+        }
 
-        // Ignore things behind
-        if cos_angle < 0.0 {
+        // Crossing vehicles not on the path yet, the one reaching the conflict point last yields.
+        // Stopped ones aren't going anywhere, waiting for them could deadlock.
+        if approaching_turn
+            || !is_vehicle
+            || obj.speed < STOPPED_SPEED
+            || obj.dir.dot(direction) > 0.85
+            || (his_pos - position).dot(direction) < 0.0
+        {
             continue;
         }
 
-        let dist_to_side = towards_vec.perp_dot(direction).abs();
+        let his_ray = Ray {
+            from: his_pos,
+            dir: obj.dir,
+        };
+        let (my_dist, his_dist) = unwrap_or!(both_dist_to_inter(my_ray, his_ray), continue);
 
-        let is_vehicle = matches!(nei_physics_obj.group, PhysicsGroup::Vehicles);
+        let my_time = (my_dist - my_radius).max(0.0) / speed.max(1.0);
+        let his_time = (his_dist - obj.radius).max(0.0) / obj.speed.max(1.0);
+        if his_time < my_time {
+            leaders.push((my_dist - my_radius - obj.radius, 0.0));
+        }
+    }
 
-        let cos_direction_angle = nei_physics_obj.dir.dot(direction);
+    let acc = leaders
+        .into_iter()
+        .map(|leader| idm_acceleration(kind, speed, desired_speed, Some(leader)))
+        .fold(idm_acceleration(kind, speed, desired_speed, None), f32::min);
 
-        // front cone
-        if cos_angle > 0.85 - 0.015 * speed.min(10.0)
-            && (!is_vehicle || cos_direction_angle > 0.0)
-            && (!on_lane || dist_to_side < 3.0)
-        {
-            let mut dist_to_obj = dist - my_radius - nei_physics_obj.radius;
-            if !is_vehicle {
-                dist_to_obj -= 1.0;
-            }
-            min_front_dist = min_front_dist.min(dist_to_obj);
-            continue;
-        }
+    ((speed + acc * time.delta).max(0.0), dir_to_pos)
+}
 
-        // don't do ray checks for other things than cars
-        if !is_vehicle {
-            continue;
+#[cfg(test)]
+mod tests {
+    use super::{idm_acceleration, lookahead};
+    use crate::vehicles::VehicleKind;
+
+    const DT: f32 = 0.1;
+
+    /// Follows a leader going at `leader_speed` from `gap` meters for `seconds`, the same way
+    /// `calc_decision` and `physics` do. Returns the final (gap, speed) and the smallest gap.
+    fn follow(
+        kind: VehicleKind,
+        mut speed: f32,
+        desired_speed: f32,
+        mut gap: f32,
+        leader_speed: f32,
+        seconds: f32,
+    ) -> (f32, f32, f32) {
+        let mut min_gap = gap;
+        for _ in 0..(seconds / DT) as usize {
+            let acc = idm_acceleration(kind, speed, desired_speed, Some((gap, leader_speed)));
+            let next = (speed + acc * DT).max(0.0);
+            let next = speed + (next - speed).max(-DT * kind.deceleration());
+            gap += (leader_speed - (speed + next) * 0.5) * DT;
+            speed = next;
+            min_gap = min_gap.min(gap);
         }
+        (gap, speed, min_gap)
+    }
 
-        // closest win
-        let his_ray = Ray {
-            from: his_pos - nei_physics_obj.radius * nei_physics_obj.dir,
-            dir: nei_physics_obj.dir,
-        };
+    #[test]
+    fn free_road_converges_to_desired_speed() {
+        for &kind in &[VehicleKind::Car, VehicleKind::Bus, VehicleKind::Bike] {
+            let desired = 15.0;
+            let mut speed = 0.0;
+            for _ in 0..(120.0 / DT) as usize {
+                let acc = idm_acceleration(kind, speed, desired, None);
+                assert!(acc <= kind.acceleration());
+                speed += acc * DT;
+                assert!(speed <= desired);
+            }
+            assert!(desired - speed < 0.5, "{:?} only reached {}", kind, speed);
+        }
+    }
 
-        let (my_dist, his_dist) = unwrap_or!(both_dist_to_inter(my_ray, his_ray), continue);
+    #[test]
+    fn equilibrium_gap() {
+        let kind = VehicleKind::Car;
+        let (v, v0) = (10.0f32, 15.0f32);
+        // Zero acceleration where the leader's repulsion equals the free road term
+        let s_star = kind.min_gap() + v * kind.time_headway();
+        let s_e = s_star / (1.0 - (v / v0).powi(4)).sqrt();
+        assert!(idm_acceleration(kind, v, v0, Some((s_e, v))).abs() < 1e-3);
+        assert!(idm_acceleration(kind, v, v0, Some((s_e * 0.9, v))) < 0.0);
+        assert!(idm_acceleration(kind, v, v0, Some((s_e * 1.1, v))) > 0.0);
+
+        // Catching up with the leader settles at that gap and speed
+        let (gap, speed, _) = follow(kind, v0, v0, 60.0, v, 120.0);
+        assert!((gap - s_e).abs() < 1.0, "gap {} instead of {}", gap, s_e);
+        assert!((speed - v).abs() < 0.1);
+    }
 
-        if my_dist - speed.min(2.5) - my_radius
-            < his_dist - nei_physics_obj.speed.min(2.5) - nei_physics_obj.radius
-        {
-            continue;
+    #[test]
+    fn brakes_behind_stopped_leader() {
+        for &kind in &[VehicleKind::Car, VehicleKind::Truck, VehicleKind::Bike] {
+            let speed = 15.0;
+            let gap = lookahead(kind, speed);
+            let (gap, speed, min_gap) = follow(kind, speed, speed, gap, 0.0, 60.0);
+            assert!(speed < 0.1);
+            assert!(min_gap > 0.0, "{:?} hit the leader", kind);
+            assert!(
+                (gap - kind.min_gap()).abs() < 1.0,
+                "{:?} stopped at {}",
+                kind,
+                gap
+            );
         }
+    }
 
-        min_front_dist = min_front_dist.min(dist - my_radius - nei_physics_obj.radius - 5.0);
+    #[test]
+    fn lookahead_covers_braking() {
+        for &kind in &[VehicleKind::Car, VehicleKind::Bus, VehicleKind::Bike] {
+            for &speed in &[0.0, 5.0, 15.0, 30.0] {
+                let braking = speed * speed / (2.0 * kind.comfortable_deceleration());
+                assert!(lookahead(kind, speed) > kind.min_gap() + braking);
+            }
+            assert!(lookahead(kind, 20.0) > lookahead(kind, 10.0));
+        }
     }
-    min_front_dist
 }
@@ -1,16 +1,94 @@
+use crate::report::Report;
 use argh::FromArgs;
-use common::TimeInfo;
+use common::{SimulationClock, SECONDS_PER_DAY};
+use egregoria::replay::{Replay, ReplayPlayer};
+use egregoria::saveslots;
 use egregoria::Egregoria;
 use log::LevelFilter;
+use map_model::{Map, SignalControl};
 use std::path::Path;
+use std::time::Instant;
+
+mod report;
 
 #[derive(FromArgs)]
 #[argh(description = "\
-Egregoria's headless cli for running egregoria scenarios.\n\
-Example: goria test.lua")]
+Egregoria's headless cli for running egregoria scenarios or benchmarking saved worlds.\n\
+Example: goria test.lua\n\
+Example: goria --world saves/autosave_0 --ticks 3000 --report report.json\n\
+Example: goria --world saves/autosave_0 --days 2\n\
+Example: goria --world saves/autosave_0 --signals actuated --report actuated.json\n\
+Example: goria --replay replays/replay_1600000000.json\n\
+Example: goria export saves/autosave_0 --geojson map.geojson --svg map.svg")]
 struct Args {
+    #[argh(subcommand)]
+    command: Option<Command>,
+
     #[argh(positional)]
     scenario: Vec<String>,
+
+    /// directory of a saved world to run instead of scenarios, like saves/quicksave
+    #[argh(option)]
+    world: Option<String>,
+
+    /// number of ticks to run the saved world for (default 1000)
+    #[argh(option, default = "1000")]
+    ticks: u32,
+
+    /// fast-forward the saved world by this many in-game days instead of a number of ticks
+    #[argh(option)]
+    days: Option<f64>,
+
+    /// path of the JSON metrics report, printed to stdout if absent
+    #[argh(option)]
+    report: Option<String>,
+
+    /// run in deterministic mode with the given seed, the report then includes the state hash
+    #[argh(option)]
+    seed: Option<u64>,
+
+    /// replay file to play back until its last command, like replays/replay_1600000000.json
+    #[argh(option)]
+    replay: Option<String>,
+
+    /// times the lights of the saved world with "fixed" or "actuated" signals, to compare them
+    #[argh(option, from_str_fn(parse_signals))]
+    signals: Option<SignalControl>,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum Command {
+    Export(ExportArgs),
+}
+
+/// export the map of a saved world
+#[derive(FromArgs)]
+#[argh(subcommand, name = "export")]
+struct ExportArgs {
+    /// directory of the saved world, like saves/quicksave
+    #[argh(positional)]
+    world: String,
+
+    /// path of the GeoJSON file to write, around longitude 0, latitude 0 for maps that weren't
+    /// imported from OpenStreetMap
+    #[argh(option)]
+    geojson: Option<String>,
+
+    /// path of the SVG file to write
+    #[argh(option)]
+    svg: Option<String>,
+}
+
+fn parse_signals(s: &str) -> Result<SignalControl, String> {
+    match s {
+        "fixed" => Ok(SignalControl::FixedTime),
+        "actuated" => Ok(SignalControl::Actuated),
+        _ => Err(format!(
+            "unknown signal control {}, expected fixed or actuated",
+            s
+        )),
+    }
 }
 
 fn main() {
@@ -21,6 +99,28 @@ fn main() {
 
     let args: Args = argh::from_env();
 
+    if let Some(Command::Export(export)) = args.command {
+        run_export(export);
+        return;
+    }
+
+    if let Some(replay) = args.replay {
+        run_replay(replay.as_ref(), args.report.as_deref());
+        return;
+    }
+
+    if let Some(world) = args.world {
+        run_world(
+            world.as_ref(),
+            args.ticks,
+            args.days,
+            args.seed,
+            args.signals,
+            args.report.as_deref(),
+        );
+        return;
+    }
+
     for scenario in args.scenario {
         if let Ok(r) = std::fs::read_dir(&scenario) {
             for p in r.filter_map(|x| x.ok()) {
@@ -34,6 +134,142 @@ fn main() {
     }
 }
 
+fn run_world(
+    dir: &Path,
+    ticks: u32,
+    days: Option<f64>,
+    seed: Option<u64>,
+    signals: Option<SignalControl>,
+    report_path: Option<&str>,
+) {
+    let mut state = match seed {
+        Some(seed) => egregoria::Egregoria::init_deterministic(seed),
+        None => egregoria::Egregoria::init(),
+    };
+    if let Err(e) = egregoria::load_from_disk(&mut state, dir) {
+        log::error!("could not load the saved world: {}", e);
+        return;
+    }
+
+    if let Some(control) = signals {
+        state.write::<Map>().set_signal_control(control);
+    }
+
+    let ticks = match days {
+        Some(days) => state
+            .read::<SimulationClock>()
+            .ticks_in(days * SECONDS_PER_DAY as f64),
+        None => ticks as u64,
+    };
+
+    run_ticks(state, ticks, None, seed.is_some(), report_path);
+}
+
+fn run_export(args: ExportArgs) {
+    if args.geojson.is_none() && args.svg.is_none() {
+        log::error!("nothing to export, pass --geojson and/or --svg");
+        return;
+    }
+
+    let mut state = egregoria::Egregoria::init();
+    if let Err(e) = egregoria::load_from_disk(&mut state, args.world.as_ref()) {
+        log::error!("could not load the saved world: {}", e);
+        return;
+    }
+
+    let map = state.read::<Map>();
+    if let Some(path) = args.geojson {
+        map_model::save_geojson(&map, path);
+    }
+    if let Some(path) = args.svg {
+        map_model::save_svg(&map, path);
+    }
+}
+
+fn run_replay(path: &Path, report_path: Option<&str>) {
+    let replay = match Replay::load(path) {
+        Some(x) => x,
+        None => {
+            log::error!("could not load the replay {:?}", path);
+            return;
+        }
+    };
+
+    let mut state = egregoria::Egregoria::init_deterministic(replay.seed);
+    if let Some(start) = &replay.start {
+        if let Err(e) = saveslots::load_slot(&mut state, start) {
+            log::error!("could not load the starting slot {}: {}", start, e);
+            return;
+        }
+    }
+    if let Some(hash) = replay.initial_hash {
+        if state.state_hash() != hash {
+            match &replay.start {
+                Some(start) => log::error!(
+                    "the starting slot {} changed since the replay was recorded",
+                    start
+                ),
+                None => log::error!("the replay didn't start from an empty world nor a save slot"),
+            }
+            return;
+        }
+    }
+
+    let ticks = replay.last_tick();
+    run_ticks(
+        state,
+        ticks,
+        Some(ReplayPlayer::new(replay)),
+        true,
+        report_path,
+    );
+}
+
+fn run_ticks(
+    mut state: Egregoria,
+    ticks: u64,
+    mut player: Option<ReplayPlayer>,
+    deterministic: bool,
+    report_path: Option<&str>,
+) {
+    let mut report = Report::default();
+
+    let start = Instant::now();
+    if let Some(player) = &mut player {
+        player.apply(&mut state);
+    }
+    for _ in 0..ticks {
+        state.tick();
+        if let Some(player) = &mut player {
+            player.apply(&mut state);
+        }
+        report.record_tick(&state);
+    }
+    report.finish(&state, start.elapsed().as_secs_f32());
+    if deterministic {
+        report.state_hash = Some(state.state_hash());
+    }
+
+    let json = match serde_json::to_string_pretty(&report) {
+        Ok(x) => x,
+        Err(e) => {
+            log::error!("could not serialize report: {}", e);
+            return;
+        }
+    };
+
+    match report_path {
+        Some(path) => {
+            if let Err(e) = std::fs::write(path, json) {
+                log::error!("could not write report to {}: {}", path, e);
+                return;
+            }
+            log::info!("ran {} ticks, report written to {}", ticks, path);
+        }
+        None => println!("{}", json),
+    }
+}
+
 fn run(mut state: Egregoria, name: &Path) {
     let l = match mods::load(name) {
         Some(l) => l,
@@ -46,7 +282,7 @@ fn run(mut state: Egregoria, name: &Path) {
     mods::eval_f(&l, "Init");
 
     for i in 1..1000 {
-        step(&mut state);
+        state.tick();
 
         let v: Option<bool> = mods::call_f(&l, "Success");
         let v = match v {
@@ -64,16 +300,3 @@ fn run(mut state: Egregoria, name: &Path) {
 
     log::warn!("failure for {:?}", name);
 }
-
-const TIME_STEP: f64 = 1.0 / 30.0;
-
-fn step(state: &mut Egregoria) {
-    {
-        let mut time = state.write::<TimeInfo>();
-        time.delta = TIME_STEP as f32;
-        time.time_speed = 1.0;
-        time.time += TIME_STEP;
-        time.time_seconds = time.time as u64;
-    }
-    state.run();
-}
@@ -0,0 +1,224 @@
+use egregoria::api::Location;
+use egregoria::economy::{Cargo, Good, Market};
+use egregoria::engine_interaction::RenderStats;
+use egregoria::map_dynamic::{read_detector, BikeParking, ParkingManagement};
+use egregoria::mode_choice::TravelMode;
+use egregoria::pedestrians::Pedestrian;
+use egregoria::physics::{CollisionWorld, Kinematics};
+use egregoria::souls::Souls;
+use egregoria::transit::{Bus, TransitNetwork};
+use egregoria::vehicles::{Vehicle, VehicleKind, VehicleState};
+use egregoria::Egregoria;
+use legion::IntoQuery;
+use map_model::Map;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Default, Serialize)]
+pub struct AgentStats {
+    pub count: usize,
+    /// Agents that are actually out on the map (driving or walking)
+    pub active: usize,
+    /// Average speed of active agents in m/s
+    pub avg_speed: f32,
+}
+
+#[derive(Default, Serialize)]
+pub struct ParkingStats {
+    pub spots: usize,
+    pub reserved: usize,
+    pub occupancy: f32,
+    /// Bikes locked or about to be at the racks of buildings
+    pub bike_racks_used: u32,
+}
+
+#[derive(Default, Serialize)]
+pub struct MarketStats {
+    pub agents: usize,
+    pub money: i64,
+    /// Total stock of every good
+    pub goods: BTreeMap<Good, i64>,
+    pub jobs: u32,
+    pub employed: usize,
+    /// Reference price of every good at the end, in cents
+    pub prices: BTreeMap<Good, i32>,
+}
+
+#[derive(Default, Serialize)]
+pub struct FreightStats {
+    /// Trucks still on their way at the end
+    pub trucks: usize,
+    pub delivered: u64,
+    /// Roads taken by the most truck trips, busiest first
+    pub busiest_roads: Vec<(String, u32)>,
+}
+
+#[derive(Default, Serialize)]
+pub struct TransitStats {
+    pub lines: usize,
+    pub stops: usize,
+    /// Buses running at the end
+    pub buses: usize,
+    /// Pedestrians riding a bus at the end
+    pub riders: u32,
+}
+
+#[derive(Default, Serialize)]
+pub struct SignalStats {
+    /// Intersections with fixed-time and with actuated lights
+    pub fixed: usize,
+    pub actuated: usize,
+    /// Vehicles queued at the lights, averaged over the ticks
+    pub avg_queue: f32,
+    pub max_queue: u32,
+}
+
+/// Average time spent per tick, in milliseconds
+#[derive(Default, Serialize)]
+pub struct Timings {
+    pub world_update: f32,
+    pub souls_desires: f32,
+    pub souls_apply: f32,
+    pub systems: BTreeMap<String, f32>,
+}
+
+#[derive(Default, Serialize)]
+pub struct Report {
+    pub ticks: u32,
+    pub sim_seconds: f64,
+    pub wall_seconds: f32,
+    pub vehicles: AgentStats,
+    pub bikes: AgentStats,
+    pub pedestrians: AgentStats,
+    pub parking: ParkingStats,
+    pub market: MarketStats,
+    pub freight: FreightStats,
+    pub transit: TransitStats,
+    pub signals: SignalStats,
+    /// Humans by mode of their last trip
+    pub mode_shares: BTreeMap<TravelMode, usize>,
+    pub timings: Timings,
+    pub state_hash: Option<u64>,
+}
+
+impl Report {
+    /// Accumulates the timings of the last tick, call it once after every tick
+    pub fn record_tick(&mut self, goria: &Egregoria) {
+        let stats = goria.read::<RenderStats>();
+        self.ticks += 1;
+        self.timings.world_update += stats.world_update.last();
+        self.timings.souls_desires += stats.souls_desires.last();
+        self.timings.souls_apply += stats.souls_apply.last();
+        for (name, h) in &stats.systems {
+            *self.timings.systems.entry(name.clone()).or_default() += h.last();
+        }
+
+        let map = goria.read::<Map>();
+        let cow = goria.read::<CollisionWorld>();
+        let queue: u32 = map
+            .lanes()
+            .values()
+            .filter(|l| l.control.is_light())
+            .map(|l| read_detector(l, &cow).queue)
+            .sum();
+        self.signals.avg_queue += queue as f32;
+        self.signals.max_queue = self.signals.max_queue.max(queue);
+    }
+
+    /// Computes the final state metrics and turns the accumulated timings into averages
+    pub fn finish(&mut self, goria: &Egregoria, wall_seconds: f32) {
+        self.wall_seconds = wall_seconds;
+        self.sim_seconds = goria.read::<common::GameTime>().timestamp;
+
+        let to_avg_ms = |x: &mut f32, ticks: u32| *x = *x * 1000.0 / ticks.max(1) as f32;
+        to_avg_ms(&mut self.timings.world_update, self.ticks);
+        to_avg_ms(&mut self.timings.souls_desires, self.ticks);
+        to_avg_ms(&mut self.timings.souls_apply, self.ticks);
+        for v in self.timings.systems.values_mut() {
+            to_avg_ms(v, self.ticks);
+        }
+        self.signals.avg_queue /= self.ticks.max(1) as f32;
+
+        let map = goria.read::<Map>();
+        for inter in map.intersections().values() {
+            if inter.signal.is_some() {
+                self.signals.actuated += 1;
+            } else if inter.roads.iter().any(|&r| {
+                map.roads()[r]
+                    .incoming_lanes_to(inter.id)
+                    .iter()
+                    .any(|&(l, _)| map.lanes()[l].control.is_light())
+            }) {
+                self.signals.fixed += 1;
+            }
+        }
+
+        let mut speed_sum = 0.0;
+        let mut bike_speed_sum = 0.0;
+        for (v, kin) in <(&Vehicle, &Kinematics)>::query().iter(&goria.world) {
+            let (stats, sum) = match v.kind {
+                VehicleKind::Bike => (&mut self.bikes, &mut bike_speed_sum),
+                _ => (&mut self.vehicles, &mut speed_sum),
+            };
+            stats.count += 1;
+            if matches!(v.state, VehicleState::Driving) {
+                stats.active += 1;
+                *sum += kin.velocity.magnitude();
+            }
+        }
+        self.vehicles.avg_speed = speed_sum / self.vehicles.active.max(1) as f32;
+        self.bikes.avg_speed = bike_speed_sum / self.bikes.active.max(1) as f32;
+
+        let mut speed_sum = 0.0;
+        for (_, loc, kin) in <(&Pedestrian, &Location, &Kinematics)>::query().iter(&goria.world) {
+            self.pedestrians.count += 1;
+            if matches!(loc, Location::Outside) {
+                self.pedestrians.active += 1;
+                speed_sum += kin.velocity.magnitude();
+            }
+        }
+        self.pedestrians.avg_speed = speed_sum / self.pedestrians.active.max(1) as f32;
+
+        self.parking.spots = goria.read::<Map>().parking.len();
+        self.parking.reserved = goria.read::<ParkingManagement>().n_reserved();
+        self.parking.occupancy = self.parking.reserved as f32 / self.parking.spots.max(1) as f32;
+        self.parking.bike_racks_used = goria.read::<BikeParking>().n_reserved();
+
+        let market = goria.read::<Market>();
+        self.market.agents = market.agents.len();
+        self.market.jobs = market.labor.n_jobs();
+        self.market.employed = market.labor.n_employed();
+        for agent in market.agents.values() {
+            self.market.money += agent.money.0 as i64;
+            for (good, amount) in agent.goods.iter() {
+                *self.market.goods.entry(good).or_default() += amount as i64;
+            }
+        }
+        for &good in &Good::ALL {
+            self.market.prices.insert(good, market.price(good).0);
+        }
+
+        let totals = goria.read::<egregoria::economy::FreightStats>();
+        self.freight.trucks = <&Cargo>::query().iter(&goria.world).count();
+        self.freight.delivered = totals.delivered;
+        let mut roads: Vec<_> = totals.trips_per_road.iter().collect();
+        roads.sort_by_key(|(_, &trips)| std::cmp::Reverse(trips));
+        self.freight.busiest_roads = roads
+            .into_iter()
+            .take(10)
+            .map(|(road, &trips)| (format!("{:?}", road), trips))
+            .collect();
+
+        for mode in goria.read::<Souls>().routers().filter_map(|r| r.mode()) {
+            *self.mode_shares.entry(mode).or_default() += 1;
+        }
+
+        let network = goria.read::<TransitNetwork>();
+        self.transit.lines = network.lines().len();
+        self.transit.stops = network.stops().len();
+        for bus in <&Bus>::query().iter(&goria.world) {
+            self.transit.buses += 1;
+            self.transit.riders += bus.passengers;
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use crate::procgen::{load_osm, load_parismap, load_testfield, OsmConfig};
+use crate::{
+    load_map_json, BuildingID, BuildingKind, IntersectionID, LanePattern, LightPolicy, LotID,
+    LotKind, Map, MapProject, RoadID, SignalControl, SignalPlan, SignalSchedule, TurnPolicy,
+};
+use geom::{Vec2, OBB};
+use serde::{Deserialize, Serialize};
+
+/// A player edit of the map.
+/// Every edit coming from the gui goes through a command so that it can be recorded and replayed.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum MapCommand {
+    MakeConnection {
+        from: MapProject,
+        to: MapProject,
+        interpoint: Option<Vec2>,
+        pattern: LanePattern,
+    },
+    RemoveIntersection(IntersectionID),
+    RemoveRoad(RoadID),
+    RemoveBuilding(BuildingID),
+    UpdateIntersection(IntersectionID, TurnPolicy, LightPolicy),
+    SetSignalControl(SignalControl),
+    /// Replaces the lights of the intersection by the plan, back to the light policy if None
+    SetSignalPlan(IntersectionID, Option<SignalPlan>),
+    /// Plans taking over from the signal plan of the intersection at times of the day
+    SetSignalSchedule(IntersectionID, Option<SignalSchedule>),
+    /// Green wave along the intersections for vehicles going at the speed, in m/s
+    CoordinateSignals(Vec<IntersectionID>, f32),
+    SetLotKind(Vec<LotID>, LotKind),
+    BuildSpecialBuilding(RoadID, OBB, BuildingKind),
+    BuildHouses,
+    LoadParis,
+    LoadTestField,
+    ImportOsm(String, OsmConfig),
+    /// Loads a map saved in the text format
+    LoadFile(String),
+    Clear,
+}
+
+impl MapCommand {
+    /// Applies the command to the map.
+    /// Returns the end intersection of the road for `MakeConnection`, None otherwise.
+    pub fn apply(&self, map: &mut Map) -> Option<IntersectionID> {
+        match *self {
+            MapCommand::MakeConnection {
+                from,
+                to,
+                interpoint,
+                ref pattern,
+            } => return Some(map.make_connection(from, to, interpoint, pattern)),
+            MapCommand::RemoveIntersection(id) => map.remove_intersection(id),
+            MapCommand::RemoveRoad(id) => {
+                map.remove_road(id);
+            }
+            MapCommand::RemoveBuilding(id) => {
+                map.remove_building(id);
+            }
+            MapCommand::UpdateIntersection(id, turn_policy, light_policy) => map
+                .update_intersection(id, |inter| {
+                    inter.turn_policy = turn_policy;
+                    inter.light_policy = light_policy;
+                }),
+            MapCommand::SetSignalControl(control) => map.set_signal_control(control),
+            MapCommand::SetSignalPlan(id, ref plan) => map.update_intersection(id, |inter| {
+                inter.signal_plan = plan.clone();
+            }),
+            MapCommand::SetSignalSchedule(id, ref schedule) => {
+                map.update_intersection(id, |inter| {
+                    inter.signal_schedule = schedule.clone();
+                })
+            }
+            MapCommand::CoordinateSignals(ref corridor, speed) => {
+                map.coordinate_signals(corridor, speed)
+            }
+            MapCommand::SetLotKind(ref lots, kind) => {
+                for &lot in lots {
+                    map.set_lot_kind(lot, kind);
+                }
+            }
+            MapCommand::BuildSpecialBuilding(road, obb, kind) => {
+                map.build_special_building(road, obb, kind);
+            }
+            MapCommand::BuildHouses => {
+                map.build_buildings().for_each(drop);
+            }
+            MapCommand::LoadParis => {
+                map.clear();
+                load_parismap(map);
+            }
+            MapCommand::LoadTestField => {
+                map.clear();
+                load_testfield(map);
+            }
+            MapCommand::ImportOsm(ref path, ref config) => {
+                // Imported aside so that a failed import leaves the map as it was
+                let mut imported = Map::empty();
+                imported.trees = map.trees.clone();
+                match load_osm(&mut imported, path, config) {
+                    Ok(()) => *map = imported,
+                    Err(e) => error!("could not import {}: {}", path, e),
+                }
+            }
+            MapCommand::LoadFile(ref path) => {
+                if let Some(loaded) = load_map_json(path) {
+                    *map = loaded;
+                }
+            }
+            MapCommand::Clear => map.clear(),
+        }
+        None
+    }
+}
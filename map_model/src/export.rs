@@ -0,0 +1,442 @@
+use crate::procgen::GeoOrigin;
+use crate::{LaneKind, LotKind, Map, TrafficControl};
+use geom::{Color, Vec2, AABB};
+use serde_json::{json, Value};
+use slotmap::KeyData;
+use std::fmt::Write;
+use std::path::Path;
+
+fn ffi(k: impl Into<KeyData>) -> u64 {
+    k.into().as_ffi()
+}
+
+/// Converts map coordinates to WGS84 longitude/latitude
+#[derive(Copy, Clone)]
+struct Coords(GeoOrigin);
+
+impl Coords {
+    fn point(self, p: Vec2) -> Value {
+        let ll = self.0.unproject(p);
+        json!([ll.lon, ll.lat])
+    }
+
+    fn line(self, points: &[Vec2]) -> Value {
+        Value::Array(points.iter().map(|&p| self.point(p)).collect())
+    }
+
+    /// GeoJSON rings are closed
+    fn ring(self, points: &[Vec2]) -> Value {
+        let mut v: Vec<_> = points.iter().map(|&p| self.point(p)).collect();
+        if let Some(&first) = points.first() {
+            v.push(self.point(first));
+        }
+        Value::Array(v)
+    }
+}
+
+fn feature(geometry: Value, properties: Value) -> Value {
+    json!({
+        "type": "Feature",
+        "geometry": geometry,
+        "properties": properties,
+    })
+}
+
+/// Exports the map as a GeoJSON FeatureCollection.
+/// Every feature has an "object" property telling what it is (road, lane, intersection, lot, building,
+/// parking_spot or tree) along with its attributes.
+/// Map coordinates are converted to longitude/latitude around `origin`, the inverse of what
+/// the OpenStreetMap importer does.
+pub fn to_geojson(map: &Map, origin: GeoOrigin) -> Value {
+    let c = Coords(origin);
+    let mut features = vec![];
+
+    for (id, road) in map.roads() {
+        features.push(feature(
+            json!({"type": "LineString", "coordinates": c.line(road.generated_points().as_slice())}),
+            json!({
+                "object": "road",
+                "id": ffi(id),
+                "src": ffi(road.src),
+                "dst": ffi(road.dst),
+                "width": road.width,
+                "length": road.length,
+                "segment": road.segment,
+                "pattern": road.pattern(),
+            }),
+        ));
+    }
+
+    for (id, lane) in map.lanes() {
+        features.push(feature(
+            json!({"type": "LineString", "coordinates": c.line(lane.points.as_slice())}),
+            json!({
+                "object": "lane",
+                "id": ffi(id),
+                "road": ffi(lane.parent),
+                "kind": lane.kind,
+                "control": lane.control,
+                "width": lane.width,
+                "speed_limit": lane.speed_limit,
+            }),
+        ));
+    }
+
+    for (id, inter) in map.intersections() {
+        let geometry = if inter.polygon.as_slice().is_empty() {
+            json!({"type": "Point", "coordinates": c.point(inter.pos)})
+        } else {
+            json!({"type": "Polygon", "coordinates": [c.ring(inter.polygon.as_slice())]})
+        };
+        features.push(feature(
+            geometry,
+            json!({
+                "object": "intersection",
+                "id": ffi(id),
+                "turn_policy": inter.turn_policy,
+                "light_policy": inter.light_policy,
+                "signal_plan": inter.signal_plan,
+                "signal_schedule": inter.signal_schedule,
+            }),
+        ));
+    }
+
+    for (id, lot) in map.lots() {
+        features.push(feature(
+            json!({"type": "Polygon", "coordinates": [c.ring(&lot.shape.corners)]}),
+            json!({
+                "object": "lot",
+                "id": ffi(id),
+                "road": ffi(lot.parent),
+                "kind": lot.kind,
+            }),
+        ));
+    }
+
+    for (id, building) in map.buildings() {
+        let polys: Vec<Value> = building
+            .draw
+            .iter()
+            .map(|(poly, _)| json!([c.ring(poly.as_slice())]))
+            .collect();
+        features.push(feature(
+            json!({"type": "MultiPolygon", "coordinates": polys}),
+            json!({
+                "object": "building",
+                "id": ffi(id),
+                "kind": building.kind,
+                "door": c.point(building.door_pos),
+            }),
+        ));
+    }
+
+    for (id, spot) in map.parking.iter() {
+        let dir = spot.trans.direction();
+        features.push(feature(
+            json!({"type": "Point", "coordinates": c.point(spot.trans.position())}),
+            json!({
+                "object": "parking_spot",
+                "id": ffi(id),
+                "lane": ffi(spot.parent),
+                "angle": dir.y.atan2(dir.x).to_degrees(),
+            }),
+        ));
+    }
+
+    for (pos, tree) in map.trees.trees() {
+        features.push(feature(
+            json!({"type": "Point", "coordinates": c.point(pos)}),
+            json!({
+                "object": "tree",
+                "size": tree.size,
+            }),
+        ));
+    }
+
+    json!({
+        "type": "FeatureCollection",
+        "features": features,
+    })
+}
+
+/// GeoJSON is always in WGS84: maps without a geographic origin are exported around
+/// `GeoOrigin::LOCAL`, distances and shapes are kept but not the location
+pub fn save_geojson(map: &Map, path: impl AsRef<Path>) -> Option<()> {
+    let origin = map.geo_origin().unwrap_or_else(|| {
+        info!("the map has no geographic origin, exporting it around longitude 0, latitude 0");
+        GeoOrigin::LOCAL
+    });
+    common::saveload::save_json(&to_geojson(map, origin), path)
+}
+
+fn svg_color(c: Color) -> String {
+    format!(
+        "rgb({},{},{})",
+        (c.r * 255.0) as u8,
+        (c.g * 255.0) as u8,
+        (c.b * 255.0) as u8
+    )
+}
+
+fn svg_points(points: &[Vec2]) -> String {
+    let mut s = String::with_capacity(points.len() * 16);
+    for p in points {
+        let _ = write!(s, "{:.2},{:.2} ", p.x, p.y);
+    }
+    s
+}
+
+fn lane_color(kind: LaneKind) -> &'static str {
+    match kind {
+        LaneKind::Driving => "#3a3a3a",
+        LaneKind::Biking => "#3c8c3c",
+        LaneKind::Parking => "#5a5a5a",
+        LaneKind::Bus => "#8c3c3c",
+        LaneKind::Construction => "#c8a03c",
+        LaneKind::Walking => "#b4b4b4",
+    }
+}
+
+fn control_color(control: TrafficControl) -> Option<&'static str> {
+    match control {
+        TrafficControl::Always => None,
+        TrafficControl::Light(_) | TrafficControl::Signal(_) => Some("#e0c020"),
+        TrafficControl::StopSign => Some("#d02020"),
+    }
+}
+
+/// Renders the map as a standalone SVG document, in map units with y pointing up.
+pub fn to_svg(map: &Map) -> String {
+    let mut bbox: Option<AABB> = None;
+    let mut grow = |p: Vec2| {
+        bbox = Some(match bbox {
+            Some(b) => b.union(AABB::new(p, p)),
+            None => AABB::new(p, p),
+        })
+    };
+    for road in map.roads().values() {
+        road.generated_points().iter().for_each(|&p| grow(p));
+    }
+    for lot in map.lots().values() {
+        lot.shape.corners.iter().for_each(|&p| grow(p));
+    }
+    for b in map.buildings().values() {
+        b.draw
+            .iter()
+            .flat_map(|(poly, _)| poly.iter())
+            .for_each(|&p| grow(p));
+    }
+    let bbox = bbox.unwrap_or_else(AABB::zero);
+
+    const MARGIN: f32 = 20.0;
+    let (w, h) = (bbox.w() + MARGIN * 2.0, bbox.h() + MARGIN * 2.0);
+
+    let mut s = String::new();
+    let _ = writeln!(
+        s,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="{:.2} {:.2} {:.2} {:.2}">"#,
+        bbox.ll.x - MARGIN,
+        -bbox.ur.y - MARGIN,
+        w,
+        h
+    );
+    let _ = writeln!(
+        s,
+        r#"<rect x="{:.2}" y="{:.2}" width="{:.2}" height="{:.2}" fill="rgb(95,130,75)"/>"#,
+        bbox.ll.x - MARGIN,
+        -bbox.ur.y - MARGIN,
+        w,
+        h
+    );
+    s.push_str("<g transform=\"scale(1,-1)\">\n");
+
+    for lot in map.lots().values() {
+        let col = match lot.kind {
+            LotKind::Residential => common::config().lot_residential_col,
+            LotKind::Commercial => common::config().lot_commercial_col,
+        };
+        let _ = writeln!(
+            s,
+            r#"<polygon points="{}" fill="{}" fill-opacity="0.5"/>"#,
+            svg_points(&lot.shape.corners),
+            svg_color(col)
+        );
+    }
+
+    for inter in map.intersections().values() {
+        let _ = writeln!(
+            s,
+            r#"<polygon points="{}" fill="{}"/>"#,
+            svg_points(inter.polygon.as_slice()),
+            lane_color(LaneKind::Driving)
+        );
+    }
+
+    for lane in map.lanes().values() {
+        let _ = writeln!(
+            s,
+            r#"<polyline points="{}" fill="none" stroke="{}" stroke-width="{:.2}"/>"#,
+            svg_points(lane.points.as_slice()),
+            lane_color(lane.kind),
+            lane.width
+        );
+        if let Some(col) = control_color(lane.control) {
+            let end = lane.points.last();
+            let _ = writeln!(
+                s,
+                r#"<circle cx="{:.2}" cy="{:.2}" r="{:.2}" fill="{}"/>"#,
+                end.x,
+                end.y,
+                lane.width * 0.3,
+                col
+            );
+        }
+    }
+
+    for spot in map.parking.iter().map(|(_, spot)| spot) {
+        let pos = spot.trans.position();
+        let _ = writeln!(
+            s,
+            r#"<circle cx="{:.2}" cy="{:.2}" r="0.5" fill="white"/>"#,
+            pos.x, pos.y
+        );
+    }
+
+    for b in map.buildings().values() {
+        for (poly, col) in &b.draw {
+            let _ = writeln!(
+                s,
+                r#"<polygon points="{}" fill="{}"/>"#,
+                svg_points(poly.as_slice()),
+                svg_color((*col).into())
+            );
+        }
+    }
+
+    for (pos, tree) in map.trees.trees() {
+        let _ = writeln!(
+            s,
+            r#"<circle cx="{:.2}" cy="{:.2}" r="{:.2}" fill="rgb(40,90,40)"/>"#,
+            pos.x,
+            pos.y,
+            tree.size * 0.5
+        );
+    }
+
+    s.push_str("</g>\n</svg>\n");
+    s
+}
+
+pub fn save_svg(map: &Map, path: impl AsRef<Path>) -> Option<()> {
+    let path = path.as_ref();
+    if let Some(parent) = path.parent() {
+        let _ = std::fs::create_dir_all(parent);
+    }
+    std::fs::write(path, to_svg(map))
+        .map_err(|e| error!("{}: {}", path.display(), e))
+        .ok()?;
+    info!("successfully saved {}", path.display());
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::procgen::LatLon;
+
+    fn grid() -> Map {
+        let mut map = Map::empty();
+        crate::procgen::add_grid(Vec2::ZERO, &mut map, 3);
+        map.build_buildings().for_each(drop);
+        map
+    }
+
+    fn count(features: &[Value], object: &str) -> usize {
+        features
+            .iter()
+            .filter(|f| f["properties"]["object"] == object)
+            .count()
+    }
+
+    #[test]
+    fn geojson_has_every_object() {
+        let map = grid();
+        let v = to_geojson(&map, GeoOrigin::LOCAL);
+        assert_eq!(v["type"], "FeatureCollection");
+
+        let features = v["features"].as_array().unwrap();
+        assert_eq!(count(features, "road"), map.roads().len());
+        assert_eq!(count(features, "lane"), map.lanes().len());
+        assert_eq!(count(features, "intersection"), map.intersections().len());
+        assert_eq!(count(features, "lot"), map.lots().len());
+        assert_eq!(count(features, "building"), map.buildings().len());
+        assert!(!map.buildings().is_empty());
+
+        for f in features
+            .iter()
+            .filter(|f| f["properties"]["object"] == "lot")
+        {
+            let ring = f["geometry"]["coordinates"][0].as_array().unwrap();
+            assert_eq!(ring.first(), ring.last(), "rings are closed");
+        }
+    }
+
+    #[test]
+    fn geojson_coordinates_are_unprojected() {
+        let map = grid();
+        let origin = GeoOrigin {
+            center: LatLon {
+                lat: 48.85,
+                lon: 2.35,
+            },
+            scale: 1.0,
+        };
+        let v = to_geojson(&map, origin);
+
+        let (id, inter) = map
+            .intersections()
+            .iter()
+            .find(|(_, i)| !i.polygon.as_slice().is_empty())
+            .unwrap();
+        let f = v["features"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|f| {
+                f["properties"]["object"] == "intersection" && f["properties"]["id"] == ffi(id)
+            })
+            .unwrap();
+        let corner = &f["geometry"]["coordinates"][0][0];
+        let ll = LatLon {
+            lat: corner[1].as_f64().unwrap(),
+            lon: corner[0].as_f64().unwrap(),
+        };
+        let back = origin.center.project(ll, origin.scale);
+        assert!(back.distance(inter.polygon.as_slice()[0]) < 0.01);
+    }
+
+    #[test]
+    fn svg_draws_every_lane() {
+        let map = grid();
+        let svg = to_svg(&map);
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\""));
+        assert!(svg.ends_with("</svg>\n"));
+        assert_eq!(svg.matches("<polyline").count(), map.lanes().len());
+
+        let polygons: usize = map.lots().len()
+            + map.intersections().len()
+            + map
+                .buildings()
+                .values()
+                .map(|b| b.draw.len())
+                .sum::<usize>();
+        assert_eq!(svg.matches("<polygon").count(), polygons);
+    }
+
+    #[test]
+    fn svg_of_an_empty_map_is_valid() {
+        let svg = to_svg(&Map::empty());
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.ends_with("</svg>\n"));
+    }
+}
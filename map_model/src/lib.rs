@@ -36,31 +36,43 @@ pub use objects::*;
 pub mod procgen {
     mod building;
     pub mod heightmap;
+    mod osm;
     mod presets;
     mod trees;
 
     pub use building::*;
+    pub use osm::*;
     pub use presets::*;
     pub use trees::*;
 }
 
+mod commands;
+mod export;
 mod light_policy;
 mod map;
 mod pathfinding;
 mod serializing;
+mod signal_plan;
+mod signals;
 mod spatial_map;
 mod traffic_control;
 mod traversable;
+mod turn_conflicts;
 mod turn_policy;
 
 // Use self or else it would be ambiguous with "pathfinding" crate
 pub use self::pathfinding::*;
+pub use commands::*;
+pub use export::*;
 pub use light_policy::*;
 pub use map::*;
 pub use serializing::*;
+pub use signal_plan::*;
+pub use signals::*;
 pub use spatial_map::*;
 pub use traffic_control::*;
 pub use traversable::*;
+pub use turn_conflicts::*;
 pub use turn_policy::*;
 
 pub const CROSSWALK_WIDTH: f32 = 4.0;
@@ -1,4 +1,7 @@
-use crate::{Intersection, LaneID, Lanes, Roads, TrafficControl, TrafficLightSchedule};
+use crate::{
+    ActuatedSignal, Intersection, LaneID, Lanes, Roads, SignalControl, TrafficControl,
+    TrafficLightSchedule,
+};
 use imgui_inspect::{
     imgui::{im_str, Ui},
     InspectArgsDefault, InspectRenderDefault,
@@ -6,7 +9,7 @@ use imgui_inspect::{
 use rand::{Rng, SeedableRng};
 use serde::{Deserialize, Serialize};
 
-#[derive(Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum LightPolicy {
     NoLights,
     StopSigns,
@@ -21,20 +24,15 @@ impl Default for LightPolicy {
 }
 
 impl LightPolicy {
-    pub fn apply(self, inter: &Intersection, lanes: &mut Lanes, roads: &Roads) {
-        let in_road_lanes: Vec<Vec<LaneID>> = inter
-            .roads
-            .iter()
-            .map(|&x| {
-                roads[x]
-                    .incoming_lanes_to(inter.id)
-                    .iter()
-                    .filter(|(_, kind)| kind.needs_light())
-                    .map(|&(id, _)| id)
-                    .collect::<Vec<_>>()
-            })
-            .filter(|v| !v.is_empty())
-            .collect();
+    /// Sets the traffic control of the incoming lanes.
+    /// Returns the controller of the lights when they are actuated.
+    pub fn apply(
+        self,
+        inter: &Intersection,
+        lanes: &mut Lanes,
+        roads: &Roads,
+    ) -> Option<ActuatedSignal> {
+        let in_road_lanes = inter.incoming_signal_lanes(roads);
 
         for incoming_lanes in &in_road_lanes {
             for &lane in incoming_lanes {
@@ -42,26 +40,32 @@ impl LightPolicy {
             }
         }
 
+        // Entering vehicles yield to the ones on the ring instead
+        if inter.is_roundabout() {
+            return None;
+        }
+
         match self {
             LightPolicy::NoLights => {}
             LightPolicy::StopSigns => {
                 self.stop_signs(in_road_lanes, lanes);
             }
             LightPolicy::Lights => {
-                self.lights(in_road_lanes, inter, lanes);
+                return self.lights(in_road_lanes, inter, lanes);
             }
             LightPolicy::Smart => {
                 if in_road_lanes.len() <= 2 {
-                    return;
+                    return None;
                 }
 
                 if inter.turn_policy.left_turns {
-                    self.lights(in_road_lanes, inter, lanes);
+                    return self.lights(in_road_lanes, inter, lanes);
                 } else {
                     self.stop_signs(in_road_lanes, lanes);
                 }
             }
         }
+        None
     }
 
     fn stop_signs(self, in_road_lanes: Vec<Vec<LaneID>>, lanes: &mut Lanes) {
@@ -72,8 +76,29 @@ impl LightPolicy {
         }
     }
 
-    fn lights(self, in_road_lanes: Vec<Vec<LaneID>>, inter: &Intersection, lanes: &mut Lanes) {
+    fn lights(
+        self,
+        in_road_lanes: Vec<Vec<LaneID>>,
+        inter: &Intersection,
+        lanes: &mut Lanes,
+    ) -> Option<ActuatedSignal> {
         let n_cycles = (in_road_lanes.len() + 1) / 2;
+
+        if inter.signal_control == SignalControl::Actuated {
+            // Opposite roads share a phase like the fixed-time lights
+            let mut phases = vec![vec![]; n_cycles];
+            for (i, incoming_lanes) in in_road_lanes.into_iter().enumerate() {
+                phases[i % n_cycles].extend(incoming_lanes);
+            }
+            let signal = ActuatedSignal::new(phases);
+            for phase in &signal.phases {
+                for &lane in phase {
+                    lanes[lane].control = TrafficControl::Signal(signal.behavior(lane));
+                }
+            }
+            return Some(signal);
+        }
+
         let cycle_size = 14;
         let orange_length = 4;
 
@@ -95,6 +120,7 @@ impl LightPolicy {
                 lanes[lane].control = light;
             }
         }
+        None
     }
 }
 
@@ -1,14 +1,16 @@
-use crate::procgen::Trees;
+use crate::procgen::{GeoOrigin, Trees};
 use crate::{
-    Building, BuildingID, BuildingKind, Intersection, IntersectionID, Lane, LaneID, LaneKind,
-    LanePattern, Lot, LotID, LotKind, ParkingSpotID, ParkingSpots, ProjectKind, Road, RoadID,
-    RoadSegmentKind, SpatialMap,
+    Building, BuildingID, BuildingKind, Coordination, DetectorReading, Intersection,
+    IntersectionID, Lane, LaneID, LaneKind, LanePattern, Lot, LotID, LotKind, ParkingSpotID,
+    ParkingSpots, ProjectKind, Road, RoadID, RoadSegmentKind, SignalControl, SpatialMap,
+    TrafficControl, TurnID, TurnKind, CYCLE_PER_PHASE,
 };
 use geom::{Intersect, Shape, Vec2};
 use geom::{Spline, OBB};
 use ordered_float::OrderedFloat;
 use rand::prelude::IteratorRandom;
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 use slotmap::DenseSlotMap;
 
 pub type Roads = DenseSlotMap<RoadID, Road>;
@@ -17,7 +19,7 @@ pub type Intersections = DenseSlotMap<IntersectionID, Intersection>;
 pub type Buildings = DenseSlotMap<BuildingID, Building>;
 pub type Lots = DenseSlotMap<LotID, Lot>;
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub struct MapProject {
     pub pos: Vec2,
     pub kind: ProjectKind,
@@ -30,6 +32,7 @@ pub struct Map {
     pub(crate) buildings: Buildings,
     pub(crate) lots: Lots,
     pub(crate) spatial_map: SpatialMap,
+    pub(crate) geo_origin: Option<GeoOrigin>,
     pub trees: Trees,
     pub parking: ParkingSpots,
     pub dirty: bool,
@@ -53,14 +56,27 @@ impl Map {
             trees: Trees::default(),
             dirty: true,
             spatial_map: SpatialMap::default(),
+            geo_origin: None,
         }
     }
 
+    /// Where the map lies on the globe, if it was imported from OpenStreetMap
+    pub fn geo_origin(&self) -> Option<GeoOrigin> {
+        self.geo_origin
+    }
+
     pub fn update_intersection(&mut self, id: IntersectionID, f: impl Fn(&mut Intersection)) {
         info!("update_intersection {:?}", id);
         let inter = unwrap_or!(self.intersections.get_mut(id), return);
+        let was_roundabout = inter.is_roundabout();
         f(inter);
 
+        // The roads need to make room for the ring, or take it back
+        if inter.is_roundabout() != was_roundabout {
+            self.invalidate(id);
+            return;
+        }
+
         let inter = &mut self.intersections[id];
         inter.update_traffic_control(&mut self.lanes, &self.roads);
         inter.update_turns(&self.lanes, &self.roads);
@@ -192,6 +208,35 @@ impl Map {
         id
     }
 
+    /// Connects two projected points with a road, creating intersections (and splitting roads)
+    /// where needed. `interpoint` is the elbow of a curved road.
+    /// Returns the intersection at `to`.
+    pub fn make_connection(
+        &mut self,
+        from: MapProject,
+        to: MapProject,
+        interpoint: Option<Vec2>,
+        pattern: &LanePattern,
+    ) -> IntersectionID {
+        let connection_segment = match interpoint {
+            Some(x) => RoadSegmentKind::from_elbow(from.pos, to.pos, x),
+            None => RoadSegmentKind::Straight,
+        };
+
+        let mut mk_inter = |proj: MapProject| match proj.kind {
+            ProjectKind::Ground => self.add_intersection(proj.pos),
+            ProjectKind::Inter(id) => id,
+            ProjectKind::Road(id) => self.split_road(id, proj.pos),
+            ProjectKind::Building(_) | ProjectKind::Lot(_) => unreachable!(),
+        };
+
+        let from = mk_inter(from);
+        let to = mk_inter(to);
+
+        self.connect(from, to, pattern, connection_segment);
+        to
+    }
+
     fn cleanup_lot(roads: &mut Roads, spatial_map: &mut SpatialMap, lot: Lot) {
         let rlots = &mut roads[lot.parent].lots;
         rlots.remove(rlots.iter().position(|&x| x == lot.id).unwrap());
@@ -251,7 +296,8 @@ impl Map {
             let lotkind = lot.kind;
             Self::cleanup_lot(roads, spatial_map, lot);
 
-            let r = rand::random::<f32>();
+            let c = obb.center();
+            let r = common::rand::rand3(c.x, c.y, 1.0);
 
             let kind = match lotkind {
                 LotKind::Residential => BuildingKind::House,
@@ -304,6 +350,139 @@ impl Map {
         }
     }
 
+    /// Switches the lights of every intersection to fixed-time or actuated
+    pub fn set_signal_control(&mut self, control: SignalControl) {
+        info!("set_signal_control {:?}", control);
+        for inter in self.intersections.values_mut() {
+            inter.signal_control = control;
+            inter.update_traffic_control(&mut self.lanes, &self.roads);
+        }
+        self.dirty = true;
+    }
+
+    /// Advances the actuated signals by `delta` seconds and sets the lights of their lanes,
+    /// intersections with signal plans follow the one of the time of the day instead.
+    /// `demand` reads the detector of an incoming lane.
+    pub fn update_signals(
+        &mut self,
+        delta: f32,
+        seconds: f32,
+        demand: impl Fn(&Lane) -> DetectorReading,
+    ) {
+        let lanes = &mut self.lanes;
+        for inter in self.intersections.values_mut() {
+            if let Some((plan, t)) = inter.current_signal_plan(seconds as u32) {
+                for id in inter.signal_plans().flat_map(|p| p.lanes()) {
+                    if let Some(lane) = lanes.get_mut(id) {
+                        lane.control = TrafficControl::Signal(plan.lane_behavior(id, t));
+                    }
+                }
+                continue;
+            }
+
+            let signal = unwrap_or!(inter.signal.as_mut(), continue);
+            signal.update(delta, seconds, |id| {
+                lanes.get(id).map(&demand).unwrap_or_default()
+            });
+
+            for phase in &signal.phases {
+                for &id in phase {
+                    if let Some(lane) = lanes.get_mut(id) {
+                        lane.control = TrafficControl::Signal(signal.behavior(id));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Whether the signal plans of the intersection let the turn be taken at `seconds`
+    pub fn turn_allowed(&self, turn: TurnID, seconds: u32) -> bool {
+        let inter = unwrap_or!(self.intersections.get(turn.parent), return true);
+        let (plan, t) = unwrap_or!(inter.current_signal_plan(seconds), return true);
+        match unwrap_or!(inter.find_turn(turn), return true).kind {
+            TurnKind::Crosswalk => plan.walk_allowed(t),
+            TurnKind::Driving => plan.turn_allowed(turn, t),
+            TurnKind::WalkingCorner => true,
+        }
+    }
+
+    /// Times the lights along the corridor for a green wave, vehicles leaving the first
+    /// intersection at `speed` m/s get to the next ones as they turn green
+    pub fn coordinate_signals(&mut self, corridor: &[IntersectionID], speed: f32) {
+        info!("coordinate_signals {:?} {}", corridor, speed);
+        let roads = &self.roads;
+        let lanes = &mut self.lanes;
+        let intersections = &mut self.intersections;
+
+        let max_phases = corridor
+            .iter()
+            .filter_map(|&id| intersections.get(id)?.signal.as_ref())
+            .map(|s| s.phases.len())
+            .max()
+            .unwrap_or(1);
+        let cycle = max_phases as f32 * CYCLE_PER_PHASE;
+
+        let mut travel = 0.0;
+        for (i, &to) in corridor.iter().enumerate() {
+            // The first intersection is the reference, with an offset of 0. Its corridor phase is
+            // the one of the lanes coming from the second intersection, which the opposite
+            // lanes going towards it share.
+            let from = match i {
+                0 => *unwrap_or!(corridor.get(1), break),
+                _ => corridor[i - 1],
+            };
+            let inter = unwrap_or!(intersections.get_mut(to), continue);
+            let road = unwrap_or!(
+                inter
+                    .roads
+                    .iter()
+                    .map(|&r| &roads[r])
+                    .find(|r| r.other_end(to) == from),
+                continue
+            );
+            if i > 0 {
+                travel += road.length / speed.max(0.1);
+            }
+
+            let corridor_lane = unwrap_or!(
+                road.incoming_lanes_to(to)
+                    .iter()
+                    .find(|(_, kind)| kind.needs_light())
+                    .map(|&(id, _)| id),
+                continue
+            );
+
+            if let Some(signal) = &mut inter.signal {
+                if let Some(phase) = signal.phase_of(corridor_lane) {
+                    signal.coordination = Some(Coordination {
+                        phase,
+                        cycle,
+                        offset: travel % cycle,
+                    });
+                }
+                continue;
+            }
+
+            // Fixed-time lights of the intersection are shifted all together
+            let schedule = match lanes.get(corridor_lane).map(|l| l.control) {
+                Some(TrafficControl::Light(schedule)) => schedule,
+                _ => continue,
+            };
+            let period = schedule.period();
+            let target = travel.round() as usize % period;
+            let delta = (schedule.green_start() + period - target) % period;
+
+            for &r in &inter.roads {
+                for &(id, _) in roads[r].incoming_lanes_to(to) {
+                    let lane = unwrap_or!(lanes.get_mut(id), continue);
+                    if let TrafficControl::Light(schedule) = lane.control {
+                        lane.control = TrafficControl::Light(schedule.shifted(delta));
+                    }
+                }
+            }
+        }
+    }
+
     pub fn clear(&mut self) {
         info!("clear");
         let before = std::mem::take(self);
@@ -1,5 +1,7 @@
 use crate::{Buildings, Road, SpatialMap};
 use geom::{Color, LinearColor, Polygon, Shape, Vec2, AABB, OBB};
+use rand::rngs::SmallRng;
+use rand::SeedableRng;
 use serde::{Deserialize, Serialize};
 use slotmap::new_key_type;
 
@@ -44,11 +46,14 @@ impl Building {
         let axis = (obb.corners[1] - obb.corners[0]).normalize();
         let size = obb.corners[0].distance(obb.corners[1]);
 
+        // Seeded by position so that the same map always gets the same buildings
+        let mut rng = SmallRng::seed_from_u64(common::rand::rand2(at.x, at.y).to_bits() as u64);
+
         let (mut draw, mut door_pos) = match kind {
-            BuildingKind::House => crate::procgen::gen_exterior_house(size),
-            BuildingKind::Workplace => crate::procgen::gen_exterior_workplace(size),
-            BuildingKind::Supermarket => crate::procgen::gen_exterior_supermarket(size),
-            BuildingKind::Farm => crate::procgen::gen_exterior_farm(size),
+            BuildingKind::House => crate::procgen::gen_exterior_house(size, &mut rng),
+            BuildingKind::Workplace => crate::procgen::gen_exterior_workplace(size, &mut rng),
+            BuildingKind::Supermarket => crate::procgen::gen_exterior_supermarket(size, &mut rng),
+            BuildingKind::Farm => crate::procgen::gen_exterior_farm(size, &mut rng),
         };
 
         assert!(!draw.is_empty());
@@ -1,6 +1,8 @@
+use crate::turn_conflicts::turn_conflicts;
 use crate::{
-    Intersections, LaneID, Lanes, LightPolicy, RoadID, Roads, SpatialMap, TraverseDirection, Turn,
-    TurnID, TurnPolicy,
+    ActuatedSignal, Intersections, LaneID, Lanes, LightPolicy, RoadID, Roads, SignalControl,
+    SignalPlan, SignalSchedule, SpatialMap, TrafficControl, TraverseDirection, Turn, TurnID,
+    TurnPolicy,
 };
 use geom::pseudo_angle;
 use geom::Polygon;
@@ -10,6 +12,13 @@ use geom::AABB;
 use ordered_float::OrderedFloat;
 use serde::{Deserialize, Serialize};
 use slotmap::new_key_type;
+use std::f32::consts::PI;
+
+/// Smallest radius of the circulating lane of roundabouts, in meters
+pub const ROUNDABOUT_RADIUS: f32 = 12.0;
+
+/// Length of the entries and exits of roundabouts, from the end of the roads to the ring
+pub const ROUNDABOUT_APPROACH: f32 = 10.0;
 
 new_key_type! {
     pub struct IntersectionID;
@@ -33,6 +42,17 @@ pub struct Intersection {
 
     pub turn_policy: TurnPolicy,
     pub light_policy: LightPolicy,
+    #[serde(default)]
+    pub signal_control: SignalControl,
+    /// Controller of the lights when they are actuated
+    #[serde(default)]
+    pub signal: Option<ActuatedSignal>,
+    /// Timings entered by hand, replacing the lights of the light policy
+    #[serde(default)]
+    pub signal_plan: Option<SignalPlan>,
+    /// Plans taking over from the signal plan at times of the day
+    #[serde(default)]
+    pub signal_schedule: Option<SignalSchedule>,
 
     pub polygon: Polygon,
 }
@@ -46,6 +66,10 @@ impl Intersection {
             roads: Default::default(),
             turn_policy: Default::default(),
             light_policy: Default::default(),
+            signal_control: Default::default(),
+            signal: None,
+            signal_plan: None,
+            signal_schedule: None,
             polygon: Default::default(),
         });
         spatial.insert(id, AABB::new(pos, pos));
@@ -72,16 +96,112 @@ impl Intersection {
             .map(|(id, kind)| Turn::new(id, kind))
             .collect();
 
+        let ring = if self.is_roundabout() {
+            Some((self.pos, self.roundabout_radius(roads)))
+        } else {
+            None
+        };
+
         for turn in self.turns.iter_mut() {
-            turn.make_points(lanes);
+            match ring {
+                Some((center, radius)) if turn.kind.is_driving() => {
+                    turn.make_roundabout_points(lanes, center, radius)
+                }
+                _ => turn.make_points(lanes),
+            }
+        }
+
+        let conflicts = turn_conflicts(&self.turns, lanes, roads, ring);
+        for (turn, conflicts) in self.turns.iter_mut().zip(conflicts) {
+            turn.conflicts = conflicts;
+        }
+    }
+
+    pub fn is_roundabout(&self) -> bool {
+        self.turn_policy.roundabout
+    }
+
+    /// Radius of the circulating lane, large enough for the roads to fit around it
+    pub fn roundabout_radius(&self, roads: &Roads) -> f32 {
+        let widths: f32 = self.roads.iter().map(|&r| roads[r].width).sum();
+        (widths * 1.5 / (2.0 * PI)).max(ROUNDABOUT_RADIUS)
+    }
+
+    /// Incoming lanes that can have lights, grouped by road
+    pub fn incoming_signal_lanes(&self, roads: &Roads) -> Vec<Vec<LaneID>> {
+        self.roads
+            .iter()
+            .map(|&x| {
+                roads[x]
+                    .incoming_lanes_to(self.id)
+                    .iter()
+                    .filter(|(_, kind)| kind.needs_light())
+                    .map(|&(id, _)| id)
+                    .collect::<Vec<_>>()
+            })
+            .filter(|v| !v.is_empty())
+            .collect()
+    }
+
+    pub fn update_traffic_control(&mut self, lanes: &mut Lanes, roads: &Roads) {
+        // Edits keep the intersection in its green wave
+        let coordination = self.signal.as_ref().and_then(|s| s.coordination);
+        self.signal = self.light_policy.apply(self, lanes, roads);
+        if let (Some(signal), Some(c)) = (&mut self.signal, coordination) {
+            if c.phase < signal.phases.len() {
+                signal.coordination = Some(c);
+            }
+        }
+
+        // Vehicles yield on entry, the plans are kept in case the roundabout is removed
+        if self.is_roundabout() {
+            return;
+        }
+        let incoming: Vec<LaneID> = self
+            .incoming_signal_lanes(roads)
+            .into_iter()
+            .flatten()
+            .collect();
+        if let Some(plan) = &mut self.signal_plan {
+            plan.retain_lanes(|l| incoming.contains(&l));
+            if let Some(schedule) = &mut self.signal_schedule {
+                schedule.retain_lanes(|l| incoming.contains(&l));
+            }
+            self.signal = None;
+            for &l in &incoming {
+                lanes[l].control = TrafficControl::Signal(plan.lane_behavior(l, 0));
+            }
         }
     }
 
-    pub fn update_traffic_control(&self, lanes: &mut Lanes, roads: &Roads) {
-        self.light_policy.apply(self, lanes, roads);
+    /// Signal plan running at `seconds` and the time at which to read it
+    pub fn current_signal_plan(&self, seconds: u32) -> Option<(&SignalPlan, u32)> {
+        if self.is_roundabout() {
+            return None;
+        }
+        let plan = self.signal_plan.as_ref()?;
+        Some(match &self.signal_schedule {
+            Some(schedule) => schedule.current(plan, seconds),
+            None => (plan, seconds),
+        })
+    }
+
+    /// The signal plan and the plans of its schedule
+    pub fn signal_plans(&self) -> impl Iterator<Item = &SignalPlan> {
+        self.signal_plan
+            .iter()
+            .chain(self.signal_schedule.iter().flat_map(|s| &s.plans))
     }
 
     pub fn update_interface_radius(&self, roads: &mut Roads) {
+        if self.is_roundabout() {
+            let interface = self.roundabout_radius(roads) + ROUNDABOUT_APPROACH;
+            for &r in &self.roads {
+                roads[r].set_interface(self.id, interface);
+            }
+            return;
+        }
+
         for &r in &self.roads {
             roads[r].set_interface(self.id, 9.0);
         }
@@ -129,6 +249,16 @@ impl Intersection {
             let next_right = next_road.interface_point(self.id)
                 + next_road.width * 0.5 * dst_orient.perpendicular();
 
+            if self.is_roundabout() {
+                let edge = self.ring_edge(roads, left, next_right);
+                self.polygon.extend(
+                    std::iter::once(left)
+                        .chain(edge)
+                        .chain(std::iter::once(next_right)),
+                );
+                continue;
+            }
+
             let ang = (-src_orient).angle(dst_orient);
 
             const TURN_ANG_ADD: f32 = 0.29;
@@ -150,6 +280,24 @@ impl Intersection {
         }
     }
 
+    /// Points of the outer edge of the ring of a roundabout, counterclockwise from the side of
+    /// one road to the side of the next one
+    fn ring_edge(&self, roads: &Roads, from: Vec2, to: Vec2) -> impl Iterator<Item = Vec2> {
+        const STEP: f32 = 0.2;
+
+        let center = self.pos;
+        // Leaves room for the width of the circulating lane
+        let radius = self.roundabout_radius(roads) + 5.0;
+        let angle = |p: Vec2| (p - center).y.atan2((p - center).x);
+
+        let start = angle(from);
+        let sweep = (angle(to) - start).rem_euclid(2.0 * PI);
+        let n = (sweep / STEP).ceil() as usize;
+        (0..=n).map(move |i| {
+            center + Vec2::from_angle(start + sweep * i as f32 / n.max(1) as f32) * radius
+        })
+    }
+
     pub fn neighbors<'a>(&'a self, roads: &'a Roads) -> impl Iterator<Item = IntersectionID> + 'a {
         self.roads.iter().map(move |&x| roads[x].other_end(self.id))
     }
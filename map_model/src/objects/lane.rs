@@ -9,6 +9,21 @@ new_key_type! {
     pub struct LaneID;
 }
 
+/// Speed limit of the roads that don't say otherwise, in km/h
+pub const DEFAULT_SPEED_LIMIT: u32 = 50;
+
+pub(crate) fn default_speed_limit() -> u32 {
+    DEFAULT_SPEED_LIMIT
+}
+
+fn default_lane_speed_limit() -> f32 {
+    kmh_to_ms(DEFAULT_SPEED_LIMIT)
+}
+
+pub fn kmh_to_ms(kmh: u32) -> f32 {
+    kmh as f32 / 3.6
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Hash)]
 pub enum LaneKind {
     Driving,
@@ -30,7 +45,8 @@ impl LaneKind {
 
     pub fn width(self) -> f32 {
         match self {
-            LaneKind::Driving | LaneKind::Biking | LaneKind::Bus => 8.0,
+            LaneKind::Driving | LaneKind::Bus => 8.0,
+            LaneKind::Biking => 4.0,
             LaneKind::Parking => 4.0,
             LaneKind::Construction => 4.0,
             LaneKind::Walking => 4.0,
@@ -63,12 +79,19 @@ pub struct Lane {
 
     /// Length from start to end
     pub length: f32,
+
+    /// Fastest vehicles are allowed to go, in m/s
+    #[serde(default = "default_lane_speed_limit")]
+    pub speed_limit: f32,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct LanePattern {
     pub lanes_forward: Vec<LaneKind>,
     pub lanes_backward: Vec<LaneKind>,
+    /// In km/h
+    #[serde(default = "default_speed_limit")]
+    pub speed_limit: u32,
 }
 
 impl LanePattern {
@@ -89,7 +112,10 @@ pub struct LanePatternBuilder {
     pub n_lanes: u32,
     pub sidewalks: bool,
     pub parking: bool,
+    pub bike_lanes: bool,
     pub one_way: bool,
+    /// In km/h
+    pub speed_limit: u32,
 }
 
 impl Default for LanePatternBuilder {
@@ -98,7 +124,9 @@ impl Default for LanePatternBuilder {
             n_lanes: 1,
             sidewalks: true,
             parking: true,
+            bike_lanes: false,
             one_way: false,
+            speed_limit: DEFAULT_SPEED_LIMIT,
         }
     }
 }
@@ -124,11 +152,21 @@ impl LanePatternBuilder {
         self
     }
 
+    pub fn bike_lanes(mut self, bike_lanes: bool) -> Self {
+        self.bike_lanes = bike_lanes;
+        self
+    }
+
     pub fn one_way(mut self, one_way: bool) -> Self {
         self.one_way = one_way;
         self
     }
 
+    pub fn speed_limit(mut self, speed_limit: u32) -> Self {
+        self.speed_limit = speed_limit;
+        self
+    }
+
     pub fn width(self) -> f32 {
         let mut w = 0.0;
         if self.sidewalks {
@@ -137,6 +175,9 @@ impl LanePatternBuilder {
         if self.parking {
             w += LaneKind::Parking.width() * 2.0;
         }
+        if self.bike_lanes {
+            w += LaneKind::Biking.width() * 2.0;
+        }
         w += self.n_lanes as f32 * 2.0 * LaneKind::Driving.width();
         w + 0.5
     }
@@ -150,6 +191,15 @@ impl LanePatternBuilder {
 
         let mut forward: Vec<_> = (0..self.n_lanes).map(|_| LaneKind::Driving).collect();
 
+        // Between the cars and the parked cars, parking_next_to expects parking to be
+        // one of the last two lanes
+        if self.bike_lanes {
+            if !self.one_way {
+                backward.push(LaneKind::Biking);
+            }
+            forward.push(LaneKind::Biking);
+        }
+
         if self.parking {
             if !self.one_way {
                 backward.push(LaneKind::Parking);
@@ -165,6 +215,7 @@ impl LanePatternBuilder {
         LanePattern {
             lanes_backward: backward,
             lanes_forward: forward,
+            speed_limit: self.speed_limit,
         }
     }
 }
@@ -190,6 +241,7 @@ impl Lane {
             points: parent.generated_points().clone(),
             width: lane_type.width(),
             length: 0.0,
+            speed_limit: kmh_to_ms(parent.speed_limit),
             control: TrafficControl::Always,
         })
     }
@@ -2,7 +2,9 @@ use crate::{Buildings, Intersections, Lots, Map, ProjectKind, RoadID, Roads, Spa
 use geom::OBB;
 use geom::{Intersect, Polygon};
 use geom::{Shape, Vec2};
+use rand::rngs::SmallRng;
 use rand::seq::SliceRandom;
+use rand::SeedableRng;
 use serde::{Deserialize, Serialize};
 use slotmap::new_key_type;
 
@@ -97,11 +99,10 @@ impl Lot {
 
             let w = r.width * 0.5;
 
-            fn picksize() -> f32 {
-                *[20.0f32, 30.0, 40.0]
-                    .choose(&mut rand::thread_rng())
-                    .unwrap()
-            }
+            let mut rng = SmallRng::seed_from_u64(
+                common::rand::rand3(r.src_point.x, r.src_point.y, side).to_bits() as u64,
+            );
+            let mut picksize = move || *[20.0f32, 30.0, 40.0].choose(&mut rng).unwrap();
 
             let mut along = r.generated_points.points_dirs_manual();
             let mut size = picksize();
@@ -27,6 +27,18 @@ impl ParkingSpots {
         self.spots.get(spot)
     }
 
+    pub fn iter(&self) -> impl Iterator<Item = (ParkingSpotID, &ParkingSpot)> {
+        self.spots.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.spots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.spots.is_empty()
+    }
+
     pub fn contains(&self, spot: ParkingSpotID) -> bool {
         self.spots.contains_key(spot)
     }
@@ -1,3 +1,4 @@
+use crate::objects::lane::default_speed_limit;
 use crate::{
     IntersectionID, Intersections, Lane, LaneDirection, LaneID, LaneKind, LanePattern, Lanes,
     LotID, Map, ParkingSpots,
@@ -48,6 +49,10 @@ pub struct Road {
     pub src_interface: f32,
     pub dst_interface: f32,
 
+    /// In km/h, the lanes have it in m/s
+    #[serde(default = "default_speed_limit")]
+    pub speed_limit: u32,
+
     lanes_forward: Vec<(LaneID, LaneKind)>,
     lanes_backward: Vec<(LaneID, LaneKind)>,
 
@@ -81,6 +86,7 @@ impl Road {
             segment,
             width: 0.0,
             length: 1.0,
+            speed_limit: lane_pattern.speed_limit,
             lanes_forward: vec![],
             lanes_backward: vec![],
             generated_points: PolyLine::new(vec![Vec2::ZERO]),
@@ -129,6 +135,23 @@ impl Road {
         })
     }
 
+    /// Driving lanes right next to `lane` going the same way, that vehicles can change lanes to
+    pub fn adjacent_driving_lanes(&self, lane: LaneID) -> impl Iterator<Item = LaneID> + '_ {
+        let lanes = if self.lanes_forward.iter().any(|&(id, _)| id == lane) {
+            &self.lanes_forward
+        } else {
+            &self.lanes_backward
+        };
+        let pos = lanes.iter().position(|&(id, _)| id == lane);
+        lanes
+            .iter()
+            .enumerate()
+            .filter(move |&(i, &(_, kind))| {
+                kind == LaneKind::Driving && pos.map_or(false, |p| i + 1 == p || i == p + 1)
+            })
+            .map(|(_, &(id, _))| id)
+    }
+
     pub fn parking_next_to(&self, lane: &Lane) -> Option<LaneID> {
         let lanes = if lane.src == self.src {
             &self.lanes_forward
@@ -204,6 +227,7 @@ impl Road {
         LanePattern {
             lanes_forward: self.lanes_forward.iter().map(|&(_, kind)| kind).collect(),
             lanes_backward: self.lanes_backward.iter().map(|&(_, kind)| kind).collect(),
+            speed_limit: self.speed_limit,
         }
     }
 
@@ -1,8 +1,9 @@
-use crate::{IntersectionID, LaneID, Lanes};
+use crate::{IntersectionID, LaneID, Lanes, TurnConflict};
 use geom::PolyLine;
 use geom::Spline;
 use geom::Vec2;
 use serde::{Deserialize, Serialize};
+use std::f32::consts::{FRAC_PI_2, PI};
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Hash)]
 pub struct TurnID {
@@ -34,6 +35,10 @@ impl TurnKind {
     pub fn is_crosswalk(self) -> bool {
         matches!(self, TurnKind::Crosswalk)
     }
+
+    pub fn is_driving(self) -> bool {
+        matches!(self, TurnKind::Driving)
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -41,6 +46,9 @@ pub struct Turn {
     pub id: TurnID,
     pub points: PolyLine,
     pub kind: TurnKind,
+    /// Driving turns of the intersection crossing or merging with this one, recomputed on load
+    #[serde(skip)]
+    pub conflicts: Vec<TurnConflict>,
 }
 
 const TURN_ANG_ADD: f32 = 0.29;
@@ -48,12 +56,40 @@ const TURN_ANG_MUL: f32 = 0.36;
 const TURN_MUL: f32 = 0.46;
 const N_SPLINE: usize = 6;
 
+/// Angle between a road of a roundabout and the points where vehicles join and leave the ring
+const RING_JOIN_ANGLE: f32 = 0.35;
+/// Angle between two points of the ring part of a turn
+const RING_STEP: f32 = 0.15;
+
+fn ring_angle(center: Vec2, p: Vec2) -> f32 {
+    let d = p - center;
+    d.y.atan2(d.x)
+}
+
+/// Point where vehicles taking the turn join the ring of a roundabout
+pub(crate) fn ring_entry(id: TurnID, lanes: &Lanes, center: Vec2, radius: f32) -> Vec2 {
+    let pos_src = lanes[id.src].get_inter_node_pos(id.parent);
+    center + Vec2::from_angle(ring_angle(center, pos_src) + RING_JOIN_ANGLE) * radius
+}
+
+/// Smooth path from `from` to `to`, leaving and arriving along the given directions
+fn join(from: Vec2, from_dir: Vec2, to: Vec2, to_dir: Vec2) -> Spline {
+    let dist = (to - from).magnitude();
+    Spline {
+        from,
+        to,
+        from_derivative: from_dir * dist,
+        to_derivative: to_dir * dist,
+    }
+}
+
 impl Turn {
     pub fn new(id: TurnID, kind: TurnKind) -> Self {
         Self {
             id,
             points: PolyLine::new(vec![Vec2::ZERO; N_SPLINE + 2]),
             kind,
+            conflicts: vec![],
         }
     }
 
@@ -92,4 +128,79 @@ impl Turn {
         self.points
             .extend(spline.smart_points(0.3, 0.0, 1.0).skip(1));
     }
+
+    /// Path around the ring of a roundabout: joins it a bit after the road it comes from, goes
+    /// counterclockwise and leaves it a bit before the road it goes to.
+    pub fn make_roundabout_points(&mut self, lanes: &Lanes, center: Vec2, radius: f32) {
+        let src_lane = &lanes[self.id.src];
+        let dst_lane = &lanes[self.id.dst];
+
+        let pos_src = src_lane.get_inter_node_pos(self.id.parent);
+        let pos_dst = dst_lane.get_inter_node_pos(self.id.parent);
+
+        let src_dir = -src_lane.orientation_from(self.id.parent);
+        let dst_dir = dst_lane.orientation_from(self.id.parent);
+
+        let ring = |ang: f32| center + Vec2::from_angle(ang) * radius;
+        let tangent = |ang: f32| Vec2::from_angle(ang + FRAC_PI_2);
+
+        let start = ring_angle(center, pos_src) + RING_JOIN_ANGLE;
+        let sweep = (ring_angle(center, pos_dst) - RING_JOIN_ANGLE - start).rem_euclid(2.0 * PI);
+        let end = start + sweep;
+
+        let entry = join(pos_src, src_dir, ring(start), tangent(start));
+        let exit = join(ring(end), tangent(end), pos_dst, dst_dir);
+
+        self.points.clear_push(pos_src);
+        self.points
+            .extend(entry.smart_points(0.3, 0.0, 1.0).skip(1));
+
+        let n = (sweep / RING_STEP).ceil() as usize;
+        for i in 1..=n {
+            self.points.push(ring(start + sweep * i as f32 / n as f32));
+        }
+
+        self.points.extend(exit.smart_points(0.3, 0.0, 1.0).skip(1));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::procgen::add_roundabout;
+    use crate::Map;
+    use geom::Vec2;
+
+    #[test]
+    fn roundabout_turns_go_counterclockwise_around_the_ring() {
+        let mut map = Map::empty();
+        add_roundabout(Vec2::ZERO, &mut map);
+        let inter = map
+            .intersections()
+            .values()
+            .find(|i| i.is_roundabout())
+            .unwrap();
+        let radius = inter.roundabout_radius(map.roads());
+        let lanes = map.lanes();
+
+        let mut checked = 0;
+        for turn in inter.turns().iter().filter(|t| t.kind.is_driving()) {
+            let points = turn.points.as_slice();
+            let src = lanes[turn.id.src].get_inter_node_pos(inter.id);
+            let dst = lanes[turn.id.dst].get_inter_node_pos(inter.id);
+            assert!(points[0].is_close(src, 0.01));
+            assert!(points[points.len() - 1].is_close(dst, 0.01));
+
+            let ring: Vec<Vec2> = points
+                .iter()
+                .copied()
+                .filter(|p| (p.magnitude() - radius).abs() < 0.01)
+                .collect();
+            assert!(ring.len() >= 2);
+            for w in ring.windows(2) {
+                assert!(w[0].perp_dot(w[1]) > 0.0);
+            }
+            checked += 1;
+        }
+        assert!(checked > 0);
+    }
 }
@@ -1,5 +1,5 @@
 #![allow(clippy::or_fun_call)]
-use crate::{LaneID, LaneKind, Map, Traversable, TraverseDirection, TraverseKind, TurnID};
+use crate::{Lane, LaneID, LaneKind, Map, Traversable, TraverseDirection, TraverseKind, TurnID};
 use geom::{PolyLine, Vec2};
 use ordered_float::OrderedFloat;
 use slotmap::Key;
@@ -76,6 +76,9 @@ impl Pathfinder for PedestrianPath {
     }
 }
 
+/// Extra cost of changing lanes, in meters, so that vehicles don't weave for nothing
+pub const LANE_CHANGE_COST: f32 = 20.0;
+
 pub struct CarPath;
 
 impl Pathfinder for CarPath {
@@ -104,9 +107,14 @@ impl Pathfinder for CarPath {
                 p
             };
             let inter = &inters[l.dst];
+            let lane_changes = map.roads[l.parent]
+                .adjacent_driving_lanes(p)
+                .map(|x| (x, OrderedFloat(LANE_CHANGE_COST)));
             inter
                 .turns_from(p)
+                .filter(|(x, _)| lanes[x.dst].kind != LaneKind::Biking)
                 .map(|(x, _)| (x.dst, OrderedFloat(lanes[x.dst].length)))
+                .chain(lane_changes)
         };
 
         let (v, _) =
@@ -118,12 +126,17 @@ impl Pathfinder for CarPath {
         let mut last_id = start_lane;
 
         for lane in v.into_iter().skip(1) {
-            let inter_end = &inters[lanes[lane].src];
-            let id = TurnID::new(inter_end.id, last_id, lane, false);
-            path.push(Traversable::new(
-                TraverseKind::Turn(id),
-                TraverseDirection::Forward,
-            ));
+            let (l, last) = (&lanes[lane], &lanes[last_id]);
+            // Two lanes of the same road going the same way follow each other when changing lanes
+            let lane_change = l.parent == last.parent && l.src == last.src;
+            if !lane_change {
+                let inter_end = &inters[l.src];
+                let id = TurnID::new(inter_end.id, last_id, lane, false);
+                path.push(Traversable::new(
+                    TraverseKind::Turn(id),
+                    TraverseDirection::Forward,
+                ));
+            }
             path.push(Traversable::new(
                 TraverseKind::Lane(lane),
                 TraverseDirection::Forward,
@@ -159,3 +172,102 @@ impl Pathfinder for CarPath {
         Some(PolyLine::new(v))
     }
 }
+
+/// Cyclists ride this much more reluctantly on driving lanes than on biking lanes
+pub const DRIVING_LANE_PENALTY: f32 = 1.5;
+
+/// Biking lanes further than this from the nearest driving lane aren't worth the detour
+/// to start or end the route, in meters
+pub const MAX_BIKE_LANE_DETOUR: f32 = 30.0;
+
+pub struct BikePath;
+
+impl BikePath {
+    fn cost(lane: &Lane) -> f32 {
+        match lane.kind {
+            LaneKind::Biking => lane.length,
+            _ => lane.length * DRIVING_LANE_PENALTY,
+        }
+    }
+
+    fn rideable(kind: LaneKind) -> bool {
+        matches!(kind, LaneKind::Biking | LaneKind::Driving)
+    }
+}
+
+impl Pathfinder for BikePath {
+    fn path(&self, map: &Map, start: Traversable, end: LaneID) -> Option<Vec<Traversable>> {
+        let inters = &map.intersections;
+        let lanes = &map.lanes;
+
+        let start_lane = start.destination_lane();
+
+        let end_pos = inters[lanes[end].dst].pos;
+
+        let dummy = LaneID::null();
+
+        let heuristic = |&p: &LaneID| {
+            let pos = inters[lanes[p].dst].pos;
+            OrderedFloat(pos.distance(end_pos) * 1.2) // Inexact but (much) faster
+        };
+
+        let successors = |&p: &LaneID| {
+            let l;
+            let p = if p == dummy {
+                l = &lanes[start_lane];
+                start_lane
+            } else {
+                l = &lanes[p];
+                p
+            };
+            let inter = &inters[l.dst];
+            inter
+                .turns_from(p)
+                .filter(|(x, _)| Self::rideable(lanes[x.dst].kind))
+                .map(|(x, _)| (x.dst, OrderedFloat(Self::cost(&lanes[x.dst]))))
+        };
+
+        let (v, _) =
+            pathfinding::directed::astar::astar(&dummy, successors, heuristic, |p| *p == end)?;
+
+        let mut path = Vec::with_capacity(v.len() * 2);
+        path.push(start);
+
+        let mut last_id = start_lane;
+
+        for lane in v.into_iter().skip(1) {
+            let inter_end = &inters[lanes[lane].src];
+            let id = TurnID::new(inter_end.id, last_id, lane, false);
+            path.push(Traversable::new(
+                TraverseKind::Turn(id),
+                TraverseDirection::Forward,
+            ));
+            path.push(Traversable::new(
+                TraverseKind::Lane(lane),
+                TraverseDirection::Forward,
+            ));
+
+            last_id = lane;
+        }
+        Some(path)
+    }
+
+    /// Nearest biking lane, or the nearest driving lane if there is no biking lane around
+    fn nearest_lane(&self, map: &Map, pos: Vec2) -> Option<LaneID> {
+        let driving = map.nearest_lane(pos, LaneKind::Driving);
+        let biking = match map.nearest_lane(pos, LaneKind::Biking) {
+            Some(x) => x,
+            None => return driving,
+        };
+        let driving_dist = driving.map_or(f32::INFINITY, |l| map.lanes[l].dist2_to(pos).sqrt());
+        if map.lanes[biking].dist2_to(pos).sqrt() <= driving_dist + MAX_BIKE_LANE_DETOUR {
+            Some(biking)
+        } else {
+            driving
+        }
+    }
+
+    fn local_route(&self, map: &Map, lane: LaneID, start: Vec2, end: Vec2) -> Option<PolyLine> {
+        CarPath.local_route(map, lane, start, end)
+    }
+}
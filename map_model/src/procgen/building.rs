@@ -8,19 +8,22 @@ pub struct RoofFace {
     pub normal: Vec3,
 }
 
-pub fn gen_exterior_workplace(size: f32) -> (Vec<(Polygon, LinearColor)>, Vec2) {
-    let a = rand_in(15.0, 20.0);
-    let b = rand_in(15.0, 20.0);
+pub fn gen_exterior_workplace<R: Rng>(
+    size: f32,
+    rng: &mut R,
+) -> (Vec<(Polygon, LinearColor)>, Vec2) {
+    let a = rand_in(rng, 15.0, 20.0);
+    let b = rand_in(rng, 15.0, 20.0);
 
     let width = f32::max(a, b) * (size / 40.0) * 1.5;
     let height = f32::min(a, b) * (size / 40.0);
 
     let mut p = Polygon::rect(width, height);
-    let corn_coeff = rand_in(0.2, 0.3);
+    let corn_coeff = rand_in(rng, 0.2, 0.3);
 
     p.split_segment(0, corn_coeff);
     p.split_segment(1, 1.0 - corn_coeff / (1.0 - corn_coeff));
-    let extrude = rand_in(height * 0.3, height * 0.4);
+    let extrude = rand_in(rng, height * 0.3, height * 0.4);
     p.extrude(2, extrude);
     p.extrude(0, extrude);
 
@@ -44,14 +47,14 @@ pub fn gen_exterior_workplace(size: f32) -> (Vec<(Polygon, LinearColor)>, Vec2)
 ///   0         1      2
 ///            w
 ///
-pub fn gen_exterior_house(size: f32) -> (Vec<(Polygon, LinearColor)>, Vec2) {
-    let width = rand_in(10.0, 15.0) * (size / 40.0);
-    let height = rand_in(15.0, 20.0) * (size / 40.0);
+pub fn gen_exterior_house<R: Rng>(size: f32, rng: &mut R) -> (Vec<(Polygon, LinearColor)>, Vec2) {
+    let width = rand_in(rng, 10.0, 15.0) * (size / 40.0);
+    let height = rand_in(rng, 15.0, 20.0) * (size / 40.0);
 
     let mut p = Polygon::rect(width, height);
-    let corn_coeff = rand_in(0.5, 0.75);
+    let corn_coeff = rand_in(rng, 0.5, 0.75);
     p.split_segment(1, corn_coeff);
-    p.extrude(1, rand_in(5.0, 10.0));
+    p.extrude(1, rand_in(rng, 5.0, 10.0));
 
     let a = vec2(width * 0.5, height);
     let c = (p[3] + p[2]) / 2.0;
@@ -89,7 +92,7 @@ pub fn gen_exterior_house(size: f32) -> (Vec<(Polygon, LinearColor)>, Vec2) {
     door_pos += off;
     p.translate(off);
 
-    let rot = rand_in(0.0, 4.0) as usize;
+    let rot = rand_in(rng, 0.0, 4.0) as usize;
 
     let rv = [
         vec2(1.0, 0.0),
@@ -121,9 +124,12 @@ pub fn gen_exterior_house(size: f32) -> (Vec<(Polygon, LinearColor)>, Vec2) {
     (polys, door_pos)
 }
 
-pub fn gen_exterior_supermarket(size: f32) -> (Vec<(Polygon, LinearColor)>, Vec2) {
-    let mut h = rand_in(25.0, 30.0);
-    let mut w = h + rand_in(5.0, 10.0);
+pub fn gen_exterior_supermarket<R: Rng>(
+    size: f32,
+    rng: &mut R,
+) -> (Vec<(Polygon, LinearColor)>, Vec2) {
+    let mut h = rand_in(rng, 25.0, 30.0);
+    let mut w = h + rand_in(rng, 5.0, 10.0);
 
     w *= size / 40.0;
     h *= size / 40.0;
@@ -149,12 +155,12 @@ pub fn gen_exterior_supermarket(size: f32) -> (Vec<(Polygon, LinearColor)>, Vec2
 ///  XXXXX   
 ///    XXX   
 ///     |    
-pub fn gen_exterior_farm(size: f32) -> (Vec<(Polygon, LinearColor)>, Vec2) {
+pub fn gen_exterior_farm<R: Rng>(size: f32, rng: &mut R) -> (Vec<(Polygon, LinearColor)>, Vec2) {
     let h_size = 30.0;
-    let (mut polys, mut door_pos) = gen_exterior_house(h_size);
+    let (mut polys, mut door_pos) = gen_exterior_house(h_size, rng);
 
     let mut off = Vec2::splat(h_size * 0.5 - size * 0.5);
-    off.x += rand_in(0.0, size - h_size);
+    off.x += rand_in(rng, 0.0, size - h_size);
     for p in &mut polys {
         p.0.translate(off);
     }
@@ -177,8 +183,8 @@ pub fn gen_exterior_farm(size: f32) -> (Vec<(Polygon, LinearColor)>, Vec2) {
     (polys, door_pos)
 }
 
-fn rand_in(min: f32, max: f32) -> f32 {
-    rand::thread_rng().gen_range(min, max)
+fn rand_in(rng: &mut impl Rng, min: f32, max: f32) -> f32 {
+    rng.gen_range(min, max)
 }
 
 /*
@@ -234,3 +240,31 @@ impl HGrid {
 }
 */
 //fn gen_house
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn same_seed_same_buildings() {
+        let gen = |seed| {
+            let mut rng = SmallRng::seed_from_u64(seed);
+            vec![
+                gen_exterior_house(40.0, &mut rng),
+                gen_exterior_workplace(40.0, &mut rng),
+                gen_exterior_supermarket(60.0, &mut rng),
+                gen_exterior_farm(80.0, &mut rng),
+            ]
+        };
+
+        for (a, b) in gen(42).into_iter().zip(gen(42)) {
+            assert_eq!(a.1, b.1);
+            assert_eq!(a.0.len(), b.0.len());
+            for ((poly_a, _), (poly_b, _)) in a.0.iter().zip(&b.0) {
+                assert_eq!(poly_a.as_slice(), poly_b.as_slice());
+            }
+        }
+    }
+}
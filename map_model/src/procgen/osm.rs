@@ -0,0 +1,696 @@
+use crate::{IntersectionID, LanePatternBuilder, LightPolicy, Map, RoadSegmentKind};
+use flat_spatial::SparseGrid;
+use geom::{vec2, Vec2};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::str::FromStr;
+use xml::attribute::OwnedAttribute;
+use xml::reader::{EventReader, XmlEvent};
+
+/// Junctions joined by a road shorter than this (in meters) end up as the same intersection
+const MERGE_DIST: f32 = 15.0;
+/// A road is a single spline, so a chain of nodes bending more than this is cut at its sharpest turn
+const MAX_BEND: f32 = 100.0 * std::f32::consts::PI / 180.0;
+/// Chains bending less than this become straight roads
+const MIN_BEND: f32 = 5.0 * std::f32::consts::PI / 180.0;
+/// Signals are usually tagged on the stop line, slightly before the junction
+const SIGNAL_DIST: f32 = 30.0;
+
+const METERS_PER_DEGREE: f64 = 111_320.0;
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct LatLon {
+    pub lat: f64,
+    pub lon: f64,
+}
+
+impl LatLon {
+    /// Equirectangular projection of `p` with self as the origin, x pointing east and y north.
+    /// `scale` is in map units per meter.
+    pub fn project(self, p: LatLon, scale: f64) -> Vec2 {
+        let cos_lat = self.lat.to_radians().cos();
+        vec2(
+            ((p.lon - self.lon) * cos_lat * METERS_PER_DEGREE * scale) as f32,
+            ((p.lat - self.lat) * METERS_PER_DEGREE * scale) as f32,
+        )
+    }
+
+    /// Inverse of `project`
+    pub fn unproject(self, p: Vec2, scale: f64) -> LatLon {
+        let cos_lat = self.lat.to_radians().cos();
+        LatLon {
+            lat: self.lat + p.y as f64 / (METERS_PER_DEGREE * scale),
+            lon: self.lon + p.x as f64 / (cos_lat * METERS_PER_DEGREE * scale),
+        }
+    }
+}
+
+/// Where the map lies on the globe, known for the maps imported from OpenStreetMap
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct GeoOrigin {
+    /// Point mapped to the map origin
+    pub center: LatLon,
+    /// Map units per meter
+    pub scale: f64,
+}
+
+impl GeoOrigin {
+    /// Stand-in for the maps that weren't imported: they are laid around longitude 0, latitude 0,
+    /// where longitude and latitude are closest to a metric projection
+    pub const LOCAL: GeoOrigin = GeoOrigin {
+        center: LatLon { lat: 0.0, lon: 0.0 },
+        scale: 1.0,
+    };
+
+    pub fn unproject(self, p: Vec2) -> LatLon {
+        self.center.unproject(p, self.scale)
+    }
+}
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct LatLonBox {
+    pub min: LatLon,
+    pub max: LatLon,
+}
+
+impl LatLonBox {
+    pub fn contains(&self, p: LatLon) -> bool {
+        self.min.lat <= p.lat
+            && p.lat <= self.max.lat
+            && self.min.lon <= p.lon
+            && p.lon <= self.max.lon
+    }
+
+    pub fn center(&self) -> LatLon {
+        LatLon {
+            lat: (self.min.lat + self.max.lat) * 0.5,
+            lon: (self.min.lon + self.max.lon) * 0.5,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OsmConfig {
+    /// Only the nodes inside this box are imported, ways leaving it are cut
+    pub bbox: Option<LatLonBox>,
+    /// Point mapped to the origin, the center of the bounding box or of the data by default
+    pub center: Option<LatLon>,
+    /// Map units per meter
+    pub scale: f64,
+    /// Light policy of the junctions that are neither signalised nor stop controlled
+    pub unsignalised: LightPolicy,
+}
+
+impl Default for OsmConfig {
+    fn default() -> Self {
+        Self {
+            bbox: None,
+            center: None,
+            scale: 1.0,
+            unsignalised: LightPolicy::Smart,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum OsmError {
+    Io(std::io::Error),
+    Parse(String),
+    Empty,
+}
+
+impl Display for OsmError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OsmError::Io(e) => write!(f, "io error: {}", e),
+            OsmError::Parse(e) => write!(f, "parse error: {}", e),
+            OsmError::Empty => write!(f, "no road inside the bounding box"),
+        }
+    }
+}
+
+impl std::error::Error for OsmError {}
+
+impl From<std::io::Error> for OsmError {
+    fn from(e: std::io::Error) -> Self {
+        OsmError::Io(e)
+    }
+}
+
+struct OsmNode {
+    pos: LatLon,
+    control: Option<LightPolicy>,
+}
+
+struct OsmWay {
+    nodes: Vec<i64>,
+    pattern: LanePatternBuilder,
+}
+
+#[derive(Default)]
+struct OsmData {
+    nodes: HashMap<i64, OsmNode>,
+    ways: Vec<OsmWay>,
+}
+
+impl OsmData {
+    fn add_node(&mut self, id: i64, pos: LatLon, highway: Option<&str>) {
+        let control = match highway {
+            Some("traffic_signals") => Some(LightPolicy::Lights),
+            Some("stop") => Some(LightPolicy::StopSigns),
+            _ => None,
+        };
+        self.nodes.insert(id, OsmNode { pos, control });
+    }
+
+    fn add_way(&mut self, mut nodes: Vec<i64>, tags: &HashMap<String, String>) {
+        let (pattern, reversed) = unwrap_or!(highway_pattern(tags), return);
+        if reversed {
+            nodes.reverse();
+        }
+        self.ways.push(OsmWay { nodes, pattern });
+    }
+}
+
+/// Imports the roads of an OpenStreetMap extract, either `.osm` xml or `.osm.pbf`.
+/// Nodes shared by several roads become intersections, the other nodes only shape the roads.
+pub fn load_osm(map: &mut Map, path: impl AsRef<Path>, config: &OsmConfig) -> Result<(), OsmError> {
+    let time = std::time::Instant::now();
+    let path = path.as_ref();
+
+    let is_pbf = path
+        .extension()
+        .map_or(false, |ext| ext.eq_ignore_ascii_case("pbf"));
+    let data = if is_pbf {
+        read_pbf(path)?
+    } else {
+        read_xml(path)?
+    };
+
+    build_map(map, &data, config)?;
+
+    info!(
+        "importing {:?} took {}ms: {} intersections, {} roads",
+        path,
+        time.elapsed().as_secs_f32() * 1000.0,
+        map.intersections.len(),
+        map.roads.len()
+    );
+    Ok(())
+}
+
+fn read_xml(path: &Path) -> Result<OsmData, OsmError> {
+    enum Current {
+        Node(i64, LatLon),
+        Way(Vec<i64>),
+        Other,
+    }
+
+    let parser = EventReader::new(BufReader::new(File::open(path)?));
+
+    let mut data = OsmData::default();
+    let mut current = Current::Other;
+    let mut tags = HashMap::new();
+
+    for event in parser {
+        match event.map_err(|e| OsmError::Parse(e.to_string()))? {
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } => match name.local_name.as_str() {
+                "node" => {
+                    let id = parse_attr(&attributes, "id")?;
+                    let pos = LatLon {
+                        lat: parse_attr(&attributes, "lat")?,
+                        lon: parse_attr(&attributes, "lon")?,
+                    };
+                    current = Current::Node(id, pos);
+                    tags.clear();
+                }
+                "way" => {
+                    current = Current::Way(vec![]);
+                    tags.clear();
+                }
+                "relation" => current = Current::Other,
+                "nd" => {
+                    if let Current::Way(ref mut nodes) = current {
+                        nodes.push(parse_attr(&attributes, "ref")?);
+                    }
+                }
+                "tag" => {
+                    if let (Some(k), Some(v)) = (attr(&attributes, "k"), attr(&attributes, "v")) {
+                        tags.insert(k.to_string(), v.to_string());
+                    }
+                }
+                _ => {}
+            },
+            XmlEvent::EndElement { name } => match name.local_name.as_str() {
+                "node" | "way" | "relation" => {
+                    match std::mem::replace(&mut current, Current::Other) {
+                        Current::Node(id, pos) => {
+                            data.add_node(id, pos, tags.get("highway").map(String::as_str))
+                        }
+                        Current::Way(nodes) => data.add_way(nodes, &tags),
+                        Current::Other => {}
+                    }
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    Ok(data)
+}
+
+fn attr<'a>(attributes: &'a [OwnedAttribute], key: &str) -> Option<&'a str> {
+    attributes
+        .iter()
+        .find(|x| x.name.local_name == key)
+        .map(|x| x.value.as_str())
+}
+
+fn parse_attr<T: FromStr>(attributes: &[OwnedAttribute], key: &str) -> Result<T, OsmError> {
+    attr(attributes, key)
+        .and_then(|x| x.parse().ok())
+        .ok_or_else(|| OsmError::Parse(format!("missing or invalid attribute {}", key)))
+}
+
+fn read_pbf(path: &Path) -> Result<OsmData, OsmError> {
+    use osmpbfreader::{OsmObj, OsmPbfReader};
+
+    let mut pbf = OsmPbfReader::new(File::open(path)?);
+    let mut data = OsmData::default();
+
+    for obj in pbf.iter() {
+        match obj.map_err(|e| OsmError::Parse(e.to_string()))? {
+            OsmObj::Node(n) => data.add_node(
+                n.id.0,
+                LatLon {
+                    lat: n.lat(),
+                    lon: n.lon(),
+                },
+                n.tags.get("highway").map(|x| x.as_str()),
+            ),
+            OsmObj::Way(w) => {
+                if !w.tags.contains_key("highway") {
+                    continue;
+                }
+                let tags = w
+                    .tags
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect();
+                data.add_way(w.nodes.iter().map(|x| x.0).collect(), &tags)
+            }
+            OsmObj::Relation(_) => {}
+        }
+    }
+
+    Ok(data)
+}
+
+/// Lane pattern of a highway way and whether it is one-way against the order of its nodes.
+/// None for ways that aren't roads (footways, railways...).
+fn highway_pattern(tags: &HashMap<String, String>) -> Option<(LanePatternBuilder, bool)> {
+    let tag = |key: &str| tags.get(key).map(String::as_str);
+
+    let (n_lanes, sidewalks, parking, one_way, speed_limit) = match tag("highway")? {
+        "motorway" => (2, false, false, true, 110),
+        "motorway_link" | "trunk_link" => (1, false, false, true, 70),
+        "trunk" => (2, false, false, false, 90),
+        "primary" | "secondary" => (2, true, false, false, 50),
+        "primary_link" | "secondary_link" | "tertiary_link" => (1, true, false, false, 50),
+        "tertiary" | "unclassified" => (1, true, false, false, 50),
+        "residential" => (1, true, true, false, 30),
+        "living_street" => (1, true, true, false, 20),
+        "service" => (1, false, false, false, 20),
+        _ => return None,
+    };
+
+    let (one_way, reversed) = match tag("oneway") {
+        Some("yes") | Some("true") | Some("1") => (true, false),
+        Some("-1") | Some("reverse") => (true, true),
+        Some("no") | Some("false") | Some("0") => (false, false),
+        _ => (one_way || tag("junction") == Some("roundabout"), false),
+    };
+
+    // The lanes tag counts both directions
+    let n_lanes = match tag("lanes").and_then(|x| x.parse::<u32>().ok()) {
+        Some(n) if one_way => n,
+        Some(n) => n / 2,
+        None => n_lanes,
+    }
+    .max(1)
+    .min(4);
+
+    let sidewalks = match tag("sidewalk") {
+        Some("no") | Some("none") => false,
+        Some(_) => true,
+        None => sidewalks,
+    };
+
+    let parking = [
+        "parking:lane:both",
+        "parking:lane:right",
+        "parking:lane:left",
+    ]
+    .iter()
+    .filter_map(|&key| tag(key))
+    .fold(None, |acc, v| {
+        let allowed = !matches!(v, "no" | "no_parking" | "no_stopping" | "fire_lane");
+        Some(acc.unwrap_or(false) || allowed)
+    })
+    .unwrap_or(parking);
+
+    let bike_lanes = [
+        "cycleway",
+        "cycleway:both",
+        "cycleway:right",
+        "cycleway:left",
+    ]
+    .iter()
+    .filter_map(|&key| tag(key))
+    .any(|v| matches!(v, "lane" | "track" | "opposite_lane" | "opposite_track"));
+
+    let speed_limit = tag("maxspeed")
+        .and_then(parse_maxspeed)
+        .unwrap_or(speed_limit);
+
+    Some((
+        LanePatternBuilder::new()
+            .n_lanes(n_lanes)
+            .sidewalks(sidewalks)
+            .parking(parking)
+            .bike_lanes(bike_lanes)
+            .one_way(one_way)
+            .speed_limit(speed_limit),
+        reversed,
+    ))
+}
+
+/// Reads a maxspeed tag such as "50" or "30 mph" as km/h
+fn parse_maxspeed(v: &str) -> Option<u32> {
+    let v = v.trim();
+    let (num, mph) = match v.strip_suffix("mph") {
+        Some(num) => (num, true),
+        None => (v.strip_suffix("km/h").unwrap_or(v), false),
+    };
+    let speed = num.trim().parse::<f32>().ok().filter(|&x| x > 0.0)?;
+    Some(if mph { speed * 1.609 } else { speed }.round() as u32)
+}
+
+fn build_map(map: &mut Map, data: &OsmData, config: &OsmConfig) -> Result<(), OsmError> {
+    let inside = |id: &i64| {
+        data.nodes
+            .get(id)
+            .map_or(false, |n| config.bbox.map_or(true, |b| b.contains(n.pos)))
+    };
+
+    // Ways are cut where they leave the bounding box
+    let pieces: Vec<(&[i64], LanePatternBuilder)> = data
+        .ways
+        .iter()
+        .flat_map(move |way| {
+            way.nodes
+                .split(move |id| !inside(id))
+                .filter(|x| x.len() >= 2)
+                .map(move |x| (x, way.pattern))
+        })
+        .collect();
+
+    if pieces.is_empty() {
+        return Err(OsmError::Empty);
+    }
+
+    let center = config
+        .center
+        .or_else(|| config.bbox.map(|b| b.center()))
+        .unwrap_or_else(|| {
+            let n = pieces.iter().map(|(x, _)| x.len()).sum::<usize>() as f64;
+            let (lat, lon) = pieces
+                .iter()
+                .flat_map(|(x, _)| x.iter())
+                .map(|id| data.nodes[id].pos)
+                .fold((0.0, 0.0), |(lat, lon), p| (lat + p.lat, lon + p.lon));
+            LatLon {
+                lat: lat / n,
+                lon: lon / n,
+            }
+        });
+
+    let project = |id: &i64| -> Vec2 { center.project(data.nodes[id].pos, config.scale) };
+    map.geo_origin = Some(GeoOrigin {
+        center,
+        scale: config.scale,
+    });
+
+    // Nodes used by several ways (or twice by the same way) and way ends are junctions
+    let mut uses: HashMap<i64, u32> = HashMap::new();
+    for (nodes, _) in &pieces {
+        for id in nodes.iter() {
+            *uses.entry(*id).or_default() += 1;
+        }
+        *uses.entry(nodes[0]).or_default() += 1;
+        *uses.entry(*nodes.last().unwrap()).or_default() += 1; // Unwrap ok: len >= 2
+    }
+
+    // Ways are cut at junctions, the inner nodes of a chain only give the shape of its road
+    let mut chains: Vec<(&[i64], LanePatternBuilder)> = vec![];
+    for &(nodes, pattern) in &pieces {
+        let mut start = 0;
+        for (i, id) in nodes.iter().enumerate().skip(1) {
+            if uses[id] >= 2 {
+                split_bends(&nodes[start..=i], &project, &mut |c| {
+                    chains.push((c, pattern))
+                });
+                start = i;
+            }
+        }
+    }
+
+    // Junctions joined by a short road are fused
+    let merge_dist = MERGE_DIST * config.scale as f32;
+    let mut parent: HashMap<i64, i64> = HashMap::new();
+    for (chain, _) in &chains {
+        let len: f32 = chain
+            .windows(2)
+            .map(|w| project(&w[0]).distance(project(&w[1])))
+            .sum();
+        if len < merge_dist {
+            let a = find_root(&parent, chain[0]);
+            let b = find_root(&parent, *chain.last().unwrap()); // Unwrap ok: len >= 2
+            if a != b {
+                parent.insert(a, b);
+            }
+        }
+    }
+
+    let mut grid: SparseGrid<IntersectionID> = SparseGrid::new(50);
+    let mut inters: HashMap<i64, IntersectionID> = HashMap::new();
+    for (chain, _) in &chains {
+        for &id in &[chain[0], *chain.last().unwrap()] {
+            if inters.contains_key(&id) {
+                continue;
+            }
+            let root = find_root(&parent, id);
+            let inter = match inters.get(&root) {
+                Some(&inter) => inter,
+                None => {
+                    let pos = project(&root);
+                    let inter = map.add_intersection(pos);
+                    grid.insert(pos, inter);
+                    inters.insert(root, inter);
+                    inter
+                }
+            };
+            inters.insert(id, inter);
+        }
+    }
+
+    for (chain, pattern) in &chains {
+        let src = inters[&chain[0]];
+        let dst = inters[chain.last().unwrap()]; // Unwrap ok: len >= 2
+        if src == dst || map.find_road(src, dst).is_some() || map.find_road(dst, src).is_some() {
+            continue;
+        }
+        let points: Vec<Vec2> = chain.iter().map(|id| project(id)).collect();
+        let segment = fit_segment(
+            &points,
+            map.intersections[src].pos,
+            map.intersections[dst].pos,
+        );
+        map.connect(src, dst, &pattern.build(), segment);
+    }
+
+    let signal_dist = SIGNAL_DIST * config.scale as f32;
+    let mut controls: HashMap<IntersectionID, LightPolicy> = HashMap::new();
+    for (id, node) in &data.nodes {
+        let control = unwrap_or!(node.control, continue);
+        if !inside(id) {
+            continue;
+        }
+        let target = inters.get(id).copied().or_else(|| {
+            let pos = project(id);
+            grid.query_around(pos, signal_dist)
+                .next()
+                .map(|(h, _)| *grid.get(h).unwrap().1) // Unwrap ok: handle comes from the query
+        });
+        if let Some(target) = target {
+            // Lights take precedence over stop signs
+            let v = controls.entry(target).or_insert(control);
+            if control == LightPolicy::Lights {
+                *v = control;
+            }
+        }
+    }
+
+    for &id in grid.objects() {
+        let policy = controls.get(&id).copied().unwrap_or(config.unsignalised);
+        map.update_intersection(id, |inter| inter.light_policy = policy);
+    }
+
+    Ok(())
+}
+
+fn find_root(parent: &HashMap<i64, i64>, mut id: i64) -> i64 {
+    while let Some(&p) = parent.get(&id) {
+        id = p;
+    }
+    id
+}
+
+/// Sum of the turns along `points` and the index of the sharpest one
+fn bend(points: &[Vec2]) -> (f32, usize) {
+    let mut total = 0.0;
+    let mut sharpest = (0.0, 0);
+    for (i, w) in points.windows(3).enumerate() {
+        let turn = (w[1] - w[0]).angle(w[2] - w[1]).abs();
+        total += turn;
+        if turn > sharpest.0 {
+            sharpest = (turn, i + 1);
+        }
+    }
+    (total, sharpest.1)
+}
+
+fn split_bends<'a>(
+    chain: &'a [i64],
+    project: &impl Fn(&i64) -> Vec2,
+    out: &mut impl FnMut(&'a [i64]),
+) {
+    let points: Vec<Vec2> = chain.iter().map(project).collect();
+    let (total, sharpest) = bend(&points);
+    if total > MAX_BEND && chain.len() > 2 {
+        split_bends(&chain[..=sharpest], project, out);
+        split_bends(&chain[sharpest..], project, out);
+        return;
+    }
+    out(chain)
+}
+
+/// Spline from `from` to `to` following the inner points of the chain
+fn fit_segment(points: &[Vec2], from: Vec2, to: Vec2) -> RoadSegmentKind {
+    if points.len() <= 2 || bend(points).0 < MIN_BEND {
+        return RoadSegmentKind::Straight;
+    }
+    let len: f32 = points.windows(2).map(|w| w[0].distance(w[1])).sum();
+    let n = points.len();
+    let dir_from = points[1] - from;
+    let dir_to = to - points[n - 2];
+    match (
+        dir_from.try_normalize_to(len / 3.0),
+        dir_to.try_normalize_to(len / 3.0),
+    ) {
+        (Some(a), Some(b)) => RoadSegmentKind::Curved((a, b)),
+        _ => RoadSegmentKind::Straight,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MapCommand;
+
+    fn node(data: &mut OsmData, id: i64, x: f64, y: f64) {
+        let origin = LatLon { lat: 0.0, lon: 0.0 };
+        let pos = origin.unproject(vec2(x as f32, y as f32), 1.0);
+        data.add_node(id, pos, None);
+    }
+
+    fn way(data: &mut OsmData, nodes: Vec<i64>) {
+        let mut tags = HashMap::new();
+        tags.insert("highway".to_string(), "residential".to_string());
+        data.add_way(nodes, &tags);
+    }
+
+    #[test]
+    fn inner_nodes_shape_roads() {
+        let mut data = OsmData::default();
+        // A winding road crossing a straight one at node 3
+        for (id, (x, y)) in [
+            (0.0, 0.0),
+            (100.0, 10.0),
+            (200.0, 0.0),
+            (300.0, 0.0),
+            (400.0, 20.0),
+        ]
+        .iter()
+        .enumerate()
+        {
+            node(&mut data, id as i64, *x, *y);
+        }
+        node(&mut data, 10, 300.0, -200.0);
+        node(&mut data, 11, 300.0, 200.0);
+        way(&mut data, vec![0, 1, 2, 3, 4]);
+        way(&mut data, vec![10, 3, 11]);
+
+        let config = OsmConfig {
+            center: Some(LatLon { lat: 0.0, lon: 0.0 }),
+            ..Default::default()
+        };
+        let mut map = Map::empty();
+        build_map(&mut map, &data, &config).unwrap();
+
+        // The two ends of each way and the shared node
+        assert_eq!(map.intersections.len(), 5);
+        assert_eq!(map.roads.len(), 4);
+    }
+
+    #[test]
+    fn close_unconnected_nodes_are_not_merged() {
+        let mut data = OsmData::default();
+        node(&mut data, 0, 0.0, 0.0);
+        node(&mut data, 1, 200.0, 0.0);
+        node(&mut data, 2, 0.0, 5.0);
+        node(&mut data, 3, 200.0, 5.0);
+        node(&mut data, 4, 205.0, 5.0);
+        way(&mut data, vec![0, 1]);
+        way(&mut data, vec![2, 3, 4]);
+
+        let config = OsmConfig {
+            center: Some(LatLon { lat: 0.0, lon: 0.0 }),
+            ..Default::default()
+        };
+        let mut map = Map::empty();
+        build_map(&mut map, &data, &config).unwrap();
+
+        // Parallel ways 5m apart are not connected and stay apart
+        assert_eq!(map.intersections.len(), 4);
+        assert_eq!(map.roads.len(), 2);
+    }
+
+    #[test]
+    fn failed_import_keeps_the_map() {
+        let mut map = Map::empty();
+        map.add_intersection(vec2(0.0, 0.0));
+
+        MapCommand::ImportOsm("does_not_exist.osm".to_string(), OsmConfig::default())
+            .apply(&mut map);
+
+        assert_eq!(map.intersections.len(), 1);
+    }
+}
@@ -194,6 +194,18 @@ pub fn add_doublecircle(pos: Vec2, m: &mut Map) {
     }
 }
 
+/// Roundabout with four roads around it
+pub fn add_roundabout(pos: Vec2, m: &mut Map) {
+    let center = m.add_intersection(pos);
+    let pat = LanePatternBuilder::new().build();
+    for i in 0..4 {
+        let angle = i as f32 * std::f32::consts::FRAC_PI_2;
+        let end = m.add_intersection(pos + Vec2::from_angle(angle) * 100.0);
+        m.connect(center, end, &pat, RoadSegmentKind::Straight);
+    }
+    m.update_intersection(center, |inter| inter.turn_policy.roundabout = true);
+}
+
 pub fn add_grid(pos: Vec2, m: &mut Map, size: usize) {
     if size == 0 {
         return;
@@ -234,4 +246,5 @@ fn print_stats(map: &Map) {
 pub fn load_testfield(map: &mut Map) {
     //add_doublecircle([0.0, 0.0].into(), map);
     add_grid([0.0, 350.0].into(), map, 10);
+    add_roundabout([-300.0, 800.0].into(), map);
 }
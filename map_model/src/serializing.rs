@@ -1,7 +1,9 @@
-use crate::procgen::{Tree, Trees};
+use crate::procgen::{GeoOrigin, Tree, Trees};
 use crate::{Buildings, Intersections, Lanes, Lots, Map, ParkingSpots, Roads, SpatialMap};
 use geom::{Shape, Vec2};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::Path;
 
 #[derive(Default, Serialize, Deserialize)]
 pub struct SerializedMap {
@@ -12,6 +14,8 @@ pub struct SerializedMap {
     pub(crate) parking: ParkingSpots,
     pub(crate) lots: Lots,
     pub(crate) trees: Vec<(Vec2, Tree)>,
+    #[serde(default)]
+    pub(crate) geo_origin: Option<GeoOrigin>,
 }
 
 impl From<&Map> for SerializedMap {
@@ -28,6 +32,7 @@ impl From<&Map> for SerializedMap {
             parking: m.parking.clone(),
             lots: m.lots.clone(),
             trees: m.trees.trees().collect(),
+            geo_origin: m.geo_origin,
         }
     }
 }
@@ -35,6 +40,8 @@ impl From<&Map> for SerializedMap {
 impl From<SerializedMap> for Map {
     fn from(mut sel: SerializedMap) -> Self {
         for inter in sel.intersections.values_mut() {
+            // Turn conflicts aren't saved
+            inter.update_turns(&sel.lanes, &sel.roads);
             inter.update_polygon(&sel.roads);
         }
 
@@ -48,6 +55,7 @@ impl From<SerializedMap> for Map {
             lots: sel.lots,
             parking: sel.parking,
             trees: Trees::from_positions(sel.trees),
+            geo_origin: sel.geo_origin,
             dirty: true,
         }
     }
@@ -69,3 +77,137 @@ fn mk_spatial_map(m: &SerializedMap) -> SpatialMap {
     }
     sm
 }
+
+/// Prefix of slotmap keys in the text format, keys are written as their index only
+const KEY_PREFIX: char = '#';
+/// Field name of the objects holding the slots of a slotmap in the text format
+const SLOTS_FIELD: &str = "#slots";
+
+/// Serializes the map to pretty JSON meant to be checked into git and reviewed.
+///
+/// The plain serde representation of slotmaps carries the versions of keys and slots,
+/// which depend on the history of edits. The text format only keeps key indices
+/// (as `"#12"`) and writes slotmaps as their slots, `null` for vacant ones. Object fields
+/// are sorted and floats are written in their shortest exact form, so saving a loaded map
+/// gives back the same text and an edit only changes the objects it touches.
+pub fn map_to_json(map: &Map) -> serde_json::Result<String> {
+    let v = serde_json::to_value(SerializedMap::from(map))?;
+    serde_json::to_string_pretty(&compact_keys(v))
+}
+
+pub fn map_from_json(s: &str) -> serde_json::Result<Map> {
+    let v: serde_json::Value = serde_json::from_str(s)?;
+    let sel: SerializedMap = serde_json::from_value(expand_keys(v))?;
+    Ok(sel.into())
+}
+
+pub fn save_map_json(map: &Map, path: impl AsRef<Path>) -> Option<()> {
+    let path = path.as_ref();
+    let s = map_to_json(map)
+        .map_err(|e| error!("failed serializing map: {}", e))
+        .ok()?;
+    if let Some(parent) = path.parent() {
+        let _ = std::fs::create_dir_all(parent);
+    }
+    std::fs::write(path, s)
+        .map_err(|e| error!("{}: {}", path.display(), e))
+        .ok()?;
+    info!("successfully saved {}", path.display());
+    Some(())
+}
+
+pub fn load_map_json(path: impl AsRef<Path>) -> Option<Map> {
+    let path = path.as_ref();
+    let s = std::fs::read_to_string(path)
+        .map_err(|e| error!("{}: {}", path.display(), e))
+        .ok()?;
+    map_from_json(&s)
+        .map_err(|e| error!("failed deserializing {}: {}", path.display(), e))
+        .ok()
+}
+
+fn is_object_with(v: &Value, a: &str, b: &str) -> bool {
+    v.as_object().map_or(false, |o| {
+        o.len() == 2 && o.contains_key(a) && o.contains_key(b)
+    })
+}
+
+fn compact_keys(v: Value) -> Value {
+    match v {
+        Value::Object(o) => {
+            if o.len() == 2 && o.contains_key("version") {
+                if let Some(idx) = o.get("idx").and_then(Value::as_u64) {
+                    return Value::String(format!("{}{}", KEY_PREFIX, idx));
+                }
+            }
+            Value::Object(o.into_iter().map(|(k, v)| (k, compact_keys(v))).collect())
+        }
+        Value::Array(a) => {
+            if a.is_empty() || !a.iter().all(|x| is_object_with(x, "value", "version")) {
+                return Value::Array(a.into_iter().map(compact_keys).collect());
+            }
+            let slots = a
+                .into_iter()
+                .map(|mut slot| compact_keys(slot["value"].take()))
+                .collect();
+            let mut o = serde_json::Map::new();
+            o.insert(SLOTS_FIELD.to_string(), Value::Array(slots));
+            Value::Object(o)
+        }
+        x => x,
+    }
+}
+
+/// Inverse of `compact_keys`. Occupied slots and keys all get version 1.
+fn expand_keys(v: Value) -> Value {
+    match v {
+        Value::String(s) => {
+            if s.starts_with(KEY_PREFIX) {
+                if let Ok(idx) = s[KEY_PREFIX.len_utf8()..].parse::<u32>() {
+                    return serde_json::json!({"idx": idx, "version": 1});
+                }
+            }
+            Value::String(s)
+        }
+        Value::Object(mut o) => {
+            if o.len() == 1 {
+                if let Some(Value::Array(slots)) = o.remove(SLOTS_FIELD) {
+                    return Value::Array(
+                        slots
+                            .into_iter()
+                            .map(|x| match x {
+                                Value::Null => serde_json::json!({"value": null, "version": 0}),
+                                x => serde_json::json!({"value": expand_keys(x), "version": 1}),
+                            })
+                            .collect(),
+                    );
+                }
+            }
+            Value::Object(o.into_iter().map(|(k, v)| (k, expand_keys(v))).collect())
+        }
+        Value::Array(a) => Value::Array(a.into_iter().map(expand_keys).collect()),
+        x => x,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{map_from_json, map_to_json};
+    use crate::Map;
+
+    #[test]
+    fn json_roundtrip_is_stable() {
+        let mut map = Map::empty();
+        crate::procgen::load_testfield(&mut map);
+        let r = map.roads().keys().next().unwrap();
+        map.remove_road(r);
+
+        let text = map_to_json(&map).unwrap();
+        let loaded = map_from_json(&text).unwrap();
+
+        assert_eq!(loaded.roads().len(), map.roads().len());
+        assert_eq!(loaded.lanes().len(), map.lanes().len());
+        assert_eq!(loaded.intersections().len(), map.intersections().len());
+        assert_eq!(text, map_to_json(&loaded).unwrap());
+    }
+}
@@ -0,0 +1,413 @@
+use crate::{Intersection, LaneID, Roads, TrafficBehavior, TurnID};
+use common::{DayTime, SECONDS_PER_DAY, SECONDS_PER_HOUR};
+use serde::{Deserialize, Serialize};
+
+/// Cycles of the new plan during which it catches up with its offset after a plan change
+const TRANSITION_CYCLES: u32 = 2;
+
+/// Interval of a signal plan during which its movements have green together
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignalPhase {
+    /// Incoming lanes given green
+    pub lanes: Vec<LaneID>,
+    /// Turns allowed from the lanes. All the turns of a lane with none listed are allowed.
+    pub turns: Vec<TurnID>,
+    /// Durations in seconds
+    pub green: u32,
+    pub orange: u32,
+    /// Everything stays red after the orange to clear the intersection
+    pub all_red: u32,
+    /// Seconds at the start of the green during which pedestrians may cross, 0 if they can't
+    pub walk: u32,
+}
+
+impl SignalPhase {
+    /// Pedestrians may cross during the whole green
+    pub fn new(lanes: Vec<LaneID>) -> Self {
+        let green = 10;
+        Self {
+            lanes,
+            turns: vec![],
+            green,
+            orange: 4,
+            all_red: 0,
+            walk: green,
+        }
+    }
+
+    pub fn duration(&self) -> u32 {
+        self.green + self.orange + self.all_red
+    }
+
+    /// Whether the turn is part of the phase
+    pub fn allows(&self, turn: TurnID) -> bool {
+        self.lanes.contains(&turn.src)
+            && (self.turns.contains(&turn) || self.turns.iter().all(|t| t.src != turn.src))
+    }
+
+    /// Behavior of the lights of the phase `t` seconds after it started
+    pub fn behavior(&self, t: u32) -> TrafficBehavior {
+        if t < self.green {
+            TrafficBehavior::GREEN
+        } else if t < self.green + self.orange {
+            TrafficBehavior::ORANGE
+        } else {
+            TrafficBehavior::RED
+        }
+    }
+
+    /// Whether pedestrians may cross `t` seconds after the phase started
+    pub fn walk_allowed(&self, t: u32) -> bool {
+        t < self.walk.min(self.green)
+    }
+}
+
+/// Fixed-time plan of an intersection, written phase by phase to replicate real timings
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignalPlan {
+    #[serde(default)]
+    pub name: String,
+    pub phases: Vec<SignalPhase>,
+    /// Seconds of the cycle already elapsed at time 0
+    pub offset: u32,
+    /// The lights blink: the lanes of the first phase go through, the others stop and yield
+    #[serde(default)]
+    pub flash: bool,
+}
+
+impl SignalPlan {
+    /// Same phases as the lights placed by the light policy, opposite roads going together
+    pub fn generate(inter: &Intersection, roads: &Roads) -> Self {
+        let in_road_lanes = inter.incoming_signal_lanes(roads);
+        let n_phases = (in_road_lanes.len() + 1) / 2;
+        let mut phases: Vec<SignalPhase> =
+            (0..n_phases).map(|_| SignalPhase::new(vec![])).collect();
+        for (i, lanes) in in_road_lanes.into_iter().enumerate() {
+            phases[i % n_phases].lanes.extend(lanes);
+        }
+        Self {
+            name: "Default".to_string(),
+            phases,
+            offset: 0,
+            flash: false,
+        }
+    }
+
+    pub fn cycle(&self) -> u32 {
+        self.phases.iter().map(SignalPhase::duration).sum()
+    }
+
+    /// Index of the phase running at `seconds` and seconds since it started
+    pub fn phase_at(&self, seconds: u32) -> Option<(usize, u32)> {
+        let cycle = self.cycle();
+        if cycle == 0 {
+            return None;
+        }
+        let mut t = (seconds + self.offset) % cycle;
+        for (i, phase) in self.phases.iter().enumerate() {
+            if t < phase.duration() {
+                return Some((i, t));
+            }
+            t -= phase.duration();
+        }
+        None
+    }
+
+    /// Lanes given green by the phases
+    pub fn lanes(&self) -> impl Iterator<Item = LaneID> + '_ {
+        self.phases
+            .iter()
+            .flat_map(|phase| phase.lanes.iter().copied())
+    }
+
+    /// Whether the lane is given green by one of the phases
+    pub fn controls(&self, lane: LaneID) -> bool {
+        self.phases.iter().any(|phase| phase.lanes.contains(&lane))
+    }
+
+    /// Lanes left out of every phase stop and yield as at a stop sign instead of staying red
+    pub fn lane_behavior(&self, lane: LaneID, seconds: u32) -> TrafficBehavior {
+        if !self.controls(lane) {
+            return TrafficBehavior::STOP;
+        }
+        if self.flash {
+            return match self.phases.first() {
+                Some(phase) if phase.lanes.contains(&lane) => TrafficBehavior::GREEN,
+                _ => TrafficBehavior::STOP,
+            };
+        }
+        match self.phase_at(seconds) {
+            Some((i, t)) if self.phases[i].lanes.contains(&lane) => self.phases[i].behavior(t),
+            _ => TrafficBehavior::RED,
+        }
+    }
+
+    /// Whether vehicles may take the turn at `seconds`, their lane must not be red either
+    pub fn turn_allowed(&self, turn: TurnID, seconds: u32) -> bool {
+        if self.flash || !self.controls(turn.src) {
+            return true;
+        }
+        match self.phase_at(seconds) {
+            Some((i, t)) => {
+                let phase = &self.phases[i];
+                phase.allows(turn) && !phase.behavior(t).is_red()
+            }
+            None => true,
+        }
+    }
+
+    /// Whether pedestrians may cross at `seconds`
+    pub fn walk_allowed(&self, seconds: u32) -> bool {
+        if self.flash {
+            return true;
+        }
+        match self.phase_at(seconds) {
+            Some((i, t)) => self.phases[i].walk_allowed(t),
+            None => true,
+        }
+    }
+
+    /// Forgets the lanes and turns that aren't signalized anymore
+    pub fn retain_lanes(&mut self, keep: impl Fn(LaneID) -> bool) {
+        for phase in &mut self.phases {
+            phase.lanes.retain(|&l| keep(l));
+            let lanes = &phase.lanes;
+            phase.turns.retain(|t| lanes.contains(&t.src));
+        }
+    }
+}
+
+/// Hour of the day from which a plan of the schedule runs, until the next period starts
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SchedulePeriod {
+    pub hour: i32,
+    /// 0 for the signal plan of the intersection, `i` for the plan `i - 1` of the schedule
+    pub plan: usize,
+}
+
+/// Plans taking over from the signal plan of an intersection at times of the day.
+/// A new plan takes over at the end of the cycle of the previous one, starting with its first
+/// phase, then runs slightly faster until it catches up with its offset.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignalSchedule {
+    pub plans: Vec<SignalPlan>,
+    /// The signal plan runs all day without periods
+    pub periods: Vec<SchedulePeriod>,
+}
+
+impl SignalSchedule {
+    /// Peak plans with longer greens in the morning and evening, flashing lights at night,
+    /// `base` running off-peak
+    pub fn daily(base: &SignalPlan) -> Self {
+        let mut peak = base.clone();
+        for phase in &mut peak.phases {
+            phase.green += phase.green / 2;
+            phase.walk += phase.walk / 2;
+        }
+        let am_peak = SignalPlan {
+            name: "AM peak".to_string(),
+            ..peak.clone()
+        };
+        let pm_peak = SignalPlan {
+            name: "PM peak".to_string(),
+            ..peak
+        };
+        let night = SignalPlan {
+            name: "Night flash".to_string(),
+            flash: true,
+            ..base.clone()
+        };
+
+        let period = |hour, plan| SchedulePeriod { hour, plan };
+        Self {
+            plans: vec![am_peak, pm_peak, night],
+            periods: vec![
+                period(6, 0),
+                period(7, 1),
+                period(10, 0),
+                period(16, 2),
+                period(19, 0),
+                period(22, 3),
+            ],
+        }
+    }
+
+    /// Plan `i` of the periods
+    pub fn plan<'a>(&'a self, base: &'a SignalPlan, i: usize) -> Option<&'a SignalPlan> {
+        match i {
+            0 => Some(base),
+            _ => self.plans.get(i - 1),
+        }
+    }
+
+    /// Removes the plan `i` of the periods and the periods running it, the signal plan stays
+    pub fn remove_plan(&mut self, i: usize) {
+        if i == 0 || i > self.plans.len() {
+            return;
+        }
+        self.plans.remove(i - 1);
+        self.periods.retain(|p| p.plan != i);
+        for p in &mut self.periods {
+            if p.plan > i {
+                p.plan -= 1;
+            }
+        }
+    }
+
+    /// Plan running at `seconds` (since the start of the game) and the time at which to read it.
+    /// The time is shifted during the transition from the previous plan.
+    pub fn current<'a>(&'a self, base: &'a SignalPlan, seconds: u32) -> (&'a SignalPlan, u32) {
+        let daytime = DayTime::new(seconds as i32);
+        let daysec = daytime.daysec() as i64;
+        let periods = || {
+            self.periods
+                .iter()
+                .filter(move |p| p.plan <= self.plans.len())
+        };
+        let last_before = |hour: i32| {
+            periods()
+                .filter(|p| p.hour < hour)
+                .max_by_key(|p| p.hour)
+                // Wraps around to the last period of the previous day
+                .or_else(|| periods().max_by_key(|p| p.hour))
+                .copied()
+        };
+        let plan = |i| self.plan(base, i).unwrap_or(base);
+
+        let cur = match last_before(daytime.hour + 1) {
+            Some(p) => p,
+            None => return (base, seconds),
+        };
+        let prev = last_before(cur.hour).unwrap_or(cur);
+        let new = plan(cur.plan);
+        if prev.plan == cur.plan {
+            return (new, seconds);
+        }
+        let old = plan(prev.plan);
+
+        let mut start = (cur.hour * SECONDS_PER_HOUR) as i64;
+        if start > daysec {
+            start -= SECONDS_PER_DAY as i64;
+        }
+        let start = seconds as i64 - daysec + start;
+        if start < 0 {
+            return (new, seconds);
+        }
+        let start = start as u32;
+
+        let old_cycle = old.cycle();
+        let switch = if old.flash || old_cycle == 0 {
+            start
+        } else {
+            start + (old_cycle - (start + old.offset) % old_cycle) % old_cycle
+        };
+        if seconds < switch {
+            return (old, seconds);
+        }
+
+        let cycle = new.cycle();
+        let transition = TRANSITION_CYCLES * cycle;
+        let elapsed = seconds - switch;
+        if new.flash || cycle == 0 || elapsed >= transition {
+            return (new, seconds);
+        }
+
+        // Time into the cycle of the new plan, 0 at the switch, then catching up with the offset
+        let target = (switch + new.offset) % cycle;
+        let t = elapsed + target * elapsed / transition;
+        (new, (t + cycle - new.offset % cycle) % cycle)
+    }
+
+    pub fn retain_lanes(&mut self, keep: impl Fn(LaneID) -> bool) {
+        for plan in &mut self.plans {
+            plan.retain_lanes(&keep);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{IntersectionID, LanePatternBuilder, Map, RoadSegmentKind};
+    use geom::vec2;
+
+    /// Four roads with sidewalks meeting at the returned intersection
+    fn crossing(map: &mut Map) -> IntersectionID {
+        let center = map.add_intersection(vec2(0.0, 0.0));
+        let pattern = LanePatternBuilder::new().n_lanes(2).build();
+        for &(x, y) in &[(100.0, 0.0), (0.0, 100.0), (-100.0, 0.0), (0.0, -100.0)] {
+            let arm = map.add_intersection(vec2(x, y));
+            map.connect(center, arm, &pattern, RoadSegmentKind::Straight);
+        }
+        center
+    }
+
+    #[test]
+    fn generated_plan_lets_pedestrians_cross() {
+        let mut map = Map::empty();
+        let id = crossing(&mut map);
+        let plan = SignalPlan::generate(&map.intersections()[id], map.roads());
+        assert_eq!(plan.phases.len(), 2);
+        map.update_intersection(id, |inter| inter.signal_plan = Some(plan.clone()));
+
+        let inter = &map.intersections()[id];
+        let crosswalks: Vec<TurnID> = inter
+            .turns()
+            .iter()
+            .filter(|t| t.kind.is_crosswalk())
+            .map(|t| t.id)
+            .collect();
+        assert!(!crosswalks.is_empty());
+        for turn in crosswalks {
+            assert!((0..plan.cycle()).any(|s| map.turn_allowed(turn, s)));
+        }
+    }
+
+    #[test]
+    fn lanes_in_no_phase_are_not_left_red() {
+        let mut map = Map::empty();
+        let id = crossing(&mut map);
+        let mut plan = SignalPlan::generate(&map.intersections()[id], map.roads());
+        let left_out = plan.phases.pop().unwrap().lanes;
+
+        let inter = &map.intersections()[id];
+        let turns: Vec<TurnID> = inter
+            .turns()
+            .iter()
+            .filter(|t| t.kind.is_driving() && left_out.contains(&t.id.src))
+            .map(|t| t.id)
+            .collect();
+        assert!(!turns.is_empty());
+        for s in 0..plan.cycle() {
+            for &l in &left_out {
+                assert_eq!(plan.lane_behavior(l, s), TrafficBehavior::STOP);
+            }
+            for &turn in &turns {
+                assert!(plan.turn_allowed(turn, s));
+            }
+        }
+    }
+
+    #[test]
+    fn daily_schedule_runs_the_signal_plan_off_peak() {
+        let base = SignalPlan {
+            name: "Default".to_string(),
+            phases: vec![SignalPhase::new(vec![]), SignalPhase::new(vec![])],
+            offset: 0,
+            flash: false,
+        };
+        let schedule = SignalSchedule::daily(&base);
+        let at = |hour: i32| {
+            let seconds = (hour * SECONDS_PER_HOUR) as u32;
+            schedule.current(&base, seconds).0.name.clone()
+        };
+        assert_eq!(at(3), "Night flash");
+        assert_eq!(at(8), "AM peak");
+        assert_eq!(at(12), "Default");
+        assert_eq!(at(17), "PM peak");
+        assert_eq!(at(23), "Night flash");
+        for phase in schedule.plans.iter().flat_map(|p| &p.phases) {
+            assert!(phase.walk > 0 && phase.walk <= phase.green);
+        }
+    }
+}
@@ -0,0 +1,300 @@
+use crate::{LaneID, TrafficBehavior};
+use serde::{Deserialize, Serialize};
+
+/// Green time of the phases of actuated signals, in seconds
+pub const MIN_GREEN: f32 = 6.0;
+pub const MAX_GREEN: f32 = 30.0;
+pub const ACTUATED_ORANGE: f32 = 4.0;
+
+/// Length of the cycle given to every phase of coordinated signals, in seconds.
+/// The same as the fixed-time lights for them to be compared.
+pub const CYCLE_PER_PHASE: f32 = 14.0;
+
+/// How the lights of the signalized intersections are timed
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SignalControl {
+    /// Fixed green, orange and red durations
+    FixedTime,
+    /// Phases extend or skip depending on what the detectors see
+    Actuated,
+}
+
+impl Default for SignalControl {
+    fn default() -> Self {
+        SignalControl::FixedTime
+    }
+}
+
+/// Demand measured by the virtual detector covering the end of an incoming lane
+#[derive(Copy, Clone, Debug, Default)]
+pub struct DetectorReading {
+    /// Vehicles stopped or crawling towards the stop line
+    pub queue: u32,
+    /// Vehicles arriving at speed
+    pub arrivals: u32,
+}
+
+impl DetectorReading {
+    pub fn has_demand(&self) -> bool {
+        self.queue + self.arrivals > 0
+    }
+}
+
+/// Ties an actuated signal to its neighbours along a corridor for a green wave.
+/// The coordinated phase turns green `offset` seconds into every cycle.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct Coordination {
+    pub phase: usize,
+    pub cycle: f32,
+    pub offset: f32,
+}
+
+impl Coordination {
+    /// Seconds since the coordinated phase was due to turn green
+    fn cycle_time(&self, seconds: f32) -> f32 {
+        (seconds - self.offset).rem_euclid(self.cycle)
+    }
+}
+
+/// Controller of an intersection whose lights respond to the queues on its incoming lanes
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ActuatedSignal {
+    /// Incoming lanes getting green together, served in order
+    pub phases: Vec<Vec<LaneID>>,
+    pub min_green: f32,
+    pub max_green: f32,
+    pub orange: f32,
+    pub coordination: Option<Coordination>,
+
+    cur: usize,
+    /// Seconds since the current phase turned green, or orange when `ending`
+    elapsed: f32,
+    ending: bool,
+}
+
+impl ActuatedSignal {
+    pub fn new(phases: Vec<Vec<LaneID>>) -> Self {
+        Self {
+            phases,
+            min_green: MIN_GREEN,
+            max_green: MAX_GREEN,
+            orange: ACTUATED_ORANGE,
+            coordination: None,
+            cur: 0,
+            elapsed: 0.0,
+            ending: false,
+        }
+    }
+
+    pub fn phase(&self) -> usize {
+        self.cur
+    }
+
+    pub fn phase_of(&self, lane: LaneID) -> Option<usize> {
+        self.phases.iter().position(|p| p.contains(&lane))
+    }
+
+    pub fn behavior(&self, lane: LaneID) -> TrafficBehavior {
+        match self.phases.get(self.cur) {
+            Some(p) if p.contains(&lane) => {
+                if self.ending {
+                    TrafficBehavior::ORANGE
+                } else {
+                    TrafficBehavior::GREEN
+                }
+            }
+            _ => TrafficBehavior::RED,
+        }
+    }
+
+    /// Advances the controller by `delta` seconds, `seconds` being the current time
+    pub fn update(&mut self, delta: f32, seconds: f32, demand: impl Fn(LaneID) -> DetectorReading) {
+        if self.phases.is_empty() {
+            return;
+        }
+        self.elapsed += delta;
+
+        let phase_demand = |phase: &[LaneID]| {
+            phase.iter().fold(DetectorReading::default(), |acc, &l| {
+                let r = demand(l);
+                DetectorReading {
+                    queue: acc.queue + r.queue,
+                    arrivals: acc.arrivals + r.arrivals,
+                }
+            })
+        };
+
+        if self.ending {
+            if self.elapsed >= self.orange {
+                self.cur = self.next_phase(seconds, &phase_demand);
+                self.elapsed = 0.0;
+                self.ending = false;
+            }
+            return;
+        }
+
+        if self.elapsed < self.min_green {
+            return;
+        }
+
+        if let Some(c) = self.coordination {
+            let t = c.cycle_time(seconds);
+            if self.cur == c.phase && t < self.max_green {
+                return;
+            }
+            // Force-off so that the coordinated phase is green on time
+            if self.cur != c.phase && t >= c.cycle - self.orange {
+                self.ending = true;
+                self.elapsed = 0.0;
+                return;
+            }
+        }
+
+        let n = self.phases.len();
+        let others_waiting = (1..n)
+            .map(|i| self.phases[(self.cur + i) % n].as_slice())
+            .any(|p| phase_demand(p).has_demand());
+        // Rest in green while nobody else is waiting
+        if !others_waiting {
+            return;
+        }
+
+        let extend = phase_demand(self.phases[self.cur].as_slice()).arrivals > 0
+            && self.elapsed < self.max_green;
+        if !extend {
+            self.ending = true;
+            self.elapsed = 0.0;
+        }
+    }
+
+    /// First phase after the current one with vehicles waiting, phases without demand are skipped
+    fn next_phase(
+        &self,
+        seconds: f32,
+        phase_demand: &impl Fn(&[LaneID]) -> DetectorReading,
+    ) -> usize {
+        if let Some(c) = self.coordination {
+            let t = c.cycle_time(seconds);
+            if c.phase < self.phases.len() && (t >= c.cycle - self.orange || t < self.min_green) {
+                return c.phase;
+            }
+        }
+
+        let n = self.phases.len();
+        (1..=n)
+            .map(|i| (self.cur + i) % n)
+            .find(|&i| phase_demand(self.phases[i].as_slice()).has_demand())
+            .unwrap_or((self.cur + 1) % n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slotmap::DenseSlotMap;
+
+    const STEP: f32 = 0.1;
+
+    fn signal() -> (ActuatedSignal, LaneID, LaneID) {
+        let mut ids = DenseSlotMap::<LaneID, ()>::with_key();
+        let (a, b) = (ids.insert(()), ids.insert(()));
+        (ActuatedSignal::new(vec![vec![a], vec![b]]), a, b)
+    }
+
+    /// Runs the signal from `from` to `to` seconds with the detectors reading `demand`
+    fn run(
+        s: &mut ActuatedSignal,
+        from: f32,
+        to: f32,
+        demand: impl Fn(f32, LaneID) -> DetectorReading,
+    ) {
+        let mut t = from;
+        while t < to {
+            t += STEP;
+            s.update(STEP, t, |l| demand(t, l));
+        }
+    }
+
+    fn reading(queue: u32, arrivals: u32) -> DetectorReading {
+        DetectorReading { queue, arrivals }
+    }
+
+    #[test]
+    fn min_green_is_always_given() {
+        let (mut s, a, b) = signal();
+        let demand = |_, l| reading((l == b) as u32, 0);
+
+        run(&mut s, 0.0, MIN_GREEN - 1.0, demand);
+        assert_eq!(s.behavior(a), TrafficBehavior::GREEN);
+
+        run(&mut s, MIN_GREEN - 1.0, MIN_GREEN + 1.0, demand);
+        assert_eq!(s.behavior(a), TrafficBehavior::ORANGE);
+        assert_eq!(s.behavior(b), TrafficBehavior::RED);
+
+        run(
+            &mut s,
+            MIN_GREEN + 1.0,
+            MIN_GREEN + ACTUATED_ORANGE + 2.0,
+            demand,
+        );
+        assert_eq!(s.phase(), 1);
+        assert_eq!(s.behavior(a), TrafficBehavior::RED);
+        assert_eq!(s.behavior(b), TrafficBehavior::GREEN);
+    }
+
+    #[test]
+    fn arrivals_extend_up_to_max_green() {
+        let (mut s, a, b) = signal();
+        let demand = |_, l| {
+            if l == a {
+                reading(0, 1)
+            } else {
+                reading(1, 0)
+            }
+        };
+
+        run(&mut s, 0.0, MAX_GREEN - 1.0, demand);
+        assert_eq!(s.behavior(a), TrafficBehavior::GREEN);
+
+        run(&mut s, MAX_GREEN - 1.0, MAX_GREEN + 1.0, demand);
+        assert_eq!(s.behavior(a), TrafficBehavior::ORANGE);
+    }
+
+    #[test]
+    fn gap_out_ends_the_phase() {
+        let (mut s, a, b) = signal();
+        let gap = 12.0;
+        let demand = move |t: f32, l| {
+            if l == a {
+                reading(0, (t < gap) as u32)
+            } else {
+                reading(1, 0)
+            }
+        };
+
+        run(&mut s, 0.0, gap - 0.5, demand);
+        assert_eq!(s.behavior(a), TrafficBehavior::GREEN);
+
+        run(&mut s, gap - 0.5, gap + 0.5, demand);
+        assert_eq!(s.behavior(a), TrafficBehavior::ORANGE);
+    }
+
+    #[test]
+    fn rests_in_green_without_other_demand() {
+        let (mut s, a, _) = signal();
+        run(&mut s, 0.0, MAX_GREEN * 2.0, |_, _| reading(0, 0));
+        assert_eq!(s.behavior(a), TrafficBehavior::GREEN);
+    }
+
+    #[test]
+    fn empty_phases_are_skipped() {
+        let mut ids = DenseSlotMap::<LaneID, ()>::with_key();
+        let (a, b, c) = (ids.insert(()), ids.insert(()), ids.insert(()));
+        let mut s = ActuatedSignal::new(vec![vec![a], vec![b], vec![c]]);
+        let demand = |_, l| reading((l == c) as u32, 0);
+
+        run(&mut s, 0.0, MIN_GREEN + ACTUATED_ORANGE + 1.0, demand);
+        assert_eq!(s.phase(), 2);
+        assert_eq!(s.behavior(c), TrafficBehavior::GREEN);
+    }
+}
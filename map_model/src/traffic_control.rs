@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Copy, Clone, Serialize, Deserialize)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum TrafficBehavior {
     RED,
     ORANGE,
@@ -33,12 +33,31 @@ impl TrafficLightSchedule {
             offset,
         }
     }
+
+    pub fn period(&self) -> usize {
+        self.period
+    }
+
+    /// Same schedule with the green starting `delta` seconds earlier
+    pub fn shifted(self, delta: usize) -> Self {
+        Self {
+            offset: (self.offset + delta) % self.period,
+            ..self
+        }
+    }
+
+    /// Seconds of the period at which the green starts
+    pub fn green_start(&self) -> usize {
+        (self.period - self.offset % self.period) % self.period
+    }
 }
 
 #[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum TrafficControl {
     Always,
     Light(TrafficLightSchedule),
+    /// Light set every tick by the signal plan or the actuated signal of its intersection
+    Signal(TrafficBehavior),
     StopSign,
 }
 
@@ -52,7 +71,7 @@ impl TrafficControl {
     }
 
     pub fn is_light(&self) -> bool {
-        matches!(self, TrafficControl::Light(_))
+        matches!(self, TrafficControl::Light(_) | TrafficControl::Signal(_))
     }
 
     pub fn get_behavior(&self, seconds: u32) -> TrafficBehavior {
@@ -68,6 +87,7 @@ impl TrafficControl {
                     TrafficBehavior::RED
                 }
             }
+            TrafficControl::Signal(behavior) => *behavior,
             TrafficControl::StopSign => TrafficBehavior::STOP,
         }
     }
@@ -0,0 +1,318 @@
+use crate::{ring_entry, LaneID, Lanes, Roads, Turn, TurnID};
+use geom::Vec2;
+use serde::{Deserialize, Serialize};
+
+/// How the paths of two turns of an intersection meet
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConflictKind {
+    Crossing,
+    /// Both turns lead to the same lane
+    Merging,
+    /// One of the turns joins the ring of a roundabout where the other goes around it
+    Entering,
+}
+
+/// Other turn of the same intersection whose path meets this one
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct TurnConflict {
+    pub other: TurnID,
+    pub kind: ConflictKind,
+    /// Distance along this turn to the conflict point
+    pub dist: f32,
+    /// Distance along the other turn to the conflict point
+    pub other_dist: f32,
+    /// Whether vehicles taking this turn give way to the ones taking the other
+    pub yields: bool,
+}
+
+/// Direction vehicles enter the turn with and direction they leave it with
+fn directions(id: TurnID, lanes: &Lanes) -> (Vec2, Vec2) {
+    (
+        -lanes[id.src].orientation_from(id.parent),
+        lanes[id.dst].orientation_from(id.parent),
+    )
+}
+
+/// Left turns and U-turns cross the oncoming traffic
+fn is_left(id: TurnID, lanes: &Lanes) -> bool {
+    let (enter, leave) = directions(id, lanes);
+    enter.perp_dot(leave) > 0.1 || enter.dot(leave) < -0.9
+}
+
+/// Importance of the road the lane comes from, lanes with a stop sign are always minor
+fn rank(lane: LaneID, lanes: &Lanes, roads: &Roads) -> (bool, u32, usize) {
+    let lane = &lanes[lane];
+    let road = &roads[lane.parent];
+    (
+        !lane.control.is_stop_sign(),
+        road.speed_limit,
+        road.n_lanes(),
+    )
+}
+
+/// Whether vehicles taking `a` give way to the ones taking `b`.
+/// The minor road gives way to the major road, then permissive left turns give way to the
+/// oncoming traffic, then vehicles give way to the ones coming from their right.
+/// Exactly one of the two turns gives way.
+fn gives_way(a: TurnID, b: TurnID, lanes: &Lanes, roads: &Roads) -> bool {
+    let (rank_a, rank_b) = (rank(a.src, lanes, roads), rank(b.src, lanes, roads));
+    if rank_a != rank_b {
+        return rank_a < rank_b;
+    }
+
+    let (enter_a, _) = directions(a, lanes);
+    let (enter_b, _) = directions(b, lanes);
+    let (left_a, left_b) = (is_left(a, lanes), is_left(b, lanes));
+    if enter_a.dot(enter_b) < -0.7 && left_a != left_b {
+        return left_a;
+    }
+
+    let from_right = enter_a.perp_dot(enter_b);
+    if from_right.abs() > 0.1 {
+        return from_right > 0.0;
+    }
+
+    a > b
+}
+
+/// Distances along both polylines to their first crossing point
+fn crossing(a: &[Vec2], b: &[Vec2]) -> Option<(f32, f32)> {
+    let mut dist_a = 0.0;
+    for wa in a.windows(2) {
+        let r = wa[1] - wa[0];
+        let mut dist_b = 0.0;
+        for wb in b.windows(2) {
+            let s = wb[1] - wb[0];
+            let div = r.perp_dot(s);
+            if div.abs() > 1e-6 {
+                let q = wb[0] - wa[0];
+                let t = q.perp_dot(s) / div;
+                let u = q.perp_dot(r) / div;
+                if (0.0..=1.0).contains(&t) && (0.0..=1.0).contains(&u) {
+                    return Some((dist_a + t * r.magnitude(), dist_b + u * s.magnitude()));
+                }
+            }
+            dist_b += s.magnitude();
+        }
+        dist_a += r.magnitude();
+    }
+    None
+}
+
+/// Vehicles joining the ring of a roundabout give way to the ones going past their entry,
+/// the paths of the turns only meet on the ring
+fn roundabout_conflicts(
+    turns: &[Turn],
+    lanes: &Lanes,
+    center: Vec2,
+    radius: f32,
+) -> Vec<Vec<TurnConflict>> {
+    let mut conflicts = vec![vec![]; turns.len()];
+    for (i, a) in turns.iter().enumerate() {
+        if !a.kind.is_driving() {
+            continue;
+        }
+        let entry = ring_entry(a.id, lanes, center, radius);
+        let dist_a = a.points.distance_along(entry);
+
+        for (j, b) in turns.iter().enumerate() {
+            if !b.kind.is_driving() || a.id.src == b.id.src || b.points.project_dist(entry) > 1.0 {
+                continue;
+            }
+            let dist_b = b.points.distance_along(b.points.project(entry));
+
+            conflicts[i].push(TurnConflict {
+                other: b.id,
+                kind: ConflictKind::Entering,
+                dist: dist_a,
+                other_dist: dist_b,
+                yields: true,
+            });
+            conflicts[j].push(TurnConflict {
+                other: a.id,
+                kind: ConflictKind::Entering,
+                dist: dist_b,
+                other_dist: dist_a,
+                yields: false,
+            });
+        }
+    }
+    conflicts
+}
+
+/// Conflicts of each of the turns between the driving turns whose paths meet.
+/// `ring` is the center and radius of the ring when the intersection is a roundabout.
+pub(crate) fn turn_conflicts(
+    turns: &[Turn],
+    lanes: &Lanes,
+    roads: &Roads,
+    ring: Option<(Vec2, f32)>,
+) -> Vec<Vec<TurnConflict>> {
+    if let Some((center, radius)) = ring {
+        return roundabout_conflicts(turns, lanes, center, radius);
+    }
+
+    let mut conflicts = vec![vec![]; turns.len()];
+    for (i, a) in turns.iter().enumerate() {
+        for (j, b) in turns.iter().enumerate().skip(i + 1) {
+            if !a.kind.is_driving() || !b.kind.is_driving() || a.id.src == b.id.src {
+                continue;
+            }
+
+            let (kind, dist_a, dist_b) = if a.id.dst == b.id.dst {
+                (ConflictKind::Merging, a.points.length(), b.points.length())
+            } else {
+                let (dist_a, dist_b) =
+                    unwrap_or!(crossing(a.points.as_slice(), b.points.as_slice()), continue);
+                (ConflictKind::Crossing, dist_a, dist_b)
+            };
+
+            let yields = gives_way(a.id, b.id, lanes, roads);
+            conflicts[i].push(TurnConflict {
+                other: b.id,
+                kind,
+                dist: dist_a,
+                other_dist: dist_b,
+                yields,
+            });
+            conflicts[j].push(TurnConflict {
+                other: a.id,
+                kind,
+                dist: dist_b,
+                other_dist: dist_a,
+                yields: !yields,
+            });
+        }
+    }
+    conflicts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{IntersectionID, LanePatternBuilder, Map, RoadSegmentKind};
+    use geom::vec2;
+
+    /// Roads going east and west with `major` lanes crossing roads going north and south
+    /// with `minor` lanes
+    fn four_way(map: &mut Map, major: u32, minor: u32) -> IntersectionID {
+        let center = map.add_intersection(vec2(0.0, 0.0));
+        let major = LanePatternBuilder::new()
+            .n_lanes(major)
+            .speed_limit(70)
+            .build();
+        let minor = LanePatternBuilder::new().n_lanes(minor).build();
+        for &(x, y, pattern) in &[
+            (100.0, 0.0, &major),
+            (0.0, 100.0, &minor),
+            (-100.0, 0.0, &major),
+            (0.0, -100.0, &minor),
+        ] {
+            let arm = map.add_intersection(vec2(x, y));
+            map.connect(center, arm, pattern, RoadSegmentKind::Straight);
+        }
+        center
+    }
+
+    /// Pairs of turns of the intersection and the conflict of the first one with the second
+    fn conflicts(map: &Map, id: IntersectionID) -> Vec<(&Turn, &Turn, TurnConflict)> {
+        let turns = map.intersections()[id].turns();
+        let mut pairs = vec![];
+        for a in turns {
+            for &c in &a.conflicts {
+                let b = turns.iter().find(|t| t.id == c.other).unwrap();
+                pairs.push((a, b, c));
+            }
+        }
+        pairs
+    }
+
+    #[test]
+    fn crossing_measures_both_paths() {
+        let a = [vec2(0.0, 0.0), vec2(10.0, 0.0), vec2(20.0, 0.0)];
+        let b = [vec2(15.0, -5.0), vec2(15.0, 5.0)];
+        assert_eq!(crossing(&a, &b), Some((15.0, 5.0)));
+        assert_eq!(crossing(&b, &a), Some((5.0, 15.0)));
+
+        let parallel = [vec2(0.0, 1.0), vec2(20.0, 1.0)];
+        assert_eq!(crossing(&a, &parallel), None);
+    }
+
+    #[test]
+    fn exactly_one_turn_of_a_conflict_yields() {
+        let mut map = Map::empty();
+        let id = four_way(&mut map, 1, 1);
+        let pairs = conflicts(&map, id);
+        assert!(!pairs.is_empty());
+        for (a, b, c) in pairs {
+            let back = b.conflicts.iter().find(|x| x.other == a.id).unwrap();
+            assert_ne!(c.yields, back.yields);
+            assert_eq!(c.kind, back.kind);
+            assert_eq!(
+                gives_way(a.id, b.id, map.lanes(), map.roads()),
+                !gives_way(b.id, a.id, map.lanes(), map.roads())
+            );
+        }
+    }
+
+    #[test]
+    fn minor_road_yields() {
+        let mut map = Map::empty();
+        let id = four_way(&mut map, 2, 1);
+        let road_of = |t: &Turn| map.lanes()[t.id.src].parent;
+        let is_major = |t: &Turn| map.roads()[road_of(t)].speed_limit == 70;
+
+        let mut checked = 0;
+        for (a, b, c) in conflicts(&map, id) {
+            if is_major(a) != is_major(b) {
+                assert_eq!(c.yields, !is_major(a));
+                checked += 1;
+            }
+        }
+        assert!(checked > 0);
+    }
+
+    #[test]
+    fn permissive_left_turn_yields_to_oncoming_traffic() {
+        let mut map = Map::empty();
+        let id = four_way(&mut map, 1, 1);
+        let lanes = map.lanes();
+
+        let mut checked = 0;
+        for (a, b, c) in conflicts(&map, id) {
+            let (enter_a, _) = directions(a.id, lanes);
+            let (enter_b, _) = directions(b.id, lanes);
+            if enter_a.dot(enter_b) < -0.7 && is_left(a.id, lanes) && !is_left(b.id, lanes) {
+                assert!(c.yields);
+                checked += 1;
+            }
+        }
+        assert!(checked > 0);
+    }
+
+    #[test]
+    fn entering_vehicles_yield_to_the_ring() {
+        let mut map = Map::empty();
+        crate::procgen::add_roundabout(Vec2::ZERO, &mut map);
+        let inter = map
+            .intersections()
+            .values()
+            .find(|i| i.is_roundabout())
+            .unwrap();
+        let radius = inter.roundabout_radius(map.roads());
+        let lanes = map.lanes();
+
+        let mut checked = 0;
+        for (a, b, c) in conflicts(&map, inter.id) {
+            assert_eq!(c.kind, ConflictKind::Entering);
+            assert_ne!(a.id.src, b.id.src);
+            // The paths meet where the yielding turn joins the ring
+            let entering = if c.yields { a } else { b };
+            let entry = ring_entry(entering.id, lanes, inter.pos, radius);
+            assert!(a.points.project_dist(entry) <= 1.0);
+            assert!(b.points.project_dist(entry) <= 1.0);
+            checked += 1;
+        }
+        assert!(checked > 0);
+    }
+}
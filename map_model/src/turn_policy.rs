@@ -4,11 +4,14 @@ use imgui_inspect_derive::*;
 use serde::{Deserialize, Serialize};
 use std::iter::{Extend, Iterator};
 
-#[derive(Copy, Clone, PartialEq, Eq, Serialize, Deserialize, Inspect)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Inspect)]
 pub struct TurnPolicy {
     pub back_turns: bool,
     pub left_turns: bool,
     pub crosswalks: bool,
+    /// Vehicles go counterclockwise around a ring and give way to the ones already on it
+    #[serde(default)]
+    pub roundabout: bool,
 }
 
 impl Default for TurnPolicy {
@@ -17,6 +20,7 @@ impl Default for TurnPolicy {
             back_turns: false,
             left_turns: true,
             crosswalks: true,
+            roundabout: false,
         }
     }
 }
@@ -85,6 +89,18 @@ impl TurnPolicy {
         roads: &Roads,
         turns: &mut Vec<(TurnID, TurnKind)>,
     ) {
+        if self.roundabout {
+            // Every exit can be reached going around the ring, including the entry road
+            for road1 in &inter.roads {
+                let incoming = filter_vehicles(roads[*road1].incoming_lanes_to(inter.id));
+                for road2 in &inter.roads {
+                    let outgoing = filter_vehicles(roads[*road2].outgoing_lanes_from(inter.id));
+                    turns.extend(Self::zip_on_same_length(inter.id, &incoming, &outgoing));
+                }
+            }
+            return;
+        }
+
         match inter.roads.as_slice() {
             [road_id] => {
                 let road = &roads[*road_id];
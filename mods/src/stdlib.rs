@@ -1,7 +1,16 @@
 use geom::Polygon;
 use geom::Vec2;
 use mlua::prelude::LuaResult;
-use mlua::{FromLuaMulti, Lua, MetaMethod, ToLuaMulti, UserData, UserDataMethods};
+use mlua::{AnyUserData, FromLuaMulti, Lua, MetaMethod, ToLuaMulti, UserData, UserDataMethods};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+const RNG_KEY: &str = "egregoria_rng";
+
+/// Random generator used by the lua stdlib, stored in the lua registry
+struct LuaRng(StdRng);
+
+impl UserData for LuaRng {}
 
 #[derive(Clone)]
 pub struct LuaPolygon(pub Polygon);
@@ -85,8 +94,16 @@ fn poly_rect(_: &Lua, (w, h): (f32, f32)) -> LuaResult<LuaPolygon> {
     Ok(LuaPolygon(Polygon::rect(w, h)))
 }
 
-fn rand_in(_: &Lua, (min, max): (f32, f32)) -> LuaResult<f32> {
-    Ok(min + rand::random::<f32>() * (max - min))
+fn rand_in(l: &Lua, (min, max): (f32, f32)) -> LuaResult<f32> {
+    let rng: AnyUserData = l.named_registry_value(RNG_KEY)?;
+    let mut rng = rng.borrow_mut::<LuaRng>()?;
+    Ok(min + rng.0.gen::<f32>() * (max - min))
+}
+
+/// Reseeds the generator behind `rand_in`, the stdlib is seeded randomly otherwise.
+pub fn seed_rand(lua: &Lua, seed: u64) {
+    lua.set_named_registry_value(RNG_KEY, LuaRng(StdRng::seed_from_u64(seed)))
+        .unwrap()
 }
 
 fn vec2(_: &Lua, (x, y): (f32, f32)) -> LuaResult<LuaVec2> {
@@ -94,6 +111,7 @@ fn vec2(_: &Lua, (x, y): (f32, f32)) -> LuaResult<LuaVec2> {
 }
 
 pub fn add_std(lua: &Lua) {
+    seed_rand(lua, rand::random());
     add_fn(lua, "poly_rect", poly_rect);
     add_fn(lua, "rand_in", rand_in);
     add_fn(lua, "vec2", vec2);
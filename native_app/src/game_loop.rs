@@ -1,18 +1,23 @@
 use crate::audio::GameAudio;
 use crate::context::Context;
 use crate::gui::windows::debug::DebugObjs;
-use crate::gui::{setup_gui, FollowEntity, Gui, Settings, UiTextures};
+use crate::gui::windows::saves::LoadRequest;
+use crate::gui::{gui_schedule, setup_gui, FollowEntity, Gui, Settings, UiTextures};
 use crate::rendering::imgui_wrapper::ImguiWrapper;
 use crate::rendering::{CameraHandler, InstancedRender, MeshRenderer, RoadRenderer};
-use common::GameTime;
-use egregoria::engine_interaction::{KeyboardInfo, MouseInfo, RenderStats, TimeWarp};
+use common::saveload::LoadError;
+use common::{GameTime, SimulationClock};
+use egregoria::engine_interaction::{KeyboardInfo, MouseInfo, RenderStats};
 use egregoria::rendering::immediate::{ImmediateDraw, ImmediateOrder, ImmediateSound, OrderKind};
-use egregoria::souls::Souls;
+use egregoria::replay::ReplayRecorder;
+use egregoria::saveslots;
+use egregoria::utils::scheduler::SeqSchedule;
 use egregoria::{load_from_disk, Egregoria};
 use geom::{vec3, Vec2};
 use geom::{Camera, Vec3};
 use map_model::Map;
 use std::borrow::Cow;
+use std::path::Path;
 use std::time::Instant;
 use wgpu_engine::lighting::LightInstance;
 use wgpu_engine::{FrameContext, GfxContext, GuiRenderContext};
@@ -21,6 +26,7 @@ use winit::window::{Fullscreen, Window};
 
 pub struct State {
     goria: Egregoria,
+    gui_schedule: SeqSchedule,
 
     camera: CameraHandler,
 
@@ -31,14 +37,12 @@ pub struct State {
     road_renderer: RoadRenderer,
     gui: Gui,
 
-    souls: Souls,
-
     all_audio: GameAudio,
 }
 
 impl State {
     pub fn new(ctx: &mut Context) -> Self {
-        let camera = common::saveload::load("camera")
+        let camera = common::saveload::load(common::saveload::world_path("camera"))
             .map(|camera| CameraHandler {
                 camera,
                 last_pos: Vec2::ZERO,
@@ -56,33 +60,126 @@ impl State {
 
         crate::rendering::prepare_background(&mut ctx.gfx);
 
-        let mut goria = egregoria::Egregoria::init();
-
-        goria.insert(UiTextures::new(&ctx.gfx, &mut imgui_render.renderer));
-
-        load_from_disk(&mut goria);
-        setup_gui(&mut goria);
+        let gui: Gui = common::saveload::load_json(common::saveload::world_path_json("gui"))
+            .unwrap_or_default();
 
-        let gui: Gui = common::saveload::load_json("gui").unwrap_or_default();
+        let last_slot = saveslots::list_slots().into_iter().next().map(|x| x.name);
+        let mut goria = Self::make_goria(
+            ctx,
+            &mut imgui_render,
+            last_slot.as_deref(),
+            gui.settings.record_replays,
+        );
 
         goria.insert(camera.camera.clone());
 
         let mut me = Self {
             goria,
+            gui_schedule: gui_schedule(),
             camera,
             imgui_render,
             last_time: Instant::now(),
             instanced_renderer: InstancedRender::new(&mut ctx.gfx),
             road_renderer: RoadRenderer::new(&mut ctx.gfx),
             gui,
-            souls: Souls::default(),
             all_audio: GameAudio::new(&mut ctx.audio),
         };
         me.manage_settings(ctx, me.gui.settings);
         me
     }
 
+    /// Creates a new Egregoria instance loaded from the given save slot,
+    /// or from the `world/` directory of older versions if there is none.
+    /// A new world is started if the save can't be loaded.
+    fn make_goria(
+        ctx: &mut Context,
+        imgui_render: &mut ImguiWrapper,
+        slot: Option<&str>,
+        record: bool,
+    ) -> Egregoria {
+        let loaded = match slot {
+            Some(name) => Self::load_slot(name, record),
+            None => {
+                let mut goria = Self::new_goria(record);
+                load_from_disk(&mut goria, Path::new("world")).map(|_| goria)
+            }
+        };
+        let (goria, start, record) = match loaded {
+            Ok(goria) => {
+                // Replays start from a slot or an empty world, the `world/` directory
+                // can't be loaded back to play them
+                if record && slot.is_none() {
+                    log::warn!("the world doesn't come from a save slot, it won't be recorded");
+                }
+                (goria, slot.map(String::from), record && slot.is_some())
+            }
+            Err(LoadError::NotFound(_)) => {
+                log::info!("no save found, starting a new world");
+                (Self::new_goria(record), None, record)
+            }
+            Err(e) => {
+                log::error!("could not load the save: {}", e);
+                (Self::new_goria(record), None, record)
+            }
+        };
+        Self::start_goria(ctx, imgui_render, goria, start, record)
+    }
+
+    /// Souls decide in parallel unless the session is recorded,
+    /// as a replay can only be played back on a deterministic world
+    fn new_goria(record: bool) -> Egregoria {
+        if record {
+            egregoria::Egregoria::init_deterministic(saveslots::now())
+        } else {
+            egregoria::Egregoria::init()
+        }
+    }
+
+    /// Loads the slot in a new Egregoria instance, so that a failed load leaves the current one be
+    fn load_slot(slot: &str, record: bool) -> Result<Egregoria, LoadError> {
+        let mut goria = Self::new_goria(record);
+        saveslots::load_slot(&mut goria, slot)?;
+        Ok(goria)
+    }
+
+    /// Readies a loaded instance to be played: gui resources and the replay of the session
+    fn start_goria(
+        ctx: &mut Context,
+        imgui_render: &mut ImguiWrapper,
+        mut goria: Egregoria,
+        start: Option<String>,
+        record: bool,
+    ) -> Egregoria {
+        goria.insert(UiTextures::new(&ctx.gfx, &mut imgui_render.renderer));
+
+        if record {
+            let initial_hash = goria.state_hash();
+            goria.write::<ReplayRecorder>().start(start, initial_hash);
+        }
+        setup_gui(&mut goria);
+
+        goria
+    }
+
     pub fn update(&mut self, ctx: &mut Context) {
+        let load_request = self.goria.write::<LoadRequest>().slot.take();
+        if let Some(slot) = load_request {
+            let record = self.gui.settings.record_replays;
+            match Self::load_slot(&slot, record) {
+                Ok(goria) => {
+                    self.goria =
+                        Self::start_goria(ctx, &mut self.imgui_render, goria, Some(slot), record);
+                    self.goria.insert(self.camera.camera.clone());
+                    self.gui_schedule = gui_schedule();
+                }
+                Err(e) => {
+                    log::error!("could not load {}: {}", slot, e);
+                    self.goria.write::<LoadRequest>().error =
+                        Some(format!("Could not load {}: {}", slot, e));
+                }
+            }
+        }
+
         let delta = self.last_time.elapsed().as_secs_f64();
         self.last_time = Instant::now();
 
@@ -93,8 +190,6 @@ impl State {
 
         self.manage_settings(ctx, self.gui.settings);
 
-        self.manage_time(delta, &mut ctx.gfx);
-
         self.manage_io(ctx);
 
         self.camera.movespeed = self.gui.settings.camera_sensibility / 100.0;
@@ -110,10 +205,9 @@ impl State {
             self.goria.write::<MouseInfo>().unprojected = self.unproject(ctx.input.mouse.screen);
         }
 
-        self.goria.run();
+        self.goria.execute(&mut self.gui_schedule);
 
-        self.souls.add_souls_to_empty_buildings(&mut self.goria);
-        self.souls.update(&mut self.goria);
+        self.manage_time(delta, &mut ctx.gfx);
 
         for (sound, kind) in self.goria.write::<ImmediateSound>().orders.drain(..) {
             ctx.audio.play(sound, kind);
@@ -273,17 +367,15 @@ impl State {
         ctx.audio.set_settings(settings);
     }
 
+    /// Runs as many simulation ticks as the clock asks for this frame
     fn manage_time(&mut self, delta: f64, gfx: &mut GfxContext) {
-        const MAX_TIMESTEP: f64 = 1.0 / 15.0;
-
-        let mut time = self.goria.write::<GameTime>();
-        let warp = self.goria.read::<TimeWarp>().0;
+        let ticks = self.goria.write::<SimulationClock>().update(delta);
 
-        let delta = (delta * warp as f64).min(MAX_TIMESTEP);
-
-        *time = GameTime::new(delta as f32, time.timestamp + delta);
+        for _ in 0..ticks {
+            self.goria.tick();
+        }
 
-        gfx.set_time(time.timestamp as f32);
+        gfx.set_time(self.goria.read::<GameTime>().timestamp as f32);
     }
 
     fn manage_entity_follow(&mut self) {
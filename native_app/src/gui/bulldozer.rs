@@ -2,15 +2,17 @@ use super::Tool;
 use crate::gui::Z_TOOL;
 use egregoria::engine_interaction::{MouseButton, MouseInfo};
 use egregoria::rendering::immediate::ImmediateDraw;
+use egregoria::replay::ReplayRecorder;
 use geom::Color;
 use legion::system;
-use map_model::{Map, ProjectKind};
+use map_model::{Map, MapCommand, ProjectKind};
 
 #[system]
 pub fn bulldozer(
     #[resource] tool: &Tool,
     #[resource] mouseinfo: &MouseInfo,
     #[resource] map: &mut Map,
+    #[resource] recorder: &mut ReplayRecorder,
     #[resource] draw: &mut ImmediateDraw,
 ) {
     if !matches!(*tool, Tool::Bulldozer) {
@@ -27,7 +29,7 @@ pub fn bulldozer(
         match cur_proj.kind {
             ProjectKind::Inter(id) => {
                 potentially_empty.extend(map.intersections()[id].neighbors(map.roads()));
-                map.remove_intersection(id)
+                recorder.apply(map, MapCommand::RemoveIntersection(id));
             }
             ProjectKind::Road(id) => {
                 let r = &map.roads()[id];
@@ -35,17 +37,17 @@ pub fn bulldozer(
                 potentially_empty.push(r.src);
                 potentially_empty.push(r.dst);
 
-                map.remove_road(id);
+                recorder.apply(map, MapCommand::RemoveRoad(id));
             }
             ProjectKind::Building(id) => {
-                map.remove_building(id);
+                recorder.apply(map, MapCommand::RemoveBuilding(id));
             }
             ProjectKind::Ground | ProjectKind::Lot(_) => {}
         }
 
         for id in potentially_empty {
             if map.intersections()[id].roads.is_empty() {
-                map.remove_intersection(id);
+                recorder.apply(map, MapCommand::RemoveIntersection(id));
             }
         }
     }
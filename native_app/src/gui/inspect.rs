@@ -98,6 +98,10 @@ pub fn debug_souls(ui: &Ui, goria: &mut Egregoria) {
         }
 
         if let Some(router) = dsoul.router.as_mut() {
+            match router.mode() {
+                Some(mode) => ui.text(format!("Travelling by {:?}", mode)),
+                None => ui.text("Not travelling yet"),
+            }
             <Router as InspectRenderDefault<Router>>::render_mut(
                 &mut [router],
                 "router",
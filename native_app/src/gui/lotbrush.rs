@@ -2,8 +2,9 @@ use super::Tool;
 use crate::gui::Z_TOOL;
 use egregoria::engine_interaction::{MouseButton, MouseInfo};
 use egregoria::rendering::immediate::ImmediateDraw;
+use egregoria::replay::ReplayRecorder;
 use legion::system;
-use map_model::{LotKind, Map, ProjectKind};
+use map_model::{LotKind, Map, MapCommand, ProjectKind};
 
 pub struct LotBrushResource {
     pub kind: LotKind,
@@ -25,6 +26,7 @@ pub fn lotbrush(
     #[resource] tool: &Tool,
     #[resource] mouseinfo: &MouseInfo,
     #[resource] map: &mut Map,
+    #[resource] recorder: &mut ReplayRecorder,
     #[resource] draw: &mut ImmediateDraw,
 ) {
     if !matches!(tool, Tool::LotBrush) {
@@ -53,8 +55,8 @@ pub fn lotbrush(
             }
         }
 
-        for hit in hits {
-            map.set_lot_kind(hit, kind);
+        if !hits.is_empty() {
+            recorder.apply(map, MapCommand::SetLotKind(hits, kind));
         }
     }
 }
@@ -1,15 +1,20 @@
 use crate::gui::lotbrush::LotBrushResource;
 use crate::gui::specialbuilding::SpecialBuildingResource;
 use crate::gui::windows::debug::DebugObjs;
+use crate::gui::windows::saves::LoadRequest;
 use common::inspect::InspectedEntity;
 use egregoria::engine_interaction::{KeyCode, KeyboardInfo};
+use egregoria::replay::ReplayRecorder;
+use egregoria::utils::scheduler::SeqSchedule;
 use egregoria::Egregoria;
 use imgui::TextureId;
 use legion::system;
+use map_model::Map;
 use movable::MovableSystem;
 use roadbuild::RoadBuildResource;
 use roadeditor::RoadEditorResource;
 use std::collections::HashMap;
+use transit::TransitToolResource;
 use wgpu_engine::GfxContext;
 
 mod bulldozer;
@@ -23,15 +28,18 @@ mod roadeditor;
 mod selectable;
 mod specialbuilding;
 mod topgui;
+mod transit;
 pub mod windows;
 
 pub use follow::FollowEntity;
 pub use inspect::*;
 pub use topgui::*;
 
-pub fn setup_gui(goria: &mut Egregoria) {
-    goria
-        .schedule
+/// The gui systems run once per frame, whether the simulation ticks or not
+pub fn gui_schedule() -> SeqSchedule {
+    let mut schedule = SeqSchedule::default();
+    schedule
+        .add_system(undo_redo_system())
         .add_system(selectable::selectable_select_system())
         .add_system(selectable::selectable_cleanup_system())
         .add_system(roadbuild::roadbuild_system())
@@ -40,18 +48,24 @@ pub fn setup_gui(goria: &mut Egregoria) {
         .add_system(lotbrush::lotbrush_system())
         .add_system(inspected_aura::inspected_aura_system())
         .add_system(specialbuilding::special_building_system())
+        .add_system(transit::transit_tool_system())
         .add_system(hand_reset_system())
         .add_system(movable::movable_system(MovableSystem::default()));
+    schedule
+}
 
+pub fn setup_gui(goria: &mut Egregoria) {
     goria.insert(InspectedEntity::default());
     goria.insert(FollowEntity::default());
     goria.insert(Tool::default());
     goria.insert(DebugObjs::default());
+    goria.insert(LoadRequest::default());
 
     goria.insert(RoadBuildResource::default());
     goria.insert(RoadEditorResource::default());
     goria.insert(LotBrushResource::default());
     goria.insert(SpecialBuildingResource::default());
+    goria.insert(TransitToolResource::default());
 }
 
 #[system]
@@ -61,6 +75,25 @@ pub fn hand_reset(#[resource] info: &KeyboardInfo, #[resource] tool: &mut Tool)
     }
 }
 
+#[system]
+pub fn undo_redo(
+    #[resource] info: &KeyboardInfo,
+    #[resource] recorder: &mut ReplayRecorder,
+    #[resource] map: &mut Map,
+) {
+    let ctrl = info.is_pressed.contains(&KeyCode::LControl)
+        || info.is_pressed.contains(&KeyCode::RControl);
+    if !ctrl {
+        return;
+    }
+    if info.just_pressed.contains(&KeyCode::Z) {
+        recorder.undo(map);
+    }
+    if info.just_pressed.contains(&KeyCode::Y) {
+        recorder.redo(map);
+    }
+}
+
 #[derive(Copy, Clone)]
 pub enum Tool {
     Hand,
@@ -70,6 +103,7 @@ pub enum Tool {
     Bulldozer,
     LotBrush,
     SpecialBuilding,
+    Transit,
 }
 
 const Z_TOOL: f32 = 0.9;
@@ -82,6 +116,7 @@ pub enum UiTex {
     Bulldozer,
     Buildings,
     LotBrush,
+    Transit,
 }
 
 const UI_TEXTURES: &[(UiTex, &str)] = &[
@@ -91,6 +126,7 @@ const UI_TEXTURES: &[(UiTex, &str)] = &[
     (UiTex::Bulldozer, "assets/ui/bulldozer.png"),
     (UiTex::Buildings, "assets/ui/buildings.png"),
     (UiTex::LotBrush, "assets/ui/lotbrush.png"),
+    (UiTex::Transit, "assets/ui/transit.png"),
 ];
 
 pub struct UiTextures {
@@ -1,13 +1,12 @@
 use crate::gui::{Tool, Z_TOOL};
 use egregoria::engine_interaction::{MouseButton, MouseInfo};
 use egregoria::rendering::immediate::{ImmediateDraw, ImmediateSound};
+use egregoria::replay::ReplayRecorder;
 use geom::Color;
 use geom::Spline;
 use geom::Vec2;
 use legion::system;
-use map_model::{
-    IntersectionID, LanePattern, LanePatternBuilder, Map, MapProject, ProjectKind, RoadSegmentKind,
-};
+use map_model::{LanePatternBuilder, Map, MapCommand, MapProject, ProjectKind};
 
 const MAX_TURN_ANGLE: f32 = 30.0 * std::f32::consts::PI / 180.0;
 
@@ -40,6 +39,7 @@ pub fn roadbuild(
     #[resource] mouseinfo: &MouseInfo,
     #[resource] tool: &Tool,
     #[resource] map: &mut Map,
+    #[resource] recorder: &mut ReplayRecorder,
     #[resource] immdraw: &mut ImmediateDraw,
     #[resource] immsound: &mut ImmediateSound,
 ) {
@@ -52,6 +52,16 @@ pub fn roadbuild(
         state.build_state = BuildState::Hover;
     }
 
+    // The selected point might have been removed by an undo
+    match state.build_state {
+        Start(selected_proj) | Interpolation(_, selected_proj) => {
+            if !exists(map, selected_proj.kind) {
+                state.build_state = BuildState::Hover;
+            }
+        }
+        Hover => {}
+    }
+
     let mut cur_proj = map.project(mouseinfo.unprojected);
     if matches!(cur_proj.kind, ProjectKind::Lot(_)) {
         cur_proj.kind = ProjectKind::Ground;
@@ -124,72 +134,52 @@ pub fn roadbuild(
             (Start(selected_proj), _, _) => {
                 // Straight connection to something
                 immsound.play("road_lay", AudioKind::Ui);
-                let selected_after = make_connection(
+                let selected_after = recorder.apply(
                     map,
-                    selected_proj,
-                    cur_proj,
-                    None,
-                    &state.pattern_builder.build(),
+                    MapCommand::MakeConnection {
+                        from: selected_proj,
+                        to: cur_proj,
+                        interpoint: None,
+                        pattern: state.pattern_builder.build(),
+                    },
                 );
 
-                let hover = MapProject {
-                    pos: map.intersections()[selected_after].pos,
-                    kind: Inter(selected_after),
-                };
+                if let Some(selected_after) = selected_after {
+                    let hover = MapProject {
+                        pos: map.intersections()[selected_after].pos,
+                        kind: Inter(selected_after),
+                    };
 
-                state.build_state = Start(hover);
+                    state.build_state = Start(hover);
+                }
             }
             (Interpolation(interpoint, selected_proj), _, _) => {
                 // Interpolated connection to something
                 immsound.play("road_lay", AudioKind::Ui);
-                let selected_after = make_connection(
+                let selected_after = recorder.apply(
                     map,
-                    selected_proj,
-                    cur_proj,
-                    Some(interpoint),
-                    &state.pattern_builder.build(),
+                    MapCommand::MakeConnection {
+                        from: selected_proj,
+                        to: cur_proj,
+                        interpoint: Some(interpoint),
+                        pattern: state.pattern_builder.build(),
+                    },
                 );
 
-                let hover = MapProject {
-                    pos: map.intersections()[selected_after].pos,
-                    kind: Inter(selected_after),
-                };
+                if let Some(selected_after) = selected_after {
+                    let hover = MapProject {
+                        pos: map.intersections()[selected_after].pos,
+                        kind: Inter(selected_after),
+                    };
 
-                state.build_state = Start(hover);
+                    state.build_state = Start(hover);
+                }
             }
             _ => {}
         }
     }
 }
 
-fn make_connection(
-    map: &mut Map,
-    from: MapProject,
-    to: MapProject,
-    interpoint: Option<Vec2>,
-    pattern: &LanePattern,
-) -> IntersectionID {
-    use ProjectKind::*;
-
-    let connection_segment = match interpoint {
-        Some(x) => RoadSegmentKind::from_elbow(from.pos, to.pos, x),
-        None => RoadSegmentKind::Straight,
-    };
-
-    let mut mk_inter = |proj: MapProject| match proj.kind {
-        Ground => map.add_intersection(proj.pos),
-        Inter(id) => id,
-        Road(id) => map.split_road(id, proj.pos),
-        Building(_) | Lot(_) => unreachable!(),
-    };
-
-    let from = mk_inter(from);
-    let to = mk_inter(to);
-
-    map.connect(from, to, pattern, connection_segment);
-    to
-}
-
 fn check_angle(map: &Map, from: MapProject, to: Vec2) -> bool {
     match from.kind {
         Inter(i) => {
@@ -218,6 +208,16 @@ fn check_angle(map: &Map, from: MapProject, to: Vec2) -> bool {
     }
 }
 
+fn exists(map: &Map, x: ProjectKind) -> bool {
+    match x {
+        Inter(id) => map.intersections().contains_key(id),
+        Road(id) => map.roads().contains_key(id),
+        Building(id) => map.buildings().contains_key(id),
+        Lot(id) => map.lots().contains_key(id),
+        Ground => true,
+    }
+}
+
 fn compatible(map: &Map, x: ProjectKind, y: ProjectKind) -> bool {
     use ProjectKind::*;
     match (x, y) {
@@ -2,14 +2,15 @@ use crate::gui::{Tool, Z_TOOL};
 use common::inspect::InspectedEntity;
 use egregoria::engine_interaction::{MouseButton, MouseInfo};
 use egregoria::rendering::immediate::ImmediateDraw;
+use egregoria::replay::ReplayRecorder;
 use geom::Color;
 use imgui_inspect_derive::*;
 use legion::systems::CommandBuffer;
 use legion::world::SubWorld;
 use legion::Entity;
 use legion::{system, IntoQuery};
-use map_model::{IntersectionID, LightPolicy, TurnPolicy};
-use map_model::{Map, ProjectKind};
+use map_model::{IntersectionID, LightPolicy, SignalPlan, SignalSchedule, TurnPolicy};
+use map_model::{Map, MapCommand, ProjectKind};
 
 #[derive(Clone, Inspect)]
 pub struct IntersectionComponent {
@@ -19,9 +20,35 @@ pub struct IntersectionComponent {
     pub light_policy: LightPolicy,
 }
 
-#[derive(Default)]
 pub struct RoadEditorResource {
     inspect_e: Option<Entity>,
+    /// Intersection whose signal plans are being edited
+    pub inter: Option<IntersectionID>,
+    /// Plans being edited, only applied to the map when asked to
+    pub signal_plan: Option<SignalPlan>,
+    pub schedule: SignalSchedule,
+    /// Index of the plan shown in the editor, as in the periods of the schedule
+    pub plan: usize,
+    pub preview_phase: usize,
+    /// Intersections of the green wave being drawn, in driving order
+    pub corridor: Vec<IntersectionID>,
+    /// Speed of the green wave, in km/h
+    pub wave_speed: f32,
+}
+
+impl Default for RoadEditorResource {
+    fn default() -> Self {
+        Self {
+            inspect_e: None,
+            inter: None,
+            signal_plan: None,
+            schedule: SignalSchedule::default(),
+            plan: 0,
+            preview_phase: 0,
+            corridor: vec![],
+            wave_speed: 50.0,
+        }
+    }
 }
 
 #[system]
@@ -29,6 +56,7 @@ pub struct RoadEditorResource {
 pub fn roadeditor(
     #[resource] tool: &Tool,
     #[resource] map: &mut Map,
+    #[resource] recorder: &mut ReplayRecorder,
     #[resource] mouseinfo: &MouseInfo,
     #[resource] state: &mut RoadEditorResource,
     #[resource] inspected: &mut InspectedEntity,
@@ -53,6 +81,16 @@ pub fn roadeditor(
         .color(Color::BLUE)
         .z(Z_TOOL);
 
+    for w in state.corridor.windows(2) {
+        let inters = map.intersections();
+        if let (Some(a), Some(b)) = (inters.get(w[0]), inters.get(w[1])) {
+            imm_draw
+                .line(a.pos, b.pos, 1.0)
+                .color(Color::BLUE)
+                .z(Z_TOOL);
+        }
+    }
+
     if mouseinfo.just_pressed.contains(&MouseButton::Left) {
         if let ProjectKind::Inter(id) = cur_proj.kind {
             let inter = &map.intersections()[id];
@@ -62,16 +100,59 @@ pub fn roadeditor(
                 light_policy: inter.light_policy,
             },)));
             inspected.e = state.inspect_e;
+
+            state.inter = Some(id);
+            state.signal_plan = inter.signal_plan.clone();
+            state.schedule = inter.signal_schedule.clone().unwrap_or_default();
+            state.plan = 0;
+            state.preview_phase = 0;
+        }
+    }
+
+    if let Some(inter) = state.inter.and_then(|id| map.intersections().get(id)) {
+        if let Some(phase) = state
+            .signal_plan
+            .as_ref()
+            .and_then(|base| state.schedule.plan(base, state.plan))
+            .and_then(|p| p.phases.get(state.preview_phase))
+        {
+            let lanes = map.lanes();
+            for &l in inter.incoming_signal_lanes(map.roads()).iter().flatten() {
+                let col = if phase.lanes.contains(&l) {
+                    Color::GREEN
+                } else {
+                    Color::RED
+                };
+                imm_draw
+                    .circle(lanes[l].control_point(), 1.0)
+                    .color(col)
+                    .z(Z_TOOL);
+            }
+            for turn in inter.turns() {
+                if turn.kind.is_crosswalk() || !phase.allows(turn.id) {
+                    continue;
+                }
+                imm_draw
+                    .polyline(turn.points.as_slice(), 0.5)
+                    .color(Color::GREEN)
+                    .z(Z_TOOL);
+            }
         }
+    } else {
+        state.inter = None;
     }
 
     if let Some(insp) = state.inspect_e {
         if inspected.e == Some(insp) && inspected.dirty {
             let selected_interc = <&IntersectionComponent>::query().get(sw, insp).unwrap();
-            map.update_intersection(selected_interc.id, |inter| {
-                inter.turn_policy = selected_interc.turn_policy;
-                inter.light_policy = selected_interc.light_policy;
-            });
+            recorder.apply(
+                map,
+                MapCommand::UpdateIntersection(
+                    selected_interc.id,
+                    selected_interc.turn_policy,
+                    selected_interc.light_policy,
+                ),
+            );
         }
     }
 }
@@ -2,9 +2,10 @@ use super::Tool;
 use crate::gui::Z_TOOL;
 use egregoria::engine_interaction::{MouseButton, MouseInfo};
 use egregoria::rendering::immediate::ImmediateDraw;
+use egregoria::replay::ReplayRecorder;
 use geom::{Vec2, OBB};
 use legion::system;
-use map_model::{BuildingKind, Map, ProjectKind};
+use map_model::{BuildingKind, Map, MapCommand, ProjectKind};
 use ordered_float::OrderedFloat;
 
 pub struct SpecialBuildingResource {
@@ -25,6 +26,7 @@ pub fn special_building(
     #[resource] tool: &Tool,
     #[resource] mouseinfo: &MouseInfo,
     #[resource] map: &mut Map,
+    #[resource] recorder: &mut ReplayRecorder,
     #[resource] draw: &mut ImmediateDraw,
 ) {
     if !matches!(tool, Tool::SpecialBuilding) {
@@ -94,6 +96,6 @@ pub fn special_building(
     let rid = closest_road.id;
 
     if mouseinfo.just_pressed.contains(&MouseButton::Left) {
-        map.build_special_building(rid, obb, kind);
+        recorder.apply(map, MapCommand::BuildSpecialBuilding(rid, obb, kind));
     }
 }
@@ -1,15 +1,22 @@
 use crate::gui::lotbrush::LotBrushResource;
+use crate::gui::roadeditor::RoadEditorResource;
 use crate::gui::specialbuilding::SpecialBuildingResource;
+use crate::gui::transit::TransitToolResource;
 use crate::gui::windows::ImguiWindows;
 use crate::gui::{RoadBuildResource, Tool, UiTex, UiTextures};
 use common::inspect::InspectedEntity;
-use common::GameTime;
-use egregoria::engine_interaction::{KeyCode, KeyboardInfo, TimeWarp};
+use common::{GameTime, SimulationClock, SECONDS_PER_HOUR};
+use egregoria::engine_interaction::{KeyCode, KeyboardInfo};
+use egregoria::replay::ReplayRecorder;
+use egregoria::transit::{TransitCommand, TransitNetwork};
 use egregoria::Egregoria;
 use imgui::{im_str, StyleColor, StyleVar};
 use imgui::{Ui, Window};
 use imgui_inspect::{InspectArgsStruct, InspectRenderStruct};
-use map_model::{BuildingKind, LanePatternBuilder, LotKind};
+use map_model::{
+    BuildingKind, LaneID, LanePatternBuilder, LotKind, Map, MapCommand, SchedulePeriod,
+    SignalPhase, SignalPlan, SignalSchedule, TurnID,
+};
 use serde::{Deserialize, Serialize};
 use std::time::{Duration, Instant};
 
@@ -22,6 +29,11 @@ pub struct Settings {
     pub music_volume_percent: f32,
     pub effects_volume_percent: f32,
     pub ui_volume_percent: f32,
+
+    /// Souls decide one after the other so that the session can be played back, which is slower.
+    /// Taken into account when a world is loaded.
+    #[serde(default)]
+    pub record_replays: bool,
 }
 
 impl Default for Settings {
@@ -32,6 +44,7 @@ impl Default for Settings {
             effects_volume_percent: 100.0,
             ui_volume_percent: 100.0,
             fullscreen: true,
+            record_replays: false,
         }
     }
 }
@@ -75,7 +88,6 @@ pub struct Gui {
     #[serde(skip)]
     pub n_cars: i32,
     pub n_pedestrians: i32,
-    pub depause_warp: u32,
     pub settings: Settings,
 }
 
@@ -88,7 +100,6 @@ impl Default for Gui {
             last_gui_save: Instant::now(),
             n_cars: 100,
             n_pedestrians: 100,
-            depause_warp: 1,
             settings: Settings::default(),
         }
     }
@@ -119,13 +130,13 @@ impl Gui {
     pub fn auto_save(&mut self, goria: &mut Egregoria) {
         if let Some(every) = self.auto_save_every.into() {
             if self.last_save.elapsed() > every {
-                egregoria::save_to_disk(goria);
+                egregoria::saveslots::autosave(goria);
                 self.last_save = Instant::now();
             }
         }
 
         if self.last_gui_save.elapsed() > Duration::from_secs(1) {
-            common::saveload::save_silent_json(self, "gui");
+            common::saveload::save_silent_json(self, common::saveload::world_path_json("gui"));
             self.last_gui_save = Instant::now();
         }
     }
@@ -148,6 +159,7 @@ impl Gui {
             (UiTex::LotBrush, Tool::LotBrush),
             (UiTex::Buildings, Tool::SpecialBuilding),
             (UiTex::Bulldozer, Tool::Bulldozer),
+            (UiTex::Transit, Tool::Transit),
         ];
 
         Window::new(im_str!("Toolbox"))
@@ -300,6 +312,375 @@ impl Gui {
                 });
         }
 
+        if matches!(*goria.read::<Tool>(), Tool::Transit) {
+            Window::new(im_str!("Transit lines"))
+                .size([300.0, 300.0], imgui::Condition::FirstUseEver)
+                .position(
+                    [w - toolbox_w - 300.0, h * 0.5 - 30.0],
+                    imgui::Condition::FirstUseEver,
+                )
+                .build(&ui, || {
+                    let mut res = goria.write::<TransitToolResource>();
+                    let mut network = goria.write::<TransitNetwork>();
+                    let mut recorder = goria.write::<ReplayRecorder>();
+                    let map = goria.read::<Map>();
+
+                    ui.text(im_str!(
+                        "Click to add stops, right click to remove one. {} stops so far.",
+                        res.stops.len()
+                    ));
+                    ui.input_text(im_str!("Name"), &mut res.name).build();
+                    imgui::Slider::new(im_str!("First bus (h)"))
+                        .range(0..=23)
+                        .build(ui, &mut res.first_hour);
+                    imgui::Slider::new(im_str!("Last bus (h)"))
+                        .range(0..=23)
+                        .build(ui, &mut res.last_hour);
+                    imgui::Slider::new(im_str!("Headway (s)"))
+                        .range(10..=SECONDS_PER_HOUR * 4)
+                        .build(ui, &mut res.headway);
+
+                    if ui.small_button(im_str!("Create line")) {
+                        if let Some(command) = res.make_line() {
+                            recorder.apply_transit(&mut network, &map, command);
+                        }
+                    }
+                    ui.same_line(0.0);
+                    if ui.small_button(im_str!("Clear")) {
+                        res.stops.clear();
+                    }
+
+                    ui.separator();
+
+                    let timetable = res.timetable();
+                    let lines: Vec<_> = network
+                        .lines()
+                        .iter()
+                        .map(|(id, l)| (id, l.name.clone(), l.stops.len(), l.timetable))
+                        .collect();
+                    for (id, name, n_stops, tt) in lines {
+                        ui.text(im_str!(
+                            "{}: {} stops, {:02}h-{:02}h every {}s",
+                            name,
+                            n_stops,
+                            tt.first / SECONDS_PER_HOUR,
+                            tt.last / SECONDS_PER_HOUR,
+                            tt.headway
+                        ));
+                        let id_tok = ui.push_id(&*format!("{:?}", id));
+                        if ui.small_button(im_str!("Apply timetable")) {
+                            recorder.apply_transit(
+                                &mut network,
+                                &map,
+                                TransitCommand::SetTimetable(id, timetable),
+                            );
+                        }
+                        ui.same_line(0.0);
+                        if ui.small_button(im_str!("Remove")) {
+                            recorder.apply_transit(
+                                &mut network,
+                                &map,
+                                TransitCommand::RemoveLine(id),
+                            );
+                        }
+                        id_tok.pop(ui);
+                    }
+                });
+        }
+
+        if matches!(*goria.read::<Tool>(), Tool::RoadEditor)
+            && goria.read::<RoadEditorResource>().inter.is_some()
+        {
+            Window::new(im_str!("Signal plans"))
+                .size([300.0, 400.0], imgui::Condition::FirstUseEver)
+                .position(
+                    [w - toolbox_w - 300.0, h * 0.5 - 30.0],
+                    imgui::Condition::FirstUseEver,
+                )
+                .build(&ui, || {
+                    let mut res = goria.write::<RoadEditorResource>();
+                    let mut recorder = goria.write::<ReplayRecorder>();
+                    let mut map = goria.write::<Map>();
+
+                    let id = match res.inter {
+                        Some(id) => id,
+                        None => return,
+                    };
+
+                    ui.text(im_str!(
+                        "Green wave through {} intersections",
+                        res.corridor.len()
+                    ));
+                    if ui.small_button(im_str!("Add to green wave"))
+                        && res.corridor.last() != Some(&id)
+                    {
+                        res.corridor.push(id);
+                    }
+                    ui.same_line(0.0);
+                    if ui.small_button(im_str!("Clear green wave")) {
+                        res.corridor.clear();
+                    }
+                    imgui::Slider::new(im_str!("Wave speed (km/h)"))
+                        .range(10.0..=90.0)
+                        .build(ui, &mut res.wave_speed);
+                    if res.corridor.len() >= 2 && ui.small_button(im_str!("Coordinate signals")) {
+                        recorder.apply(
+                            &mut map,
+                            MapCommand::CoordinateSignals(
+                                res.corridor.clone(),
+                                res.wave_speed / 3.6,
+                            ),
+                        );
+                    }
+                    ui.separator();
+
+                    let inter = match map.intersections().get(id) {
+                        Some(inter) => inter,
+                        None => {
+                            res.inter = None;
+                            return;
+                        }
+                    };
+
+                    if inter.is_roundabout() {
+                        ui.text("Roundabout: vehicles yield to the ring, lights are disabled.");
+                        return;
+                    }
+
+                    let groups = inter.incoming_signal_lanes(map.roads());
+                    let lane_name = |l: LaneID| {
+                        groups.iter().enumerate().find_map(|(i, g)| {
+                            let j = g.iter().position(|&x| x == l)?;
+                            Some(format!("Road {} lane {}", i + 1, j + 1))
+                        })
+                    };
+                    let turns: Vec<(TurnID, String)> = inter
+                        .turns()
+                        .iter()
+                        .filter(|t| !t.kind.is_crosswalk())
+                        .filter_map(|t| {
+                            let to = map.lanes().get(t.id.dst)?.parent;
+                            let to = inter.roads.iter().position(|&r| r == to)?;
+                            Some((t.id, format!("{} to road {}", lane_name(t.id.src)?, to + 1)))
+                        })
+                        .collect();
+
+                    if res.signal_plan.is_none() {
+                        ui.text("The lights follow the light policy.");
+                        let base = SignalPlan::generate(inter, map.roads());
+                        let mut schedule = None;
+                        if ui.small_button(im_str!("New plan from lights")) {
+                            schedule = Some(SignalSchedule::default());
+                        }
+                        ui.same_line(0.0);
+                        if ui.small_button(im_str!("Daily plans from lights")) {
+                            schedule = Some(SignalSchedule::daily(&base));
+                        }
+                        if let Some(schedule) = schedule {
+                            res.signal_plan = Some(base);
+                            res.schedule = schedule;
+                            res.plan = 0;
+                            res.preview_phase = 0;
+                        }
+                        return;
+                    }
+
+                    let mut sel = res.plan;
+                    let mut preview = res.preview_phase;
+                    let mut commands = vec![];
+                    let RoadEditorResource {
+                        signal_plan,
+                        schedule,
+                        ..
+                    } = &mut *res;
+                    if let Some(base) = signal_plan {
+                        let n_plans = schedule.plans.len() + 1;
+                        for i in 0..n_plans {
+                            if i > 0 {
+                                ui.same_line(0.0);
+                            }
+                            let name = schedule.plan(base, i).map_or("", |p| p.name.as_str());
+                            let id_tok = ui.push_id(&*format!("plan {}", i));
+                            let tok = ui.push_style_var(StyleVar::Alpha(if i == sel {
+                                1.0
+                            } else {
+                                0.5
+                            }));
+                            if ui.small_button(&im_str!("{}", name)) {
+                                sel = i;
+                                preview = 0;
+                            }
+                            tok.pop(ui);
+                            id_tok.pop(ui);
+                        }
+                        sel = sel.min(n_plans - 1);
+                        if ui.small_button(im_str!("Copy plan")) {
+                            if let Some(plan) = schedule.plan(base, sel) {
+                                let mut plan = plan.clone();
+                                plan.name = format!("{} copy", plan.name);
+                                schedule.plans.push(plan);
+                                sel = schedule.plans.len();
+                                preview = 0;
+                            }
+                        }
+                        // The signal plan runs outside of the periods and can't be removed
+                        if sel > 0 {
+                            ui.same_line(0.0);
+                            if ui.small_button(im_str!("Remove this plan")) {
+                                schedule.remove_plan(sel);
+                                sel -= 1;
+                                preview = 0;
+                            }
+                        }
+
+                        ui.separator();
+                        ui.text("Schedule");
+                        if schedule.periods.is_empty() {
+                            ui.text("The first plan runs all day.");
+                        }
+                        let n_plans = schedule.plans.len() as i32 + 1;
+                        let mut remove = None;
+                        for (i, period) in schedule.periods.iter_mut().enumerate() {
+                            let id_tok = ui.push_id(&*format!("period {}", i));
+                            let name = match period.plan {
+                                0 => base.name.as_str(),
+                                p => schedule.plans.get(p - 1).map_or("", |p| p.name.as_str()),
+                            };
+                            ui.text(im_str!("From {:02}h: {}", period.hour, name));
+                            imgui::Slider::new(im_str!("Hour"))
+                                .range(0..=23)
+                                .build(ui, &mut period.hour);
+                            let mut plan = period.plan as i32;
+                            imgui::Slider::new(im_str!("Plan"))
+                                .range(0..=n_plans - 1)
+                                .build(ui, &mut plan);
+                            period.plan = plan as usize;
+                            if ui.small_button(im_str!("Remove period")) {
+                                remove = Some(i);
+                            }
+                            id_tok.pop(ui);
+                        }
+                        if let Some(i) = remove {
+                            schedule.periods.remove(i);
+                        }
+                        if ui.small_button(im_str!("Add period")) {
+                            schedule.periods.push(SchedulePeriod { hour: 0, plan: sel });
+                        }
+
+                        ui.separator();
+                        let plan = match sel {
+                            0 => &mut *base,
+                            i => &mut schedule.plans[i - 1],
+                        };
+                        ui.text(im_str!("{}: cycle of {}s", plan.name, plan.cycle()));
+                        ui.checkbox(im_str!("Flashing lights"), &mut plan.flash);
+                        imgui::Slider::new(im_str!("Offset (s)"))
+                            .range(0..=plan.cycle().max(1))
+                            .build(ui, &mut plan.offset);
+
+                        let mut remove = None;
+                        for (i, phase) in plan.phases.iter_mut().enumerate() {
+                            ui.separator();
+                            let id_tok = ui.push_id(&*format!("phase {}", i));
+                            ui.text(im_str!("Phase {}", i + 1));
+                            imgui::Slider::new(im_str!("Green (s)"))
+                                .range(1..=120)
+                                .build(ui, &mut phase.green);
+                            imgui::Slider::new(im_str!("Orange (s)"))
+                                .range(0..=10)
+                                .build(ui, &mut phase.orange);
+                            imgui::Slider::new(im_str!("All red (s)"))
+                                .range(0..=10)
+                                .build(ui, &mut phase.all_red);
+                            imgui::Slider::new(im_str!("Walk (s)"))
+                                .range(0..=phase.green)
+                                .build(ui, &mut phase.walk);
+
+                            for &l in groups.iter().flatten() {
+                                let mut on = phase.lanes.contains(&l);
+                                let name = lane_name(l).unwrap_or_default();
+                                if ui.checkbox(&im_str!("{}", name), &mut on) {
+                                    if on {
+                                        phase.lanes.push(l);
+                                    } else {
+                                        phase.lanes.retain(|&x| x != l);
+                                        phase.turns.retain(|t| t.src != l);
+                                    }
+                                }
+                            }
+
+                            for (turn, name) in &turns {
+                                if !phase.lanes.contains(&turn.src) {
+                                    continue;
+                                }
+                                let mut on = phase.allows(*turn);
+                                if !ui.checkbox(&im_str!("{}", name), &mut on) {
+                                    continue;
+                                }
+                                if on {
+                                    phase.turns.push(*turn);
+                                    continue;
+                                }
+                                if phase.turns.iter().all(|t| t.src != turn.src) {
+                                    // Every turn of the lane was allowed, list all the others
+                                    phase.turns.extend(
+                                        turns
+                                            .iter()
+                                            .map(|&(t, _)| t)
+                                            .filter(|t| t.src == turn.src && t != turn),
+                                    );
+                                } else {
+                                    phase.turns.retain(|t| t != turn);
+                                }
+                                // No turn left, the lane doesn't have green anymore
+                                if phase.turns.iter().all(|t| t.src != turn.src) {
+                                    phase.lanes.retain(|&x| x != turn.src);
+                                }
+                            }
+
+                            if ui.small_button(im_str!("Preview")) {
+                                preview = i;
+                            }
+                            ui.same_line(0.0);
+                            if ui.small_button(im_str!("Remove")) {
+                                remove = Some(i);
+                            }
+                            id_tok.pop(ui);
+                        }
+                        if let Some(i) = remove {
+                            plan.phases.remove(i);
+                        }
+
+                        ui.separator();
+                        if ui.small_button(im_str!("Add phase")) {
+                            plan.phases.push(SignalPhase::new(vec![]));
+                        }
+                        ui.same_line(0.0);
+                        if ui.small_button(im_str!("Apply")) {
+                            let scheduled = Some(schedule.clone())
+                                .filter(|s| !s.plans.is_empty() || !s.periods.is_empty());
+                            commands.push(MapCommand::SetSignalPlan(id, Some(base.clone())));
+                            commands.push(MapCommand::SetSignalSchedule(id, scheduled));
+                        }
+                        ui.same_line(0.0);
+                        if ui.small_button(im_str!("Remove plans")) {
+                            commands.push(MapCommand::SetSignalPlan(id, None));
+                            commands.push(MapCommand::SetSignalSchedule(id, None));
+                        }
+                    }
+                    res.plan = sel;
+                    res.preview_phase = preview;
+
+                    for command in commands {
+                        if let MapCommand::SetSignalPlan(_, None) = command {
+                            res.signal_plan = None;
+                            res.schedule = SignalSchedule::default();
+                        }
+                        recorder.apply(&mut map, command);
+                    }
+                });
+        }
+
         tok.pop(ui);
     }
 
@@ -327,7 +708,9 @@ impl Gui {
     }
 
     pub fn time_controls(&mut self, ui: &Ui, goria: &mut Egregoria) {
-        let mut warp = goria.write::<TimeWarp>();
+        const MAX_SPEED: f64 = 1000.0;
+
+        let mut clock = goria.write::<SimulationClock>();
         let time = goria.read::<GameTime>().daytime;
 
         if goria
@@ -335,12 +718,7 @@ impl Gui {
             .just_pressed
             .contains(&KeyCode::Space)
         {
-            if warp.0 == 0 {
-                warp.0 = self.depause_warp;
-            } else {
-                self.depause_warp = warp.0;
-                warp.0 = 0;
-            }
+            clock.toggle_pause();
         }
 
         let [_, h] = ui.io().display_size;
@@ -349,7 +727,7 @@ impl Gui {
             StyleVar::ItemSpacing([10.0, 7.0]),
         ]);
         Window::new(im_str!("Time controls"))
-            .size([165.0, 55.0], imgui::Condition::Always)
+            .size([205.0, 55.0], imgui::Condition::Always)
             .position([-1.0, h - 50.0], imgui::Condition::Always)
             .no_decoration()
             .collapsible(false)
@@ -365,43 +743,58 @@ impl Gui {
 
                 if imgui::Selectable::new(im_str!(" ||"))
                     .size([29.0, 15.0])
-                    .selected(warp.0 == 0)
+                    .selected(clock.is_paused())
                     .build(ui)
                 {
-                    self.depause_warp = warp.0;
-                    warp.0 = 0;
+                    clock.pause();
                 }
 
                 red.pop(ui);
 
                 ui.same_line(0.0);
 
+                if imgui::Selectable::new(im_str!(" >|"))
+                    .size([29.0, 15.0])
+                    .build(ui)
+                {
+                    clock.pause();
+                    clock.step_once();
+                }
+
+                ui.same_line(0.0);
+
+                let running = !clock.is_paused();
+                let speed = clock.speed();
+
                 if imgui::Selectable::new(im_str!(" 1x"))
                     .size([27.0, 15.0])
-                    .selected(warp.0 == 1)
+                    .selected(running && speed < 2.0)
                     .build(ui)
                 {
-                    warp.0 = 1;
+                    clock.resume();
+                    clock.set_speed(1.0);
                 }
 
                 ui.same_line(0.0);
 
                 if imgui::Selectable::new(im_str!(" 3x"))
                     .size([27.0, 15.0])
-                    .selected(warp.0 == 3)
+                    .selected(running && speed >= 2.0 && speed < MAX_SPEED)
                     .build(ui)
                 {
-                    warp.0 = 3;
+                    clock.resume();
+                    clock.set_speed(3.0);
                 }
 
                 ui.same_line(0.0);
 
                 if imgui::Selectable::new(im_str!(" Max"))
                     .size([33.0, 15.0])
-                    .selected(warp.0 == 1000)
+                    .selected(running && speed >= MAX_SPEED)
                     .build(ui)
                 {
-                    warp.0 = 1000;
+                    clock.resume();
+                    clock.set_speed(MAX_SPEED);
                 }
             });
         tok.pop(ui);
@@ -435,9 +828,10 @@ impl Gui {
                 imgui::Slider::new(im_str!("Music volume")).range(0.0..=100.0).display_format(im_str!("%.0f")).build(ui, &mut self.settings.music_volume_percent);
                 imgui::Slider::new(im_str!("Effects volume")).range(0.0..=100.0).display_format(im_str!("%.0f")).build(ui, &mut self.settings.effects_volume_percent);
                 imgui::Slider::new(im_str!("Ui volume")).range(0.0..=100.0).display_format(im_str!("%.0f")).build(ui, &mut self.settings.ui_volume_percent);
+                ui.checkbox(im_str!("Record replays (slower, from the next load)"), &mut self.settings.record_replays);
             });
-            if ui.small_button(im_str!("Save")) {
-                egregoria::save_to_disk(goria);
+            if ui.small_button(im_str!("Quicksave")) {
+                egregoria::saveslots::save_slot(goria, "quicksave");
             }
             ui.menu(im_str!("Help"), true, || {
                 ui.text(im_str!("Pan: Right click or Arrow keys"));
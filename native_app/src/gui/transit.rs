@@ -0,0 +1,135 @@
+use super::Tool;
+use crate::gui::Z_TOOL;
+use common::SECONDS_PER_HOUR;
+use egregoria::engine_interaction::{MouseButton, MouseInfo};
+use egregoria::rendering::immediate::ImmediateDraw;
+use egregoria::replay::ReplayRecorder;
+use egregoria::transit::{StopID, Timetable, TransitCommand, TransitNetwork};
+use geom::{Color, Vec2};
+use imgui::ImString;
+use legion::system;
+use map_model::Map;
+
+/// Distance from the mouse at which an existing stop is picked instead of adding a new one
+const PICK_DIST: f32 = 8.0;
+
+const STOP_COL: Color = Color::WHITE;
+const LINE_COL: Color = Color::new(0.2, 0.4, 0.9, 0.8);
+const DRAWING_COL: Color = Color::new(0.2, 0.9, 0.4, 0.8);
+
+/// Line being drawn with the transit tool, stop by stop
+pub struct TransitToolResource {
+    pub stops: Vec<StopID>,
+    pub name: ImString,
+    pub first_hour: i32,
+    pub last_hour: i32,
+    /// Game seconds between two buses
+    pub headway: i32,
+}
+
+impl Default for TransitToolResource {
+    fn default() -> Self {
+        let timetable = Timetable::default();
+        let mut name = ImString::with_capacity(64);
+        name.push_str("Line");
+        Self {
+            stops: vec![],
+            name,
+            first_hour: timetable.first / SECONDS_PER_HOUR,
+            last_hour: timetable.last / SECONDS_PER_HOUR,
+            headway: timetable.headway,
+        }
+    }
+}
+
+impl TransitToolResource {
+    pub fn timetable(&self) -> Timetable {
+        Timetable::new(
+            self.first_hour * SECONDS_PER_HOUR,
+            self.last_hour * SECONDS_PER_HOUR,
+            self.headway,
+        )
+    }
+
+    /// Command creating the line drawn so far, None if it has less than two stops
+    pub fn make_line(&mut self) -> Option<TransitCommand> {
+        if self.stops.len() < 2 {
+            return None;
+        }
+        Some(TransitCommand::AddLine {
+            name: self.name.to_str().to_string(),
+            stops: std::mem::take(&mut self.stops),
+            timetable: self.timetable(),
+        })
+    }
+}
+
+/// Left click adds a stop (or picks an existing one) to the line being drawn,
+/// right click removes the stop under the mouse.
+#[system]
+pub fn transit_tool(
+    #[resource] res: &mut TransitToolResource,
+    #[resource] tool: &Tool,
+    #[resource] mouseinfo: &MouseInfo,
+    #[resource] map: &Map,
+    #[resource] network: &mut TransitNetwork,
+    #[resource] recorder: &mut ReplayRecorder,
+    #[resource] draw: &mut ImmediateDraw,
+) {
+    if !matches!(tool, Tool::Transit) {
+        return;
+    }
+
+    res.stops.retain(|&s| network.stops().contains_key(s));
+
+    for line in network.lines().values() {
+        let points: Vec<Vec2> = line
+            .stops
+            .iter()
+            .filter_map(|&s| network.stop_pos(s))
+            .collect();
+        if points.len() >= 2 {
+            draw.polyline(points, 1.5).color(LINE_COL).z(Z_TOOL);
+        }
+    }
+    for stop in network.stops().values() {
+        draw.circle(stop.pos, 3.0).color(STOP_COL).z(Z_TOOL + 0.01);
+    }
+
+    let mpos = mouseinfo.unprojected;
+    let hovered = network.nearest_stop(mpos, PICK_DIST);
+    let cursor = hovered.and_then(|s| network.stop_pos(s)).unwrap_or(mpos);
+
+    let mut points: Vec<Vec2> = res
+        .stops
+        .iter()
+        .filter_map(|&s| network.stop_pos(s))
+        .collect();
+    points.push(cursor);
+    if points.len() >= 2 {
+        draw.polyline(points, 1.5)
+            .color(DRAWING_COL)
+            .z(Z_TOOL + 0.02);
+    }
+    draw.stroke_circle(cursor, 4.5, 0.5)
+        .color(DRAWING_COL)
+        .z(Z_TOOL + 0.02);
+
+    if mouseinfo.just_pressed.contains(&MouseButton::Left) {
+        let stop = match hovered {
+            Some(x) => Some(x),
+            None => recorder.apply_transit(network, map, TransitCommand::AddStop(mpos)),
+        };
+        if let Some(stop) = stop {
+            if res.stops.last() != Some(&stop) {
+                res.stops.push(stop);
+            }
+        }
+    }
+
+    if mouseinfo.just_pressed.contains(&MouseButton::Right) {
+        if let Some(stop) = hovered {
+            recorder.apply_transit(network, map, TransitCommand::RemoveStop(stop));
+        }
+    }
+}
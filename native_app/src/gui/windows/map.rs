@@ -1,41 +1,130 @@
-use egregoria::map_dynamic::BuildingInfos;
+use crate::gui::windows::ImguiWindow;
 use egregoria::pedestrians::Pedestrian;
+use egregoria::replay::ReplayRecorder;
 use egregoria::vehicles::Vehicle;
 use egregoria::Egregoria;
-use imgui::{im_str, Ui};
+use imgui::{im_str, ImString, Ui};
 use legion::IntoQuery;
-use map_model::Map;
+use map_model::procgen::{LatLon, LatLonBox, OsmConfig};
+use map_model::{Map, MapCommand, SignalControl};
 
-pub fn map(ui: &Ui, goria: &mut Egregoria) {
-    let mut map = goria.write::<Map>();
+pub struct MapWindow {
+    map_path: ImString,
+    osm_path: ImString,
+    /// min lat, min lon, max lat, max lon. All zeros to import the whole file
+    osm_bbox: [f32; 4],
+}
 
-    if ui.small_button(im_str!("build houses")) {
-        let mut infos = goria.write::<BuildingInfos>();
-        for build in map.build_buildings() {
-            infos.insert(build);
+impl Default for MapWindow {
+    fn default() -> Self {
+        let mut map_path = ImString::with_capacity(256);
+        map_path.push_str("maps/map.json");
+        Self {
+            map_path,
+            osm_path: ImString::with_capacity(256),
+            osm_bbox: [0.0; 4],
         }
     }
+}
 
-    if ui.small_button(im_str!("load Paris map")) {
-        map.clear();
-        map_model::procgen::load_parismap(&mut map);
+impl MapWindow {
+    fn osm_config(&self) -> OsmConfig {
+        let [min_lat, min_lon, max_lat, max_lon] = self.osm_bbox;
+        let bbox = if self.osm_bbox.iter().all(|&x| x == 0.0) {
+            None
+        } else {
+            Some(LatLonBox {
+                min: LatLon {
+                    lat: min_lat as f64,
+                    lon: min_lon as f64,
+                },
+                max: LatLon {
+                    lat: max_lat as f64,
+                    lon: max_lon as f64,
+                },
+            })
+        };
+        OsmConfig {
+            bbox,
+            ..Default::default()
+        }
     }
+}
 
-    if ui.small_button(im_str!("load test field")) {
-        map.clear();
-        map_model::procgen::load_testfield(&mut map);
-    }
+impl ImguiWindow for MapWindow {
+    fn render(&mut self, ui: &Ui, goria: &mut Egregoria) {
+        let mut command = None;
 
-    if ui.small_button(im_str!("clear the map")) {
-        map.clear();
-    }
+        if ui.small_button(im_str!("build houses")) {
+            command = Some(MapCommand::BuildHouses);
+        }
 
-    ui.text(im_str!(
-        "{} pedestrians",
-        <&Pedestrian>::query().iter(&goria.world).count()
-    ));
-    ui.text(im_str!(
-        "{} vehicles",
-        <&Vehicle>::query().iter(&goria.world).count()
-    ));
+        if ui.small_button(im_str!("load Paris map")) {
+            command = Some(MapCommand::LoadParis);
+        }
+
+        if ui.small_button(im_str!("load test field")) {
+            command = Some(MapCommand::LoadTestField);
+        }
+
+        if ui.small_button(im_str!("clear the map")) {
+            command = Some(MapCommand::Clear);
+        }
+
+        if ui.small_button(im_str!("fixed-time signals")) {
+            command = Some(MapCommand::SetSignalControl(SignalControl::FixedTime));
+        }
+        ui.same_line(0.0);
+        if ui.small_button(im_str!("actuated signals")) {
+            command = Some(MapCommand::SetSignalControl(SignalControl::Actuated));
+        }
+
+        ui.separator();
+        ui.input_text(im_str!("Map file (.json)"), &mut self.map_path)
+            .build();
+        if ui.small_button(im_str!("load map file")) {
+            command = Some(MapCommand::LoadFile(self.map_path.to_str().to_string()));
+        }
+        ui.same_line(0.0);
+        if ui.small_button(im_str!("save map file")) {
+            map_model::save_map_json(&goria.read::<Map>(), self.map_path.to_str());
+        }
+
+        ui.separator();
+        ui.input_text(im_str!("OSM file (.osm or .osm.pbf)"), &mut self.osm_path)
+            .build();
+        ui.input_float4(im_str!("Bounding box"), &mut self.osm_bbox)
+            .build();
+        ui.text("min lat, min lon, max lat, max lon. Zeros import everything");
+        if ui.small_button(im_str!("import OpenStreetMap")) {
+            command = Some(MapCommand::ImportOsm(
+                self.osm_path.to_str().to_string(),
+                self.osm_config(),
+            ));
+        }
+        ui.separator();
+
+        if ui.small_button(im_str!("export GeoJSON")) {
+            map_model::save_geojson(&goria.read::<Map>(), "export/map.geojson");
+        }
+        ui.same_line(0.0);
+        if ui.small_button(im_str!("export SVG")) {
+            map_model::save_svg(&goria.read::<Map>(), "export/map.svg");
+        }
+
+        if let Some(command) = command {
+            goria
+                .write::<ReplayRecorder>()
+                .apply(&mut goria.write::<Map>(), command);
+        }
+
+        ui.text(im_str!(
+            "{} pedestrians",
+            <&Pedestrian>::query().iter(&goria.world).count()
+        ));
+        ui.text(im_str!(
+            "{} vehicles",
+            <&Vehicle>::query().iter(&goria.world).count()
+        ));
+    }
 }
@@ -1,6 +1,7 @@
 mod config;
 pub mod debug;
 mod map;
+pub mod saves;
 mod scenarios;
 
 use egregoria::Egregoria;
@@ -39,7 +40,7 @@ impl Default for ImguiWindows {
             windows: vec![],
             opened: vec![],
         };
-        s.insert(imgui::im_str!("Map"), map::map, true);
+        s.insert(imgui::im_str!("Map"), map::MapWindow::default(), true);
         s.insert(
             imgui::im_str!("Scenarios"),
             scenarios::Scenarios::default(),
@@ -47,6 +48,11 @@ impl Default for ImguiWindows {
         );
         s.insert(imgui::im_str!("Config"), config::config, false);
         s.insert(imgui::im_str!("Debug"), debug::debug, false);
+        s.insert(
+            imgui::im_str!("Saves"),
+            saves::SaveBrowser::default(),
+            false,
+        );
         s
     }
 }
@@ -0,0 +1,92 @@
+use crate::gui::windows::ImguiWindow;
+use egregoria::replay::ReplayRecorder;
+use egregoria::saveslots::{self, SlotMeta};
+use egregoria::Egregoria;
+use imgui::{im_str, ImString, Ui};
+
+/// Slot to load at the start of the next frame. Loading replaces the whole Egregoria
+/// instance so it cannot be done from within the gui.
+#[derive(Default)]
+pub struct LoadRequest {
+    pub slot: Option<String>,
+    /// Why the last requested slot couldn't be loaded, the current world is kept then
+    pub error: Option<String>,
+}
+
+pub struct SaveBrowser {
+    slot_name: ImString,
+    slots: Vec<SlotMeta>,
+}
+
+impl Default for SaveBrowser {
+    fn default() -> Self {
+        Self {
+            slot_name: ImString::with_capacity(64),
+            slots: saveslots::list_slots(),
+        }
+    }
+}
+
+impl ImguiWindow for SaveBrowser {
+    fn render(&mut self, ui: &Ui, goria: &mut Egregoria) {
+        ui.input_text(im_str!("Slot name"), &mut self.slot_name)
+            .build();
+        if ui.small_button(im_str!("Save")) {
+            saveslots::save_slot(goria, self.slot_name.to_str());
+            self.slots = saveslots::list_slots();
+        }
+        ui.same_line(0.0);
+        if ui.small_button(im_str!("Refresh")) {
+            self.slots = saveslots::list_slots();
+        }
+        if goria.read::<ReplayRecorder>().is_recording() {
+            ui.same_line(0.0);
+            if ui.small_button(im_str!("Save replay")) {
+                match goria.read::<ReplayRecorder>().save() {
+                    Some(path) => log::info!("replay saved to {}", path.display()),
+                    None => log::error!("could not save the replay"),
+                }
+            }
+        }
+
+        if let Some(error) = &goria.read::<LoadRequest>().error {
+            ui.text_colored([1.0, 0.3, 0.3, 1.0], im_str!("{}", error));
+        }
+
+        let mut to_delete = None;
+        for (i, slot) in self.slots.iter().enumerate() {
+            ui.separator();
+            let id = ui.push_id(i as i32);
+
+            ui.text(im_str!("{}", slot.name));
+            ui.text(im_str!(
+                "Day {} {:02}h - {} inhabitants",
+                slot.game_day,
+                slot.game_hour,
+                slot.population
+            ));
+            ui.text(im_str!("{}", slot.summary));
+
+            if ui.small_button(im_str!("Load")) {
+                let mut request = goria.write::<LoadRequest>();
+                request.slot = Some(slot.name.clone());
+                request.error = None;
+            }
+            ui.same_line(0.0);
+            if ui.small_button(im_str!("Overwrite")) {
+                saveslots::save_slot(goria, &slot.name);
+            }
+            ui.same_line(0.0);
+            if ui.small_button(im_str!("Delete")) {
+                to_delete = Some(slot.name.clone());
+            }
+
+            id.pop(ui);
+        }
+
+        if let Some(name) = to_delete {
+            saveslots::delete_slot(&name);
+            self.slots = saveslots::list_slots();
+        }
+    }
+}
@@ -57,7 +57,7 @@ impl CameraHandler {
                 self.camera.position.x -= p.x - self.last_pos.x;
                 self.camera.position.y -= p.y - self.last_pos.y;
                 self.camera.update();
-                common::saveload::save_silent(&self.camera, "camera");
+                common::saveload::save_silent(&self.camera, common::saveload::world_path("camera"));
             }
 
             self.last_pos = self.unproject_mouse_click(ctx.input.mouse.screen);
@@ -119,7 +119,7 @@ impl CameraHandler {
         self.camera.position.x += m.x;
         self.camera.position.y += m.y;
         self.camera.update();
-        common::saveload::save_silent(&self.camera, "camera");
+        common::saveload::save_silent(&self.camera, common::saveload::world_path("camera"));
     }
 
     fn zoom_by(&mut self, ctx: &mut Context, multiply: f32) {
@@ -130,6 +130,6 @@ impl CameraHandler {
         self.camera.position.x -= after.x - self.last_pos.x;
         self.camera.position.y -= after.y - self.last_pos.y;
         self.update(ctx);
-        common::saveload::save_silent(&self.camera, "camera");
+        common::saveload::save_silent(&self.camera, common::saveload::world_path("camera"));
     }
 }
@@ -84,6 +84,7 @@ impl RoadRenderer {
         let mid_col: LinearColor = common::config().road_mid_col.into();
         let hig_col: LinearColor = common::config().road_hig_col.into();
         let line_col: LinearColor = common::config().road_line_col.into();
+        let bike_col: LinearColor = common::config().road_bike_col.into();
 
         let inters = map.intersections();
         let lanes = map.lanes();
@@ -105,6 +106,7 @@ impl RoadRenderer {
             tess.set_color(match l.kind {
                 LaneKind::Walking => hig_col,
                 LaneKind::Parking => low_col,
+                LaneKind::Biking => bike_col,
                 _ => mid_col,
             });
             let z = match l.kind {
@@ -130,6 +132,17 @@ impl RoadRenderer {
             tess.set_color(mid_col);
             tess.draw_filled_polygon(inter.polygon.as_slice(), Z_INTER_BG);
 
+            // Central island of roundabouts, inside the circulating lane
+            if inter.is_roundabout() {
+                let island = inter.roundabout_radius(map.roads()) - 3.0;
+
+                tess.set_color(line_col);
+                tess.draw_circle(inter.pos, Z_LANE, island + 0.5);
+
+                tess.set_color(hig_col);
+                tess.draw_circle(inter.pos, Z_SIDEWALK, island);
+            }
+
             // Walking corners
             for turn in inter
                 .turns()
@@ -216,10 +229,10 @@ impl RoadRenderer {
         });
 
         let offset = match n.control.get_behavior(time) {
-            TrafficBehavior::RED => -size,
+            // Stop is the flashing red of night plans
+            TrafficBehavior::RED | TrafficBehavior::STOP => -size,
             TrafficBehavior::ORANGE => 0.0,
             TrafficBehavior::GREEN => size,
-            _ => unreachable!(),
         };
 
         sr.draw_circle(r_center + offset * dir_perp, Z_SIGNAL, size * 0.5);